@@ -0,0 +1,189 @@
+//! Control API over HTTP, so the operators can intervene without a restart, eg. `--admin-addr
+//! 127.0.0.1:9101`. It isn't authenticated, it should only be reachable from the host.
+//!
+//! - `GET /state`: the position, the size and the lag of every file, the latest errors, and
+//!   whether it's paused
+//! - `POST /pause` and `POST /resume`: stop reading the files, the lines already read are still
+//!   published
+//! - `POST /rotate`: rotate every file right away, as `SIGUSR1`
+//! - `POST /reload`: reload the config file, as `SIGHUP`
+//! - `POST /flush`: publish the lines of `--spool-file`, then empty it
+//! - `POST /log-level?filter=debug`: replace the filter of the logs, as `RUST_LOG`
+use crate::logs::LogFilter;
+use crate::stats;
+use serde_json::json;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Notify;
+
+/// What the API controls
+pub struct Admin {
+    /// The readers don't read the files while it's set
+    pub paused: Arc<AtomicBool>,
+    pub rotate: Arc<Notify>,
+    pub reload: Arc<Notify>,
+    pub flush: Arc<Notify>,
+    pub log_filter: Arc<LogFilter>,
+}
+
+impl Admin {
+    /// The status and the JSON body of the response
+    fn handle(&self, method: &str, target: &str) -> (&'static str, String) {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+        match (method, path) {
+            ("GET", "/state") => ("200 OK", self.state().to_string()),
+            ("POST", "/pause") => {
+                info!("Reading paused by the admin API");
+                self.paused.store(true, Ordering::Relaxed);
+                ("200 OK", json!({ "paused": true }).to_string())
+            }
+            ("POST", "/resume") => {
+                info!("Reading resumed by the admin API");
+                self.paused.store(false, Ordering::Relaxed);
+                ("200 OK", json!({ "paused": false }).to_string())
+            }
+            ("POST", "/rotate") => {
+                self.rotate.notify_one();
+                ("202 Accepted", json!({ "rotating": true }).to_string())
+            }
+            ("POST", "/reload") => {
+                self.reload.notify_one();
+                ("202 Accepted", json!({ "reloading": true }).to_string())
+            }
+            ("POST", "/flush") => {
+                self.flush.notify_one();
+                ("202 Accepted", json!({ "flushing": true }).to_string())
+            }
+            ("POST", "/log-level") => {
+                let filter = url::form_urlencoded::parse(query.as_bytes())
+                    .find(|(name, _)| name == "filter")
+                    .map(|(_, filter)| filter.into_owned());
+
+                match filter.map(|filter| self.log_filter.set(&filter).map(|_| filter)) {
+                    Some(Ok(filter)) => {
+                        info!("Log filter set to `{}` by the admin API", filter);
+                        ("200 OK", json!({ "filter": filter }).to_string())
+                    }
+                    Some(Err(e)) => ("400 Bad Request", json!({ "error": e }).to_string()),
+                    None => (
+                        "400 Bad Request",
+                        json!({ "error": "expected `?filter=`" }).to_string(),
+                    ),
+                }
+            }
+            (
+                _,
+                "/state" | "/pause" | "/resume" | "/rotate" | "/reload" | "/flush" | "/log-level",
+            ) => (
+                "405 Method Not Allowed",
+                json!({ "error": "method not allowed" }).to_string(),
+            ),
+            _ => ("404 Not Found", json!({ "error": "not found" }).to_string()),
+        }
+    }
+
+    fn state(&self) -> serde_json::Value {
+        let files = stats::stats()
+            .lags()
+            .iter()
+            .map(|lag| {
+                json!({
+                    "file": lag.path,
+                    "position": lag.position,
+                    "size": lag.size,
+                    "lag": lag.bytes(),
+                    "since_publish_s": lag.since.map(|since| since.as_secs()),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        json!({
+            "paused": self.paused.load(Ordering::Relaxed),
+            "files": files,
+            "errors": stats::stats().recent_errors(),
+        })
+    }
+}
+
+/// Serve the API until the process exits
+pub async fn serve(addr: SocketAddr, admin: Admin) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    let admin = Arc::new(admin);
+    info!("The admin API listens on `http://{}`", addr);
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let admin = admin.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = respond(stream, &admin).await {
+                debug!("Admin API: {}", e);
+            }
+        });
+    }
+}
+
+async fn respond(mut stream: TcpStream, admin: &Admin) -> std::io::Result<()> {
+    let mut buffer = [0u8; 4096];
+    let n = stream.read(&mut buffer).await?;
+    let request = String::from_utf8_lossy(&buffer[..n]);
+    let mut request_line = request.split_whitespace();
+    let method = request_line.next().unwrap_or("GET");
+    let target = request_line.next().unwrap_or("/");
+
+    let (status, body) = admin.handle(method, target);
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logs;
+    use serde_json::Value;
+
+    #[test]
+    fn test_handle() {
+        let (log_filter, filters) = logs::tests::recording();
+        let admin = Admin {
+            paused: Arc::default(),
+            rotate: Arc::default(),
+            reload: Arc::default(),
+            flush: Arc::default(),
+            log_filter: Arc::new(log_filter),
+        };
+
+        assert_eq!(admin.handle("POST", "/pause").0, "200 OK");
+        let (_, body) = admin.handle("GET", "/state");
+        assert_eq!(
+            serde_json::from_str::<Value>(&body).unwrap()["paused"],
+            true
+        );
+        admin.handle("POST", "/resume");
+        assert!(!admin.paused.load(Ordering::Relaxed));
+
+        let (status, _) = admin.handle("POST", "/log-level?filter=info%2Clog_bouncer%3Ddebug");
+        assert_eq!(status, "200 OK");
+        assert!(filters.lock().unwrap()[0].contains("log_bouncer=debug"));
+        let (status, _) = admin.handle("POST", "/log-level?filter=log_bouncer%3A%3Areader%3Dtrace");
+        assert_eq!(status, "200 OK");
+        assert!(filters.lock().unwrap()[1].contains("log_bouncer::reader=trace"));
+        assert_eq!(admin.handle("POST", "/log-level").0, "400 Bad Request");
+
+        assert_eq!(admin.handle("POST", "/flush").0, "202 Accepted");
+
+        assert_eq!(admin.handle("GET", "/rotate").0, "405 Method Not Allowed");
+        assert_eq!(admin.handle("GET", "/").0, "404 Not Found");
+    }
+}
@@ -0,0 +1,148 @@
+//! Alerts are distinct messages, published separately from the normal stream
+use crate::output::{Message, OutputAdapter};
+use chrono::{SecondsFormat, Utc};
+use serde_json::json;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Alerts that can be waiting to be sent, then they're discarded
+const ALERT_BUFFER: usize = 1024;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("invalid rate `{0}`, expected eg. `10/min`")]
+    InvalidRate(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Alert {
+    /// Name of the rule that triggered the alert
+    pub name: String,
+    pub message: String,
+    /// The line that triggered the alert, if any
+    pub line: Option<String>,
+    pub position: Option<u64>,
+}
+
+impl Alert {
+    pub fn to_json(&self) -> String {
+        json!({
+            "alert": self.name,
+            "message": self.message,
+            "line": self.line,
+            "position": self.position,
+            "@timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        })
+        .to_string()
+    }
+}
+
+/// Send alerts to the `Alerter`, never blocks the pipeline
+#[derive(Debug, Clone)]
+pub struct AlertSender {
+    tx: mpsc::Sender<Alert>,
+}
+
+impl AlertSender {
+    pub fn send(&self, alert: Alert) {
+        if let Err(e) = self.tx.try_send(alert) {
+            warn!("Alert discarded: {}", e);
+        }
+    }
+}
+
+pub fn channel() -> (AlertSender, mpsc::Receiver<Alert>) {
+    let (tx, rx) = mpsc::channel(ALERT_BUFFER);
+    (AlertSender { tx }, rx)
+}
+
+/// A threshold such as `10/min`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rate {
+    pub count: u64,
+    pub per: Duration,
+}
+
+impl FromStr for Rate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidRate(s.to_owned());
+        let (count, unit) = s.trim().split_once('/').ok_or_else(invalid)?;
+        let count = count.trim().parse().map_err(|_| invalid())?;
+        let per = match unit.trim() {
+            "s" | "sec" | "second" => Duration::from_secs(1),
+            "m" | "min" | "minute" => Duration::from_secs(60),
+            "h" | "hour" => Duration::from_secs(3600),
+            _ => return Err(invalid()),
+        };
+
+        Ok(Self { count, per })
+    }
+}
+
+/// Publish the alerts to a dedicated output and/or a webhook
+pub struct Alerter {
+    output: Option<Box<dyn OutputAdapter + Send + Sync>>,
+    webhook: Option<String>,
+    #[cfg(feature = "http")]
+    client: reqwest::Client,
+}
+
+impl Alerter {
+    pub fn new(
+        output: Option<Box<dyn OutputAdapter + Send + Sync>>,
+        webhook: Option<String>,
+    ) -> Self {
+        Self {
+            output,
+            webhook,
+            #[cfg(feature = "http")]
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Alerts that can't be delivered are logged, they never stop the software
+    pub async fn run(self, mut rx: mpsc::Receiver<Alert>) {
+        while let Some(alert) = rx.recv().await {
+            warn!("Alert `{}`: {}", alert.name, alert.message);
+            let payload = alert.to_json();
+
+            if let Some(output) = &self.output {
+                let message = Message {
+                    payload: payload.clone(),
+                    ..Message::default()
+                };
+
+                if let Err(e) = output.send(message).await {
+                    error!("Can't publish the alert `{}`: {}", alert.name, e);
+                }
+            }
+
+            if let Some(url) = &self.webhook {
+                if let Err(e) = self.call(url, payload).await {
+                    error!("Can't call the alert webhook `{}`: {}", alert.name, e);
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "http")]
+    async fn call(&self, url: &str, payload: String) -> Result<(), reqwest::Error> {
+        self.client
+            .post(url)
+            .header("Content-Type", "application/json")
+            .body(payload)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "http"))]
+    async fn call(&self, _url: &str, _payload: String) -> Result<(), &'static str> {
+        Err("the `http` feature isn't enabled")
+    }
+}
@@ -0,0 +1,398 @@
+//! Upload the rotated files to an S3-compatible object storage (AWS S3, GCS through its
+//! interoperability API, MinIO...), then delete them locally.
+//!
+//! Requests are signed with AWS Signature Version 4, the signed payload hash is checked by the
+//! storage which rejects the upload if the file got corrupted on the way.
+//!
+//! With `--compress`, the files are uploaded compressed.
+use crate::compression::Compression;
+use crate::pipeline::template::Template;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use url::Url;
+
+/// Uploads of a rotated file before giving up, it's kept locally then
+const UPLOAD_ATTEMPTS: u32 = 4;
+
+/// Waited before retrying an upload, doubled after each failure
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// The file is hashed by chunks of that size, it's never held in memory as a whole
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("invalid endpoint: {0}")]
+    Endpoint(#[from] url::ParseError),
+    #[error("invalid key template: {0}")]
+    Template(#[from] crate::pipeline::Error),
+    #[error("the key template references an unknown placeholder")]
+    UnresolvedKey,
+    #[error("i/o: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("http: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("upload rejected with status {0}: {1}")]
+    Rejected(reqwest::StatusCode, String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+pub struct Credentials {
+    pub access_key: String,
+    pub secret_key: String,
+}
+
+pub struct Archiver {
+    client: reqwest::Client,
+    /// eg. `https://s3.eu-west-1.amazonaws.com`, objects are addressed path-style
+    endpoint: Url,
+    bucket: String,
+    region: String,
+    credentials: Credentials,
+    /// Placeholders: `{filename}` of the rotated file, `{year}`, `{month}` and `{day}`
+    key: Template,
+    /// How long the rotated file is kept locally once uploaded
+    delete_after: Duration,
+    /// The files are compressed before being uploaded, unless they already are
+    compression: Option<Compression>,
+}
+
+impl fmt::Debug for Archiver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the credentials are left out on purpose
+        f.debug_struct("Archiver")
+            .field("endpoint", &self.endpoint.as_str())
+            .field("bucket", &self.bucket)
+            .field("region", &self.region)
+            .finish()
+    }
+}
+
+impl Archiver {
+    pub fn new(
+        endpoint: Option<&str>,
+        bucket: String,
+        region: String,
+        credentials: Credentials,
+        key: &str,
+        delete_after: Duration,
+    ) -> Result<Self> {
+        let endpoint = match endpoint {
+            Some(endpoint) => Url::parse(endpoint)?,
+            None => Url::parse(&format!("https://s3.{}.amazonaws.com", region))?,
+        };
+
+        Ok(Self {
+            client: reqwest::Client::new(),
+            endpoint,
+            bucket,
+            region,
+            credentials,
+            key: Template::parse(key)?,
+            delete_after,
+            compression: None,
+        })
+    }
+
+    /// Upload the files compressed, the local copy is replaced by the compressed one
+    pub fn with_compression(mut self, compression: Option<Compression>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Object key of the rotated file
+    pub fn key_for(&self, path: &Path, now: DateTime<Utc>) -> Result<String> {
+        let filename = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+
+        self.key
+            .render(|name| match name {
+                "filename" => filename.clone(),
+                "year" => Some(now.format("%Y").to_string()),
+                "month" => Some(now.format("%m").to_string()),
+                "day" => Some(now.format("%d").to_string()),
+                _ => None,
+            })
+            .ok_or(Error::UnresolvedKey)
+    }
+
+    /// Upload the file, retried a few times, then delete it once the grace period has elapsed
+    pub async fn archive(&self, path: &Path) -> Result<()> {
+        let path = &self.compressed(path).await?;
+        let key = self.key_for(path, Utc::now())?;
+
+        let mut delay = RETRY_DELAY;
+        for attempt in 1.. {
+            match self.upload(&key, path).await {
+                Ok(()) => break,
+                Err(e) if attempt < UPLOAD_ATTEMPTS => {
+                    warn!(
+                        "Can't upload `{}`, retrying in {}s: {}",
+                        path.to_string_lossy(),
+                        delay.as_secs(),
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        info!(
+            "Rotated file `{}` uploaded to `{}/{}`",
+            path.to_string_lossy(),
+            self.bucket,
+            key
+        );
+
+        tokio::time::sleep(self.delete_after).await;
+        tokio::fs::remove_file(path).await?;
+        debug!("Archived file `{}` deleted", path.to_string_lossy());
+
+        Ok(())
+    }
+
+    /// The file to upload, compressed if it has to be and it isn't yet, eg. by the rotator
+    async fn compressed(&self, path: &Path) -> Result<PathBuf> {
+        match self.compression {
+            Some(compression) if Compression::of_path(path).is_none() => {
+                Ok(compression.compress(path).await?)
+            }
+            _ => Ok(path.to_path_buf()),
+        }
+    }
+
+    /// The file is streamed, once it's been hashed
+    async fn upload(&self, key: &str, path: &Path) -> Result<()> {
+        let now = Utc::now();
+        let (payload_hash, size) = hash_file(path).await?;
+        let canonical_uri = format!("/{}/{}", uri_encode(&self.bucket), uri_encode(key));
+
+        let mut url = self.endpoint.clone();
+        url.set_path(&canonical_uri);
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_owned(),
+        };
+
+        let authorization = self.authorization(&canonical_uri, &host, &payload_hash, now);
+        let content_type =
+            Compression::of_path(path).map_or("text/plain", Compression::content_type);
+
+        let response = self
+            .client
+            .put(url)
+            .header("x-amz-content-sha256", &payload_hash)
+            .header("x-amz-date", now.format("%Y%m%dT%H%M%SZ").to_string())
+            .header("authorization", authorization)
+            .header(reqwest::header::CONTENT_LENGTH, size)
+            .header(reqwest::header::CONTENT_TYPE, content_type)
+            .body(reqwest::Body::from(tokio::fs::File::open(path).await?))
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(Error::Rejected(status, response.text().await?));
+        }
+
+        Ok(())
+    }
+
+    /// The `Authorization` header of a `PUT` request
+    fn authorization(
+        &self,
+        canonical_uri: &str,
+        host: &str,
+        payload_hash: &str,
+        now: DateTime<Utc>,
+    ) -> String {
+        let date = now.format("%Y%m%d").to_string();
+        let timestamp = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+
+        let canonical_request = format!(
+            "PUT\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            canonical_uri, host, payload_hash, timestamp, signed_headers, payload_hash
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            timestamp,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let key = signing_key(&self.credentials.secret_key, &date, &self.region, "s3");
+        let signature = hex::encode(hmac(&key, string_to_sign.as_bytes()));
+
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.credentials.access_key, scope, signed_headers, signature
+        )
+    }
+}
+
+pub(crate) fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    // unwrap() is safe, HMAC accepts keys of any size
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+pub(crate) fn signing_key(secret_key: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac(format!("AWS4{}", secret_key).as_bytes(), date.as_bytes());
+    let key = hmac(&key, region.as_bytes());
+    let key = hmac(&key, service.as_bytes());
+
+    hmac(&key, b"aws4_request")
+}
+
+/// SHA-256 of the file, hex encoded, along with its size
+async fn hash_file(path: &Path) -> std::io::Result<(String, u64)> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut chunk = vec![0; CHUNK_SIZE];
+    let mut size = 0;
+
+    loop {
+        let n = file.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&chunk[..n]);
+        size += n as u64;
+    }
+
+    Ok((hex::encode(hasher.finalize()), size))
+}
+
+/// Percent-encode everything but the unreserved characters and the slashes
+fn uri_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_hmac() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            hex::encode(hmac(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_hash_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log.1");
+        let data = vec![b'a'; CHUNK_SIZE * 2 + 3];
+        std::fs::write(&path, &data).unwrap();
+
+        let (hash, size) = hash_file(&path).await.unwrap();
+        assert_eq!(hash, hex::encode(Sha256::digest(&data)));
+        assert_eq!(size, data.len() as u64);
+    }
+
+    #[test]
+    fn test_signing_key() {
+        // from the AWS documentation
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+
+        assert_eq!(
+            hex::encode(key),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+    }
+
+    #[test]
+    fn test_key_template() {
+        let archiver = Archiver::new(
+            None,
+            "logs".to_owned(),
+            "eu-west-1".to_owned(),
+            Credentials {
+                access_key: "key".to_owned(),
+                secret_key: "secret".to_owned(),
+            },
+            "{year}/{month}/{day}/{filename}",
+            Duration::ZERO,
+        )
+        .unwrap();
+        let now = Utc.with_ymd_and_hms(2021, 9, 7, 3, 37, 53).unwrap();
+
+        assert_eq!(
+            archiver
+                .key_for(Path::new("/var/log/app.log.2021-09-07"), now)
+                .unwrap(),
+            "2021/09/07/app.log.2021-09-07"
+        );
+        assert_eq!(
+            archiver.endpoint.as_str(),
+            "https://s3.eu-west-1.amazonaws.com/"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compressed_upload() {
+        let dir = tempfile::tempdir().unwrap();
+        let credentials = Credentials {
+            access_key: "key".to_owned(),
+            secret_key: "secret".to_owned(),
+        };
+        let archiver = Archiver::new(
+            None,
+            "logs".to_owned(),
+            "eu-west-1".to_owned(),
+            credentials,
+            "{filename}",
+            Duration::ZERO,
+        )
+        .unwrap()
+        .with_compression(Some(Compression::Zstd));
+
+        let path = dir.path().join("app.log.1");
+        std::fs::write(&path, "first\n").unwrap();
+        let compressed = archiver.compressed(&path).await.unwrap();
+        assert_eq!(compressed, dir.path().join("app.log.1.zst"));
+        assert_eq!(
+            archiver.key_for(&compressed, Utc::now()).unwrap(),
+            "app.log.1.zst"
+        );
+        assert!(!path.exists());
+
+        // already compressed by the rotator
+        let gzip = dir.path().join("app.log.2.gz");
+        std::fs::write(&gzip, "").unwrap();
+        assert_eq!(archiver.compressed(&gzip).await.unwrap(), gzip);
+    }
+
+    #[test]
+    fn test_uri_encode() {
+        assert_eq!(uri_encode("2021/app log+1.gz"), "2021/app%20log%2B1.gz");
+    }
+}
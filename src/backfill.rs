@@ -0,0 +1,207 @@
+//! Publish historical files before tailing, eg. the lines written while log-bouncer was down,
+//! they can be compressed with gzip or zstd
+use crate::pipeline::timestamp::TimeFormat;
+use crate::publisher::Source;
+use crate::reader::{self, Batch, Line};
+use crate::state::Cursor;
+use crate::tail::LineBreak;
+use chrono::{DateTime, Utc};
+use flate2::bufread::MultiGzDecoder;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::Notify;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Read the file, it's decompressed according to its first bytes rather than its extension
+pub fn open(path: &Path) -> std::io::Result<Box<dyn BufRead + Send>> {
+    let mut file = BufReader::new(File::open(path)?);
+    let magic = file.fill_buf()?;
+
+    Ok(if magic.starts_with(GZIP_MAGIC) {
+        Box::new(BufReader::new(MultiGzDecoder::new(file)))
+    } else if magic.starts_with(ZSTD_MAGIC) {
+        Box::new(BufReader::new(zstd::Decoder::with_buffer(file)?))
+    } else {
+        Box::new(file)
+    })
+}
+
+/// Where the files start being published, from their beginning by default
+#[derive(Debug, Clone, Default)]
+pub struct Bounds {
+    /// Bytes skipped, once decompressed. The line numbers are counted from there
+    pub from_offset: u64,
+    /// The lines before the first one with a timestamp at or after it are skipped
+    pub from_time: Option<DateTime<Utc>>,
+    /// Formats of the timestamp at the beginning of the lines
+    pub formats: Vec<TimeFormat>,
+}
+
+impl Bounds {
+    /// Skip the beginning of the input, then return the first line to publish if it's been read
+    fn skip(
+        &self,
+        input: &mut impl BufRead,
+        cursor: &mut Cursor,
+        line_break: LineBreak,
+    ) -> io::Result<Option<Vec<u8>>> {
+        cursor.position = io::copy(&mut input.take(self.from_offset), &mut io::sink())?;
+
+        let from_time = match self.from_time {
+            Some(from_time) => from_time,
+            None => return Ok(None),
+        };
+
+        loop {
+            let mut line = vec![];
+            let n = match line_break.read_line(input, &mut line)? {
+                0 => return Ok(None),
+                n => n as u64,
+            };
+
+            cursor.position += n;
+            cursor.line += 1;
+            line.truncate(line_break.strip(&line).len());
+
+            let text = String::from_utf8_lossy(&line);
+            let at = self
+                .formats
+                .iter()
+                .find_map(|format| format.parse_prefix(&text));
+            // the lines without timestamp, eg. a stack trace, go along with the former line
+            if at.is_some_and(|at| at >= from_time) {
+                return Ok(Some(line));
+            }
+        }
+    }
+}
+
+/// Send every line of the files, one file after the other, then notify
+///
+/// Nothing is saved, the files are published again if log-bouncer is stopped meanwhile.
+pub fn read(
+    paths: Vec<PathBuf>,
+    tx: Sender<Batch>,
+    line_break: LineBreak,
+    bounds: Bounds,
+) -> Arc<Notify> {
+    let done = Arc::new(Notify::new());
+    let notifier = done.clone();
+
+    std::thread::spawn(move || {
+        for path in paths {
+            info!("Backfilling `{}`", path.to_string_lossy());
+            let source = Arc::new(Source::unsaved(path.clone()));
+
+            let result = open(&path).and_then(|mut input| {
+                let mut cursor = Cursor::default();
+                if let Some(line) = bounds.skip(&mut input, &mut cursor, line_break)? {
+                    if let Err(e) = tx.blocking_send(vec![Line::new(&source, cursor, line)]) {
+                        error!("Can't send to mpsc: {}", e);
+                        return Ok(false);
+                    }
+                }
+
+                reader::read_to_end(&mut input, &source, &mut cursor, &tx, line_break)
+            });
+            match result {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => error!("Can't backfill `{}`: {}", path.to_string_lossy(), e),
+            }
+        }
+
+        notifier.notify_one();
+    });
+
+    done
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn test_open_compressed() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = "first\nsecond\n";
+
+        let gzip = dir.path().join("app.log.1.gz");
+        let mut encoder =
+            flate2::write::GzEncoder::new(File::create(&gzip).unwrap(), Default::default());
+        encoder.write_all(content.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+
+        let zstd = dir.path().join("app.log.2.zst");
+        std::fs::write(&zstd, zstd::encode_all(content.as_bytes(), 0).unwrap()).unwrap();
+
+        let plain = dir.path().join("app.log.3");
+        std::fs::write(&plain, content).unwrap();
+
+        for path in [gzip, zstd, plain] {
+            let mut read = String::new();
+            open(&path).unwrap().read_to_string(&mut read).unwrap();
+            assert_eq!(read, content);
+        }
+    }
+
+    #[test]
+    fn test_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log.1.zst");
+        std::fs::write(&path, zstd::encode_all(&b"first\nsecond"[..], 0).unwrap()).unwrap();
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(4);
+        read(
+            vec![dir.path().join("missing.gz"), path],
+            tx,
+            LineBreak::Byte,
+            Bounds::default(),
+        );
+
+        let lines = std::iter::from_fn(|| rx.blocking_recv())
+            .flatten()
+            .map(|line| (line.cursor.line, line.bytes.to_vec()))
+            .collect::<Vec<_>>();
+        assert_eq!(lines, vec![(1, b"first".to_vec()), (2, b"second".to_vec())]);
+    }
+
+    #[test]
+    fn test_bounds() {
+        let content =
+            "skipped\n2024-01-01T10:00:00Z early\n2024-01-01T12:00:00Z late\n  at main()\n";
+        let from_time = "2024-01-01T11:00:00Z".parse().ok();
+        let bounds = Bounds {
+            from_offset: 8,
+            from_time,
+            formats: vec![TimeFormat::Rfc3339],
+        };
+
+        let mut input = content.as_bytes();
+        let mut cursor = Cursor::default();
+        let line = bounds
+            .skip(&mut input, &mut cursor, LineBreak::Byte)
+            .unwrap();
+        assert_eq!(line.unwrap(), b"2024-01-01T12:00:00Z late");
+        assert_eq!((cursor.position, cursor.line), (61, 2));
+        assert_eq!(input, b"  at main()\n");
+
+        let bounds = Bounds {
+            from_time: "2025-01-01T00:00:00Z".parse().ok(),
+            ..bounds
+        };
+        let mut input = content.as_bytes();
+        assert_eq!(
+            bounds
+                .skip(&mut input, &mut cursor, LineBreak::Byte)
+                .unwrap(),
+            None
+        );
+    }
+}
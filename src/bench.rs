@@ -0,0 +1,261 @@
+//! `log-bouncer bench`: write synthetic lines to a temporary file at a given rate while shipping
+//! it, then report the throughput and the latency from the line being written until the output
+//! confirms it, eg. to size the instances. The lines are discarded unless an exchange is set.
+use crate::opt::BenchOpt;
+use crate::output::amqp::AmqpOutput;
+use crate::output::{Message, OutputAdapter};
+use crate::pipeline::Pipeline;
+use crate::publisher::{InvalidUtf8, Publisher, Source};
+use crate::reader::Reader;
+use crate::shutdown::Shutdown;
+use crate::state::Cursor;
+use async_trait::async_trait;
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch};
+
+/// Digits of the sequence number starting every line
+const SEQUENCE_DIGITS: usize = 10;
+
+/// How long the lines still being shipped once the writing is over are waited for
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// When every line has been written
+type Written = Arc<Mutex<Vec<Instant>>>;
+
+/// Discards the lines
+struct Null;
+
+#[async_trait]
+impl OutputAdapter for Null {
+    async fn send(&self, _message: Message) -> crate::error::Result<()> {
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        "null"
+    }
+}
+
+/// Records how long after being written every line has been confirmed
+struct Measured {
+    output: Box<dyn OutputAdapter + Send + Sync>,
+    written: Written,
+    latencies: Arc<Mutex<Vec<Duration>>>,
+}
+
+#[async_trait]
+impl OutputAdapter for Measured {
+    async fn send(&self, message: Message) -> crate::error::Result<()> {
+        let sequence = message
+            .payload
+            .get(..SEQUENCE_DIGITS)
+            .and_then(|sequence| sequence.parse::<usize>().ok());
+        self.output.send(message).await?;
+
+        // unless the pipeline changed the line
+        let written =
+            sequence.and_then(|sequence| self.written.lock().unwrap().get(sequence).copied());
+        if let Some(written) = written {
+            self.latencies.lock().unwrap().push(written.elapsed());
+        }
+
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        self.output.name()
+    }
+}
+
+pub async fn run(opts: BenchOpt) -> Result<(), Box<dyn Error>> {
+    let dir = std::env::temp_dir().join(format!("log-bouncer-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join("bench.log");
+    File::create(&path)?;
+
+    let benched = bench(&opts, &path).await;
+    let _ = std::fs::remove_dir_all(&dir);
+    println!("{}", benched?);
+
+    Ok(())
+}
+
+async fn bench(opts: &BenchOpt, path: &Path) -> Result<String, Box<dyn Error>> {
+    let output: Box<dyn OutputAdapter + Send + Sync> = match &opts.amqp_exchange {
+        Some(exchange) => {
+            Box::new(AmqpOutput::new(&opts.amqp_uri, exchange, &opts.amqp_routing_key).await?)
+        }
+        None => Box::new(Null),
+    };
+    let written = Written::default();
+    let latencies = Arc::new(Mutex::new(vec![]));
+    let output = Measured {
+        output,
+        written: written.clone(),
+        latencies: latencies.clone(),
+    };
+
+    let (tx, rx) = mpsc::channel(opts.buffer_publish);
+    let (state_tx, _state_rx) = watch::channel(Cursor::default());
+    let source = Arc::new(Source::new(path.to_owned(), state_tx));
+    let reader = Reader::new(
+        source,
+        Cursor::default(),
+        Duration::from_millis(100),
+        true,
+        None,
+        Arc::new(AtomicU64::new(0)),
+    )?;
+    let shutdown = Shutdown::new();
+    reader.with_shutdown(shutdown.clone()).work(tx);
+
+    let (_reload_tx, reload_rx) = mpsc::channel(1);
+    let (_events_tx, events_rx) = mpsc::channel(1);
+    let mut publisher = Publisher::new(
+        output,
+        Pipeline::new(),
+        rx,
+        reload_rx,
+        events_rx,
+        InvalidUtf8::Replace,
+        None,
+    )
+    .with_shutdown(shutdown.clone());
+    let publishing = tokio::spawn(async move {
+        let _ = publisher.publish().await;
+    });
+
+    let started = Instant::now();
+    let writing = {
+        let (path, written) = (path.to_owned(), written.clone());
+        let (rate, line_size) = (opts.rate, opts.line_size);
+        let duration = Duration::from_secs(opts.duration);
+        tokio::task::spawn_blocking(move || write(&path, rate, line_size, duration, &written))
+    };
+    writing.await??;
+
+    // the lines still being shipped
+    let count = written.lock().unwrap().len();
+    let deadline = Instant::now() + DRAIN_TIMEOUT;
+    while latencies.lock().unwrap().len() < count && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let elapsed = started.elapsed();
+
+    // the output may not confirm the line being sent anymore
+    shutdown.trigger();
+    let _ = tokio::time::timeout(DRAIN_TIMEOUT, publishing).await;
+
+    let mut latencies = latencies.lock().unwrap().clone();
+    Ok(report(count, opts.line_size, elapsed, &mut latencies))
+}
+
+/// Append the lines at the rate, a batch every 10ms
+fn write(
+    path: &Path,
+    rate: u64,
+    line_size: usize,
+    duration: Duration,
+    written: &Written,
+) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().append(true).open(path)?;
+    let started = Instant::now();
+
+    while started.elapsed() < duration {
+        let due = (rate as f64 * started.elapsed().as_secs_f64()) as usize;
+        let mut batch = vec![];
+        {
+            let mut written = written.lock().unwrap();
+            while written.len() < due {
+                batch.extend(line(written.len(), line_size));
+                written.push(Instant::now());
+            }
+        }
+
+        file.write_all(&batch)?;
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    Ok(())
+}
+
+/// The sequence number, padded to the size, then the line break
+fn line(sequence: usize, size: usize) -> Vec<u8> {
+    let mut line = format!("{:0width$}", sequence, width = SEQUENCE_DIGITS).into_bytes();
+    line.resize(size.max(SEQUENCE_DIGITS), b'x');
+    line.push(b'\n');
+    line
+}
+
+fn percentile(sorted: &[Duration], percentile: f64) -> f64 {
+    let index = ((sorted.len() - 1) as f64 * percentile).round() as usize;
+    sorted[index].as_secs_f64() * 1000.0
+}
+
+fn report(
+    written: usize,
+    line_size: usize,
+    elapsed: Duration,
+    latencies: &mut [Duration],
+) -> String {
+    latencies.sort();
+    let published = latencies.len();
+    let seconds = elapsed.as_secs_f64();
+    let mut report = format!(
+        "written:    {} lines\npublished:  {} lines in {:.1}s, {:.0} lines/s, {:.2} MB/s",
+        written,
+        published,
+        seconds,
+        published as f64 / seconds,
+        (published * (line_size + 1)) as f64 / seconds / 1_000_000.0
+    );
+
+    if published > 0 {
+        report.push_str(&format!(
+            "\nlatency:    p50 {:.1}ms, p90 {:.1}ms, p99 {:.1}ms, max {:.1}ms",
+            percentile(latencies, 0.5),
+            percentile(latencies, 0.9),
+            percentile(latencies, 0.99),
+            percentile(latencies, 1.0)
+        ));
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line() {
+        assert_eq!(line(42, 14), b"0000000042xxxx\n");
+        assert_eq!(line(42, 0), b"0000000042\n");
+    }
+
+    #[test]
+    fn test_report() {
+        let mut latencies = (1..=100)
+            .rev()
+            .map(Duration::from_millis)
+            .collect::<Vec<_>>();
+        let report = report(120, 99, Duration::from_secs(2), &mut latencies);
+        let lines = report.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines[0], "written:    120 lines");
+        assert_eq!(
+            lines[1],
+            "published:  100 lines in 2.0s, 50 lines/s, 0.01 MB/s"
+        );
+        assert_eq!(
+            lines[2],
+            "latency:    p50 51.0ms, p90 90.0ms, p99 99.0ms, max 100.0ms"
+        );
+    }
+}
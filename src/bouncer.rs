@@ -0,0 +1,439 @@
+//! Embed the tailing and the shipping in another application, without the command line:
+//!
+//! ```no_run
+//! # async fn ship(output: log_bouncer::output::stdout::StdOut) -> Result<(), log_bouncer::Error> {
+//! let bouncer = log_bouncer::LogBouncer::builder()
+//!     .file("/var/log/app.log")
+//!     .state_file("/var/lib/app/log-bouncer.json")
+//!     .output(output)
+//!     .build()?;
+//! let shutdown = bouncer.shutdown_handle();
+//! tokio::spawn(async move {
+//!     let _ = tokio::signal::ctrl_c().await;
+//!     shutdown.shutdown();
+//! });
+//! bouncer.run().await
+//! # }
+//! ```
+//!
+//! The application sets up its own logs, the files are neither rotated nor reloaded.
+use crate::alert::{self, Alert, Alerter};
+use crate::error::Result;
+use crate::hooks::{self, Callback, Hooks};
+use crate::output::OutputAdapter;
+use crate::pipeline::config::StageConfig;
+use crate::pipeline::Pipeline;
+use crate::publisher::{InvalidUtf8, Publisher, Source};
+use crate::reader::{Batch, Reader};
+use crate::shutdown::Shutdown;
+use crate::state::{InstanceLock, SavedState, StartFrom, StateSaver, StateStore};
+use crate::stats;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, watch, Notify};
+use tokio::task::JoinHandle;
+
+/// Stops a running [`LogBouncer`], the lines already read are published first
+#[derive(Clone, Default)]
+pub struct ShutdownHandle {
+    shutdown: Shutdown,
+}
+
+impl ShutdownHandle {
+    pub fn shutdown(&self) {
+        // it stays triggered if it isn't running yet
+        self.shutdown.trigger();
+    }
+}
+
+pub struct Builder {
+    files: Vec<PathBuf>,
+    state_file: Option<PathBuf>,
+    stages: Vec<StageConfig>,
+    output: Option<Box<dyn OutputAdapter + Send + Sync>>,
+    start_from: StartFrom,
+    poll_interval: Duration,
+    save_state_interval: Duration,
+    shutdown_timeout: Duration,
+    callbacks: Vec<Callback>,
+    lag_threshold: Option<u64>,
+    alert_output: Option<Box<dyn OutputAdapter + Send + Sync>>,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self {
+            files: vec![],
+            state_file: None,
+            stages: vec![],
+            output: None,
+            start_from: StartFrom::Saved,
+            poll_interval: Duration::from_millis(500),
+            save_state_interval: Duration::from_millis(500),
+            shutdown_timeout: Duration::from_secs(5),
+            callbacks: vec![],
+            lag_threshold: None,
+            alert_output: None,
+        }
+    }
+}
+
+impl Builder {
+    /// A file to tail, can be repeated
+    pub fn file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.files.push(path.into());
+        self
+    }
+
+    /// Save every position in this file, next to each file otherwise, as `--state-file`
+    pub fn state_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.state_file = Some(path.into());
+        self
+    }
+
+    /// A stage applied to every line, in the order they're added
+    pub fn stage(mut self, stage: StageConfig) -> Self {
+        self.stages.push(stage);
+        self
+    }
+
+    /// Where the lines are published, mandatory
+    pub fn output(mut self, output: impl OutputAdapter + Send + Sync + 'static) -> Self {
+        self.output = Some(Box::new(output));
+        self
+    }
+
+    /// Where the files are read from, the saved position by default
+    pub fn start_from(mut self, start_from: StartFrom) -> Self {
+        self.start_from = start_from;
+        self
+    }
+
+    /// Check the files for new lines that often, on top of the notifications
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Save the position of the last published line that often
+    pub fn save_state_interval(mut self, interval: Duration) -> Self {
+        self.save_state_interval = interval;
+        self
+    }
+
+    /// Publish the lines already read for that long at most once shut down
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Called with every event as it happens, see [`hooks::Event`], can be repeated
+    pub fn on_event(mut self, callback: impl Fn(&hooks::Event) + Send + Sync + 'static) -> Self {
+        self.callbacks.push(Box::new(callback));
+        self
+    }
+
+    /// Send every event to the channel, the ones that don't fit are dropped
+    pub fn events(mut self, tx: mpsc::Sender<hooks::Event>) -> Self {
+        self.callbacks.push(hooks::channel(tx));
+        self
+    }
+
+    /// Tell once there's more than that many bytes of a file left to publish, then once it's
+    /// caught up again, see [`hooks::Event::LagCrossed`]
+    pub fn lag_threshold(mut self, bytes: u64) -> Self {
+        self.lag_threshold = Some(bytes);
+        self
+    }
+
+    /// Where the alerts of the stages are published, mandatory if a stage sends any
+    pub fn alert_output(mut self, output: impl OutputAdapter + Send + Sync + 'static) -> Self {
+        self.alert_output = Some(Box::new(output));
+        self
+    }
+
+    pub fn build(self) -> Result<LogBouncer> {
+        let output = self.output.ok_or("missing output")?;
+        if self.files.is_empty() {
+            return Err("missing file".into());
+        }
+        let (alert_tx, alert_rx) = alert::channel();
+        let alerter = match self.alert_output {
+            Some(output) => Some((Alerter::new(Some(output), None), alert_rx)),
+            None if self.stages.iter().any(StageConfig::sends_alerts) => {
+                return Err("missing alert output, a stage sends alerts".into());
+            }
+            None => None,
+        };
+        let pipeline = Pipeline::from_config(&self.stages, &alert_tx)?;
+
+        Ok(LogBouncer {
+            files: self.files,
+            state_file: self.state_file,
+            pipeline,
+            output,
+            start_from: self.start_from,
+            poll_interval: self.poll_interval,
+            save_state_interval: self.save_state_interval,
+            shutdown_timeout: self.shutdown_timeout,
+            shutdown: ShutdownHandle::default(),
+            hooks: Hooks::new(self.callbacks),
+            lag_threshold: self.lag_threshold,
+            alerter,
+        })
+    }
+}
+
+/// Tails the files and publishes their lines to the output until it's shut down
+pub struct LogBouncer {
+    files: Vec<PathBuf>,
+    state_file: Option<PathBuf>,
+    pipeline: Pipeline,
+    output: Box<dyn OutputAdapter + Send + Sync>,
+    start_from: StartFrom,
+    poll_interval: Duration,
+    save_state_interval: Duration,
+    shutdown_timeout: Duration,
+    shutdown: ShutdownHandle,
+    hooks: Hooks,
+    lag_threshold: Option<u64>,
+    /// Publishes the alerts of the stages, see [`Builder::alert_output`]
+    alerter: Option<(Alerter, mpsc::Receiver<Alert>)>,
+}
+
+/// A file being tailed
+struct Tailed {
+    saver_stop: Arc<Notify>,
+    saver: JoinHandle<()>,
+    _lock: Option<InstanceLock>,
+}
+
+impl LogBouncer {
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Until it's shut down, or a file can't be followed anymore
+    pub async fn run(mut self) -> Result<()> {
+        let (publish_tx, publish_rx) = mpsc::channel::<Batch>(1);
+        let (stopped_tx, mut stopped_rx) = mpsc::channel(self.files.len());
+        let (store, _lock) = match &self.state_file {
+            Some(state_file) => {
+                let lock = InstanceLock::acquire(state_file)?;
+                let store = StateStore::open(state_file.clone())?;
+                (Some(Arc::new(Mutex::new(store))), Some(lock))
+            }
+            None => (None, None),
+        };
+
+        let mut tailed = vec![];
+        for path in &self.files {
+            tailed.push(self.tail(path.clone(), &store, &publish_tx, &stopped_tx)?);
+        }
+        drop(publish_tx);
+        // it stops along with the pipeline
+        if let Some((alerter, alert_rx)) = self.alerter.take() {
+            tokio::spawn(alerter.run(alert_rx));
+        }
+        let lags = self.lag_threshold.map(|threshold| {
+            tokio::spawn(self.hooks.clone().watch_lags(self.files.clone(), threshold))
+        });
+
+        // the bouncer has no reload nor events of its own
+        let (_reload_tx, reload_rx) = mpsc::channel(1);
+        let (_events_tx, events_rx) = mpsc::channel(1);
+        let mut publisher = Publisher::new(
+            self.output,
+            self.pipeline,
+            publish_rx,
+            reload_rx,
+            events_rx,
+            InvalidUtf8::Replace,
+            None,
+        )
+        .with_shutdown(self.shutdown.shutdown.clone())
+        .with_hooks(self.hooks.clone());
+
+        let stopped = {
+            let publishing = publisher.publish();
+            tokio::pin!(publishing);
+
+            tokio::select! {
+                published = &mut publishing => published,
+                Some(path) = stopped_rx.recv() => {
+                    Err(format!("`{}` isn't followed anymore", path.to_string_lossy()).into())
+                }
+                // the publisher returns as well, unless the output hangs on the line being sent
+                _ = self.shutdown.shutdown.triggered() => Ok(()),
+            }
+        };
+
+        // the readers read their file a last time, the lines already read are published, the
+        // ones left are read again on the next start
+        self.shutdown.shutdown.trigger();
+        if tokio::time::timeout(self.shutdown_timeout, publisher.drain())
+            .await
+            .is_err()
+        {
+            warn!(
+                "The lines read haven't all been published within {}s",
+                self.shutdown_timeout.as_secs()
+            );
+        }
+        for tailed in tailed {
+            tailed.saver_stop.notify_one();
+            tailed.saver.await?;
+        }
+        if let Some(lags) = lags {
+            lags.abort();
+        }
+
+        stopped
+    }
+
+    fn tail(
+        &self,
+        path: PathBuf,
+        store: &Option<Arc<Mutex<StateStore>>>,
+        publish_tx: &mpsc::Sender<Batch>,
+        stopped_tx: &mpsc::Sender<PathBuf>,
+    ) -> Result<Tailed> {
+        let mut saved_state = match store {
+            Some(store) => SavedState::in_store(&path, store.clone()),
+            None => SavedState::new(&path)?,
+        };
+        let lock = match store {
+            Some(_) => None,
+            None => Some(saved_state.lock()?),
+        };
+        let cursor = saved_state.start(self.start_from)?;
+        let (state_tx, state_rx) = watch::channel(cursor);
+
+        let saver = StateSaver::new(
+            saved_state,
+            state_rx,
+            self.save_state_interval,
+            self.save_state_interval,
+        )
+        .with_hooks(self.hooks.clone());
+        let saver_stop = saver.stop_trigger();
+
+        stats::stats().follow(&path, cursor.position);
+        let source = Arc::new(Source::new(path.clone(), state_tx));
+        let reader = Reader::new(
+            source,
+            cursor,
+            self.poll_interval,
+            true,
+            None,
+            Arc::default(),
+        )?;
+        // it's stopped along with the bouncer
+        let failed = reader
+            .with_shutdown(self.shutdown.shutdown.clone())
+            .with_hooks(self.hooks.clone())
+            .work(publish_tx.clone());
+
+        let stopped_tx = stopped_tx.clone();
+        tokio::spawn(async move {
+            failed.notified().await;
+            let _ = stopped_tx.send(path).await;
+        });
+
+        Ok(Tailed {
+            saver_stop,
+            saver: saver.watch(),
+            _lock: lock,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::Message;
+    use async_trait::async_trait;
+    use std::io::Write;
+
+    #[derive(Clone, Default)]
+    struct Recorded(Arc<Mutex<Vec<String>>>);
+
+    #[async_trait]
+    impl OutputAdapter for Recorded {
+        async fn send(&self, message: Message) -> Result<()> {
+            self.0.lock().unwrap().push(message.payload);
+            Ok(())
+        }
+    }
+
+    async fn ship(path: &std::path::Path, state_file: &std::path::Path) -> Vec<String> {
+        let recorded = Recorded::default();
+        let (events_tx, mut events_rx) = mpsc::channel(16);
+        let bouncer = LogBouncer::builder()
+            .file(path)
+            .state_file(state_file)
+            .output(recorded.clone())
+            .poll_interval(Duration::from_millis(10))
+            .events(events_tx)
+            .build()
+            .unwrap();
+        let shutdown = bouncer.shutdown_handle();
+        let shut_down = async {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            shutdown.shutdown();
+        };
+
+        let (ran, _) = tokio::join!(bouncer.run(), shut_down);
+        ran.unwrap();
+
+        let lines = recorded.0.lock().unwrap().clone();
+        let mut published = 0;
+        let mut saved = None;
+        while let Ok(event) = events_rx.try_recv() {
+            match event {
+                hooks::Event::Published { .. } => published += 1,
+                hooks::Event::StateSaved { position, .. } => saved = Some(position),
+                event => panic!("unexpected {:?}", event),
+            }
+        }
+        assert_eq!(published, lines.len());
+        // the last position is saved once it's shut down
+        assert_eq!(saved, Some(std::fs::metadata(path).unwrap().len()));
+        lines
+    }
+
+    #[tokio::test]
+    async fn test_builder() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        let state_file = dir.path().join("state.json");
+        std::fs::write(&path, "first\nsecond\n").unwrap();
+
+        assert_eq!(ship(&path, &state_file).await, vec!["first", "second"]);
+
+        // it resumes after the last published line
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(b"third\n").unwrap();
+        assert_eq!(ship(&path, &state_file).await, vec!["third"]);
+
+        assert!(LogBouncer::builder().file(&path).build().is_err());
+        let alerting = || {
+            LogBouncer::builder()
+                .file(&path)
+                .output(Recorded::default())
+                .stage(StageConfig::Alert {
+                    rules: vec!["panic=panicked".to_owned()],
+                })
+        };
+        assert!(alerting().build().is_err());
+        assert!(alerting().alert_output(Recorded::default()).build().is_ok());
+    }
+}
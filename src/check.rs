@@ -0,0 +1,165 @@
+//! `log-bouncer check`: what would prevent log-bouncer from running, without starting it, eg. in
+//! CI or before a deploy. The options, the config file, the stages, the files along with their
+//! saved positions are checked, and the output with `--connect`. Every problem is printed, it
+//! fails if there is any.
+use crate::alert;
+use crate::config::Config;
+use crate::discovery;
+use crate::opt::{CheckOpt, Opt};
+use crate::output::amqp::AmqpOutput;
+use crate::pipeline::Pipeline;
+use crate::reader;
+use std::error::Error;
+use std::path::Path;
+use std::time::Duration;
+
+/// Given up on connecting to the output after that long
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub async fn check(opts: CheckOpt) -> Result<(), Box<dyn Error>> {
+    let config = match &opts.opts.config {
+        Some(path) => match Config::load(path) {
+            Ok(config) => config,
+            Err(e) => {
+                println!("error: {}", e);
+                return Err("1 problem found".into());
+            }
+        },
+        None => Config::default(),
+    };
+
+    let mut found = 0;
+    for source in crate::sources(&opts.opts, &config) {
+        let mut problems = problems(&source, &config);
+        if opts.connect {
+            problems.extend(connect(&source).await.err());
+        }
+
+        let name = source
+            .source
+            .as_deref()
+            .map_or(String::new(), |name| format!("[{}] ", name));
+        for problem in &problems {
+            println!("error: {}{}", name, problem);
+        }
+        found += problems.len();
+    }
+
+    match found {
+        0 => {
+            println!("ok");
+            Ok(())
+        }
+        1 => Err("1 problem found".into()),
+        _ => Err(format!("{} problems found", found).into()),
+    }
+}
+
+/// Everything that can be checked without connecting to the output
+fn problems(opts: &Opt, config: &Config) -> Vec<String> {
+    let mut problems = vec![];
+    let (alert_tx, _alert_rx) = alert::channel();
+
+    let pipeline = crate::load_stages(opts, config)
+        .and_then(|stages| Ok(Pipeline::from_config(&stages, &alert_tx)?));
+    if let Err(e) = pipeline {
+        problems.push(format!("invalid pipeline: {}", e));
+    }
+    if let Err(e) = crate::archiver(opts) {
+        problems.push(format!("invalid archive: {}", e));
+    }
+
+    if let Some(dir) = &opts.watch_dir {
+        if !dir.is_dir() {
+            problems.push(format!("`{}` isn't a directory", dir.to_string_lossy()));
+        }
+    }
+
+    for path in opts.files() {
+        match discovery::expand([path]) {
+            Ok(files) => problems.extend(
+                files
+                    .iter()
+                    .filter_map(|file| check_file(opts, file).err())
+                    .map(|e| format!("`{}`: {}", path.to_string_lossy(), e)),
+            ),
+            Err(e) => problems.push(format!("`{}`: {}", path.to_string_lossy(), e)),
+        }
+    }
+
+    problems
+}
+
+/// The file can be read, rotated, and its saved position is consistent
+fn check_file(opts: &Opt, path: &Path) -> Result<(), Box<dyn Error>> {
+    std::fs::File::open(path)?;
+    // neither saved nor rotated
+    if reader::is_fifo(path) {
+        return Ok(());
+    }
+
+    crate::rotation_policy(opts, path)?;
+    let status = crate::saved_state(opts.state_file.as_deref(), path)?.status()?;
+    if status.saved && status.position > status.file_size {
+        Err(format!(
+            "the saved position {} is past the end of the file ({} bytes)",
+            status.position, status.file_size
+        ))?;
+    }
+
+    Ok(())
+}
+
+/// The output accepts the connection
+async fn connect(opts: &Opt) -> Result<(), String> {
+    let mut opts = opts.clone();
+    crate::resolve_secrets(&mut opts, Duration::ZERO)
+        .await
+        .map_err(|e| format!("can't fetch the secret: {}", e))?;
+    let output = AmqpOutput::new(&opts.amqp_uri, &opts.amqp_exchange, &opts.amqp_routing_key);
+
+    match tokio::time::timeout(CONNECT_TIMEOUT, output).await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(e)) => Err(format!("can't connect to the output: {}", e)),
+        Err(_) => Err(format!(
+            "can't connect to the output within {}s",
+            CONNECT_TIMEOUT.as_secs()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Clap;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_problems() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("app.log");
+        std::fs::write(&file, "line\n").unwrap();
+        let missing = dir.path().join("missing.log");
+
+        let state_file = dir.path().join("state.json");
+        let mut opts = Opt::parse_from([
+            PathBuf::from("log-bouncer"),
+            "--amqp-exchange".into(),
+            "logs".into(),
+            "--amqp-routing-key".into(),
+            "app".into(),
+            "--state-file".into(),
+            state_file,
+            "-f".into(),
+            file.clone(),
+        ]);
+        assert!(problems(&opts, &Config::default()).is_empty());
+
+        opts.file.push(missing);
+        opts.watch_dir = Some(file);
+        let problems = problems(&opts, &Config::default());
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].ends_with("isn't a directory"));
+        assert!(problems[1].contains("missing.log"));
+    }
+}
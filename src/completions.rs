@@ -0,0 +1,460 @@
+//! Shell completions, generated from the definition of the command line so they never lag behind
+//! it, eg. `log-bouncer completions bash > /etc/bash_completion.d/log-bouncer`
+use clap::{App, Arg, ArgSettings};
+use std::fmt::Write;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl FromStr for Shell {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bash" => Ok(Self::Bash),
+            "zsh" => Ok(Self::Zsh),
+            "fish" => Ok(Self::Fish),
+            _ => Err(format!("unknown shell `{}`, expected bash, zsh or fish", s)),
+        }
+    }
+}
+
+/// A command of the command line, eg. `log-bouncer state show`
+pub struct Node<'a, 'help> {
+    /// The names from the binary to the command
+    pub path: Vec<&'a str>,
+    pub app: &'a App<'help>,
+    /// Its own arguments, then the global ones of its parents
+    pub args: Vec<&'a Arg<'help>>,
+}
+
+impl<'a, 'help> Node<'a, 'help> {
+    pub fn subcommands(&self) -> impl Iterator<Item = &'a App<'help>> {
+        self.app.get_subcommands()
+    }
+
+    /// eg. `log-bouncer__state__show`, to name the shell functions
+    fn id(&self) -> String {
+        self.path.join("__")
+    }
+}
+
+/// Every command, parents first, along with the global arguments they inherit
+pub fn nodes<'a, 'help>(app: &'a App<'help>) -> Vec<Node<'a, 'help>> {
+    fn visit<'a, 'help>(
+        app: &'a App<'help>,
+        mut path: Vec<&'a str>,
+        globals: Vec<&'a Arg<'help>>,
+        nodes: &mut Vec<Node<'a, 'help>>,
+    ) {
+        path.push(app.get_name());
+        let mut args = app
+            .get_arguments()
+            .filter(|arg| !arg.is_set(ArgSettings::Hidden))
+            .collect::<Vec<_>>();
+        let inherited = globals
+            .iter()
+            .filter(|global| args.iter().all(|arg| arg.get_name() != global.get_name()))
+            .copied()
+            .collect::<Vec<_>>();
+        let mut globals = globals;
+        globals.extend(args.iter().filter(|arg| arg.get_global()));
+        args.extend(inherited);
+
+        nodes.push(Node {
+            path: path.clone(),
+            app,
+            args,
+        });
+        for sub in app.get_subcommands() {
+            visit(sub, path.clone(), globals.clone(), nodes);
+        }
+    }
+
+    let mut nodes = vec![];
+    visit(app, vec![], vec![], &mut nodes);
+    nodes
+}
+
+/// The arguments completed for the node: the binary alone takes the ones of its default
+/// subcommand as well, `run`
+fn completed<'a, 'help>(nodes: &[Node<'a, 'help>], node: &Node<'a, 'help>) -> Vec<&'a Arg<'help>> {
+    let mut args = node.args.clone();
+    if node.path.len() == 1 {
+        if let Some(run) = nodes.iter().find(|other| other.path[1..] == ["run"]) {
+            let own = args.iter().map(|arg| arg.get_name()).collect::<Vec<_>>();
+            let defaults = run
+                .args
+                .iter()
+                .filter(|arg| !own.contains(&arg.get_name()))
+                .copied()
+                .collect::<Vec<_>>();
+            args.extend(defaults);
+        }
+    }
+
+    args
+}
+
+fn is_option(arg: &Arg) -> bool {
+    arg.get_index().is_none() && (arg.get_long().is_some() || arg.get_short().is_some())
+}
+
+fn takes_value(arg: &Arg) -> bool {
+    arg.is_set(ArgSettings::TakesValue)
+}
+
+/// The first line of the help of the argument or the command
+fn summary(about: Option<&str>) -> &str {
+    about
+        .and_then(|about| about.lines().next())
+        .unwrap_or_default()
+}
+
+/// Every way to spell the option, eg. `-f --file`
+fn flags(arg: &Arg) -> Vec<String> {
+    let shorts = arg.get_short().map(|short| format!("-{}", short));
+    let longs = arg.get_long().map(|long| format!("--{}", long));
+    shorts.into_iter().chain(longs).collect()
+}
+
+pub fn generate(shell: Shell, app: &App) -> String {
+    let nodes = nodes(app);
+    match shell {
+        Shell::Bash => bash(&nodes),
+        Shell::Zsh => zsh(&nodes),
+        Shell::Fish => fish(&nodes),
+    }
+}
+
+fn bash(nodes: &[Node]) -> String {
+    let bin = nodes[0].path[0];
+    let function = format!("_{}", bin.replace('-', "_"));
+    let mut script = String::new();
+
+    let _ = writeln!(script, "{}() {{", function);
+    let _ = writeln!(script, "    local cur prev cmd opts i");
+    let _ = writeln!(script, "    COMPREPLY=()");
+    let _ = writeln!(script, "    cur=\"${{COMP_WORDS[COMP_CWORD]}}\"");
+    let _ = writeln!(script, "    prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"");
+    let _ = writeln!(script, "    cmd=\"{}\"", bin);
+    let _ = writeln!(script, "    for ((i = 1; i < COMP_CWORD; i++)); do");
+    let _ = writeln!(script, "        case \"${{cmd}}__${{COMP_WORDS[i]}}\" in");
+    for node in &nodes[1..] {
+        let _ = writeln!(
+            script,
+            "            {}) cmd=\"{}\" ;;",
+            node.id(),
+            node.id()
+        );
+    }
+    let _ = writeln!(script, "        esac");
+    let _ = writeln!(script, "    done");
+    let _ = writeln!(script);
+    let _ = writeln!(script, "    case \"${{cmd}}\" in");
+
+    for node in nodes {
+        let args = completed(nodes, node);
+        let words = node
+            .subcommands()
+            .map(|sub| sub.get_name().to_owned())
+            .chain(
+                args.iter()
+                    .filter(|arg| is_option(arg))
+                    .flat_map(|arg| flags(arg)),
+            )
+            .collect::<Vec<_>>();
+
+        let _ = writeln!(script, "        {})", node.id());
+        let _ = writeln!(script, "            opts=\"{}\"", words.join(" "));
+        let _ = writeln!(script, "            case \"${{prev}}\" in");
+        for arg in args.iter().filter(|arg| is_option(arg) && takes_value(arg)) {
+            let values = match arg.get_possible_values() {
+                Some(values) => format!(
+                    "COMPREPLY=($(compgen -W \"{}\" -- \"${{cur}}\")); return 0",
+                    values.join(" ")
+                ),
+                // completed as a file by default
+                None => "return 0".to_owned(),
+            };
+            let _ = writeln!(
+                script,
+                "                {}) {} ;;",
+                flags(arg).join("|"),
+                values
+            );
+        }
+        let _ = writeln!(script, "            esac");
+        let _ = writeln!(script, "            ;;");
+    }
+
+    let _ = writeln!(script, "    esac");
+    let _ = writeln!(script);
+    let _ = writeln!(
+        script,
+        "    COMPREPLY=($(compgen -W \"${{opts}}\" -- \"${{cur}}\"))"
+    );
+    let _ = writeln!(script, "}}");
+    let _ = writeln!(script);
+    let _ = writeln!(
+        script,
+        "complete -F {} -o bashdefault -o default {}",
+        function, bin
+    );
+
+    script
+}
+
+/// Escape the help for the single-quoted specs of `_arguments`
+fn zsh_escape(help: &str) -> String {
+    help.replace('\'', "'\\''")
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+        .replace(':', "\\:")
+}
+
+fn zsh(nodes: &[Node]) -> String {
+    let bin = nodes[0].path[0];
+    let mut script = format!("#compdef {}\n", bin);
+
+    for node in nodes {
+        let _ = writeln!(script, "\n_{}() {{", node.id());
+        let _ = writeln!(
+            script,
+            "    local context curcontext=\"$curcontext\" state line"
+        );
+        let _ = write!(script, "    _arguments -s -C");
+
+        for arg in completed(nodes, node) {
+            let help = zsh_escape(summary(arg.get_about()));
+            let repeated = match arg.is_set(ArgSettings::MultipleOccurrences) {
+                true => "*",
+                false => "",
+            };
+            let value = match arg.get_possible_values() {
+                Some(values) => format!(":{}:({})", arg.get_name(), values.join(" ")),
+                None => format!(":{}:_default", arg.get_name()),
+            };
+
+            if !is_option(arg) {
+                let _ = write!(
+                    script,
+                    " \\\n        '{}:{}{}'",
+                    repeated,
+                    help,
+                    &value[1..]
+                );
+                continue;
+            }
+            for flag in flags(arg) {
+                let (suffix, value) = match (takes_value(arg), flag.starts_with("--")) {
+                    (false, _) => ("", ""),
+                    (true, true) => ("=", value.as_str()),
+                    (true, false) => ("+", value.as_str()),
+                };
+                let _ = write!(
+                    script,
+                    " \\\n        '{}{}{}[{}]{}'",
+                    repeated, flag, suffix, help, value
+                );
+            }
+        }
+
+        if node.subcommands().next().is_none() {
+            let _ = writeln!(script, "\n}}");
+            continue;
+        }
+        let subcommands = node
+            .subcommands()
+            .map(|sub| {
+                format!(
+                    "{}\\:'{}'",
+                    sub.get_name(),
+                    zsh_escape(summary(sub.get_about()))
+                )
+            })
+            .collect::<Vec<_>>();
+        let _ = writeln!(
+            script,
+            " \\\n        \": :(({}))\" \\\n        \"*:: :->subcommand\"",
+            subcommands.join(" ")
+        );
+        let _ = writeln!(script, "    case $state in");
+        let _ = writeln!(script, "        subcommand)");
+        let _ = writeln!(script, "            case $words[1] in");
+        for sub in node.subcommands() {
+            let _ = writeln!(
+                script,
+                "                ({}) _{}__{} ;;",
+                sub.get_name(),
+                node.id(),
+                sub.get_name()
+            );
+        }
+        let _ = writeln!(script, "            esac");
+        let _ = writeln!(script, "            ;;");
+        let _ = writeln!(script, "    esac");
+        let _ = writeln!(script, "}}");
+    }
+
+    let _ = writeln!(script, "\n_{} \"$@\"", bin);
+    script
+}
+
+/// Escape the help for the single-quoted descriptions of fish
+fn fish_escape(help: &str) -> String {
+    help.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+fn fish(nodes: &[Node]) -> String {
+    let bin = nodes[0].path[0];
+    let mut script = String::new();
+
+    for node in nodes {
+        // the subcommands on the way to the node are typed, the ones of the node aren't yet
+        let mut conditions = node.path[1..]
+            .iter()
+            .map(|name| format!("__fish_seen_subcommand_from {}", name))
+            .collect::<Vec<_>>();
+        let names = node
+            .subcommands()
+            .map(|sub| sub.get_name())
+            .collect::<Vec<_>>();
+        if node.path.len() == 1 {
+            conditions.push("__fish_use_subcommand".to_owned());
+        } else if !names.is_empty() {
+            conditions.push(format!(
+                "not __fish_seen_subcommand_from {}",
+                names.join(" ")
+            ));
+        }
+        let condition = conditions.join("; and ");
+
+        for sub in node.subcommands() {
+            let _ = writeln!(
+                script,
+                "complete -c {} -n \"{}\" -f -a \"{}\" -d '{}'",
+                bin,
+                condition,
+                sub.get_name(),
+                fish_escape(summary(sub.get_about()))
+            );
+        }
+        for arg in completed(nodes, node)
+            .into_iter()
+            .filter(|arg| is_option(arg))
+        {
+            let mut line = format!("complete -c {} -n \"{}\"", bin, condition);
+            if let Some(short) = arg.get_short() {
+                let _ = write!(line, " -s {}", short);
+            }
+            if let Some(long) = arg.get_long() {
+                let _ = write!(line, " -l {}", long);
+            }
+            match (takes_value(arg), arg.get_possible_values()) {
+                (true, Some(values)) => {
+                    let _ = write!(line, " -r -f -a \"{}\"", values.join(" "));
+                }
+                (true, None) => line.push_str(" -r"),
+                (false, _) => {}
+            }
+            let _ = writeln!(
+                script,
+                "{} -d '{}'",
+                line,
+                fish_escape(summary(arg.get_about()))
+            );
+        }
+    }
+
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app() -> App<'static> {
+        App::new("log-bouncer")
+            .arg(Arg::new("verbose").short('v').long("verbose").global(true))
+            .subcommand(
+                App::new("run").about("Tail the files").arg(
+                    Arg::new("file")
+                        .short('f')
+                        .long("file")
+                        .takes_value(true)
+                        .about("The file to tail"),
+                ),
+            )
+            .subcommand(
+                App::new("state").subcommand(
+                    App::new("show").arg(
+                        Arg::new("format")
+                            .long("format")
+                            .takes_value(true)
+                            .possible_values(&["json", "text"]),
+                    ),
+                ),
+            )
+    }
+
+    #[test]
+    fn test_nodes() {
+        let app = app();
+        let nodes = nodes(&app);
+        let paths = nodes
+            .iter()
+            .map(|node| node.path.join(" "))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            vec![
+                "log-bouncer",
+                "log-bouncer run",
+                "log-bouncer state",
+                "log-bouncer state show"
+            ]
+        );
+
+        // the global arguments are inherited
+        let show = nodes[3]
+            .args
+            .iter()
+            .map(|arg| arg.get_name())
+            .collect::<Vec<_>>();
+        assert_eq!(show, vec!["help", "version", "format", "verbose"]);
+        // the options of `run` are completed without subcommand
+        let root = completed(&nodes, &nodes[0]);
+        assert!(root.iter().any(|arg| arg.get_name() == "file"));
+    }
+
+    #[test]
+    fn test_generate() {
+        let app = app();
+
+        let bash = generate(Shell::Bash, &app);
+        assert!(bash.contains("log-bouncer__state) cmd=\"log-bouncer__state\" ;;"));
+        assert!(bash.contains("opts=\"run state --help --version -v --verbose -f --file\""));
+        assert!(bash.contains("--format) COMPREPLY=($(compgen -W \"json text\""));
+        assert!(bash.ends_with("complete -F _log_bouncer -o bashdefault -o default log-bouncer\n"));
+
+        let zsh = generate(Shell::Zsh, &app);
+        assert!(zsh.starts_with("#compdef log-bouncer\n"));
+        assert!(zsh.contains("'--file=[The file to tail]:file:_default'"));
+        assert!(zsh.contains("(show) _log-bouncer__state__show ;;"));
+
+        let fish = generate(Shell::Fish, &app);
+        assert!(fish.contains(
+            "complete -c log-bouncer -n \"__fish_use_subcommand\" -f -a \"run\" -d 'Tail the files'"
+        ));
+        assert!(fish.contains(
+            "-n \"__fish_seen_subcommand_from state; and __fish_seen_subcommand_from show\" -l format -r -f -a \"json text\""
+        ));
+        assert!("powershell".parse::<Shell>().is_err());
+    }
+}
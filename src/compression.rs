@@ -0,0 +1,156 @@
+//! Compress the rotated files, so they take less room on the disk and in the object storage.
+//! They can be published again with `--backfill`, which decompresses them
+use flate2::write::GzEncoder;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("unknown compression `{0}`, expected `gzip` or `zstd`")]
+    UnknownCompression(String),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Compression {
+    #[default]
+    Gzip,
+    Zstd,
+}
+
+impl FromStr for Compression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            _ => Err(Error::UnknownCompression(s.to_owned())),
+        }
+    }
+}
+
+impl Compression {
+    pub const ALL: [Compression; 2] = [Compression::Gzip, Compression::Zstd];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gz",
+            Compression::Zstd => "zst",
+        }
+    }
+
+    /// The compression of the file, according to its extension
+    pub fn of_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?;
+
+        Self::ALL
+            .into_iter()
+            .find(|compression| extension == compression.extension())
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Compression::Gzip => "application/gzip",
+            Compression::Zstd => "application/zstd",
+        }
+    }
+
+    /// Where the file is compressed to, eg. `app.log.1.gz`
+    pub fn path(self, path: &Path) -> PathBuf {
+        let mut compressed = path.as_os_str().to_owned();
+        compressed.push(".");
+        compressed.push(self.extension());
+
+        PathBuf::from(compressed)
+    }
+
+    /// Compress the file next to it, then delete it, on a blocking thread. Returns the
+    /// compressed file
+    pub async fn compress(self, path: &Path) -> io::Result<PathBuf> {
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || self.compress_file(&path)).await?
+    }
+
+    fn compress_file(self, path: &Path) -> io::Result<PathBuf> {
+        let compressed = self.path(path);
+        let mut input = BufReader::new(File::open(path)?);
+        // an archive already there is never overwritten
+        let output = File::options()
+            .write(true)
+            .create_new(true)
+            .open(&compressed)?;
+
+        if let Err(e) = self.encode(&mut input, BufWriter::new(output)) {
+            let _ = std::fs::remove_file(&compressed);
+            return Err(e);
+        }
+
+        // the original is only deleted once the compressed file is on the disk
+        std::fs::remove_file(path)?;
+        Ok(compressed)
+    }
+
+    fn encode(self, input: &mut BufReader<File>, output: BufWriter<File>) -> io::Result<()> {
+        let output = match self {
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(output, flate2::Compression::default());
+                io::copy(input, &mut encoder)?;
+                encoder.finish()?
+            }
+            Compression::Zstd => {
+                let mut encoder = zstd::Encoder::new(output, 0)?;
+                io::copy(input, &mut encoder)?;
+                encoder.finish()?
+            }
+        };
+
+        output.into_inner().map_err(|e| e.into_error())?.sync_all()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[tokio::test]
+    async fn test_compress() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = "first\nsecond\n".repeat(100);
+
+        for compression in Compression::ALL {
+            let path = dir.path().join("app.log.1");
+            std::fs::write(&path, &content).unwrap();
+
+            let compressed = compression.compress(&path).await.unwrap();
+            assert_eq!(
+                compressed,
+                dir.path()
+                    .join(format!("app.log.1.{}", compression.extension()))
+            );
+            assert!(!path.exists());
+
+            let mut decompressed = String::new();
+            crate::backfill::open(&compressed)
+                .unwrap()
+                .read_to_string(&mut decompressed)
+                .unwrap();
+            assert_eq!(decompressed, content);
+
+            // the compressed file is kept rather than overwritten
+            std::fs::write(&path, "again").unwrap();
+            assert!(compression.compress(&path).await.is_err());
+            assert!(path.exists());
+        }
+
+        assert_eq!(
+            Compression::of_path(Path::new("app.log.1.zst")),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(Compression::of_path(Path::new("app.log.1")), None);
+        assert_eq!("zstd".parse::<Compression>().unwrap(), Compression::Zstd);
+        assert!("bzip2".parse::<Compression>().is_err());
+    }
+}
@@ -0,0 +1,196 @@
+//! Optional TOML config file, for what doesn't fit well on the command line
+//!
+//! ```toml
+//! [[pipeline]]
+//! stage = "noise"
+//!
+//! [[pipeline]]
+//! stage = "parse"
+//! format = "json"
+//!
+//! [[pipeline]]
+//! stage = "anonymize"
+//! hash_fields = ["user"]
+//! ```
+//!
+//! Several sources can be shipped by the same process, each one with its own files, output and
+//! stages, rather than running one process per exchange. The options of the command line apply to
+//! all of them, unless a source overrides them:
+//!
+//! ```toml
+//! [[source]]
+//! name = "nginx"
+//! file = ["/var/log/nginx/access.log"]
+//! exchange = "web"
+//! routing_key = "nginx.{filename}"
+//!
+//! [[source.pipeline]]
+//! stage = "parse"
+//! format = "json"
+//!
+//! [[source]]
+//! name = "app"
+//! watch_dir = "/var/log/app"
+//! exchange = "app"
+//! ```
+//!
+//! On `SIGHUP`, the stages, the catch-up rate and the files of the sources are reloaded without
+//! losing the positions. The other settings, eg. the outputs, need a restart.
+use crate::pipeline::config::StageConfig;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("can't read the config file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid config file: {0}")]
+    Toml(#[from] toml::de::Error),
+    #[error("the source `{0}` is declared twice")]
+    DuplicateSource(String),
+    #[error("the source `{0}` has neither `file` nor `watch_dir`")]
+    EmptySource(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Stages applied to every line, in this order. When set, the stages options of the command
+    /// line are ignored.
+    #[serde(default)]
+    pub pipeline: Vec<StageConfig>,
+    /// Overrides `--catch-up-rate`, in lines per second
+    pub catch_up_rate: Option<u64>,
+    /// Shipped concurrently, each one instead of the files of the command line
+    #[serde(default, rename = "source")]
+    pub sources: Vec<SourceConfig>,
+}
+
+/// Files shipped to their own output
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SourceConfig {
+    /// Tells the sources apart in the logs, and in the name of their state file
+    pub name: String,
+    #[serde(default)]
+    pub file: Vec<PathBuf>,
+    pub watch_dir: Option<PathBuf>,
+    /// `--amqp-uri`, `--amqp-exchange` and `--amqp-routing-key` of the source
+    pub amqp_uri: Option<String>,
+    pub exchange: Option<String>,
+    pub routing_key: Option<String>,
+    /// The stages of the source, the ones of the top-level pipeline otherwise
+    #[serde(default)]
+    pub pipeline: Vec<StageConfig>,
+    /// Overrides the top-level `catch_up_rate`
+    pub catch_up_rate: Option<u64>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let config: Self = toml::from_str(&std::fs::read_to_string(path)?)?;
+
+        let mut names = BTreeSet::new();
+        for source in &config.sources {
+            if !names.insert(&source.name) {
+                return Err(Error::DuplicateSource(source.name.clone()));
+            }
+            if source.file.is_empty() && source.watch_dir.is_none() {
+                return Err(Error::EmptySource(source.name.clone()));
+            }
+        }
+
+        Ok(config)
+    }
+
+    pub fn source(&self, name: Option<&str>) -> Option<&SourceConfig> {
+        self.sources
+            .iter()
+            .find(|source| Some(source.name.as_str()) == name)
+    }
+
+    /// The stages of the source, or of the whole process if it's `None`
+    pub fn stages(&self, source: Option<&str>) -> Vec<StageConfig> {
+        match self.source(source) {
+            Some(source) if !source.pipeline.is_empty() => source.pipeline.clone(),
+            _ => self.pipeline.clone(),
+        }
+    }
+
+    /// The catch-up rate of the source, or of the whole process if it's `None`
+    pub fn catch_up_rate(&self, source: Option<&str>) -> Option<u64> {
+        self.source(source)
+            .and_then(|source| source.catch_up_rate)
+            .or(self.catch_up_rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_load() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "[[pipeline]]\nstage = \"parse\"\nformat = \"cef\"").unwrap();
+        writeln!(
+            file,
+            "[[pipeline]]\nstage = \"alert\"\nrules = [\"oom=OutOfMemory\"]"
+        )
+        .unwrap();
+
+        let config = Config::load(file.path()).unwrap();
+        assert_eq!(config.pipeline.len(), 2);
+        assert!(config.pipeline[1].sends_alerts());
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "pipelines = []").unwrap();
+        assert!(matches!(Config::load(file.path()), Err(Error::Toml(_))));
+    }
+
+    #[test]
+    fn test_sources() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "catch_up_rate = 100\n[[pipeline]]\nstage = \"noise\"").unwrap();
+        writeln!(
+            file,
+            "[[source]]\nname = \"web\"\nfile = [\"/var/log/web.log\"]\nexchange = \"web\""
+        )
+        .unwrap();
+        writeln!(
+            file,
+            "[[source.pipeline]]\nstage = \"parse\"\nformat = \"json\""
+        )
+        .unwrap();
+        writeln!(
+            file,
+            "[[source]]\nname = \"app\"\nwatch_dir = \"/var/log/app\""
+        )
+        .unwrap();
+
+        let config = Config::load(file.path()).unwrap();
+        assert_eq!(config.sources.len(), 2);
+        assert_eq!(config.sources[0].exchange.as_deref(), Some("web"));
+        assert_eq!(config.sources[0].pipeline.len(), 1);
+        assert_eq!(config.sources[1].pipeline.len(), 0);
+
+        let stages = |source| Config::load(file.path()).unwrap().stages(source);
+        assert!(matches!(stages(Some("web"))[0], StageConfig::Parse { .. }));
+        assert!(matches!(stages(Some("app"))[0], StageConfig::Noise { .. }));
+        assert!(matches!(stages(None)[0], StageConfig::Noise { .. }));
+        assert_eq!(config.catch_up_rate(Some("app")), Some(100));
+
+        writeln!(
+            file,
+            "[[source]]\nname = \"app\"\nfile = [\"/var/log/other.log\"]"
+        )
+        .unwrap();
+        assert!(matches!(
+            Config::load(file.path()),
+            Err(Error::DuplicateSource(name)) if name == "app"
+        ));
+    }
+}
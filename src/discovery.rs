@@ -0,0 +1,324 @@
+//! Find the files to tail, a `--file` can be a glob pattern, eg. `/var/log/app/*.log`, and the
+//! files appearing in a `--watch-dir` are tailed as they get created
+use glob::{MatchOptions, Pattern};
+use notify::event::{EventKind, ModifyKind};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio::time::Interval;
+
+/// List the directory anyway, in case a change hasn't been notified
+const RESCAN_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A file has to be missing for that long to be considered deleted, it's only missing for an
+/// instant when it's rotated
+const REMOVAL_DELAY: Duration = Duration::from_secs(10);
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("invalid pattern `{0}`: {1}")]
+    Pattern(String, glob::PatternError),
+    #[error("no file matches `{0}`")]
+    NoMatch(String),
+    #[error("watch: {0}")]
+    Watch(#[from] notify::Error),
+    #[error("i/o: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// The hidden files aren't matched by `*`, they'd be the saved states of the tailed files
+const OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: true,
+};
+
+/// Whether the path has to be expanded, rather than being a file
+pub fn is_pattern(path: &Path) -> bool {
+    path.to_string_lossy().contains(['*', '?', '['])
+}
+
+/// The absolute path of the file, a symlink isn't resolved so the file is followed once it
+/// points to another one, eg. `current.log`
+pub fn absolute(path: &Path) -> std::io::Result<PathBuf> {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => {
+            let parent = if parent.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                parent
+            };
+            let path = std::fs::canonicalize(parent)?.join(name);
+            // it must exist, as a canonicalized path
+            std::fs::metadata(&path)?;
+            Ok(path)
+        }
+        _ => std::fs::canonicalize(path),
+    }
+}
+
+/// The absolute paths of the files, the patterns are replaced by the files they match
+pub fn expand<'a>(paths: impl IntoIterator<Item = &'a PathBuf>) -> Result<Vec<PathBuf>> {
+    let mut files = BTreeSet::new();
+
+    for path in paths {
+        if !is_pattern(path) {
+            // in case the user submit "test.log", get the absolute path
+            files.insert(absolute(path)?);
+            continue;
+        }
+
+        let pattern = path.to_string_lossy();
+        let matches = glob::glob_with(&pattern, OPTIONS)
+            .map_err(|e| Error::Pattern(pattern.to_string(), e))?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.is_file())
+            .map(|entry| absolute(&entry))
+            .collect::<std::io::Result<Vec<_>>>()?;
+
+        if matches.is_empty() {
+            return Err(Error::NoMatch(pattern.to_string()));
+        }
+
+        files.extend(matches);
+    }
+
+    Ok(files.into_iter().collect())
+}
+
+/// Which files of the watched directory are tailed, according to their name
+pub struct Filter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl Filter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self> {
+        let parse = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|pattern| {
+                    Pattern::new(pattern).map_err(|e| Error::Pattern(pattern.clone(), e))
+                })
+                .collect::<Result<Vec<_>>>()
+        };
+
+        Ok(Self {
+            include: parse(include)?,
+            exclude: parse(exclude)?,
+        })
+    }
+
+    pub fn matches(&self, path: &Path) -> bool {
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy(),
+            None => return false,
+        };
+        let matches = |pattern: &Pattern| pattern.matches_with(&name, OPTIONS);
+
+        self.include.iter().any(matches) && !self.exclude.iter().any(matches)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    /// Already in the directory when it started being watched
+    Existing(PathBuf),
+    Created(PathBuf),
+    Removed(PathBuf),
+}
+
+/// Tell the files that appear in a directory or get deleted from it
+pub struct Directory {
+    path: PathBuf,
+    filter: Filter,
+    /// Files matching the filter the last time the directory has been listed
+    known: BTreeSet<PathBuf>,
+    /// Known files that have disappeared, and since when
+    missing: BTreeMap<PathBuf, Instant>,
+    removal_delay: Duration,
+    changes: VecDeque<Change>,
+    /// Notified when a file is created, renamed or deleted
+    notified: mpsc::Receiver<()>,
+    _watcher: Option<RecommendedWatcher>,
+    rescan: Interval,
+    /// The directory has been listed once, the files found afterwards have been created
+    listed: bool,
+}
+
+impl Directory {
+    /// The files already in the directory are the first ones to be reported, as existing
+    pub fn watch(path: &Path, filter: Filter, notify: bool) -> Result<Self> {
+        let path = std::fs::canonicalize(path)?;
+        let (tx, notified) = mpsc::channel(1);
+
+        let watcher = if notify {
+            let mut watcher = notify::recommended_watcher(move |event: notify::Result<_>| {
+                let event: notify::Event = match event {
+                    Ok(event) => event,
+                    Err(_) => return,
+                };
+
+                if matches!(
+                    event.kind,
+                    EventKind::Create(_)
+                        | EventKind::Remove(_)
+                        | EventKind::Modify(ModifyKind::Name(_))
+                ) {
+                    let _ = tx.try_send(());
+                }
+            })?;
+            watcher.watch(&path, RecursiveMode::NonRecursive)?;
+            Some(watcher)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            path,
+            filter,
+            known: BTreeSet::new(),
+            missing: BTreeMap::new(),
+            removal_delay: REMOVAL_DELAY,
+            changes: VecDeque::new(),
+            notified,
+            _watcher: watcher,
+            rescan: tokio::time::interval(RESCAN_INTERVAL),
+            listed: false,
+        })
+    }
+
+    /// Wait for the next file to be created or deleted
+    pub async fn next(&mut self) -> Change {
+        loop {
+            if let Some(change) = self.changes.pop_front() {
+                return change;
+            }
+
+            tokio::select! {
+                Some(_) = self.notified.recv() => {},
+                _ = self.rescan.tick() => {},
+            }
+
+            if let Err(e) = self.scan() {
+                warn!("Can't list `{}`: {}", self.path.to_string_lossy(), e);
+            }
+        }
+    }
+
+    /// List the directory, then compare it to the files known so far
+    fn scan(&mut self) -> Result<()> {
+        let present = std::fs::read_dir(&self.path)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && self.filter.matches(path))
+            .collect::<BTreeSet<_>>();
+
+        for path in &present {
+            self.missing.remove(path);
+            if self.known.insert(path.clone()) {
+                self.changes.push_back(match self.listed {
+                    true => Change::Created(path.clone()),
+                    false => Change::Existing(path.clone()),
+                });
+            }
+        }
+        self.listed = true;
+
+        let now = Instant::now();
+        let mut removed = vec![];
+        for path in self.known.difference(&present) {
+            let since = *self.missing.entry(path.clone()).or_insert(now);
+            if now.duration_since(since) >= self.removal_delay {
+                removed.push(path.clone());
+            }
+        }
+
+        for path in removed {
+            self.known.remove(&path);
+            self.missing.remove(&path);
+            self.changes.push_back(Change::Removed(path));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a.log", "b.log", "c.txt", ".a.log.log-bouncer"] {
+            std::fs::write(dir.path().join(name), "").unwrap();
+        }
+
+        let files = expand(&[
+            dir.path().join("*.log"),
+            dir.path().join("c.txt"),
+            // matched twice, tailed once
+            dir.path().join("a.log"),
+        ])
+        .unwrap();
+        let names = files
+            .iter()
+            .map(|file| file.file_name().unwrap().to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["a.log", "b.log", "c.txt"]);
+
+        assert!(matches!(
+            expand(&[dir.path().join("*.json")]),
+            Err(Error::NoMatch(_))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_isnt_resolved() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = std::fs::canonicalize(dir.path()).unwrap();
+        std::fs::write(path.join("app-1.log"), "").unwrap();
+        std::os::unix::fs::symlink(path.join("app-1.log"), path.join("current.log")).unwrap();
+
+        assert_eq!(
+            expand(&[path.join("current.log")]).unwrap(),
+            vec![path.join("current.log")]
+        );
+        assert!(absolute(&path.join("missing.log")).is_err());
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter = Filter::new(&["*.log".to_owned()], &["debug-*".to_owned()]).unwrap();
+
+        assert!(filter.matches(Path::new("/var/log/app/worker-1.log")));
+        assert!(!filter.matches(Path::new("/var/log/app/debug-1.log")));
+        assert!(!filter.matches(Path::new("/var/log/app/worker-1.log.2021-09-07")));
+        assert!(!filter.matches(Path::new("/var/log/app/.worker-1.log")));
+    }
+
+    #[tokio::test]
+    async fn test_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = std::fs::canonicalize(dir.path()).unwrap();
+        std::fs::write(path.join("a.log"), "").unwrap();
+
+        let filter = Filter::new(&["*.log".to_owned()], &[]).unwrap();
+        let mut directory = Directory::watch(&path, filter, true).unwrap();
+        directory.removal_delay = Duration::ZERO;
+        assert_eq!(directory.next().await, Change::Existing(path.join("a.log")));
+
+        std::fs::write(path.join("b.txt"), "").unwrap();
+        std::fs::write(path.join("b.log"), "").unwrap();
+        assert_eq!(directory.next().await, Change::Created(path.join("b.log")));
+
+        std::fs::remove_file(path.join("a.log")).unwrap();
+        assert_eq!(directory.next().await, Change::Removed(path.join("a.log")));
+    }
+}
@@ -0,0 +1,61 @@
+//! Transcode the lines of the legacy applications that don't write UTF-8, eg. `latin1` or
+//! `utf-16le`
+use crate::tail::LineBreak;
+use std::str::FromStr;
+
+#[derive(thiserror::Error, Debug)]
+#[error("unknown encoding `{0}`, expected a label such as `latin1`, `shift_jis` or `utf-16le`")]
+pub struct UnknownEncoding(String);
+
+/// Encoding of the tailed files, named by the labels of the WHATWG Encoding Standard
+///
+/// As in the browsers, `latin1` and `iso-8859-1` are decoded as `windows-1252`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Encoding(&'static encoding_rs::Encoding);
+
+impl FromStr for Encoding {
+    type Err = UnknownEncoding;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        encoding_rs::Encoding::for_label_no_replacement(s.as_bytes())
+            .map(Encoding)
+            .ok_or_else(|| UnknownEncoding(s.to_owned()))
+    }
+}
+
+impl Encoding {
+    /// How the lines end, each character of UTF-16 takes two bytes at least
+    pub fn line_break(self) -> LineBreak {
+        if self.0 == encoding_rs::UTF_16LE {
+            LineBreak::Utf16Le
+        } else if self.0 == encoding_rs::UTF_16BE {
+            LineBreak::Utf16Be
+        } else {
+            LineBreak::Byte
+        }
+    }
+
+    /// The line in UTF-8, the malformed sequences are replaced by `U+FFFD` and the byte order
+    /// mark of the first line is removed
+    pub fn decode(self, line: &[u8]) -> String {
+        self.0.decode_with_bom_removal(line).0.into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let latin1 = "latin1".parse::<Encoding>().unwrap();
+        assert_eq!(latin1.line_break(), LineBreak::Byte);
+        assert_eq!(latin1.decode(b"caf\xe9"), "café");
+
+        let utf16 = "utf-16le".parse::<Encoding>().unwrap();
+        assert_eq!(utf16.line_break(), LineBreak::Utf16Le);
+        assert_eq!(utf16.decode(b"\xff\xfec\x00a\x00f\x00\xe9\x00"), "café");
+
+        assert!("ebcdic".parse::<Encoding>().is_err());
+    }
+}
@@ -0,0 +1,130 @@
+//! The errors of the crate by failure class, so an application embedding it can tell a bad
+//! config from an unreachable output, and the binary maps them to its exit codes, see `exit`
+use crate::input::Stopped;
+use crate::output::Unavailable;
+use crate::{config, pipeline, secrets, state};
+use std::error::Error as StdError;
+use std::io;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Config(#[from] config::Error),
+    #[error("{0}")]
+    Pipeline(#[from] pipeline::Error),
+    #[error("{0}")]
+    Secret(#[from] secrets::Error),
+    #[error("i/o: {0}")]
+    Io(#[from] io::Error),
+    #[error("{0}")]
+    State(#[from] state::Error),
+    /// The output refused a message or can't be reached
+    #[error("output: {0}")]
+    Output(#[source] Box<dyn StdError + Send + Sync>),
+    #[error("{0}")]
+    Unavailable(#[from] Unavailable),
+    /// An input stopped on its own, eg. `journalctl` exited
+    #[error("{0}")]
+    Input(#[from] Stopped),
+    #[error("{0}")]
+    Other(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// The error of an output, eg. `.map_err(Error::output)`
+    pub fn output(e: impl StdError + Send + Sync + 'static) -> Self {
+        Self::Output(Box::new(e))
+    }
+}
+
+impl From<&str> for Error {
+    fn from(message: &str) -> Self {
+        Self::Other(message.to_owned())
+    }
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Self::Other(message)
+    }
+}
+
+#[cfg(feature = "amqp")]
+impl From<amqp_lapin_helper::Error> for Error {
+    fn from(e: amqp_lapin_helper::Error) -> Self {
+        Self::output(e)
+    }
+}
+
+impl From<tokio::task::JoinError> for Error {
+    fn from(e: tokio::task::JoinError) -> Self {
+        Self::Other(e.to_string())
+    }
+}
+
+fn downcast<T: StdError + 'static>(
+    e: Box<dyn StdError>,
+    into: fn(T) -> Error,
+) -> std::result::Result<Error, Box<dyn StdError>> {
+    e.downcast::<T>().map(|e| into(*e))
+}
+
+/// The errors of the internals, whose class is found back
+impl From<Box<dyn StdError>> for Error {
+    fn from(e: Box<dyn StdError>) -> Self {
+        let classified = downcast(e, |e: Error| e)
+            .or_else(|e| downcast(e, Error::Config))
+            .or_else(|e| downcast(e, Error::Pipeline))
+            .or_else(|e| downcast(e, Error::Secret))
+            .or_else(|e| downcast(e, Error::Io))
+            .or_else(|e| downcast(e, Error::State))
+            .or_else(|e| downcast(e, Error::Unavailable));
+        #[cfg(feature = "amqp")]
+        let classified =
+            classified.or_else(|e| downcast::<amqp_lapin_helper::Error>(e, Error::from));
+        let e = match classified {
+            Ok(e) => return e,
+            Err(e) => e,
+        };
+
+        // eg. the i/o error of a rotation
+        let mut source = e.source();
+        while let Some(cause) = source {
+            if let Some(io) = cause.downcast_ref::<io::Error>() {
+                return Self::Io(io::Error::new(io.kind(), e.to_string()));
+            }
+            source = cause.source();
+        }
+
+        Self::Other(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_boxed() {
+        fn is_send_sync<T: Send + Sync>() {}
+        is_send_sync::<Error>();
+
+        let boxed: Box<dyn StdError> = config::Error::EmptySource("app".to_owned()).into();
+        assert!(matches!(Error::from(boxed), Error::Config(_)));
+
+        let rotated: Box<dyn StdError> =
+            crate::rotator::Error::Io(io::Error::from(io::ErrorKind::PermissionDenied)).into();
+        match Error::from(rotated) {
+            Error::Io(e) => assert_eq!(e.kind(), io::ErrorKind::PermissionDenied),
+            e => panic!("unexpected {:?}", e),
+        }
+
+        let unknown: Box<dyn StdError> = "missing option: --state-file".into();
+        assert_eq!(
+            Error::from(unknown).to_string(),
+            "missing option: --state-file"
+        );
+    }
+}
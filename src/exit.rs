@@ -0,0 +1,98 @@
+//! The exit code of the process, by failure class, so an orchestrator can tell a bad config from
+//! an unreachable broker. They follow `sysexits.h`:
+//!
+//! - 65: the saved state is corrupted
+//! - 66: a file to tail doesn't exist
+//! - 69: the output is unreachable or stopped accepting the messages
+//! - 74: any other i/o error, eg. a read-only filesystem
+//! - 75: another log-bouncer is tailing the files, retrying later may work
+//! - 77: a file can't be read or written because of its permissions
+//! - 78: the config, the pipeline, a schedule or a secret reference is invalid
+use crate::output::Unavailable;
+use crate::{config, pipeline, schedule, secrets, state};
+use std::error::Error;
+use std::io;
+
+pub const FAILURE: i32 = 1;
+pub const DATAERR: i32 = 65;
+pub const NOINPUT: i32 = 66;
+pub const UNAVAILABLE: i32 = 69;
+pub const IOERR: i32 = 74;
+pub const TEMPFAIL: i32 = 75;
+pub const NOPERM: i32 = 77;
+pub const CONFIG: i32 = 78;
+
+/// The exit code of the first error of the chain whose class is known
+pub fn code(mut e: &(dyn Error + 'static)) -> i32 {
+    loop {
+        // the other classes are told by their source
+        if let Some(crate::Error::Output(_)) = e.downcast_ref::<crate::Error>() {
+            return UNAVAILABLE;
+        }
+        if e.is::<config::Error>() || e.is::<pipeline::Error>() || e.is::<schedule::Error>() {
+            return CONFIG;
+        }
+        #[cfg(feature = "amqp")]
+        if e.is::<amqp_lapin_helper::Error>() {
+            return UNAVAILABLE;
+        }
+        if e.is::<Unavailable>() {
+            return UNAVAILABLE;
+        }
+        #[cfg(not(feature = "http"))]
+        if let Some(secrets::Error::HttpDisabled(_)) = e.downcast_ref::<secrets::Error>() {
+            return CONFIG;
+        }
+        match e.downcast_ref::<secrets::Error>() {
+            Some(secrets::Error::InvalidReference(_) | secrets::Error::MissingEnv(_)) => {
+                return CONFIG
+            }
+            Some(_) => return UNAVAILABLE,
+            None => {}
+        }
+        match e.downcast_ref::<state::Error>() {
+            Some(state::Error::Locked(_)) => return TEMPFAIL,
+            Some(state::Error::Io(_)) => {}
+            Some(_) => return DATAERR,
+            None => {}
+        }
+        if let Some(e) = e.downcast_ref::<io::Error>() {
+            return match e.kind() {
+                io::ErrorKind::NotFound => NOINPUT,
+                io::ErrorKind::PermissionDenied => NOPERM,
+                _ => IOERR,
+            };
+        }
+
+        match e.source() {
+            Some(source) => e = source,
+            None => return FAILURE,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code() {
+        let not_found: Box<dyn Error> = io::Error::from(io::ErrorKind::NotFound).into();
+        assert_eq!(code(&*not_found), NOINPUT);
+
+        let denied = state::Error::Io(io::Error::from(io::ErrorKind::PermissionDenied));
+        assert_eq!(code(&denied), NOPERM);
+        assert_eq!(code(&state::Error::Locked("a.log".to_owned())), TEMPFAIL);
+        assert_eq!(code(&state::Error::BeyondEnd(10, 5)), DATAERR);
+        assert_eq!(code(&config::Error::EmptySource("app".to_owned())), CONFIG);
+        assert_eq!(code(&Unavailable("closed".to_owned())), UNAVAILABLE);
+        assert_eq!(code(&secrets::Error::MissingEnv("VAULT_TOKEN")), CONFIG);
+
+        // the class is kept by the error of the crate
+        let boxed = Box::<dyn Error>::from(Unavailable("closed".to_owned()));
+        assert_eq!(code(&crate::Error::from(boxed)), UNAVAILABLE);
+        let denied = crate::Error::from(io::Error::from(io::ErrorKind::PermissionDenied));
+        assert_eq!(code(&denied), NOPERM);
+        assert_eq!(code(&*Box::<dyn Error>::from("unknown")), FAILURE);
+    }
+}
@@ -0,0 +1,86 @@
+//! Publish a heartbeat of every file periodically, even when it's idle, so the downstream systems
+//! can tell a silent shipper from a quiet file
+use crate::output::Message;
+use crate::rotator::EVENT_HEADER;
+use crate::stats::{self, Lag};
+use chrono::{SecondsFormat, Utc};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Name of the host, to tell the shippers apart
+pub fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|hostname| hostname.trim().to_owned())
+        .ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .filter(|hostname| !hostname.is_empty())
+        .unwrap_or_else(|| "unknown".to_owned())
+}
+
+/// The heartbeat of a file, it has no position in the file either
+fn message(host: &str, lag: &Lag) -> Message {
+    let payload = json!({
+        "event": "heartbeat",
+        "host": host,
+        "file": lag.path,
+        "position": lag.position,
+        "size": lag.size,
+        "@timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+    });
+    let mut headers = BTreeMap::new();
+    headers.insert(EVENT_HEADER.to_owned(), "heartbeat".to_owned());
+
+    Message {
+        payload: payload.to_string(),
+        headers,
+        ..Message::default()
+    }
+}
+
+/// Send the heartbeats to the publisher, they're skipped while the output is busy
+pub async fn beat(interval: Duration, events: mpsc::Sender<Message>) {
+    let host = hostname();
+    let mut ticks = tokio::time::interval(interval);
+
+    loop {
+        ticks.tick().await;
+
+        for lag in stats::stats().lags() {
+            if let Err(e) = events.try_send(message(&host, &lag)) {
+                warn!(
+                    "Can't publish the heartbeat of `{}`: {}",
+                    lag.path.to_string_lossy(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_message() {
+        let lag = Lag {
+            path: PathBuf::from("/var/log/app.log"),
+            position: 6,
+            size: 13,
+            since: None,
+        };
+
+        let message = message("web-1", &lag);
+        assert_eq!(message.headers[EVENT_HEADER], "heartbeat");
+
+        let payload = serde_json::from_str::<Value>(&message.payload).unwrap();
+        assert_eq!(payload["host"], "web-1");
+        assert_eq!(payload["file"], "/var/log/app.log");
+        assert_eq!(payload["position"], 6);
+        assert!(!hostname().is_empty());
+    }
+}
@@ -0,0 +1,136 @@
+//! What happens to the lines and the files, for the applications embedding log-bouncer to react
+//! to, eg. with metrics or alerts of their own, see [`Builder::on_event`](crate::Builder::on_event)
+//!
+//! The callbacks are called from the tasks and the reading threads as it happens, they mustn't
+//! block, a channel can be used instead, see [`Builder::events`](crate::Builder::events).
+use crate::stats;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// The lag is checked that often against the threshold
+const LAG_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Event {
+    /// The output accepted the line ending at this position
+    Published { path: PathBuf, position: u64 },
+    /// The output refused the line, or couldn't be reached, it's read again on the next start
+    PublishFailed {
+        path: PathBuf,
+        position: u64,
+        error: String,
+    },
+    /// The file has been rotated, the new one is read from its beginning
+    Rotated { path: PathBuf },
+    /// The position of the last published line has been saved
+    StateSaved { path: PathBuf, position: u64 },
+    /// The bytes left to publish went above the threshold, or back below it
+    LagCrossed {
+        path: PathBuf,
+        lag: u64,
+        above: bool,
+    },
+}
+
+pub type Callback = Box<dyn Fn(&Event) + Send + Sync>;
+
+/// The callbacks the events are passed to, none by default
+#[derive(Clone, Default)]
+pub struct Hooks {
+    callbacks: Arc<Vec<Callback>>,
+}
+
+impl Hooks {
+    pub fn new(callbacks: Vec<Callback>) -> Self {
+        Self {
+            callbacks: Arc::new(callbacks),
+        }
+    }
+
+    /// Pass the event to every callback, it's only built if there's any
+    pub fn emit(&self, event: impl FnOnce() -> Event) {
+        if self.callbacks.is_empty() {
+            return;
+        }
+
+        let event = event();
+        for callback in self.callbacks.iter() {
+            callback(&event);
+        }
+    }
+
+    /// Tell once the lag of a file goes above the threshold, then back below it
+    pub async fn watch_lags(self, paths: Vec<PathBuf>, threshold: u64) {
+        let mut ticks = tokio::time::interval(LAG_INTERVAL);
+        let mut above = BTreeSet::new();
+
+        loop {
+            ticks.tick().await;
+
+            for lag in stats::stats().lags() {
+                if !paths.contains(&lag.path) {
+                    continue;
+                }
+                let bytes = lag.bytes();
+                let crossed = if bytes > threshold {
+                    above.insert(lag.path.clone())
+                } else {
+                    above.remove(&lag.path)
+                };
+
+                if crossed {
+                    self.emit(|| Event::LagCrossed {
+                        above: bytes > threshold,
+                        path: lag.path,
+                        lag: bytes,
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// A callback sending the events to the channel, they're dropped while it's full
+pub fn channel(tx: mpsc::Sender<Event>) -> Callback {
+    Box::new(move |event| {
+        let _ = tx.try_send(event.clone());
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[tokio::test]
+    async fn test_hooks() {
+        let seen = Arc::new(Mutex::new(vec![]));
+        let (tx, mut rx) = mpsc::channel(1);
+        let hooks = Hooks::new(vec![
+            Box::new({
+                let seen = seen.clone();
+                move |event| seen.lock().unwrap().push(event.clone())
+            }),
+            channel(tx),
+        ]);
+
+        let rotated = Event::Rotated {
+            path: PathBuf::from("/var/log/app.log"),
+        };
+        hooks.emit(|| rotated.clone());
+        // the channel is full, the callbacks are still called
+        hooks.emit(|| rotated.clone());
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![rotated.clone(), rotated.clone()]
+        );
+        assert_eq!(rx.recv().await, Some(rotated));
+        assert!(rx.try_recv().is_err());
+
+        Hooks::default().emit(|| unreachable!());
+    }
+}
@@ -0,0 +1,307 @@
+//! Read the positions saved by other log shippers, so a migration neither sends the files again
+//! nor skips lines
+//!
+//! - log-bouncer: the output of `state export`, the files are also looked up by their path as
+//!   the export can come from another host
+//! - Filebeat: the registry directory, its `log.json`, a checkpoint, or the registry file of the
+//!   versions before 7
+//! - Fluent Bit: the database of the `tail` input, requires the `sqlite` feature
+use crate::state::{Export, STATE_VERSION};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("unknown registry format `{0}`, expected `log-bouncer`, `filebeat` or `fluent-bit`")]
+    UnknownFormat(String),
+    #[error("the state has been exported by a newer version (format v{0})")]
+    UnsupportedVersion(u32),
+    #[cfg(not(feature = "sqlite"))]
+    #[error("the Fluent Bit database can only be read with the `sqlite` feature")]
+    SqliteDisabled,
+    #[error("no position of the file `{0}` in the registry")]
+    NotFound(String),
+    #[error("json: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("i/o: {0}")]
+    Io(#[from] std::io::Error),
+    #[cfg(feature = "sqlite")]
+    #[error("sqlite: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    LogBouncer,
+    Filebeat,
+    FluentBit,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "log-bouncer" => Ok(Format::LogBouncer),
+            "filebeat" => Ok(Format::Filebeat),
+            "fluent-bit" | "fluentbit" => Ok(Format::FluentBit),
+            format => Err(Error::UnknownFormat(format.to_owned())),
+        }
+    }
+}
+
+/// Position of a file in the registry of another shipper
+#[derive(Debug, Clone, PartialEq)]
+pub struct Offset {
+    pub path: PathBuf,
+    /// Unknown for Fluent Bit
+    pub dev: Option<u64>,
+    pub ino: u64,
+    pub offset: u64,
+}
+
+/// Every position of the registry
+pub fn read(format: Format, registry: &Path) -> Result<Vec<Offset>> {
+    match format {
+        Format::LogBouncer => log_bouncer(registry),
+        Format::Filebeat => filebeat(registry),
+        Format::FluentBit => fluent_bit(registry),
+    }
+}
+
+/// The position of the file with this identity, the paths of the other shippers aren't compared
+/// as the file may have been rotated since
+pub fn find(format: Format, offsets: &[Offset], path: &Path, dev: u64, ino: u64) -> Result<Offset> {
+    offsets
+        .iter()
+        .find(|offset| offset.ino == ino && offset.dev.is_none_or(|known| known == dev))
+        .or_else(|| match format {
+            Format::LogBouncer => offsets.iter().find(|offset| offset.path == path),
+            _ => None,
+        })
+        .cloned()
+        .ok_or_else(|| Error::NotFound(path.to_string_lossy().into_owned()))
+}
+
+fn log_bouncer(export: &Path) -> Result<Vec<Offset>> {
+    let export = serde_json::from_str::<Export>(&std::fs::read_to_string(export)?)?;
+    if export.version > STATE_VERSION {
+        return Err(Error::UnsupportedVersion(export.version));
+    }
+
+    Ok(export
+        .files
+        .into_iter()
+        .map(|entry| Offset {
+            path: entry.path,
+            dev: Some(entry.file_id.dev),
+            ino: entry.file_id.ino,
+            offset: entry.position,
+        })
+        .collect())
+}
+
+fn filebeat(registry: &Path) -> Result<Vec<Offset>> {
+    let mut states = BTreeMap::new();
+
+    if registry.is_dir() {
+        // the latest checkpoint, then the operations logged since
+        let active = registry.join("active.dat");
+        if active.exists() {
+            let checkpoint = std::fs::read_to_string(active)?;
+            filebeat_file(Path::new(checkpoint.trim()), &mut states)?;
+        }
+
+        let log = registry.join("log.json");
+        if log.exists() {
+            filebeat_file(&log, &mut states)?;
+        }
+    } else {
+        filebeat_file(registry, &mut states)?;
+    }
+
+    Ok(states.values().filter_map(filebeat_offset).collect())
+}
+
+/// Apply a file to the states, keyed by their Filebeat key
+fn filebeat_file(path: &Path, states: &mut BTreeMap<String, Value>) -> Result<()> {
+    let content = std::fs::read_to_string(path)?;
+
+    // a checkpoint or the registry file of the former versions
+    if content.trim_start().starts_with('[') {
+        for (i, state) in serde_json::from_str::<Vec<Value>>(&content)?
+            .into_iter()
+            .enumerate()
+        {
+            let key = match state["_key"].as_str() {
+                Some(key) => key.to_owned(),
+                None => i.to_string(),
+            };
+            states.insert(key, state);
+        }
+
+        return Ok(());
+    }
+
+    // `log.json`, each operation is followed by its key and value
+    let mut removing = false;
+    for line in content.lines().filter(|line| !line.trim().is_empty()) {
+        let mut entry = serde_json::from_str::<Value>(line)?;
+
+        if let Some(op) = entry["op"].as_str() {
+            removing = op == "remove";
+            continue;
+        }
+
+        if let Some(key) = entry["k"].as_str().map(str::to_owned) {
+            if removing {
+                states.remove(&key);
+            } else {
+                states.insert(key, entry["v"].take());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn filebeat_offset(state: &Value) -> Option<Offset> {
+    Some(Offset {
+        path: PathBuf::from(state["source"].as_str()?),
+        dev: Some(state["FileStateOS"]["device"].as_u64()?),
+        ino: state["FileStateOS"]["inode"].as_u64()?,
+        offset: state["offset"].as_u64()?,
+    })
+}
+
+#[cfg(feature = "sqlite")]
+fn fluent_bit(registry: &Path) -> Result<Vec<Offset>> {
+    use rusqlite::{Connection, OpenFlags};
+
+    let connection = Connection::open_with_flags(registry, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut statement = connection.prepare("SELECT name, offset, inode FROM in_tail_files")?;
+    let offsets = statement.query_map([], |row| {
+        Ok(Offset {
+            path: PathBuf::from(row.get::<_, String>(0)?),
+            dev: None,
+            ino: row.get::<_, i64>(2)? as u64,
+            offset: row.get::<_, i64>(1)? as u64,
+        })
+    })?;
+
+    Ok(offsets.collect::<rusqlite::Result<_>>()?)
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn fluent_bit(_registry: &Path) -> Result<Vec<Offset>> {
+    Err(Error::SqliteDisabled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::SavedState;
+
+    #[test]
+    fn test_exported_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.log");
+        std::fs::write(&path, "first\nsecond\n").unwrap();
+
+        let mut state = SavedState::new(&path).unwrap();
+        state.seek(6).unwrap();
+        let export = dir.path().join("export.json");
+        std::fs::write(
+            &export,
+            serde_json::to_string(&state.export().unwrap()).unwrap(),
+        )
+        .unwrap();
+
+        let offsets = read(Format::LogBouncer, &export).unwrap();
+        // on another host, the file has another identity
+        let offset = find(Format::LogBouncer, &offsets, &path, 0, 0).unwrap();
+        assert_eq!(offset.offset, 6);
+    }
+
+    #[test]
+    fn test_filebeat_registry() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("1.json"),
+            r#"[{"_key":"filebeat::logs::native::10-2049","source":"/var/log/a.log","offset":12,"FileStateOS":{"inode":10,"device":2049}}]"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("active.dat"),
+            dir.path().join("1.json").to_string_lossy().as_bytes(),
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("log.json"),
+            [
+                r#"{"op":"set","id":2}"#,
+                r#"{"k":"filebeat::logs::native::10-2049","v":{"source":"/var/log/a.log","offset":42,"FileStateOS":{"inode":10,"device":2049}}}"#,
+                r#"{"op":"set","id":3}"#,
+                r#"{"k":"filebeat::logs::native::11-2049","v":{"source":"/var/log/b.log","offset":7,"FileStateOS":{"inode":11,"device":2049}}}"#,
+                r#"{"op":"remove","id":4}"#,
+                r#"{"k":"filebeat::logs::native::11-2049"}"#,
+            ]
+            .join("\n"),
+        )
+        .unwrap();
+
+        let offsets = read(Format::Filebeat, dir.path()).unwrap();
+        assert_eq!(offsets.len(), 1);
+
+        let offset = find(
+            Format::Filebeat,
+            &offsets,
+            Path::new("/var/log/a.log"),
+            2049,
+            10,
+        )
+        .unwrap();
+        assert_eq!(offset.offset, 42);
+        assert!(matches!(
+            find(
+                Format::Filebeat,
+                &offsets,
+                Path::new("/var/log/a.log"),
+                2049,
+                11
+            ),
+            Err(Error::NotFound(_))
+        ));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_fluent_bit_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tail.db");
+        rusqlite::Connection::open(&path)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE in_tail_files (id INTEGER PRIMARY KEY, name TEXT NOT NULL,
+                offset INTEGER, inode INTEGER, created INTEGER, rotated INTEGER DEFAULT 0);
+                INSERT INTO in_tail_files (name, offset, inode, created)
+                VALUES ('/var/log/a.log', 42, 10, 0);",
+            )
+            .unwrap();
+
+        let offsets = read(Format::FluentBit, &path).unwrap();
+        let offset = find(
+            Format::FluentBit,
+            &offsets,
+            Path::new("/var/log/a.log"),
+            2049,
+            10,
+        )
+        .unwrap();
+        assert_eq!(offset.offset, 42);
+    }
+}
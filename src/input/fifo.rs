@@ -0,0 +1,49 @@
+use crate::error::Result;
+use crate::input::{InputAdapter, Stopped};
+use crate::publisher::Source;
+use crate::reader::{self, Batch};
+use crate::shutdown::Shutdown;
+use crate::tail::LineBreak;
+use async_trait::async_trait;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::mpsc::Sender;
+
+/// A named pipe, read as long as it's written to. It has neither a position to save nor a file
+/// to rotate
+pub struct FifoInput {
+    path: PathBuf,
+    line_break: LineBreak,
+}
+
+impl FifoInput {
+    pub fn new(path: PathBuf, line_break: LineBreak) -> Self {
+        Self { path, line_break }
+    }
+}
+
+#[async_trait]
+impl InputAdapter for FifoInput {
+    async fn read(self: Box<Self>, tx: Sender<Batch>, shutdown: Shutdown) -> Result<()> {
+        info!("`{}` is a named pipe", self.path.to_string_lossy());
+
+        let source = Arc::new(Source::unsaved(self.path));
+        let reader_stopped = reader::read_fifo(source, tx, shutdown.clone(), self.line_break);
+
+        let failed = tokio::select! {
+            _ = reader_stopped.notified() => true,
+            _ = shutdown.triggered() => false,
+        };
+
+        // the reader may be waiting for a writer, it stops once the pipe gets opened
+        shutdown.trigger();
+        match failed {
+            true => Err(Stopped("the pipe can't be read anymore").into()),
+            false => Ok(()),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "fifo"
+    }
+}
@@ -0,0 +1,159 @@
+use crate::error::Result;
+use crate::input::{InputAdapter, Stopped};
+use crate::reader::{Batch, Reader};
+use crate::rotator::Rotator;
+use crate::shutdown::Shutdown;
+use crate::state::{InstanceLock, StateSaver};
+use async_trait::async_trait;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::watch;
+
+/// A file being tailed, along with its own position and rotation
+pub struct FileInput {
+    reader: Reader,
+    /// Saves the position of the last published line periodically
+    saver: StateSaver,
+    /// Rotates the file, unless it's managed by someone else, on demand once the receiver changes
+    rotator: Option<(Rotator, watch::Receiver<()>)>,
+    /// Held while the file is tailed, unless the position is kept in a shared store
+    lock: Option<InstanceLock>,
+}
+
+impl FileInput {
+    pub fn new(reader: Reader, saver: StateSaver) -> Self {
+        Self {
+            reader,
+            saver,
+            rotator: None,
+            lock: None,
+        }
+    }
+
+    /// Rotate the file as well, right away once `rotate_rx` changes, eg. on SIGUSR1
+    pub fn with_rotator(mut self, rotator: Rotator, rotate_rx: watch::Receiver<()>) -> Self {
+        self.rotator = Some((rotator, rotate_rx));
+        self
+    }
+
+    pub fn with_lock(mut self, lock: Option<InstanceLock>) -> Self {
+        self.lock = lock;
+        self
+    }
+}
+
+#[async_trait]
+impl InputAdapter for FileInput {
+    async fn read(self: Box<Self>, tx: Sender<Batch>, shutdown: Shutdown) -> Result<()> {
+        let Self {
+            reader,
+            saver,
+            rotator,
+            lock: _lock,
+        } = *self;
+
+        let saver_stop = saver.stop_trigger();
+        let mut saver_handle = saver.watch();
+        let (rotator, mut rotate_rx) = match rotator {
+            Some((rotator, rotate_rx)) => {
+                (Some(rotator.with_shutdown(shutdown.clone())), rotate_rx)
+            }
+            None => (None, watch::channel(()).1),
+        };
+        let rotator_trigger = rotator.as_ref().map(Rotator::rotate_trigger);
+        let mut rotator_handle = rotator.map(Rotator::watch);
+        let watcher = reader.with_shutdown(shutdown.clone()).work(tx);
+
+        let rotator_stopped = async {
+            match rotator_handle.as_mut() {
+                // it stops when the file turns out to be rotated by another tool
+                Some(handle) => {
+                    if let Err(e) = handle.await {
+                        error!("Rotator: {}", e);
+                        return;
+                    }
+                    std::future::pending().await
+                }
+                None => std::future::pending().await,
+            }
+        };
+        let rotate_now = async {
+            while rotate_rx.changed().await.is_ok() {
+                if let Some(trigger) = &rotator_trigger {
+                    trigger.notify_one();
+                }
+            }
+            std::future::pending().await
+        };
+
+        let (saver_stopped, stopped) = tokio::select! {
+            _ = &mut saver_handle => (true, Some("the position can't be saved anymore")),
+            _ = rotator_stopped => (false, Some("the rotator panicked")),
+            _ = watcher.notified() => (false, Some("the file can't be read anymore")),
+            _ = rotate_now => (false, None),
+            _ = shutdown.triggered() => (false, None),
+        };
+
+        // the reader is done once it's read the file a last time, the rotator once its
+        // rotation is
+        shutdown.trigger();
+        if let Some(handle) = rotator_handle.filter(|handle| !handle.is_finished()) {
+            let _ = handle.await;
+        }
+        // the position of the last published line isn't lost
+        if !saver_stopped {
+            saver_stop.notify_one();
+            let _ = saver_handle.await;
+        }
+
+        match stopped {
+            Some(reason) => Err(Stopped(reason).into()),
+            None => Ok(()),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "file"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::publisher::Source;
+    use crate::state::{SavedState, StartFrom};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        std::fs::write(&path, "first\nsecond\n").unwrap();
+
+        let mut saved_state = SavedState::new(&path).unwrap();
+        let cursor = saved_state.start(StartFrom::Beginning).unwrap();
+        let (state_tx, state_rx) = watch::channel(cursor);
+        let interval = Duration::from_millis(10);
+        let saver = StateSaver::new(saved_state, state_rx, interval, interval);
+        let source = Arc::new(Source::new(path.clone(), state_tx));
+        let reader = Reader::new(source, cursor, interval, false, None, Arc::default()).unwrap();
+        let input = Box::new(FileInput::new(reader, saver));
+
+        let (tx, mut rx) = mpsc::channel(4);
+        let shutdown = Shutdown::new();
+        let reading = tokio::spawn(input.read(tx, shutdown.clone()));
+
+        // read at once, sent together
+        let batch = rx.recv().await.unwrap();
+        let lines = batch.iter().map(|line| &line.bytes[..]).collect::<Vec<_>>();
+        assert_eq!(lines, [&b"first"[..], b"second"]);
+
+        shutdown.trigger();
+        tokio::time::timeout(Duration::from_secs(1), reading)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+    }
+}
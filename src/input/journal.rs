@@ -0,0 +1,60 @@
+use crate::error::Result;
+use crate::input::{InputAdapter, Stopped};
+use crate::journal::Journal;
+use crate::publisher::Source;
+use crate::reader::Batch;
+use crate::shutdown::Shutdown;
+use async_trait::async_trait;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::Notify;
+
+/// The entries of the systemd units, the cursor of the last published one is saved periodically
+pub struct JournalInput {
+    journal: Journal,
+    source: Arc<Source>,
+    save_interval: Duration,
+}
+
+impl JournalInput {
+    pub fn new(journal: Journal, source: Arc<Source>, save_interval: Duration) -> Self {
+        Self {
+            journal,
+            source,
+            save_interval,
+        }
+    }
+}
+
+#[async_trait]
+impl InputAdapter for JournalInput {
+    async fn read(self: Box<Self>, tx: Sender<Batch>, shutdown: Shutdown) -> Result<()> {
+        let Self {
+            mut journal,
+            source,
+            save_interval,
+        } = *self;
+        let reader_stopped = journal.read(source, tx);
+
+        let saver_stop = Arc::new(Notify::new());
+        let saver = tokio::spawn(journal.save(save_interval, saver_stop.clone()));
+
+        let failed = tokio::select! {
+            _ = reader_stopped.notified() => true,
+            _ = shutdown.triggered() => false,
+        };
+
+        // the cursor of the last published entry isn't lost
+        saver_stop.notify_one();
+        saver.await?;
+        match failed {
+            true => Err(Stopped("journalctl exited").into()),
+            false => Ok(()),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "journal"
+    }
+}
@@ -0,0 +1,29 @@
+//! Where the lines are read from, mirroring the outputs: each input is read on its own until
+//! it's over or it's stopped, eg. once its file is deleted
+pub mod fifo;
+pub mod file;
+pub mod journal;
+pub mod stdin;
+
+use crate::error::Result;
+use crate::reader::Batch;
+use crate::shutdown::Shutdown;
+use async_trait::async_trait;
+use tokio::sync::mpsc::Sender;
+
+/// The input stopped on its own, nothing more can be read from it
+#[derive(thiserror::Error, Debug)]
+#[error("the input stopped: {0}")]
+pub struct Stopped(pub &'static str);
+
+#[async_trait]
+pub trait InputAdapter {
+    /// Send the lines to the publisher until the input is over or the shutdown is triggered,
+    /// resolves once its last position has been saved
+    async fn read(self: Box<Self>, tx: Sender<Batch>, shutdown: Shutdown) -> Result<()>;
+
+    /// Label of the input in the logs, eg. `file`
+    fn name(&self) -> &'static str {
+        "input"
+    }
+}
@@ -0,0 +1,38 @@
+use crate::error::Result;
+use crate::input::InputAdapter;
+use crate::reader::{self, Batch};
+use crate::shutdown::Shutdown;
+use crate::tail::LineBreak;
+use async_trait::async_trait;
+use tokio::sync::mpsc::Sender;
+
+/// The standard input, read until it's closed. Nothing is saved, the lines can't be read again
+/// anyway
+pub struct StdinInput {
+    line_break: LineBreak,
+}
+
+impl StdinInput {
+    pub fn new(line_break: LineBreak) -> Self {
+        Self { line_break }
+    }
+}
+
+#[async_trait]
+impl InputAdapter for StdinInput {
+    async fn read(self: Box<Self>, tx: Sender<Batch>, shutdown: Shutdown) -> Result<()> {
+        let closed = reader::read_stdin(tx, self.line_break);
+
+        // the reading thread can't be interrupted, it stops along with the process
+        tokio::select! {
+            _ = closed.notified() => {}
+            _ = shutdown.triggered() => {}
+        }
+
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        "stdin"
+    }
+}
@@ -0,0 +1,217 @@
+//! Read the systemd journal through `journalctl`, the cursor of the last published entry is kept
+//! in the state store so the entries are neither sent twice nor skipped after a restart
+use crate::publisher::Source;
+use crate::reader::{Batch, Line};
+use crate::state::{self, Cursor, StateStore};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::{watch, Notify};
+
+/// Key of the journal's cursor in the state store
+pub const INPUT: &str = "journal";
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("can't run journalctl: {0}")]
+    Spawn(std::io::Error),
+    #[error("state: {0}")]
+    State(#[from] state::Error),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// The journal cursor of the lines read but not published yet, by line number
+type Pending = Arc<Mutex<VecDeque<(u64, String)>>>;
+
+/// The entries of some units, read from the last saved cursor
+pub struct Journal {
+    child: Child,
+    store: Arc<Mutex<StateStore>>,
+    pending: Pending,
+    state_rx: watch::Receiver<Cursor>,
+}
+
+impl Journal {
+    /// Start following the journal, only the new entries are read the first time
+    pub fn follow(units: &[String], store: Arc<Mutex<StateStore>>) -> Result<(Self, Arc<Source>)> {
+        let cursor = {
+            let mut store = store.lock().unwrap();
+            store.load()?;
+            store.cursor(INPUT).map(str::to_owned)
+        };
+
+        let mut command = Command::new("journalctl");
+        command.args(["--follow", "--output=json"]);
+        for unit in units {
+            command.arg(format!("--unit={}", unit));
+        }
+        match &cursor {
+            Some(cursor) => {
+                info!("Recovered the journal from the cursor <{}>", cursor);
+                command.arg(format!("--after-cursor={}", cursor))
+            }
+            None => command.arg("--lines=0"),
+        };
+
+        let child = command
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(Error::Spawn)?;
+
+        let (state_tx, state_rx) = watch::channel(Cursor::default());
+        let journal = Self {
+            child,
+            store,
+            pending: Pending::default(),
+            state_rx,
+        };
+
+        Ok((
+            journal,
+            Arc::new(Source::new(PathBuf::from(INPUT), state_tx)),
+        ))
+    }
+
+    /// Send the message of every entry to the other thread, until `journalctl` exits
+    pub fn read(&mut self, source: Arc<Source>, tx: Sender<Batch>) -> Arc<Notify> {
+        let stopped = Arc::new(Notify::new());
+        let notifier = stopped.clone();
+        // unwrap() is safe, the output is piped
+        let stdout = self.child.stdout.take().unwrap();
+        let pending = self.pending.clone();
+
+        std::thread::spawn(move || {
+            let mut cursor = Cursor::default();
+
+            for json in BufReader::new(stdout).lines() {
+                let json = match json {
+                    Ok(json) => json,
+                    Err(e) => {
+                        error!("Can't read the journal: {}", e);
+                        break;
+                    }
+                };
+                let (journal_cursor, message) = match parse_entry(&json) {
+                    Some(entry) => entry,
+                    None => {
+                        warn!("Unexpected journal entry: {}", json);
+                        continue;
+                    }
+                };
+
+                cursor.line += 1;
+                cursor.position = cursor.line;
+                pending
+                    .lock()
+                    .unwrap()
+                    .push_back((cursor.line, journal_cursor));
+
+                let line = Line::new(&source, cursor, message);
+                if let Err(e) = tx.blocking_send(vec![line]) {
+                    error!("Can't send to mpsc: {}", e);
+                    break;
+                }
+            }
+
+            notifier.notify_one();
+        });
+
+        stopped
+    }
+
+    /// Save the cursor of the last published entry periodically, until stopped
+    pub async fn save(mut self, interval: Duration, stop: Arc<Notify>) {
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            let stopping = tokio::select! {
+                _ = interval.tick() => false,
+                _ = stop.notified() => true,
+            };
+
+            let published = self.state_rx.borrow_and_update().line;
+            if let Some(cursor) = acknowledged(&self.pending, published) {
+                let mut store = self.store.lock().unwrap();
+                store.set_cursor(INPUT, cursor);
+                if let Err(e) = store.save() {
+                    error!("Can't save the journal's cursor: {}", e);
+                }
+            }
+
+            if stopping {
+                break;
+            }
+        }
+
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// The cursor of the entry and its message, an array of bytes when it's not valid UTF-8, which
+/// is left to `--invalid-utf8`
+fn parse_entry(json: &str) -> Option<(String, Vec<u8>)> {
+    let mut entry = serde_json::from_str::<Value>(json).ok()?;
+    let cursor = entry["__CURSOR"].as_str()?.to_owned();
+
+    let message = match entry["MESSAGE"].take() {
+        Value::String(message) => message.into_bytes(),
+        Value::Array(bytes) => bytes
+            .iter()
+            .filter_map(|byte| byte.as_u64().map(|byte| byte as u8))
+            .collect(),
+        _ => vec![],
+    };
+
+    Some((cursor, message))
+}
+
+/// The cursor of the last published entry, the ones before it are forgotten
+fn acknowledged(pending: &Pending, published: u64) -> Option<String> {
+    let mut pending = pending.lock().unwrap();
+    let mut cursor = None;
+
+    while pending.front().is_some_and(|(line, _)| *line <= published) {
+        cursor = pending.pop_front().map(|(_, cursor)| cursor);
+    }
+
+    cursor
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_entry() {
+        let json = r#"{"__CURSOR":"s=1;i=2","_SYSTEMD_UNIT":"app.service","MESSAGE":"started"}"#;
+        assert_eq!(
+            parse_entry(json),
+            Some(("s=1;i=2".to_owned(), b"started".to_vec()))
+        );
+
+        let json = r#"{"__CURSOR":"s=1;i=3","MESSAGE":[104,105,255]}"#;
+        assert_eq!(parse_entry(json).unwrap().1, b"hi\xff");
+
+        assert_eq!(parse_entry(r#"{"MESSAGE":"no cursor"}"#), None);
+    }
+
+    #[test]
+    fn test_acknowledged() {
+        let pending = Pending::default();
+        for (line, cursor) in [(1, "a"), (2, "b"), (3, "c")] {
+            pending.lock().unwrap().push_back((line, cursor.to_owned()));
+        }
+
+        assert_eq!(acknowledged(&pending, 0), None);
+        assert_eq!(acknowledged(&pending, 2), Some("b".to_owned()));
+        assert_eq!(pending.lock().unwrap().len(), 1);
+        assert_eq!(acknowledged(&pending, 2), None);
+    }
+}
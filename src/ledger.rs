@@ -0,0 +1,149 @@
+//! History of the rotations, so the operators can audit what was cut when and reconcile it with
+//! the downstream counts
+//!
+//! Every rotation is appended as a JSON line, only the latest entries are kept.
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// Entries kept in the ledger, the oldest ones are dropped
+pub const MAX_ENTRIES: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub rotated_at: DateTime<Utc>,
+    pub old_filename: PathBuf,
+    pub new_filename: PathBuf,
+    /// Size of the rotated file, in bytes
+    pub size: u64,
+    /// Position of the last published line when the file got rotated
+    pub published_position: u64,
+    /// Number of the last published line when the file got rotated
+    pub published_line: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Ledger {
+    path: PathBuf,
+    max_entries: usize,
+}
+
+impl Ledger {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            max_entries: MAX_ENTRIES,
+        }
+    }
+
+    /// The ledger of a file lies next to it, eg. `.app.log.rotations` for `app.log`
+    pub fn default_path(filepath: &Path) -> PathBuf {
+        let name = filepath.file_name().unwrap_or_default().to_string_lossy();
+        filepath.with_file_name(format!(".{}.rotations", name))
+    }
+
+    /// Append an entry, then drop the oldest ones if there are too many
+    pub fn record(&self, entry: &Entry) -> io::Result<()> {
+        #[cfg(feature = "sqlite")]
+        if crate::state::is_database(&self.path) {
+            return crate::sqlite::open(&self.path)
+                .and_then(|mut database| {
+                    crate::sqlite::record_rotation(&mut database, entry, self.max_entries)
+                })
+                .map_err(io::Error::other);
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(entry)?)?;
+        file.sync_all()?;
+
+        let entries = self.entries()?;
+        if entries.len() > self.max_entries {
+            self.rewrite(&entries[entries.len() - self.max_entries..])?;
+        }
+
+        Ok(())
+    }
+
+    /// Every entry, from the oldest to the latest, the unreadable ones are skipped
+    pub fn entries(&self) -> io::Result<Vec<Entry>> {
+        #[cfg(feature = "sqlite")]
+        if crate::state::is_database(&self.path) {
+            return crate::sqlite::open(&self.path)
+                .and_then(|database| crate::sqlite::rotations(&database))
+                .map_err(io::Error::other);
+        }
+
+        let file = match std::fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+
+        let mut entries = vec![];
+        for line in BufReader::new(file).lines() {
+            match serde_json::from_str(&line?) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => warn!("Skipping a corrupted entry of the rotation ledger: {}", e),
+            }
+        }
+
+        Ok(entries)
+    }
+
+    fn rewrite(&self, entries: &[Entry]) -> io::Result<()> {
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let mut file = std::fs::File::create(&tmp)?;
+        for entry in entries {
+            writeln!(file, "{}", serde_json::to_string(entry)?)?;
+        }
+        file.sync_all()?;
+
+        std::fs::rename(tmp, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(size: u64) -> Entry {
+        Entry {
+            rotated_at: Utc::now(),
+            old_filename: PathBuf::from("/var/log/app.log"),
+            new_filename: PathBuf::from("/var/log/app.log.1"),
+            size,
+            published_position: size,
+            published_line: 2,
+        }
+    }
+
+    #[test]
+    fn test_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = Ledger::default_path(&dir.path().join("app.log"));
+        assert_eq!(path, dir.path().join(".app.log.rotations"));
+
+        let mut ledger = Ledger::new(path);
+        ledger.max_entries = 2;
+        assert!(ledger.entries().unwrap().is_empty());
+
+        for size in 1..=3 {
+            ledger.record(&entry(size)).unwrap();
+        }
+
+        let sizes = ledger
+            .entries()
+            .unwrap()
+            .iter()
+            .map(|entry| entry.size)
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, vec![2, 3]);
+    }
+}
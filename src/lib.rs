@@ -1,79 +1,1332 @@
-#[forbid(unsafe_code)]
+#![forbid(unsafe_code)]
 #[macro_use]
 extern crate tracing;
 
+mod admin;
+pub mod alert;
+#[cfg(feature = "http")]
+mod archive;
+#[cfg(not(feature = "http"))]
+#[path = "archive_disabled.rs"]
+mod archive;
+mod backfill;
+mod bench;
+mod bouncer;
+mod check;
+mod completions;
+mod compression;
+pub mod config;
+mod discovery;
+mod encoding;
+pub mod error;
+mod exit;
+mod heartbeat;
+pub mod hooks;
+mod import;
+mod input;
+mod journal;
+mod ledger;
+mod logs;
+mod manpage;
+pub mod metrics;
 pub mod opt;
 pub mod output;
+pub mod pipeline;
 mod publisher;
 mod reader;
+mod retention;
 mod rotator;
-mod tail;
+mod schedule;
+mod secrets;
+mod selftest;
+mod shutdown;
+#[cfg(unix)]
+mod signals;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod state;
+mod stats;
+#[cfg(feature = "http")]
+mod status;
+pub mod stream;
+mod systemd;
+pub mod tail;
+mod telemetry;
 
-pub use opt::{parse, Opt};
+pub use bouncer::{Builder, LogBouncer, ShutdownHandle};
+pub use error::Error;
+pub use state::StartFrom;
 
+pub use opt::{
+    parse, BenchOpt, CheckOpt, Command, CompletionsOpt, GlobalOpt, ManOpt, Opt, ReplayOpt,
+    SelftestOpt, StateOpt, StatusOpt,
+};
+
+use crate::alert::Alerter;
+use crate::archive::{Archiver, Credentials};
+use crate::config::Config;
+use crate::discovery::Change;
+use crate::input::fifo::FifoInput;
+use crate::input::file::FileInput;
+use crate::input::journal::JournalInput;
+use crate::input::stdin::StdinInput;
+use crate::input::InputAdapter;
+use crate::journal::Journal;
+use crate::ledger::Ledger;
+use crate::opt::{StateAction, StateTarget};
 use crate::output::amqp::AmqpOutput;
-use crate::publisher::Publisher;
-use crate::reader::{LineInfo, Reader};
-use crate::rotator::Rotator;
-use std::error::Error;
+use crate::output::{Message, OutputAdapter};
+use crate::pipeline::config::StageConfig;
+use crate::pipeline::template::Template;
+use crate::pipeline::Pipeline;
+use crate::publisher::{BusySince, Publisher, Source};
+use crate::reader::{Batch, Reader};
+use crate::retention::Retention;
+use crate::rotator::{RotationPolicy, Rotator};
+use crate::shutdown::Shutdown;
+use crate::state::{is_database, Cursor, InstanceLock, SavedState, StateSaver, StateStore};
+use crate::tail::LineBreak;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error as StdError;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use tokio::sync::{mpsc, watch};
-use tracing_subscriber::util::SubscriberInitExt;
-use tracing_subscriber::EnvFilter;
+use tokio::sync::{mpsc, watch, Notify};
+use tokio::task::JoinHandle;
+use tracing::Instrument;
 
-pub async fn run(opts: Opt) -> Result<(), Box<dyn Error>> {
-    // Build a logger subscriber
-    let log = tracing_subscriber::fmt().with_env_filter(EnvFilter::from_default_env());
+pub async fn run(opts: Opt) -> Result<(), Error> {
+    let log_filter = Arc::new(logs::init(&opts));
+    info!("Started!");
 
-    if opts.json {
-        // activates json logging output
-        log.json().finish().init();
-    } else {
-        // or simply plain text
-        log.finish().init();
+    if let Some(addr) = opts.metrics_addr {
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(addr).await {
+                error!("Metrics endpoint: {}", e);
+            }
+        });
     }
 
-    info!("Started!");
+    tokio::spawn(stats::watch_lags());
+    if opts.stats_interval > 0 {
+        tokio::spawn(stats::log(Duration::from_secs(opts.stats_interval)));
+    }
+
+    #[cfg(not(feature = "http"))]
+    if opts.otlp_endpoint.is_some() || opts.alert_webhook.is_some() {
+        return Err("`--otlp-endpoint` and `--alert-webhook` need the `http` feature".into());
+    }
+    #[cfg(feature = "http")]
+    if let Some(endpoint) = opts.otlp_endpoint.clone() {
+        telemetry::tracer().enable(opts.otlp_trace_every);
+        tokio::spawn(telemetry::export(
+            endpoint,
+            Duration::from_secs(opts.otlp_interval),
+            opts.otlp_service_name.clone(),
+        ));
+    }
+
+    let config = match &opts.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    let pipelines = sources(&opts, &config);
+
+    // Rotate every file on SIGUSR1
+    let (rotate_tx, rotate_rx) = watch::channel(());
+    // Reload the config on SIGHUP
+    let (reload_tx, reload_rx) = watch::channel(());
+    // Publish the spooled lines, from the admin API
+    let (flush_tx, flush_rx) = watch::channel(());
+    // Once the process is asked to stop or a source stops, every source does
+    let shutdown = Shutdown::new();
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            terminated().await;
+            shutdown.trigger();
+        }
+    });
+    let shared = Shared {
+        rotate_rx,
+        reload_rx,
+        flush_rx,
+        shutdown: shutdown.clone(),
+        paused: Arc::default(),
+        busy: Arc::default(),
+        starting: Arc::new(AtomicUsize::new(pipelines.len())),
+    };
+
+    let rotator_trigger = broadcast(rotate_tx);
+    let reload = broadcast(reload_tx);
+
+    if let Some(addr) = opts.admin_addr {
+        let admin = admin::Admin {
+            paused: shared.paused.clone(),
+            rotate: rotator_trigger.clone(),
+            reload: reload.clone(),
+            flush: broadcast(flush_tx),
+            log_filter: log_filter.clone(),
+        };
+        tokio::spawn(async move {
+            if let Err(e) = admin::serve(addr, admin).await {
+                error!("Admin API: {}", e);
+            }
+        });
+    }
+
+    #[cfg(unix)]
+    signals::listen(rotator_trigger, reload, log_filter)?;
+    #[cfg(not(unix))]
+    let _ = (rotator_trigger, reload, log_filter);
+
+    if let Some(timeout) = systemd::watchdog_timeout() {
+        tokio::spawn(watchdog(timeout, shared.busy.clone()));
+    }
+
+    let handles = pipelines
+        .into_iter()
+        .map(|opts| {
+            let span = match &opts.source {
+                Some(name) => info_span!("source", name = %name),
+                None => tracing::Span::none(),
+            };
+            let shared = shared.clone();
+            let shutdown = shutdown.clone();
+
+            let exit_on_eof = opts.exit_on_eof;
+            let serving = async move {
+                let served = serve(opts, shared).await.map_err(Error::from);
+                // the other sources are left to read their files until their end
+                if !exit_on_eof || served.is_err() {
+                    shutdown.trigger();
+                }
+                served
+            };
+            tokio::spawn(serving.instrument(span))
+        })
+        .collect::<Vec<_>>();
+
+    // the first error is returned, the following ones are only logged
+    let mut served = Ok(());
+    for handle in handles {
+        match handle.await? {
+            Err(e) if served.is_ok() => served = Err(e),
+            Err(e) => error!("{}", e),
+            Ok(()) => {}
+        }
+    }
+
+    systemd::notify("STOPPING=1");
+
+    served
+}
+
+/// The exit code of the process for the error, by its class, see `exit`
+pub fn exit_code(e: &(dyn StdError + 'static)) -> i32 {
+    exit::code(e)
+}
+
+/// Every source of the config file is shipped on its own, otherwise the options are the only one
+fn sources(opts: &Opt, config: &Config) -> Vec<Opt> {
+    match config.sources.is_empty() {
+        true => vec![opts.clone()],
+        false => config
+            .sources
+            .iter()
+            .map(|source| opts.for_source(source))
+            .collect(),
+    }
+}
+
+/// What the sources of the process share
+#[derive(Clone)]
+struct Shared {
+    rotate_rx: watch::Receiver<()>,
+    reload_rx: watch::Receiver<()>,
+    flush_rx: watch::Receiver<()>,
+    /// Triggered once the process is asked to stop, or a source has stopped
+    shutdown: Shutdown,
+    /// The files aren't read while it's set
+    paused: Arc<AtomicBool>,
+    /// Since when the output of every source has been sending its current message
+    busy: Arc<Mutex<Vec<BusySince>>>,
+    /// Sources whose output isn't connected yet
+    starting: Arc<AtomicUsize>,
+}
+
+/// Ship the files of a source to its output, until it stops or the process is asked to
+async fn serve(mut opts: Opt, shared: Shared) -> Result<(), Box<dyn StdError>> {
+    // the publisher of the source stops along with it
+    let shutdown = shared.shutdown.child();
+    // the inputs once the lines already read are published, so their last position is saved
+    let inputs = Shutdown::new();
+    let refresh = Duration::from_secs(opts.secret_refresh_interval);
+    let amqp_uri_rx = resolve_secrets(&mut opts, refresh).await?;
 
     // Bounded 1 channel to make sure the watcher won't make any more progress in case rabbitmq
     // doesn't accept any more items.
-    let (publish_tx, publish_rx) = mpsc::channel::<LineInfo>(opts.buffer_publish);
-    // The last position of the file to sync
-    let (state_tx, state_rx) = watch::channel::<u64>(0);
-
-    // in case the user submit "test.log", canonicalize will get the absolute path
-    let absolute_path = std::fs::canonicalize(&opts.file)?;
-
-    // Rotate the file periodically
-    let rotator = Rotator::new(
-        absolute_path.clone(),
-        Duration::from_secs(opts.rotate_file_interval),
-        Duration::from_millis(opts.save_state_interval),
-        state_rx,
-        opts.max_filesize,
-        opts.date_format,
-    )?;
-    state_tx.send(rotator.get_position())?; // we store the last position
-
-    // Tail the file and send new entries
-    let tail = Reader::new(absolute_path, rotator.get_position(), publish_tx)?;
-    let watcher = tail.work();
-
-    let rotator_handle = rotator.watch();
+    let (publish_tx, publish_rx) = mpsc::channel::<Batch>(opts.buffer_publish);
+
+    // Processing stages applied to every line before it gets published
+    let (alert_tx, alert_rx) = alert::channel();
+    let reloadable = load_reloadable(&opts)?;
+    let pipeline = Pipeline::from_config(&reloadable.stages, &alert_tx)?;
+
+    // Alerts are published apart from the normal stream
+    if reloadable.stages.iter().any(StageConfig::sends_alerts) {
+        let output: Option<Box<dyn OutputAdapter + Send + Sync>> = if opts.alert_webhook.is_none()
+            || opts.alert_exchange.is_some()
+        {
+            let exchange = opts.alert_exchange.as_ref().unwrap_or(&opts.amqp_exchange);
+            let output = AmqpOutput::new(&opts.amqp_uri, exchange, &opts.alert_routing_key).await?;
+            Some(Box::new(output))
+        } else {
+            None
+        };
+
+        let alerter = Alerter::new(output, opts.alert_webhook.clone());
+        tokio::spawn(alerter.run(alert_rx));
+    }
+
+    // Published along with the lines, eg. the rotation events
+    let (events_tx, events_rx) = mpsc::channel(16);
+    if let Some(interval) = opts.heartbeat_interval.filter(|interval| *interval > 0) {
+        tokio::spawn(heartbeat::beat(
+            Duration::from_secs(interval),
+            events_tx.clone(),
+        ));
+    }
+    // A file can't be followed anymore
+    let (stopped_tx, mut stopped_rx) = mpsc::channel(1);
+
+    // The historical files are published before the live inputs get followed
+    let mut backfill = (!opts.backfill.is_empty()).then(|| {
+        let bounds = backfill::Bounds::default();
+        backfill::read(
+            opts.backfill.clone(),
+            publish_tx.clone(),
+            opts.line_break(),
+            bounds,
+        )
+    });
+
+    let mut files = reloadable.files;
+    // the files of the config, to tell which ones are added or removed on reload
+    let mut configured = files.iter().cloned().collect::<BTreeSet<_>>();
+    let (eof_tx, mut eof_rx) = mpsc::unbounded_channel();
+    // the files read until their end, with `--exit-on-eof`
+    let mut ended = BTreeSet::new();
+    // only the files of this source stop being read once it's drained, the other sources may
+    // still be reading theirs until their end
+    let drained = Arc::new(AtomicBool::new(false));
+    let followers = Followers {
+        store: shared_store(&opts)?,
+        opts: opts.clone(),
+        publish_tx,
+        events_tx,
+        rotate_rx: shared.rotate_rx.clone(),
+        stopped_tx,
+        shutdown: inputs.clone(),
+        paused: shared.paused.clone(),
+        drained: drained.clone(),
+        catch_up_rate: Arc::new(AtomicU64::new(reloadable.catch_up_rate.unwrap_or(0))),
+        eof_tx: opts.exit_on_eof.then_some(eof_tx),
+    };
+    // the positions of all the files are saved in the same store
+    let _lock = match &opts.state_file {
+        Some(state_file) => lock(InstanceLock::acquire(state_file), opts.force)?,
+        None => None,
+    };
+    let mut following = BTreeMap::new();
+    let mut stdin = None;
+    if backfill.is_none() {
+        following = followers.follow_live(std::mem::take(&mut files))?;
+        stdin = followers.follow_stdin();
+    }
+
+    // The files of the directory are followed as they appear
+    let mut directory = match &opts.watch_dir {
+        Some(dir) => {
+            let filter = discovery::Filter::new(&opts.include, &opts.exclude)?;
+            Some(discovery::Directory::watch(dir, filter, !opts.poll)?)
+        }
+        None => None,
+    };
 
     // let output = output::stdout::StdOut {};
-    let output =
+    let mut output =
         AmqpOutput::new(&opts.amqp_uri, &opts.amqp_exchange, &opts.amqp_routing_key).await?;
+    if let Some(amqp_uri_rx) = amqp_uri_rx {
+        output = output.with_rotating_uri(amqp_uri_rx);
+    }
+    // ready once the output of every source is
+    if shared.starting.fetch_sub(1, Ordering::SeqCst) == 1 {
+        systemd::notify("READY=1");
+    }
+
+    // Rebuild the pipeline and update the files when the config gets reloaded
+    let (reload_tx, reload_rx) = mpsc::channel(1);
+    let (files_tx, mut files_rx) = mpsc::channel(1);
+    let mut reload_notified = shared.reload_rx.clone();
+    let catch_up_rate = followers.catch_up_rate.clone();
+    tokio::spawn(async move {
+        loop {
+            if reload_notified.changed().await.is_err() {
+                break;
+            }
+            info!("Reloading the config");
+
+            let (reloadable, pipeline) = match load_reloadable(&opts).and_then(|reloadable| {
+                let pipeline = Pipeline::from_config(&reloadable.stages, &alert_tx)?;
+                Ok((reloadable, pipeline))
+            }) {
+                Ok(reloaded) => reloaded,
+                Err(e) => {
+                    error!("Can't reload the config, keeping the current one: {}", e);
+                    continue;
+                }
+            };
+
+            catch_up_rate.store(reloadable.catch_up_rate.unwrap_or(0), Ordering::Relaxed);
+            if reload_tx.send(pipeline).await.is_err()
+                || files_tx.send(reloadable.files).await.is_err()
+            {
+                break;
+            }
+        }
+    });
 
     // Send the new entries to the publisher, eg. amqp
-    let mut publisher = Publisher::new(output, publish_rx, state_tx);
+    let mut publisher = Publisher::new(
+        output,
+        pipeline,
+        publish_rx,
+        reload_rx,
+        events_rx,
+        followers.opts.invalid_utf8,
+        followers.opts.input_encoding,
+    )
+    .with_shutdown(shutdown.clone());
+    if let Some(header) = followers.opts.correlation_header.clone() {
+        publisher = publisher.with_correlation_header(header, heartbeat::hostname());
+    }
+
+    shared.busy.lock().unwrap().push(publisher.busy_since());
+    let shutdown_timeout = Duration::from_secs(followers.opts.shutdown_timeout);
+    let spool_file = followers.opts.spool_file.clone();
+    let exit_on_eof = followers.opts.exit_on_eof;
+    let mut flush_rx = shared.flush_rx.clone();
+    // the spooled lines being published, the spool is deleted once they've all been read
+    let mut flushing = None;
+    let (drain, published) = {
+        let publishing = publisher.publish();
+        tokio::pin!(publishing);
 
-    tokio::select! {
-        _ = rotator_handle => {}
-        _ = watcher.notified() => {}
-        _ = publisher.publish() => {}
+        loop {
+            tokio::select! {
+                Some(path) = stopped_rx.recv() => {
+                    error!("`{}` isn't followed anymore", path.to_string_lossy());
+                    break (false, Ok(()));
+                }
+                _ = notified(&backfill) => {
+                    info!("The backfill has been published");
+                    backfill = None;
+                    following = followers.follow_live(std::mem::take(&mut files))?;
+                    stdin = followers.follow_stdin();
+                }
+                // the new files wait for the backfill as well
+                change = directory_change(&mut directory), if backfill.is_none() => match change {
+                    Change::Existing(path) | Change::Created(path) if following.contains_key(&path) => {}
+                    Change::Existing(path) => {
+                        followers.follow_found(&mut following, path, followers.opts.start_from);
+                    }
+                    // it's never been read, it's read from its beginning
+                    Change::Created(path) => {
+                        followers.follow_found(&mut following, path, StartFrom::Saved);
+                    }
+                    Change::Removed(path) => {
+                        if let Some(follower) = following.remove(&path) {
+                            info!("`{}` has been deleted", path.to_string_lossy());
+                            stats::stats().forget(&path);
+                            follower.shutdown.trigger();
+                        }
+                    }
+                },
+                Some(reloaded) = files_rx.recv() => {
+                    let reloaded = reloaded.into_iter().collect::<BTreeSet<_>>();
+                    if backfill.is_some() {
+                        // followed once the backfill has been published
+                        files = reloaded.iter().cloned().collect();
+                    } else {
+                        for path in configured.difference(&reloaded) {
+                            if let Some(follower) = following.remove(path) {
+                                info!("`{}` has been removed from the config", path.to_string_lossy());
+                                stats::stats().forget(path);
+                                follower.shutdown.trigger();
+                            }
+                        }
+                        for path in reloaded.difference(&configured) {
+                            if !following.contains_key(path) {
+                                followers.follow_found(&mut following, path.clone(), followers.opts.start_from);
+                            }
+                        }
+                    }
+                    configured = reloaded;
+                }
+                Some(path) = eof_rx.recv() => {
+                    ended.insert(path);
+                    if backfill.is_none() && stdin.is_none() && following.keys().all(|path| ended.contains(path)) {
+                        info!("Every file has been read until its end");
+                        break (true, Ok(()));
+                    }
+                }
+                _ = finished(&mut stdin) => {
+                    info!("The standard input has been closed");
+                    stdin = None;
+                    if following.is_empty() && directory.is_none() {
+                        // the lines already read are still published
+                        drop(followers);
+                        break (shutdown.is_triggered(), (&mut publishing).await);
+                    }
+                    if exit_on_eof && following.keys().all(|path| ended.contains(path)) {
+                        info!("Every file has been read until its end");
+                        break (true, Ok(()));
+                    }
+                }
+                Ok(()) = flush_rx.changed(), if flushing.is_none() => match &spool_file {
+                    Some(spool) => match flush_spool(spool, followers.publish_tx.clone()) {
+                        Ok(flushed) => flushing = flushed,
+                        Err(e) => error!("Can't flush the spool: {}", e),
+                    },
+                    None => warn!("There's no spool to flush, see --spool-file"),
+                },
+                _ = notified(&flushing) => {
+                    flushing = None;
+                    // unwrap() is safe, it's only flushed if there's a spool
+                    let flushed = flushing_path(spool_file.as_deref().unwrap());
+                    match std::fs::remove_file(&flushed) {
+                        Ok(()) => info!("The spool has been flushed"),
+                        Err(e) => error!("Can't delete `{}`: {}", flushed.to_string_lossy(), e),
+                    }
+                }
+                // it returns once it's shut down, between two lines
+                published = &mut publishing => break (shutdown.is_triggered(), published),
+                // unless the output hangs on the line being sent
+                _ = shutdown.triggered() => {
+                    info!("Shutting down");
+                    break (true, Ok(()));
+                }
+            }
+        }
     };
 
+    if drain {
+        // the lines already read are published, the readers of the source don't read any more
+        drained.store(true, Ordering::Relaxed);
+        if tokio::time::timeout(shutdown_timeout, publisher.drain())
+            .await
+            .is_err()
+        {
+            warn!(
+                "The lines read haven't all been published within {}s, the rest will be read again",
+                shutdown_timeout.as_secs()
+            );
+        }
+
+        match publisher.spool(spool_file.as_deref()) {
+            Ok((0, 0)) => {}
+            Ok((spooled, 0)) => info!("{} lines written to the spool", spooled),
+            Ok((_, lost)) => warn!("{} lines that can't be read again are lost", lost),
+            Err(e) => error!("Can't write to the spool: {}", e),
+        }
+    }
+
+    // the position of the last published line of each file isn't lost
+    inputs.trigger();
+    for follower in following.into_values() {
+        follower.handle.await?;
+    }
+
+    Ok(published?)
+}
+
+/// Replace the uri of the AMQP server by the one of the secret store with `--amqp-uri-secret`,
+/// its next values are received when it's fetched again
+async fn resolve_secrets(
+    opts: &mut Opt,
+    refresh: Duration,
+) -> Result<Option<watch::Receiver<String>>, secrets::Error> {
+    match opts.amqp_uri_secret.clone() {
+        Some(secret) => {
+            let amqp_uri_rx = secrets::watch(secret, refresh).await?;
+            opts.amqp_uri = amqp_uri_rx.borrow().clone();
+            Ok(Some(amqp_uri_rx))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Where the spool is moved while its lines are published again, so the new ones don't mix in
+fn flushing_path(spool: &Path) -> PathBuf {
+    let mut flushing = spool.as_os_str().to_owned();
+    flushing.push(".flushing");
+
+    PathBuf::from(flushing)
+}
+
+/// Publish the lines of the spool again, along with the ones being read. Notified once they've
+/// all been read, `None` if there's nothing to flush
+///
+/// The ones left unpublished on shutdown are spooled again, a flush left over by a crash is
+/// resumed rather than overwritten.
+fn flush_spool(spool: &Path, tx: mpsc::Sender<Batch>) -> std::io::Result<Option<Arc<Notify>>> {
+    let flushing = flushing_path(spool);
+    if !flushing.exists() {
+        match std::fs::rename(spool, &flushing) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            renamed => renamed?,
+        }
+    }
+    info!("Flushing the spool `{}`", spool.to_string_lossy());
+
+    // it's written with `\n` whatever the encoding of the inputs
+    let bounds = backfill::Bounds::default();
+    Ok(Some(backfill::read(
+        vec![flushing],
+        tx,
+        LineBreak::Byte,
+        bounds,
+    )))
+}
+
+/// Resolves once the input has been read until its end, eg. the backfill, if it's read
+async fn notified(input: &Option<Arc<Notify>>) {
+    match input {
+        Some(done) => done.notified().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Resolves once the follower is done, eg. the standard input has been closed, if it's read
+async fn finished(follower: &mut Option<Follower>) {
+    match follower {
+        Some(follower) => {
+            let _ = (&mut follower.handle).await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// A trigger notifying every source, eg. from the signals or the admin API
+fn broadcast(tx: watch::Sender<()>) -> Arc<Notify> {
+    let trigger = Arc::new(Notify::new());
+    let notified = trigger.clone();
+
+    tokio::spawn(async move {
+        loop {
+            notified.notified().await;
+            if tx.send(()).is_err() {
+                break;
+            }
+        }
+    });
+
+    trigger
+}
+
+/// Notify systemd twice per timeout, as long as no output hangs
+async fn watchdog(timeout: Duration, busy: Arc<Mutex<Vec<BusySince>>>) {
+    let mut interval = tokio::time::interval(timeout / 2);
+
+    loop {
+        interval.tick().await;
+        let hanging = busy
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|busy_since| *busy_since.lock().unwrap())
+            .map(|since| since.elapsed())
+            .max();
+
+        match hanging {
+            Some(hanging) if hanging >= timeout => warn!(
+                "An output hasn't confirmed a line for {}s, the watchdog isn't notified",
+                hanging.as_secs()
+            ),
+            _ => systemd::notify("WATCHDOG=1"),
+        }
+    }
+}
+
+/// The next file created in or deleted from the watched directory, if any
+async fn directory_change(directory: &mut Option<discovery::Directory>) -> Change {
+    match directory {
+        Some(directory) => directory.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// An input being read, eg. a file along with its own position and rotation
+struct Follower {
+    /// Saves the position, then stops reading the input
+    shutdown: Shutdown,
+    handle: JoinHandle<()>,
+}
+
+/// What the followers of the files share
+struct Followers {
+    opts: Opt,
+    /// Where every position is saved when `--state-file` is set, next to each file otherwise
+    store: Option<Arc<Mutex<StateStore>>>,
+    publish_tx: mpsc::Sender<Batch>,
+    events_tx: mpsc::Sender<Message>,
+    rotate_rx: watch::Receiver<()>,
+    /// Receives the path of the files that can't be followed anymore
+    stopped_tx: mpsc::Sender<PathBuf>,
+    /// Stops every input of the source, the follower of each one is a child of it
+    shutdown: Shutdown,
+    /// The files aren't read while it's set, shared by every source
+    paused: Arc<AtomicBool>,
+    /// The files of the source aren't read while it's set, once its lines are being drained
+    drained: Arc<AtomicBool>,
+    /// Lines per second while catching up, unlimited when 0, updated on reload
+    catch_up_rate: Arc<AtomicU64>,
+    /// Receives the path of the files read until their end, with `--exit-on-eof`
+    eof_tx: Option<mpsc::UnboundedSender<PathBuf>>,
+}
+
+impl Followers {
+    /// Follow the files and the journal
+    fn follow_live(
+        &self,
+        files: Vec<PathBuf>,
+    ) -> Result<BTreeMap<PathBuf, Follower>, Box<dyn StdError>> {
+        let mut following = BTreeMap::new();
+        for path in files {
+            following.insert(path.clone(), self.follow(path, self.opts.start_from)?);
+        }
+        if !self.opts.journal_unit.is_empty() {
+            following.insert(PathBuf::from(journal::INPUT), self.follow_journal()?);
+        }
+
+        Ok(following)
+    }
+
+    /// The lines of the standard input are published along with the ones of the files
+    fn follow_stdin(&self) -> Option<Follower> {
+        self.opts.reads_stdin().then(|| {
+            let input = StdinInput::new(self.opts.line_break());
+            self.spawn(PathBuf::from("-"), Box::new(input))
+        })
+    }
+
+    /// Follow a file found in the watched directory, it's skipped if it can't be followed
+    fn follow_found(
+        &self,
+        following: &mut BTreeMap<PathBuf, Follower>,
+        path: PathBuf,
+        start_from: StartFrom,
+    ) {
+        info!("Following `{}`", path.to_string_lossy());
+        match self.follow(path.clone(), start_from) {
+            Ok(follower) => {
+                following.insert(path, follower);
+            }
+            Err(e) => error!("Can't follow `{}`: {}", path.to_string_lossy(), e),
+        }
+    }
+
+    /// Resume where we left off, then tail, rotate and save the position of the file
+    fn follow(&self, path: PathBuf, start_from: StartFrom) -> Result<Follower, Box<dyn StdError>> {
+        if reader::is_fifo(&path) {
+            let input = FifoInput::new(path.clone(), self.opts.line_break());
+            return Ok(self.spawn(path, Box::new(input)));
+        }
+
+        let opts = &self.opts;
+        let mut saved_state = match &self.store {
+            Some(store) => SavedState::in_store(&path, store.clone()),
+            None => SavedState::new(&path)?,
+        }
+        .with_head_size(opts.fingerprint_bytes)
+        .with_line_break(opts.line_break());
+        let lock = match &self.store {
+            Some(_) => None,
+            None => lock(saved_state.lock(), opts.force)?,
+        };
+        // it's been deleted since it's been found, it's read from its beginning once it's back
+        let cursor = match path.exists() {
+            true => saved_state.start(start_from)?,
+            false => Cursor::default(),
+        };
+        stats::stats().follow(&path, cursor.position);
+        // The last position of the file to sync
+        let (state_tx, state_rx) = watch::channel(cursor);
+
+        // Save the position of the last published line periodically
+        let saver = StateSaver::new(
+            saved_state,
+            state_rx.clone(),
+            Duration::from_millis(opts.save_state_interval),
+            Duration::from_secs(opts.flush_state_interval),
+        );
+        let state_reset = saver.reset_trigger();
+
+        // Tail the file and send new entries
+        let source = Arc::new(Source::new(path.clone(), state_tx));
+        let tail = Reader::new(
+            source,
+            cursor,
+            Duration::from_millis(opts.poll_interval_ms),
+            !opts.poll,
+            opts.partial_line_timeout_ms.map(Duration::from_millis),
+            self.catch_up_rate.clone(),
+        )?
+        .with_line_break(opts.line_break())
+        .with_pause(self.paused.clone())
+        .with_pause(self.drained.clone())
+        .with_eof(self.eof_tx.clone());
+        let mut input = FileInput::new(tail, saver).with_lock(lock);
+
+        // Rotate the file periodically, unless it's managed by someone else
+        // the files behind a symlink are rotated by their application, which retargets it
+        if opts.no_rotate || opts.docker || path.is_symlink() {
+            info!("Rotation is disabled");
+        } else {
+            let mut rotator = Rotator::new(
+                path.clone(),
+                Duration::from_secs(opts.rotate_file_interval),
+                state_rx,
+                state_reset,
+                rotation_policy(opts, &path)?,
+            )?;
+            if opts.rotation_events {
+                rotator = rotator.with_events(self.events_tx.clone());
+            }
+            input = input.with_rotator(rotator, self.rotate_rx.clone());
+        }
+
+        Ok(self.spawn(path, Box::new(input)))
+    }
+
+    /// Publish the messages of the units, the journal's cursor is saved in the shared store
+    fn follow_journal(&self) -> Result<Follower, Box<dyn StdError>> {
+        let store = self.store.clone().ok_or("missing option: --state-file")?;
+        let (journal, source) = Journal::follow(&self.opts.journal_unit, store)?;
+        let save_interval = Duration::from_millis(self.opts.save_state_interval);
+        let input = JournalInput::new(journal, source, save_interval);
+
+        Ok(self.spawn(PathBuf::from(journal::INPUT), Box::new(input)))
+    }
+
+    /// Read the input until it's over or it's stopped, its path is sent on `stopped_tx` if it
+    /// stops on its own
+    fn spawn(&self, path: PathBuf, input: Box<dyn InputAdapter + Send>) -> Follower {
+        let shutdown = self.shutdown.child();
+        let tx = self.publish_tx.clone();
+        let stopped_tx = self.stopped_tx.clone();
+        let name = input.name();
+
+        let handle = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move {
+                if let Err(e) = input.read(tx, shutdown).await {
+                    error!(
+                        "Can't read the {} `{}`: {}",
+                        name,
+                        path.to_string_lossy(),
+                        e
+                    );
+                    let _ = stopped_tx.send(path).await;
+                }
+            }
+        });
+
+        Follower { shutdown, handle }
+    }
+}
+
+/// The store shared by all the files, if one has been set
+fn shared_store(opts: &Opt) -> Result<Option<Arc<Mutex<StateStore>>>, Box<dyn StdError>> {
+    match &opts.state_file {
+        Some(state_file) => {
+            let store = StateStore::open(state_file.clone())?;
+            Ok(Some(Arc::new(Mutex::new(store))))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Resolves once the process is asked to stop
+async fn terminated() {
+    #[cfg(unix)]
+    signals::terminated().await;
+
+    #[cfg(not(unix))]
+    if tokio::signal::ctrl_c().await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Rotate the files once, then exit, eg. from a cron job or a runbook
+pub async fn rotate(opts: Opt) -> Result<(), Box<dyn StdError>> {
+    logs::init(&opts);
+
+    for absolute_path in discovery::expand(opts.files())? {
+        if reader::is_fifo(&absolute_path) {
+            info!(
+                "`{}` is a named pipe, it can't be rotated",
+                absolute_path.to_string_lossy()
+            );
+            continue;
+        }
+
+        let mut saved_state = saved_state(opts.state_file.as_deref(), &absolute_path)?;
+        let _lock = lock(saved_state.lock(), opts.force)?;
+        // recorded in the ledger
+        let (_state_tx, state_rx) = watch::channel(saved_state.recover()?);
+        let rotator = Rotator::new(
+            absolute_path.clone(),
+            Duration::from_secs(opts.rotate_file_interval),
+            state_rx,
+            Arc::default(),
+            rotation_policy(&opts, &absolute_path)?,
+        )?;
+
+        rotator.rotate_once().await?;
+
+        // the new file will be read from the beginning
+        saved_state.reset()?;
+    }
+
+    Ok(())
+}
+
+/// Publish historical files through the output once, then exit
+///
+/// The alerts of the pipeline aren't sent, the events are long gone.
+pub async fn replay(replay: ReplayOpt) -> Result<(), Box<dyn StdError>> {
+    logs::init(&replay.opts);
+
+    let config = match &replay.opts.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    let opts = match &replay.source_name {
+        Some(name) => replay.opts.for_source(
+            config
+                .source(Some(name))
+                .ok_or_else(|| format!("no source `{}` in the config file", name))?,
+        ),
+        None => replay.opts.clone(),
+    };
+    let mut opts = opts;
+    // replayed once, the secret isn't fetched again
+    resolve_secrets(&mut opts, Duration::ZERO).await?;
+
+    let files = discovery::expand(&replay.path)?;
+    let (alert_tx, _alert_rx) = alert::channel();
+    let pipeline = Pipeline::from_config(&load_stages(&opts, &config)?, &alert_tx)?;
+    let output =
+        AmqpOutput::new(&opts.amqp_uri, &opts.amqp_exchange, &opts.amqp_routing_key).await?;
+
+    let (publish_tx, publish_rx) = mpsc::channel::<Batch>(opts.buffer_publish);
+    let (_reload_tx, reload_rx) = mpsc::channel(1);
+    let (_events_tx, events_rx) = mpsc::channel(1);
+    let mut publisher = Publisher::new(
+        output,
+        pipeline,
+        publish_rx,
+        reload_rx,
+        events_rx,
+        opts.invalid_utf8,
+        opts.input_encoding,
+    );
+    if let Some(header) = opts.correlation_header.clone() {
+        publisher = publisher.with_correlation_header(header, heartbeat::hostname());
+    }
+
+    let bounds = backfill::Bounds {
+        from_offset: replay.from_offset,
+        from_time: replay.from_time,
+        formats: pipeline::timestamp::formats(&opts.timestamp_format),
+    };
+    // the publisher stops once every line has been sent
+    backfill::read(files, publish_tx, opts.line_break(), bounds);
+    publisher.publish().await?;
+    info!("Replayed");
+
     Ok(())
 }
+
+/// Publish test messages to the output, then report how long it took to confirm them
+pub async fn selftest(opts: SelftestOpt) -> Result<(), Box<dyn StdError>> {
+    selftest::probe(opts).await
+}
+
+/// Ship synthetic lines, then report the throughput and the latency
+pub async fn bench(opts: BenchOpt) -> Result<(), Box<dyn StdError>> {
+    bench::run(opts).await
+}
+
+/// Report every problem of the options, eg. in CI, then exit with an error if there is any
+pub async fn check(opts: CheckOpt) -> Result<(), Box<dyn StdError>> {
+    check::check(opts).await
+}
+
+/// Print the completions of the shell
+pub fn completions(opts: CompletionsOpt) -> Result<(), Box<dyn StdError>> {
+    print!("{}", completions::generate(opts.shell, &opt::app()));
+    Ok(())
+}
+
+/// Print the man page of log-bouncer, or write every page to `--out-dir`
+pub fn man(opts: ManOpt) -> Result<(), Box<dyn StdError>> {
+    let pages = manpage::pages(&opt::app());
+    let out_dir = match opts.out_dir {
+        Some(out_dir) => out_dir,
+        None => {
+            print!("{}", pages[0].1);
+            return Ok(());
+        }
+    };
+
+    std::fs::create_dir_all(&out_dir)?;
+    for (name, page) in pages {
+        std::fs::write(out_dir.join(format!("{}.1", name)), page)?;
+    }
+    println!("Man pages written to `{}`", out_dir.to_string_lossy());
+
+    Ok(())
+}
+
+/// Watch the files and the errors of a running log-bouncer, through its admin API
+#[cfg(feature = "http")]
+pub async fn status(opts: StatusOpt) -> Result<(), Box<dyn StdError>> {
+    status::watch(opts).await
+}
+
+#[cfg(not(feature = "http"))]
+pub async fn status(_opts: StatusOpt) -> Result<(), Box<dyn StdError>> {
+    Err("the `status` command needs the `http` feature".into())
+}
+
+/// Inspect or change the saved position of a file, then exit
+///
+/// The file mustn't be tailed meanwhile, its tailer would overwrite the position.
+pub fn state(opts: StateOpt) -> Result<(), Box<dyn StdError>> {
+    match opts.action {
+        StateAction::Show { target, json } => {
+            let status = target_state(&target)?.status()?;
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&status)?);
+            } else {
+                print!("{}", status);
+            }
+        }
+        StateAction::Reset { target } => {
+            let mut state = target_state(&target)?;
+            let _lock = state.lock()?;
+            state.reset()?;
+            println!("position: 0 (line 0)");
+        }
+        StateAction::Set {
+            target,
+            offset,
+            end,
+        } => {
+            let mut state = target_state(&target)?;
+            let _lock = state.lock()?;
+            let cursor = match offset {
+                Some(offset) if !end => state.seek(offset)?,
+                _ => state.seek_end()?,
+            };
+            println!("position: {} (line {})", cursor.position, cursor.line);
+        }
+        StateAction::Export { target, output } => {
+            let export = serde_json::to_string_pretty(&target_state(&target)?.export()?)?;
+
+            match output {
+                Some(output) => std::fs::write(output, export)?,
+                None => println!("{}", export),
+            }
+        }
+        StateAction::Import {
+            target,
+            from,
+            registry,
+        } => {
+            let mut state = target_state(&target)?;
+            let _lock = state.lock()?;
+            let file_id = state.file_id()?;
+            let offsets = import::read(from, &registry)?;
+            let offset = import::find(
+                from,
+                &offsets,
+                &discovery::absolute(&target.file)?,
+                file_id.dev,
+                file_id.ino,
+            )?;
+
+            let cursor = state.seek(offset.offset)?;
+            println!("position: {} (line {})", cursor.position, cursor.line);
+        }
+    }
+
+    Ok(())
+}
+
+/// Make sure the file isn't tailed by another instance
+fn lock(
+    acquired: Result<InstanceLock, state::Error>,
+    force: bool,
+) -> Result<Option<InstanceLock>, Box<dyn StdError>> {
+    match acquired {
+        Ok(lock) => Ok(Some(lock)),
+        Err(e @ state::Error::Locked(_)) if force => {
+            warn!("{}, starting anyway", e);
+            Ok(None)
+        }
+        Err(e) => Err(e)?,
+    }
+}
+
+fn target_state(target: &StateTarget) -> Result<SavedState, Box<dyn StdError>> {
+    let absolute_path = discovery::absolute(&target.file)?;
+
+    saved_state(target.state_file.as_deref(), &absolute_path)
+}
+
+/// Where the position in the file is saved
+fn saved_state(state_file: Option<&Path>, path: &Path) -> Result<SavedState, Box<dyn StdError>> {
+    match state_file {
+        Some(state_file) => {
+            let store = StateStore::open(state_file.to_owned())?;
+            Ok(SavedState::in_store(path, Arc::new(Mutex::new(store))))
+        }
+        None => Ok(SavedState::new(path)?),
+    }
+}
+
+/// When and how the file gets rotated
+fn rotation_policy(opts: &Opt, path: &Path) -> Result<RotationPolicy, Box<dyn StdError>> {
+    Ok(RotationPolicy {
+        mode: opts.rotate_mode,
+        max_size: opts.max_filesize,
+        max_lines: opts.max_lines,
+        schedule: opts.rotate_schedule.clone(),
+        filename: Template::parse(&opts.rotate_filename)?,
+        date_format: opts.date_format.clone(),
+        retention: Retention {
+            count: opts.keep_rotated_count,
+            max_age: opts
+                .keep_rotated_days
+                .map(|days| Duration::from_secs(days * 24 * 3600)),
+            total_bytes: opts.keep_rotated_total_bytes,
+        },
+        post_rotate_hook: opts.post_rotate_hook.clone(),
+        rotate_dir: opts.rotate_dir.clone(),
+        archiver: archiver(opts)?.map(Arc::new),
+        catch_up_timeout: opts.rotate_catch_up_timeout.map(Duration::from_secs),
+        ledger: Some(Ledger::new(
+            opts.rotation_ledger
+                .clone()
+                // the rotations are kept along with the positions in the database
+                .or_else(|| opts.state_file.clone().filter(|path| is_database(path)))
+                .unwrap_or_else(|| Ledger::default_path(path)),
+        )),
+        max_disk_usage: opts.max_disk_usage,
+        compression: opts.compress,
+    })
+}
+
+/// What can change when the config file gets reloaded
+struct Reloadable {
+    stages: Vec<StageConfig>,
+    catch_up_rate: Option<u64>,
+    /// The files of the source, expanded
+    files: Vec<PathBuf>,
+}
+
+/// Read from the config file, or from the command line for what it doesn't declare
+fn load_reloadable(opts: &Opt) -> Result<Reloadable, Box<dyn StdError>> {
+    let config = match &opts.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    let source = opts.source.as_deref();
+    let files = match config.source(source) {
+        Some(source) => discovery::expand(&source.file)?,
+        None => discovery::expand(opts.files())?,
+    };
+
+    Ok(Reloadable {
+        stages: load_stages(opts, &config)?,
+        catch_up_rate: config.catch_up_rate(source).or(opts.catch_up_rate),
+        files,
+    })
+}
+
+/// The stages of the pipeline, declared in the config file or enabled on the command line
+fn load_stages(opts: &Opt, config: &Config) -> Result<Vec<StageConfig>, Box<dyn StdError>> {
+    let stages = config.stages(opts.source.as_deref());
+
+    if stages.is_empty() {
+        Ok(StageConfig::from_opts(opts)?)
+    } else {
+        info!("Using the pipeline of the config file");
+        Ok(stages)
+    }
+}
+
+/// Upload the rotated files to an object storage, if a bucket has been set
+fn archiver(opts: &Opt) -> Result<Option<Archiver>, Box<dyn StdError>> {
+    let bucket = match &opts.archive_bucket {
+        Some(bucket) => bucket.clone(),
+        None => return Ok(None),
+    };
+
+    let credentials = Credentials {
+        access_key: opts
+            .archive_access_key
+            .clone()
+            .ok_or("missing option: --archive-access-key")?,
+        secret_key: opts
+            .archive_secret_key
+            .clone()
+            .ok_or("missing option: --archive-secret-key")?,
+    };
+
+    let archiver = Archiver::new(
+        opts.archive_endpoint.as_deref(),
+        bucket,
+        opts.archive_region.clone(),
+        credentials,
+        &opts.archive_key,
+        Duration::from_secs(opts.archive_delete_after),
+    )?
+    .with_compression(opts.compress);
+
+    Ok(Some(archiver))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Clap;
+    use std::io::Write;
+
+    /// The followers of a source with `--exit-on-eof`, the lines it reads and the files it ends
+    fn source(
+        paused: &Arc<AtomicBool>,
+        path: &Path,
+    ) -> (
+        Followers,
+        mpsc::Receiver<Batch>,
+        mpsc::UnboundedReceiver<PathBuf>,
+    ) {
+        let opts = Opt::parse_from([
+            "log-bouncer",
+            "-f",
+            path.to_str().unwrap(),
+            "--amqp-exchange",
+            "logs",
+            "--amqp-routing-key",
+            "app",
+            "--exit-on-eof",
+            "--no-rotate",
+            "--poll",
+            "--poll-interval-ms",
+            "10",
+        ]);
+        let (publish_tx, publish_rx) = mpsc::channel(1);
+        let (eof_tx, eof_rx) = mpsc::unbounded_channel();
+        let followers = Followers {
+            opts,
+            store: None,
+            publish_tx,
+            events_tx: mpsc::channel(1).0,
+            rotate_rx: watch::channel(()).1,
+            stopped_tx: mpsc::channel(1).0,
+            shutdown: Shutdown::new(),
+            paused: paused.clone(),
+            drained: Arc::default(),
+            catch_up_rate: Arc::default(),
+            eof_tx: Some(eof_tx),
+        };
+
+        (followers, publish_rx, eof_rx)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_drained_source_leaves_the_others_reading() {
+        let dir = tempfile::tempdir().unwrap();
+        let paused = Arc::new(AtomicBool::new(false));
+        let (short, mut short_rx, mut short_eof) = source(&paused, &dir.path().join("short.log"));
+        let (long, mut long_rx, mut long_eof) = source(&paused, &dir.path().join("long.log"));
+        writeln!(
+            std::fs::File::create(dir.path().join("short.log")).unwrap(),
+            "a"
+        )
+        .unwrap();
+        // more lines than a batch, the reader is still busy with them once the short one ends
+        let mut f = std::fs::File::create(dir.path().join("long.log")).unwrap();
+        for i in 0..2000 {
+            writeln!(f, "line {}", i).unwrap();
+        }
+
+        let short_follower = short
+            .follow(dir.path().join("short.log"), StartFrom::Saved)
+            .unwrap();
+        let long_follower = long
+            .follow(dir.path().join("long.log"), StartFrom::Saved)
+            .unwrap();
+
+        assert_eq!(short_rx.recv().await.unwrap().len(), 1);
+        short_eof.recv().await.unwrap();
+        // the short source gets drained, as `serve` does
+        short.drained.store(true, Ordering::Relaxed);
+
+        let mut read = 0;
+        let ended = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                tokio::select! {
+                    Some(batch) = long_rx.recv() => read += batch.len(),
+                    Some(path) = long_eof.recv() => break path,
+                }
+            }
+        })
+        .await
+        .expect("the long source has stopped reading");
+        assert_eq!(ended, dir.path().join("long.log"));
+        assert_eq!(read, 2000);
+
+        for (followers, follower) in [(short, short_follower), (long, long_follower)] {
+            followers.shutdown.trigger();
+            follower.handle.await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_flush_spool() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = dir.path().join("spool.log");
+        let (tx, mut rx) = mpsc::channel(4);
+        assert!(flush_spool(&spool, tx.clone()).unwrap().is_none());
+
+        std::fs::write(&spool, "piped\nagain\n").unwrap();
+        let flushed = flush_spool(&spool, tx).unwrap().unwrap();
+        flushed.notified().await;
+        assert!(!spool.exists());
+        assert!(flushing_path(&spool).exists());
+
+        let mut lines = vec![];
+        while let Ok(batch) = rx.try_recv() {
+            lines.extend(batch.into_iter().map(|line| line.bytes));
+        }
+        assert_eq!(lines, vec!["piped", "again"]);
+    }
+}
@@ -0,0 +1,110 @@
+//! The logs of the process. Their level is set by `-v`/`-q` on top of the directives of
+//! `RUST_LOG`, then can be changed while running, with `SIGUSR2` or the admin API.
+use crate::opt::Opt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+type Reload = Box<dyn Fn(EnvFilter) -> Result<(), String> + Send + Sync>;
+
+/// Replaces the filter of the logs
+pub struct LogFilter {
+    /// Set by `-v`/`-q`, the level of `RUST_LOG` otherwise
+    level: Option<LevelFilter>,
+    debug: AtomicBool,
+    reload: Reload,
+}
+
+impl LogFilter {
+    fn new(level: Option<LevelFilter>, reload: Reload) -> Self {
+        Self {
+            level,
+            debug: AtomicBool::new(false),
+            reload,
+        }
+    }
+
+    /// Replace the filter by the directives, eg. `info,log_bouncer=debug`
+    pub fn set(&self, directives: &str) -> Result<(), String> {
+        let filter = EnvFilter::try_new(directives).map_err(|e| e.to_string())?;
+        self.debug.store(false, Ordering::Relaxed);
+        (self.reload)(filter)
+    }
+
+    /// Switch to the debug logs, or back to the filter of the start, then tell which one is set
+    pub fn toggle_debug(&self) -> Result<bool, String> {
+        let debug = !self.debug.fetch_xor(true, Ordering::Relaxed);
+        let filter = match debug {
+            true => initial_filter(self.level).add_directive(LevelFilter::DEBUG.into()),
+            false => initial_filter(self.level),
+        };
+
+        (self.reload)(filter).map(|_| debug)
+    }
+}
+
+/// The directives of `RUST_LOG`, with the level of `-v`/`-q` if set
+fn initial_filter(level: Option<LevelFilter>) -> EnvFilter {
+    let filter = EnvFilter::from_default_env();
+
+    match level {
+        Some(level) => filter.add_directive(level.into()),
+        None => filter,
+    }
+}
+
+pub fn init(opts: &Opt) -> LogFilter {
+    let level = opts.global.log_level();
+    // Build a logger subscriber
+    let log = tracing_subscriber::fmt().with_env_filter(initial_filter(level));
+
+    let reload: Reload = if opts.json {
+        // activates json logging output
+        let log = log.json().with_filter_reloading();
+        let handle = log.reload_handle();
+        log.finish().init();
+
+        Box::new(move |filter| handle.reload(filter).map_err(|e| e.to_string()))
+    } else {
+        // or simply plain text
+        let log = log.with_filter_reloading();
+        let handle = log.reload_handle();
+        log.finish().init();
+
+        Box::new(move |filter| handle.reload(filter).map_err(|e| e.to_string()))
+    };
+
+    LogFilter::new(level, reload)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Records the filters rather than replacing the one of the process
+    pub(crate) fn recording() -> (LogFilter, Arc<Mutex<Vec<String>>>) {
+        let filters = Arc::new(Mutex::new(vec![]));
+        let recorded = filters.clone();
+        let reload: Reload = Box::new(move |filter| {
+            recorded.lock().unwrap().push(filter.to_string());
+            Ok(())
+        });
+
+        (LogFilter::new(Some(LevelFilter::WARN), reload), filters)
+    }
+
+    #[test]
+    fn test_toggle_debug() {
+        let (filter, filters) = recording();
+
+        assert_eq!(filter.toggle_debug(), Ok(true));
+        assert_eq!(filter.toggle_debug(), Ok(false));
+        assert_eq!(*filters.lock().unwrap(), vec!["debug", "warn"]);
+
+        assert!(filter.set("log_bouncer=trace").is_ok());
+        assert!(filter.set("log_bouncer=loud").is_err());
+        assert_eq!(filter.toggle_debug(), Ok(true));
+    }
+}
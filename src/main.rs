@@ -1,7 +1,23 @@
-use log_bouncer::parse;
-use std::error::Error;
+use log_bouncer::{parse, Command};
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    log_bouncer::run(parse()).await
+async fn main() {
+    let done = match parse() {
+        Command::Run(opts) => log_bouncer::run(opts).await.map_err(Into::into),
+        Command::Rotate(opts) => log_bouncer::rotate(opts).await,
+        Command::Check(opts) => log_bouncer::check(opts).await,
+        Command::Replay(opts) => log_bouncer::replay(opts).await,
+        Command::Selftest(opts) => log_bouncer::selftest(opts).await,
+        Command::Bench(opts) => log_bouncer::bench(opts).await,
+        Command::State(opts) => log_bouncer::state(opts),
+        Command::Status(opts) => log_bouncer::status(opts).await,
+        Command::Completions(opts) => log_bouncer::completions(opts),
+        Command::Man(opts) => log_bouncer::man(opts),
+    };
+
+    // the exit code tells the class of the failure, see `--container`
+    if let Err(e) = done {
+        eprintln!("Error: {}", e);
+        std::process::exit(log_bouncer::exit_code(&*e));
+    }
 }
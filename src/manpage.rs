@@ -0,0 +1,196 @@
+//! Man pages, generated from the definition of the command line: one for the binary, then one
+//! per subcommand, eg. `log-bouncer-state-show(1)`
+use crate::completions::{self, Node};
+use clap::{App, Arg, ArgSettings};
+use std::fmt::Write;
+
+/// The name of the page, eg. `log-bouncer-state-show`
+pub fn name(node: &Node) -> String {
+    node.path.join("-")
+}
+
+/// Every page along with its name
+pub fn pages(app: &App) -> Vec<(String, String)> {
+    let nodes = completions::nodes(app);
+    nodes
+        .iter()
+        .map(|node| (name(node), render(node, &nodes)))
+        .collect()
+}
+
+/// Escape the text for roff
+fn escape(text: &str) -> String {
+    let text = text.replace('\\', "\\e").replace('-', "\\-");
+    // a line starting with a dot or a quote would be taken for a request
+    text.lines()
+        .map(
+            |line| match line.starts_with('.') || line.starts_with('\'') {
+                true => format!("\\&{}", line),
+                false => line.to_owned(),
+            },
+        )
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The first line of the help, without its markdown heading, eg. `# Log Bouncer`
+fn summary(about: &str) -> &str {
+    about
+        .lines()
+        .map(|line| line.trim_start_matches('#').trim())
+        .find(|line| !line.is_empty())
+        .unwrap_or_default()
+}
+
+/// eg. `\fB\-f\fR, \fB\-\-file\fR <\fIFILE\fR>`
+fn synopsis(arg: &Arg) -> String {
+    let value = format!("<\\fI{}\\fR>", escape(&arg.get_name().to_uppercase()));
+    if arg.get_index().is_some() {
+        return value;
+    }
+
+    let flags = arg
+        .get_short()
+        .map(|short| format!("\\fB\\-{}\\fR", short))
+        .into_iter()
+        .chain(
+            arg.get_long()
+                .map(|long| format!("\\fB\\-\\-{}\\fR", escape(long))),
+        )
+        .collect::<Vec<_>>()
+        .join(", ");
+    match arg.is_set(ArgSettings::TakesValue) {
+        true => format!("{} {}", flags, value),
+        false => flags,
+    }
+}
+
+fn render(node: &Node, nodes: &[Node]) -> String {
+    let title = name(node);
+    let about = node.app.get_about().unwrap_or_default();
+    let mut page = String::new();
+
+    let _ = writeln!(
+        page,
+        ".TH \"{}\" 1 \"\" \"{} {}\" \"User Commands\"",
+        escape(&title.to_uppercase()),
+        escape(node.path[0]),
+        env!("CARGO_PKG_VERSION")
+    );
+    let _ = writeln!(page, ".SH NAME");
+    let _ = writeln!(page, "{} \\- {}", escape(&title), escape(summary(about)));
+
+    let _ = writeln!(page, ".SH SYNOPSIS");
+    let mut usage = format!("\\fB{}\\fR [\\fIOPTIONS\\fR]", escape(&node.path.join(" ")));
+    for positional in node.args.iter().filter(|arg| arg.get_index().is_some()) {
+        let _ = write!(usage, " {}", synopsis(positional));
+    }
+    if node.subcommands().next().is_some() {
+        usage.push_str(" <\\fISUBCOMMAND\\fR>");
+    }
+    let _ = writeln!(page, "{}", usage);
+
+    if !about.is_empty() {
+        let _ = writeln!(page, ".SH DESCRIPTION");
+        for paragraph in about.split("\n\n") {
+            let paragraph = paragraph.trim().trim_start_matches('#').trim_start();
+            let _ = writeln!(page, ".PP\n{}", escape(paragraph));
+        }
+    }
+
+    if !node.args.is_empty() {
+        let _ = writeln!(page, ".SH OPTIONS");
+    }
+    for arg in &node.args {
+        let _ = writeln!(page, ".TP\n{}", synopsis(arg));
+        if let Some(help) = arg.get_long_about().or_else(|| arg.get_about()) {
+            let _ = writeln!(page, "{}", escape(help));
+        }
+
+        let mut details = vec![];
+        if let Some(values) = arg.get_possible_values() {
+            details.push(format!("possible values: {}", values.join(", ")));
+        }
+        let defaults = arg.get_default_values();
+        if !defaults.is_empty() {
+            let defaults = defaults
+                .iter()
+                .map(|value| value.to_string_lossy())
+                .collect::<Vec<_>>();
+            details.push(format!("default: {}", defaults.join(", ")));
+        }
+        if let Some(env) = arg.get_env() {
+            details.push(format!("env: {}", env.to_string_lossy()));
+        }
+        if !details.is_empty() {
+            let _ = writeln!(page, ".br\n[{}]", escape(&details.join("; ")));
+        }
+    }
+
+    if node.subcommands().next().is_some() {
+        let _ = writeln!(page, ".SH SUBCOMMANDS");
+        for sub in node.subcommands() {
+            let _ = writeln!(
+                page,
+                ".TP\n\\fB{}\\fR\n{}",
+                escape(sub.get_name()),
+                escape(sub.get_about().unwrap_or_default())
+            );
+        }
+    }
+
+    // the parent and the subcommands
+    let related = nodes
+        .iter()
+        .filter(|other| {
+            (other.path.len() + 1 == node.path.len() && node.path.starts_with(&other.path))
+                || (other.path.len() == node.path.len() + 1 && other.path.starts_with(&node.path))
+        })
+        .map(|other| format!("\\fB{}\\fR(1)", escape(&name(other))))
+        .collect::<Vec<_>>();
+    if !related.is_empty() {
+        let _ = writeln!(page, ".SH \"SEE ALSO\"\n{}", related.join(", "));
+    }
+
+    page
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pages() {
+        let app = App::new("log-bouncer")
+            .about("Tail log files")
+            .arg(Arg::new("verbose").short('v').global(true))
+            .subcommand(
+                App::new("run")
+                    .about("Tail, publish and rotate the files")
+                    .arg(
+                        Arg::new("file")
+                            .short('f')
+                            .long("file")
+                            .takes_value(true)
+                            .env("FILE")
+                            .about("The file to tail"),
+                    ),
+            );
+
+        let pages = pages(&app);
+        assert_eq!(pages[0].0, "log-bouncer");
+        assert!(pages[0].1.starts_with(".TH \"LOG\\-BOUNCER\" 1 \"\""));
+        assert!(pages[0]
+            .1
+            .contains(".SH \"SEE ALSO\"\n\\fBlog\\-bouncer\\-run\\fR(1)"));
+
+        let (name, run) = &pages[1];
+        assert_eq!(name, "log-bouncer-run");
+        assert!(
+            run.contains(".SH NAME\nlog\\-bouncer\\-run \\- Tail, publish and rotate the files")
+        );
+        assert!(run.contains(".TP\n\\fB\\-f\\fR, \\fB\\-\\-file\\fR <\\fIFILE\\fR>\nThe file to tail\n.br\n[env: FILE]"));
+        // the global options are documented by every subcommand
+        assert!(run.contains(".TP\n\\fB\\-v\\fR\n"));
+    }
+}
@@ -0,0 +1,334 @@
+//! Process-wide metrics registry, rendered in the Prometheus text format
+//!
+//! Metrics are identified by their name and labels, they're created on first use.
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Mutex, OnceLock};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Buckets used when a histogram hasn't been registered with its own, in seconds
+pub const DEFAULT_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0,
+];
+
+type Labels = Vec<(String, String)>;
+
+#[derive(Debug, Clone)]
+struct Histogram {
+    buckets: Vec<f64>,
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(buckets: &[f64]) -> Self {
+        Self {
+            buckets: buckets.to_vec(),
+            counts: vec![0; buckets.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (bucket, count) in self.buckets.iter().zip(self.counts.iter_mut()) {
+            if value <= *bucket {
+                *count += 1;
+            }
+        }
+
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Counter(u64),
+    Gauge(f64),
+    Histogram(Histogram),
+}
+
+impl Value {
+    fn kind(&self) -> &'static str {
+        match self {
+            Value::Counter(_) => "counter",
+            Value::Gauge(_) => "gauge",
+            Value::Histogram(_) => "histogram",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Family {
+    help: String,
+    buckets: Option<Vec<f64>>,
+    series: BTreeMap<Labels, Value>,
+}
+
+#[derive(Default)]
+pub struct Registry {
+    families: Mutex<BTreeMap<String, Family>>,
+}
+
+impl Registry {
+    /// Set the help text of a metric, and the buckets if it's a histogram
+    pub fn describe(&self, name: &str, help: &str, buckets: Option<&[f64]>) {
+        let mut families = self.families.lock().unwrap();
+        let family = families.entry(name.to_owned()).or_default();
+        family.help = help.to_owned();
+        family.buckets = buckets.map(<[f64]>::to_vec);
+    }
+
+    pub fn increment(&self, name: &str, labels: &[(&str, &str)], by: u64) {
+        self.update(
+            name,
+            labels,
+            |_| Value::Counter(0),
+            |value| {
+                if let Value::Counter(counter) = value {
+                    *counter += by;
+                }
+            },
+        );
+    }
+
+    pub fn set_gauge(&self, name: &str, labels: &[(&str, &str)], gauge: f64) {
+        self.update(
+            name,
+            labels,
+            |_| Value::Gauge(0.0),
+            |value| {
+                if let Value::Gauge(current) = value {
+                    *current = gauge;
+                }
+            },
+        );
+    }
+
+    pub fn observe(&self, name: &str, labels: &[(&str, &str)], observed: f64) {
+        let new = |family: &Family| {
+            let buckets = family.buckets.as_deref().unwrap_or(DEFAULT_BUCKETS);
+            Value::Histogram(Histogram::new(buckets))
+        };
+
+        self.update(name, labels, new, |value| {
+            if let Value::Histogram(histogram) = value {
+                histogram.observe(observed);
+            }
+        });
+    }
+
+    /// Drop a serie, eg. the one of a file that isn't followed anymore
+    pub fn remove(&self, name: &str, labels: &[(&str, &str)]) {
+        let labels = labels
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<Labels>();
+
+        if let Some(family) = self.families.lock().unwrap().get_mut(name) {
+            family.series.remove(&labels);
+        }
+    }
+
+    fn update<N, F>(&self, name: &str, labels: &[(&str, &str)], new: N, update: F)
+    where
+        N: FnOnce(&Family) -> Value,
+        F: FnOnce(&mut Value),
+    {
+        let labels = labels
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<Labels>();
+
+        let mut families = self.families.lock().unwrap();
+        let family = families.entry(name.to_owned()).or_default();
+
+        if !family.series.contains_key(&labels) {
+            let value = new(family);
+            family.series.insert(labels.clone(), value);
+        }
+
+        // unwrap() is safe, the serie has been inserted right above
+        update(family.series.get_mut(&labels).unwrap());
+    }
+
+    /// Render every metric in the Prometheus text format
+    pub fn render(&self) -> String {
+        let families = self.families.lock().unwrap();
+        let mut out = String::new();
+
+        for (name, family) in families.iter() {
+            let kind = match family.series.values().next() {
+                Some(value) => value.kind(),
+                None => continue, // described but never used
+            };
+
+            if !family.help.is_empty() {
+                let _ = writeln!(out, "# HELP {} {}", name, family.help);
+            }
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+
+            for (labels, value) in &family.series {
+                match value {
+                    Value::Counter(counter) => {
+                        let _ =
+                            writeln!(out, "{}{} {}", name, format_labels(labels, None), counter);
+                    }
+                    Value::Gauge(gauge) => {
+                        let _ = writeln!(out, "{}{} {}", name, format_labels(labels, None), gauge);
+                    }
+                    Value::Histogram(histogram) => {
+                        for (bucket, count) in histogram.buckets.iter().zip(&histogram.counts) {
+                            let le = bucket.to_string();
+                            let labels = format_labels(labels, Some(&le));
+                            let _ = writeln!(out, "{}_bucket{} {}", name, labels, count);
+                        }
+                        let labels_inf = format_labels(labels, Some("+Inf"));
+                        let labels = format_labels(labels, None);
+                        let _ = writeln!(out, "{}_bucket{} {}", name, labels_inf, histogram.count);
+                        let _ = writeln!(out, "{}_sum{} {}", name, labels, histogram.sum);
+                        let _ = writeln!(out, "{}_count{} {}", name, labels, histogram.count);
+                    }
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Every metric as OTLP's `Metric`, cumulative since `start`, see [`crate::telemetry`]
+    pub fn to_otlp(&self, start: u64, now: u64) -> Vec<serde_json::Value> {
+        use crate::telemetry::attribute;
+        use serde_json::json;
+
+        let families = self.families.lock().unwrap();
+        let mut metrics = vec![];
+
+        for (name, family) in families.iter() {
+            let point = |labels: &Labels| {
+                json!({
+                    "attributes": labels
+                        .iter()
+                        .map(|(key, value)| attribute(key, json!(value)))
+                        .collect::<Vec<_>>(),
+                    "startTimeUnixNano": start.to_string(),
+                    "timeUnixNano": now.to_string(),
+                })
+            };
+            let points = family.series.iter().map(|(labels, value)| {
+                let mut point = point(labels);
+                match value {
+                    Value::Counter(counter) => point["asInt"] = json!(counter.to_string()),
+                    Value::Gauge(gauge) => point["asDouble"] = json!(gauge),
+                    Value::Histogram(histogram) => {
+                        // the counts of the buckets are cumulative, they aren't in OTLP
+                        let mut below = 0;
+                        let mut counts = vec![];
+                        for count in histogram.counts.iter().chain([&histogram.count]) {
+                            counts.push((count - below).to_string());
+                            below = *count;
+                        }
+                        point["count"] = json!(histogram.count.to_string());
+                        point["sum"] = json!(histogram.sum);
+                        point["bucketCounts"] = json!(counts);
+                        point["explicitBounds"] = json!(histogram.buckets);
+                    }
+                }
+                point
+            });
+
+            // the temporality is cumulative, as in Prometheus
+            let points = points.collect::<Vec<_>>();
+            let (kind, data) = match family.series.values().next() {
+                Some(Value::Counter(_)) => (
+                    "sum",
+                    json!({ "dataPoints": points, "aggregationTemporality": 2, "isMonotonic": true }),
+                ),
+                Some(Value::Gauge(_)) => ("gauge", json!({ "dataPoints": points })),
+                Some(Value::Histogram(_)) => (
+                    "histogram",
+                    json!({ "dataPoints": points, "aggregationTemporality": 2 }),
+                ),
+                None => continue, // described but never used
+            };
+
+            let mut metric = json!({ "name": name, "description": family.help });
+            metric[kind] = data;
+            metrics.push(metric);
+        }
+
+        metrics
+    }
+}
+
+fn format_labels(labels: &Labels, le: Option<&str>) -> String {
+    let mut pairs = labels
+        .iter()
+        .map(|(key, value)| format!("{}=\"{}\"", key, escape(value)))
+        .collect::<Vec<_>>();
+
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{}\"", le));
+    }
+
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
+
+/// The registry shared by the whole process
+pub fn registry() -> &'static Registry {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    REGISTRY.get_or_init(Registry::default)
+}
+
+/// Expose the metrics over HTTP on `/metrics`
+pub async fn serve(addr: SocketAddr) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("Metrics are exposed on `http://{}/metrics`", addr);
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+
+        tokio::spawn(async move {
+            if let Err(e) = respond(stream).await {
+                debug!("Metrics endpoint: {}", e);
+            }
+        });
+    }
+}
+
+async fn respond(mut stream: TcpStream) -> std::io::Result<()> {
+    let mut buffer = [0u8; 4096];
+    let n = stream.read(&mut buffer).await?;
+    let request = String::from_utf8_lossy(&buffer[..n]);
+    let path = request.split_whitespace().nth(1).unwrap_or("/");
+
+    let (status, body) = if path == "/metrics" {
+        ("200 OK", registry().render())
+    } else {
+        ("404 Not Found", String::from("not found\n"))
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
@@ -1,42 +1,122 @@
-use crate::output::OutputAdapter;
-use amqp_lapin_helper::{Broker};
+use crate::error::{Error, Result};
+use crate::output::{Message, OutputAdapter};
+use amqp_lapin_helper::types::{AMQPValue, FieldTable};
+use amqp_lapin_helper::{BasicProperties, BasicPublishOptions, Broker, ConfirmSelectOptions};
 use async_trait::async_trait;
-use std::error::Error;
+use std::sync::Mutex;
+use tokio::sync::{watch, RwLock};
+
+#[derive(thiserror::Error, Debug)]
+pub enum AmqpError {
+    #[error("the broker refused the message")]
+    Nacked,
+}
 
 #[async_trait]
 impl OutputAdapter for AmqpOutput {
-    async fn send(&self, position: u64, line: String) -> Result<(), Box<dyn Error>> {
-        debug!("New line is being published <{}> = `{}`", position, line);
+    async fn send(&self, message: Message) -> Result<()> {
+        debug!(
+            "New line is being published <{}> = `{}`",
+            message.position, message.payload
+        );
+
+        self.reconnect_if_rotated().await?;
+        let routing_key = message.routing_key.as_deref().unwrap_or(&self.routing_key);
 
-        // confirm ack is not used, shall we use it?
-        let _confirm = self
+        let mut headers = FieldTable::default();
+        for (name, value) in message.headers {
+            headers.insert(name.into(), AMQPValue::LongString(value.into()));
+        }
+
+        // the line is delivered once the broker has confirmed it, only then its position is saved
+        let confirmation = self
             .publisher
-            .publish_raw(&self.exchange, &self.routing_key, line.as_bytes().to_vec())
-            .await?;
+            .read()
+            .await
+            .channel()
+            .basic_publish(
+                &self.exchange,
+                routing_key,
+                BasicPublishOptions::default(),
+                message.raw.unwrap_or_else(|| message.payload.into_bytes()),
+                BasicProperties::default().with_headers(headers),
+            )
+            .await
+            .map_err(Error::output)?
+            .await
+            .map_err(Error::output)?;
+
+        if confirmation.is_nack() {
+            return Err(Error::output(AmqpError::Nacked));
+        }
 
         Ok(())
     }
+
+    fn name(&self) -> &'static str {
+        "amqp"
+    }
 }
 
 pub struct AmqpOutput {
-    publisher: amqp_lapin_helper::Publisher,
+    /// Replaced once the uri has rotated
+    publisher: RwLock<amqp_lapin_helper::Publisher>,
     exchange: String,
     routing_key: String,
+    /// The uri fetched from a secret store, see `--amqp-uri-secret`
+    uri_rx: Option<Mutex<watch::Receiver<String>>>,
 }
 
 impl AmqpOutput {
-    pub async fn new(uri: &str, exchange: &str, routing_key: &str) -> Result<Self, Box<dyn Error>> {
-        // init the broker
-        let mut broker: Broker = Broker::new();
-        broker.init(uri).await?;
-
-        // Set the publisher up then clone it
-        let publisher = broker.setup_publisher().await?.clone();
-
+    pub async fn new(uri: &str, exchange: &str, routing_key: &str) -> Result<Self> {
         Ok(Self {
-            publisher,
+            publisher: RwLock::new(connect(uri).await?),
             exchange: exchange.to_owned(),
             routing_key: routing_key.to_owned(),
+            uri_rx: None,
         })
     }
+
+    /// Reconnect with the new uri before publishing, once it has changed
+    pub fn with_rotating_uri(mut self, uri_rx: watch::Receiver<String>) -> Self {
+        self.uri_rx = Some(Mutex::new(uri_rx));
+        self
+    }
+
+    /// The new uri, if it has changed since the last message
+    fn rotated_uri(&self) -> Option<String> {
+        let mut uri_rx = self.uri_rx.as_ref()?.lock().unwrap();
+        if !uri_rx.has_changed().unwrap_or(false) {
+            return None;
+        }
+        let uri = uri_rx.borrow_and_update().clone();
+        Some(uri)
+    }
+
+    async fn reconnect_if_rotated(&self) -> Result<()> {
+        if let Some(uri) = self.rotated_uri() {
+            info!("The AMQP uri has rotated, reconnecting");
+            let publisher = connect(&uri).await?;
+            *self.publisher.write().await = publisher;
+        }
+
+        Ok(())
+    }
+}
+
+/// A publisher whose messages are confirmed by the broker
+async fn connect(uri: &str) -> Result<amqp_lapin_helper::Publisher> {
+    // init the broker
+    let mut broker: Broker = Broker::new();
+    broker.init(uri).await?;
+
+    // Set the publisher up then clone it
+    let publisher = broker.setup_publisher().await?.clone();
+    publisher
+        .channel()
+        .confirm_select(ConfirmSelectOptions::default())
+        .await
+        .map_err(amqp_lapin_helper::Error::from)?;
+
+    Ok(publisher)
 }
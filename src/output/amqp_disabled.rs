@@ -0,0 +1,33 @@
+//! Stands in for the AMQP output once the `amqp` feature is disabled, it can't be connected
+use crate::error::{Error, Result};
+use crate::output::{Message, OutputAdapter};
+use async_trait::async_trait;
+use std::convert::Infallible;
+use tokio::sync::watch;
+
+#[derive(thiserror::Error, Debug)]
+pub enum AmqpError {
+    #[error("the AMQP output isn't available, the `amqp` feature isn't enabled")]
+    Disabled,
+}
+
+pub struct AmqpOutput {
+    never: Infallible,
+}
+
+impl AmqpOutput {
+    pub async fn new(_uri: &str, _exchange: &str, _routing_key: &str) -> Result<Self> {
+        Err(Error::output(AmqpError::Disabled))
+    }
+
+    pub fn with_rotating_uri(self, _uri_rx: watch::Receiver<String>) -> Self {
+        self
+    }
+}
+
+#[async_trait]
+impl OutputAdapter for AmqpOutput {
+    async fn send(&self, _message: Message) -> Result<()> {
+        match self.never {}
+    }
+}
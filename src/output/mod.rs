@@ -1,10 +1,55 @@
+#[cfg(feature = "amqp")]
+pub mod amqp;
+#[cfg(not(feature = "amqp"))]
+#[path = "amqp_disabled.rs"]
 pub mod amqp;
 pub mod stdout;
 
+use crate::error::Result;
 use async_trait::async_trait;
-use std::error::Error;
+use std::collections::BTreeMap;
+
+/// What gets published by the outputs
+#[derive(Debug, Clone, Default)]
+pub struct Message {
+    /// Position of the end of the line in the file
+    pub position: u64,
+    /// The encoded line
+    pub payload: String,
+    /// The line as it's been read when it isn't valid UTF-8, the outputs that can publish bytes
+    /// send it instead of the payload
+    pub raw: Option<Vec<u8>>,
+    /// Overrides the output's default routing key
+    pub routing_key: Option<String>,
+    /// Headers attached to the message, if the output supports them
+    pub headers: BTreeMap<String, String>,
+}
+
+/// The output stopped accepting the messages, nothing more can be published
+#[derive(thiserror::Error, Debug)]
+#[error("the output stopped accepting the messages: {0}")]
+pub struct Unavailable(pub String);
 
 #[async_trait]
 pub trait OutputAdapter {
-    async fn send(&self, position: u64, line: String) -> Result<(), Box<dyn Error>>;
+    /// Resolves once the output has durably accepted the message, eg. the broker confirmed it,
+    /// the position of the line is saved afterwards
+    async fn send(&self, message: Message) -> Result<()>;
+
+    /// Label of the output in the metrics, eg. `amqp`
+    fn name(&self) -> &'static str {
+        "output"
+    }
+}
+
+/// The output is picked at runtime, eg. by an application embedding log-bouncer
+#[async_trait]
+impl OutputAdapter for Box<dyn OutputAdapter + Send + Sync> {
+    async fn send(&self, message: Message) -> Result<()> {
+        (**self).send(message).await
+    }
+
+    fn name(&self) -> &'static str {
+        (**self).name()
+    }
 }
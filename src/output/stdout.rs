@@ -1,6 +1,6 @@
-use crate::output::OutputAdapter;
+use crate::error::Result;
+use crate::output::{Message, OutputAdapter};
 use async_trait::async_trait;
-use std::error::Error;
 
 #[derive(thiserror::Error, Debug)]
 pub enum StdOutError {
@@ -10,8 +10,8 @@ pub enum StdOutError {
 
 #[async_trait]
 impl OutputAdapter for StdOut {
-    async fn send(&self, _position: u64, line: String) -> Result<(), Box<dyn Error>> {
-        info!("got = {}", line);
+    async fn send(&self, message: Message) -> Result<()> {
+        info!("got = {}", message.payload);
 
         // if line.chars().last().unwrap() != '}' {
         //     Err(StdOutError::Corrupted)?;
@@ -19,6 +19,10 @@ impl OutputAdapter for StdOut {
 
         Ok(())
     }
+
+    fn name(&self) -> &'static str {
+        "stdout"
+    }
 }
 
 pub struct StdOut {}
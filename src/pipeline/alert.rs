@@ -0,0 +1,147 @@
+use crate::alert::{Alert, AlertSender, Rate};
+use crate::pipeline::{split_definition, Error, Event, Result, Stage};
+use regex::Regex;
+use std::collections::VecDeque;
+use std::time::Instant;
+
+/// Trigger an alert on matching lines, optionally once a rate threshold is exceeded
+pub struct AlertRule {
+    name: String,
+    regex: Regex,
+    threshold: Option<Rate>,
+    /// When the recent matches occurred, within the threshold's window
+    matches: VecDeque<Instant>,
+    last_fired: Option<Instant>,
+}
+
+impl AlertRule {
+    /// Formatted as `NAME=REGEX` or `NAME>10/min=REGEX`
+    pub fn parse(rule: &str) -> Result<Self> {
+        let (head, regex) = split_definition(rule)?;
+        let (name, threshold) = match head.split_once('>') {
+            Some((name, rate)) => {
+                let rate = rate
+                    .parse::<Rate>()
+                    .map_err(|_| Error::InvalidDefinition(rule.to_owned()))?;
+                (name.trim(), Some(rate))
+            }
+            None => (head, None),
+        };
+
+        Ok(Self {
+            name: name.to_owned(),
+            regex: Regex::new(regex)?,
+            threshold,
+            matches: VecDeque::new(),
+            last_fired: None,
+        })
+    }
+
+    /// Returns the alert's message if it has to be triggered
+    fn check(&mut self, line: &str, now: Instant) -> Option<String> {
+        if !self.regex.is_match(line) {
+            return None;
+        }
+
+        let threshold = match self.threshold {
+            Some(threshold) => threshold,
+            None => return Some(format!("line matched `{}`", self.regex)),
+        };
+
+        self.matches.push_back(now);
+        while let Some(oldest) = self.matches.front() {
+            if now.duration_since(*oldest) > threshold.per {
+                self.matches.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        let count = self.matches.len() as u64;
+        // fire at most once per window
+        let cooled_down = self
+            .last_fired
+            .map(|fired| now.duration_since(fired) >= threshold.per)
+            .unwrap_or(true);
+
+        if count > threshold.count && cooled_down {
+            self.last_fired = Some(now);
+
+            Some(format!(
+                "{} lines matched `{}` within {}s",
+                count,
+                self.regex,
+                threshold.per.as_secs()
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+pub struct AlertStage {
+    rules: Vec<AlertRule>,
+    alerts: AlertSender,
+}
+
+impl AlertStage {
+    pub fn new(rules: &[String], alerts: AlertSender) -> Result<Self> {
+        let rules = rules
+            .iter()
+            .map(|rule| AlertRule::parse(rule))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { rules, alerts })
+    }
+}
+
+impl Stage for AlertStage {
+    fn process(&mut self, event: Event) -> Option<Event> {
+        let now = Instant::now();
+
+        for rule in &mut self.rules {
+            if let Some(message) = rule.check(&event.line, now) {
+                self.alerts.send(Alert {
+                    name: rule.name.to_owned(),
+                    message,
+                    line: Some(event.line.to_owned()),
+                    position: Some(event.position),
+                });
+            }
+        }
+
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_threshold() {
+        let mut rule = AlertRule::parse("oom>2/min=OutOfMemory").unwrap();
+        let start = Instant::now();
+
+        assert!(rule.check("OutOfMemory", start).is_none());
+        assert!(rule.check("all good", start).is_none());
+        assert!(rule.check("OutOfMemory", start).is_none());
+        assert!(rule.check("OutOfMemory", start).is_some()); // 3 > 2
+        assert!(rule.check("OutOfMemory", start).is_none()); // already fired in that window
+
+        // the window has passed, the old matches are forgotten
+        let later = start + Duration::from_secs(61);
+        assert!(rule.check("OutOfMemory", later).is_none());
+    }
+
+    #[test]
+    fn test_without_threshold() {
+        let mut rule = AlertRule::parse("panic=panicked at").unwrap();
+
+        assert!(rule
+            .check("thread 'main' panicked at", Instant::now())
+            .is_some());
+        assert!(AlertRule::parse("bad>10/fortnight=x").is_err());
+    }
+}
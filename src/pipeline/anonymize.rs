@@ -0,0 +1,125 @@
+//! Anonymize personal data for GDPR-compliant shipping
+//!
+//! The anonymized values are replaced in the raw line too, as it's published along with the
+//! fields.
+use crate::pipeline::{Event, Stage};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+pub struct AnonymizeStage {
+    /// IPs are truncated: the last octet of IPv4, the last 80 bits of IPv6
+    ip_fields: Vec<String>,
+    /// Values are replaced by their salted SHA-256
+    hash_fields: Vec<String>,
+    salt: String,
+}
+
+impl AnonymizeStage {
+    pub fn new(ip_fields: Vec<String>, hash_fields: Vec<String>, salt: String) -> Self {
+        Self {
+            ip_fields,
+            hash_fields,
+            salt,
+        }
+    }
+
+    fn hash(&self, value: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.salt.as_bytes());
+        hasher.update(value.as_bytes());
+
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+pub fn truncate_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            IpAddr::V4(Ipv4Addr::new(a, b, c, 0))
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            IpAddr::V6(Ipv6Addr::new(
+                segments[0],
+                segments[1],
+                segments[2],
+                0,
+                0,
+                0,
+                0,
+                0,
+            ))
+        }
+    }
+}
+
+fn replace(event: &mut Event, field: &str, original: &str, anonymized: String) {
+    if !original.is_empty() {
+        event.line = event.line.replace(original, &anonymized);
+    }
+    event
+        .fields
+        .insert(field.to_owned(), Value::String(anonymized));
+}
+
+impl Stage for AnonymizeStage {
+    fn process(&mut self, mut event: Event) -> Option<Event> {
+        for field in &self.ip_fields {
+            let original = match event.field_str(field) {
+                Some(original) => original,
+                None => continue,
+            };
+
+            let anonymized = match original.trim().parse::<IpAddr>() {
+                Ok(ip) => truncate_ip(ip).to_string(),
+                // better be safe than sorry, it's not an IP but it has to be anonymized
+                Err(_) => self.hash(&original),
+            };
+
+            replace(&mut event, field, &original, anonymized);
+        }
+
+        for field in &self.hash_fields {
+            if let Some(original) = event.field_str(field) {
+                let anonymized = self.hash(&original);
+                replace(&mut event, field, &original, anonymized);
+            }
+        }
+
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_anonymize() {
+        let mut stage = AnonymizeStage::new(
+            vec!["ip".to_owned(), "ip6".to_owned()],
+            vec!["user".to_owned()],
+            "salt".to_owned(),
+        );
+
+        let mut event = Event::new(0, "192.168.1.42 dizda GET /".to_owned());
+        event.fields.insert("ip".to_owned(), "192.168.1.42".into());
+        event
+            .fields
+            .insert("ip6".to_owned(), "2001:db8:85a3::8a2e:370:7334".into());
+        event.fields.insert("user".to_owned(), "dizda".into());
+        let event = stage.process(event).unwrap();
+
+        let hashed = stage.hash("dizda");
+        assert_eq!(event.fields["ip"], "192.168.1.0");
+        assert_eq!(event.fields["ip6"], "2001:db8:85a3::");
+        assert_eq!(event.fields["user"], hashed.as_str());
+        assert_eq!(event.line, format!("192.168.1.0 {} GET /", hashed));
+        assert_ne!(
+            hashed,
+            AnonymizeStage::new(vec![], vec![], "pepper".to_owned()).hash("dizda")
+        );
+    }
+}
@@ -0,0 +1,171 @@
+//! ArcSight Common Event Format, emitted by most firewalls and IDS
+//!
+//! `CEF:Version|Device Vendor|Device Product|Device Version|Device Event Class ID|Name|Severity|Extension`
+//!
+//! The line can be prefixed by a syslog header, everything before `CEF:` is ignored.
+use serde_json::{Map, Value};
+
+/// Names of the fields extracted from the header
+const HEADERS: [&str; 7] = [
+    "cefVersion",
+    "deviceVendor",
+    "deviceProduct",
+    "deviceVersion",
+    "deviceEventClassId",
+    "name",
+    "severity",
+];
+
+/// Returns `None` if the line isn't CEF
+pub fn parse(line: &str) -> Option<Map<String, Value>> {
+    let start = line.find("CEF:")?;
+    let mut rest = &line[start + 4..];
+    let mut fields = Map::new();
+
+    for name in HEADERS {
+        let (value, remaining) = split_header(rest)?;
+        fields.insert(name.to_owned(), Value::String(value));
+        rest = remaining;
+    }
+
+    for (key, value) in parse_extension(rest) {
+        fields.insert(key, Value::String(value));
+    }
+
+    Some(fields)
+}
+
+/// Split on the next unescaped pipe, `\|` and `\\` are unescaped
+fn split_header(input: &str) -> Option<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = input.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some((_, escaped @ ('|' | '\\'))) => value.push(escaped),
+                Some((_, other)) => {
+                    value.push('\\');
+                    value.push(other);
+                }
+                None => value.push('\\'),
+            },
+            '|' => return Some((value, &input[i + 1..])),
+            _ => value.push(c),
+        }
+    }
+
+    None
+}
+
+/// The extension is a list of `key=value` separated by spaces, values can contain spaces too,
+/// thus a value ends where the next ` key=` begins.
+fn parse_extension(input: &str) -> Vec<(String, String)> {
+    // (start of the key, position of the `=`)
+    let mut keys = vec![];
+    let bytes = input.as_bytes();
+
+    for (i, b) in bytes.iter().enumerate() {
+        if *b != b'=' || (i > 0 && bytes[i - 1] == b'\\') {
+            continue;
+        }
+
+        let start = input[..i].rfind(' ').map(|space| space + 1).unwrap_or(0);
+        let key = &input[start..i];
+
+        if !key.is_empty() && key.chars().all(is_key_char) {
+            keys.push((start, i));
+        }
+    }
+
+    keys.iter()
+        .enumerate()
+        .map(|(n, (start, eq))| {
+            let end = keys
+                .get(n + 1)
+                .map(|(next, _)| *next)
+                .unwrap_or(input.len());
+            let value = input[eq + 1..end].trim_end();
+
+            (input[*start..*eq].to_owned(), unescape(value))
+        })
+        .collect()
+}
+
+fn is_key_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-' | '[' | ']')
+}
+
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(escaped @ ('=' | '\\')) => unescaped.push(escaped),
+            Some(other) => {
+                unescaped.push('\\');
+                unescaped.push(other);
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let line = r"Sep 19 08:26:10 host CEF:0|Security|threatmanager|1.0|100|worm successfully stopped|10|src=10.0.0.1 dst=2.1.2.2 spt=1232 msg=Detected a \= sign, spaces and a \\ backslash act=blocked";
+        let fields = parse(line).unwrap();
+
+        assert_eq!(fields["cefVersion"], "0");
+        assert_eq!(fields["deviceVendor"], "Security");
+        assert_eq!(fields["deviceProduct"], "threatmanager");
+        assert_eq!(fields["deviceEventClassId"], "100");
+        assert_eq!(fields["name"], "worm successfully stopped");
+        assert_eq!(fields["severity"], "10");
+        assert_eq!(fields["src"], "10.0.0.1");
+        assert_eq!(fields["spt"], "1232");
+        assert_eq!(
+            fields["msg"],
+            r"Detected a = sign, spaces and a \ backslash"
+        );
+        assert_eq!(fields["act"], "blocked");
+    }
+
+    #[test]
+    fn test_escaped_pipe_in_header() {
+        let line = r"CEF:0|Vendor\|Inc|Product|1|sig|Name|5|";
+        let fields = parse(line).unwrap();
+
+        assert_eq!(fields["deviceVendor"], "Vendor|Inc");
+        assert_eq!(fields.len(), 7);
+    }
+
+    #[test]
+    fn test_value_containing_equal_sign() {
+        let line = "CEF:0|V|P|1|sig|Name|5|request=https://example.com/?a=b&c=d dst=10.0.0.2";
+        let fields = parse(line).unwrap();
+
+        assert_eq!(fields["request"], "https://example.com/?a=b&c=d");
+        assert_eq!(fields["dst"], "10.0.0.2");
+    }
+
+    #[test]
+    fn test_not_cef() {
+        assert!(parse("a plain line").is_none());
+        assert!(parse("CEF:0|truncated|header").is_none());
+    }
+}
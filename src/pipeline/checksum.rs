@@ -0,0 +1,91 @@
+use crate::pipeline::{Error, Event, Stage};
+use crate::state::HASHER;
+use sha2::{Digest, Sha256};
+use std::str::FromStr;
+
+/// Header holding the checksum of the payload
+pub const CHECKSUM_HEADER: &str = "x-checksum";
+/// Header holding the algorithm used to compute the checksum
+pub const ALGORITHM_HEADER: &str = "x-checksum-algorithm";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Checksum {
+    /// CRC-32C, same as the one used to identify the files
+    Crc32,
+    Sha256,
+}
+
+impl FromStr for Checksum {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "crc32" | "crc32c" => Ok(Checksum::Crc32),
+            "sha256" => Ok(Checksum::Sha256),
+            _ => Err(Error::UnknownFormat(s.to_owned())),
+        }
+    }
+}
+
+impl Checksum {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Checksum::Crc32 => "crc32c",
+            Checksum::Sha256 => "sha256",
+        }
+    }
+
+    /// Hex-encoded checksum
+    pub fn compute(&self, data: &[u8]) -> String {
+        match self {
+            Checksum::Crc32 => format!("{:08x}", HASHER.checksum(data)),
+            Checksum::Sha256 => format!("{:x}", Sha256::digest(data)),
+        }
+    }
+}
+
+/// Attach the checksum of the payload as a header, so consumers can verify its integrity
+///
+/// Has to be the last stage, as it's computed on the final payload.
+pub struct ChecksumStage {
+    checksum: Checksum,
+}
+
+impl ChecksumStage {
+    pub fn new(checksum: Checksum) -> Self {
+        Self { checksum }
+    }
+}
+
+impl Stage for ChecksumStage {
+    fn process(&mut self, mut event: Event) -> Option<Event> {
+        let checksum = self.checksum.compute(event.payload().as_bytes());
+
+        event.headers.insert(CHECKSUM_HEADER.to_owned(), checksum);
+        event
+            .headers
+            .insert(ALGORITHM_HEADER.to_owned(), self.checksum.name().to_owned());
+
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksums() {
+        let mut event = Event::new(0, "hello".to_owned());
+        event = ChecksumStage::new(Checksum::Sha256).process(event).unwrap();
+
+        assert_eq!(
+            event.headers[CHECKSUM_HEADER],
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+        assert_eq!(event.headers[ALGORITHM_HEADER], "sha256");
+
+        // CRC-32C check value
+        assert_eq!(Checksum::Crc32.compute(b"123456789"), "e3069283");
+    }
+}
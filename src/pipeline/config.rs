@@ -0,0 +1,431 @@
+//! Declarative definition of the stages, so the pipeline can be written in the config file in
+//! the order the stages have to run.
+//!
+//! The options take the same values as their command line counterparts.
+use crate::alert::AlertSender;
+use crate::opt::Opt;
+use crate::pipeline::checksum::Checksum;
+use crate::pipeline::level::Level;
+use crate::pipeline::parse::Format;
+use crate::pipeline::{
+    alert, anonymize, checksum, docker, geoip, grok, level, line_metrics, noise, parse, remap,
+    route, spike, timestamp, Error, Pipeline, Result, Stage,
+};
+use serde::{Deserialize, Deserializer};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "stage", rename_all = "snake_case", deny_unknown_fields)]
+pub enum StageConfig {
+    Docker,
+    Noise {
+        #[serde(default)]
+        literals: Vec<String>,
+    },
+    Parse {
+        #[serde(deserialize_with = "from_str")]
+        format: Format,
+    },
+    Grok {
+        patterns: Vec<String>,
+        #[serde(default)]
+        definitions: Vec<String>,
+    },
+    Remap {
+        #[serde(default)]
+        rename: Vec<String>,
+        #[serde(default)]
+        add_field: Vec<String>,
+    },
+    Timestamp {
+        field: Option<String>,
+        #[serde(default)]
+        formats: Vec<String>,
+    },
+    Level {
+        field: Option<String>,
+        pattern: Option<String>,
+        #[serde(default, deserialize_with = "from_str_opt")]
+        min_level: Option<Level>,
+    },
+    Geoip {
+        field: String,
+        #[serde(default = "default_geoip_target")]
+        target: String,
+        city_db: Option<PathBuf>,
+        asn_db: Option<PathBuf>,
+    },
+    Anonymize {
+        #[serde(default)]
+        ip_fields: Vec<String>,
+        #[serde(default)]
+        hash_fields: Vec<String>,
+        #[serde(default)]
+        salt: String,
+    },
+    Metrics {
+        #[serde(default)]
+        counters: Vec<String>,
+        #[serde(default)]
+        histograms: Vec<String>,
+        #[serde(default)]
+        buckets: Vec<f64>,
+    },
+    Spike {
+        multiplier: f64,
+        /// In seconds
+        #[serde(default = "default_baseline_window")]
+        baseline_window: u64,
+        #[serde(default)]
+        alert: bool,
+    },
+    Alert {
+        rules: Vec<String>,
+    },
+    Route {
+        routing_key: Option<String>,
+        #[serde(default)]
+        headers: Vec<String>,
+    },
+    Checksum {
+        #[serde(deserialize_with = "from_str")]
+        algorithm: Checksum,
+    },
+}
+
+fn default_geoip_target() -> String {
+    "geoip".to_owned()
+}
+
+fn default_baseline_window() -> u64 {
+    300
+}
+
+fn from_str<'de, D, T>(deserializer: D) -> std::result::Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    let value = String::deserialize(deserializer)?;
+    value.parse().map_err(serde::de::Error::custom)
+}
+
+fn from_str_opt<'de, D, T>(deserializer: D) -> std::result::Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    from_str(deserializer).map(Some)
+}
+
+impl StageConfig {
+    /// The stages enabled by the command line options, in their fixed order
+    pub fn from_opts(opts: &Opt) -> Result<Vec<Self>> {
+        let mut stages = vec![];
+
+        // the other stages work on the unwrapped line
+        if opts.docker {
+            stages.push(StageConfig::Docker);
+        }
+
+        // dropping the noise first saves parsing it
+        if opts.filter_noise || !opts.noise.is_empty() {
+            stages.push(StageConfig::Noise {
+                literals: opts.noise.clone(),
+            });
+        }
+
+        if let Some(format) = opts.parse {
+            stages.push(StageConfig::Parse { format });
+        }
+
+        if !opts.grok.is_empty() {
+            stages.push(StageConfig::Grok {
+                patterns: opts.grok.clone(),
+                definitions: opts.grok_definition.clone(),
+            });
+        }
+
+        if !opts.rename.is_empty() || !opts.add_field.is_empty() {
+            stages.push(StageConfig::Remap {
+                rename: opts.rename.clone(),
+                add_field: opts.add_field.clone(),
+            });
+        }
+
+        if opts.timestamp_field.is_some() || !opts.timestamp_format.is_empty() {
+            stages.push(StageConfig::Timestamp {
+                field: opts.timestamp_field.clone(),
+                formats: opts.timestamp_format.clone(),
+            });
+        }
+
+        if opts.detect_level
+            || opts.level_field.is_some()
+            || opts.level_pattern.is_some()
+            || opts.min_level.is_some()
+        {
+            stages.push(StageConfig::Level {
+                field: opts.level_field.clone(),
+                pattern: opts.level_pattern.clone(),
+                min_level: opts.min_level,
+            });
+        }
+
+        if opts.geoip_city_db.is_some() || opts.geoip_asn_db.is_some() {
+            stages.push(StageConfig::Geoip {
+                field: opts
+                    .geoip_field
+                    .clone()
+                    .ok_or(Error::MissingOption("--geoip-field"))?,
+                target: opts.geoip_target.clone(),
+                city_db: opts.geoip_city_db.clone(),
+                asn_db: opts.geoip_asn_db.clone(),
+            });
+        }
+
+        // after the GeoIP lookup, which needs the full IP
+        if !opts.anonymize_ip.is_empty() || !opts.hash_field.is_empty() {
+            stages.push(StageConfig::Anonymize {
+                ip_fields: opts.anonymize_ip.clone(),
+                hash_fields: opts.hash_field.clone(),
+                salt: opts.hash_salt.clone().unwrap_or_default(),
+            });
+        }
+
+        if !opts.metric_counter.is_empty() || !opts.metric_histogram.is_empty() {
+            stages.push(StageConfig::Metrics {
+                counters: opts.metric_counter.clone(),
+                histograms: opts.metric_histogram.clone(),
+                buckets: opts.metric_buckets.clone(),
+            });
+        }
+
+        if let Some(multiplier) = opts.spike_multiplier {
+            stages.push(StageConfig::Spike {
+                multiplier,
+                baseline_window: opts.spike_baseline_window,
+                alert: opts.spike_alert,
+            });
+        }
+
+        if !opts.alert.is_empty() {
+            stages.push(StageConfig::Alert {
+                rules: opts.alert.clone(),
+            });
+        }
+
+        if opts.routing_key_template.is_some() || !opts.header.is_empty() {
+            stages.push(StageConfig::Route {
+                routing_key: opts.routing_key_template.clone(),
+                headers: opts.header.clone(),
+            });
+        }
+
+        // computed on the final payload, thus has to be the last stage
+        if let Some(algorithm) = opts.checksum {
+            stages.push(StageConfig::Checksum { algorithm });
+        }
+
+        Ok(stages)
+    }
+
+    /// Whether the stage emits alerts, which requires the alerter to run
+    pub fn sends_alerts(&self) -> bool {
+        matches!(
+            self,
+            StageConfig::Alert { .. } | StageConfig::Spike { alert: true, .. }
+        )
+    }
+
+    pub fn build(&self, alerts: &AlertSender) -> Result<Box<dyn Stage>> {
+        Ok(match self {
+            StageConfig::Docker => Box::new(docker::DockerStage::new()),
+            StageConfig::Noise { literals } => Box::new(noise::NoiseStage::new(literals.clone())),
+            StageConfig::Parse { format } => Box::new(parse::ParseStage::new(*format)),
+            StageConfig::Grok {
+                patterns,
+                definitions,
+            } => Box::new(grok::GrokStage::new(patterns, definitions)?),
+            StageConfig::Remap { rename, add_field } => {
+                Box::new(remap::RemapStage::new(rename, add_field)?)
+            }
+            StageConfig::Timestamp { field, formats } => {
+                Box::new(timestamp::TimestampStage::new(field.clone(), formats))
+            }
+            StageConfig::Level {
+                field,
+                pattern,
+                min_level,
+            } => Box::new(level::LevelStage::new(
+                field.clone(),
+                pattern.as_deref(),
+                *min_level,
+            )?),
+            StageConfig::Geoip {
+                field,
+                target,
+                city_db,
+                asn_db,
+            } => Box::new(geoip::GeoIpStage::new(
+                field.clone(),
+                target.clone(),
+                city_db.as_deref(),
+                asn_db.as_deref(),
+            )?),
+            StageConfig::Anonymize {
+                ip_fields,
+                hash_fields,
+                salt,
+            } => Box::new(anonymize::AnonymizeStage::new(
+                ip_fields.clone(),
+                hash_fields.clone(),
+                salt.clone(),
+            )),
+            StageConfig::Metrics {
+                counters,
+                histograms,
+                buckets,
+            } => Box::new(line_metrics::LineMetricsStage::new(
+                counters, histograms, buckets,
+            )?),
+            StageConfig::Spike {
+                multiplier,
+                baseline_window,
+                alert,
+            } => Box::new(spike::SpikeStage::new(
+                *multiplier,
+                Duration::from_secs(*baseline_window),
+                alert.then(|| alerts.clone()),
+            )),
+            StageConfig::Alert { rules } => {
+                Box::new(alert::AlertStage::new(rules, alerts.clone())?)
+            }
+            StageConfig::Route {
+                routing_key,
+                headers,
+            } => Box::new(route::RouteStage::new(routing_key.as_deref(), headers)?),
+            StageConfig::Checksum { algorithm } => {
+                Box::new(checksum::ChecksumStage::new(*algorithm))
+            }
+        })
+    }
+}
+
+impl Pipeline {
+    /// Build the pipeline out of stages definitions, they run in the given order
+    pub fn from_config(stages: &[StageConfig], alerts: &AlertSender) -> Result<Self> {
+        let stages = stages
+            .iter()
+            .map(|stage| stage.build(alerts))
+            .collect::<Result<_>>()?;
+
+        Ok(Self { stages })
+    }
+
+    /// Build the pipeline out of the command line options
+    pub fn from_opts(opts: &Opt, alerts: &AlertSender) -> Result<Self> {
+        Self::from_config(&StageConfig::from_opts(opts)?, alerts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::Event;
+
+    #[derive(Deserialize)]
+    struct Stages {
+        pipeline: Vec<StageConfig>,
+    }
+
+    fn parse(toml: &str) -> Vec<StageConfig> {
+        toml::from_str::<Stages>(toml).unwrap().pipeline
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let stages = parse(
+            r#"
+            [[pipeline]]
+            stage = "docker"
+
+            [[pipeline]]
+            stage = "noise"
+
+            [[pipeline]]
+            stage = "parse"
+            format = "JSON"
+
+            [[pipeline]]
+            stage = "checksum"
+            algorithm = "sha256"
+            "#,
+        );
+
+        assert_eq!(
+            stages,
+            vec![
+                StageConfig::Docker,
+                StageConfig::Noise { literals: vec![] },
+                StageConfig::Parse {
+                    format: Format::Json
+                },
+                StageConfig::Checksum {
+                    algorithm: Checksum::Sha256
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_stage_and_option() {
+        let unknown_stage = "[[pipeline]]\nstage = \"nope\"";
+        assert!(toml::from_str::<Stages>(unknown_stage).is_err());
+
+        let unknown_option = "[[pipeline]]\nstage = \"noise\"\nliteral = [\"a\"]";
+        assert!(toml::from_str::<Stages>(unknown_option).is_err());
+    }
+
+    #[test]
+    fn test_stages_run_in_the_declared_order() {
+        let (alerts, _rx) = crate::alert::channel();
+
+        // the static field overrides the hash, unless it gets hashed too
+        let stages = parse(
+            r#"
+            [[pipeline]]
+            stage = "parse"
+            format = "json"
+
+            [[pipeline]]
+            stage = "anonymize"
+            hash_fields = ["user"]
+
+            [[pipeline]]
+            stage = "remap"
+            add_field = ["user=anonymous"]
+            "#,
+        );
+        let mut pipeline = Pipeline::from_config(&stages, &alerts).unwrap();
+        let event = pipeline
+            .process(Event::new(0, r#"{"user":"bob"}"#.to_owned()))
+            .unwrap();
+        assert_eq!(event.fields["user"], "anonymous");
+
+        let mut pipeline = Pipeline::from_config(
+            &[stages[0].clone(), stages[2].clone(), stages[1].clone()],
+            &alerts,
+        )
+        .unwrap();
+        let event = pipeline
+            .process(Event::new(0, r#"{"user":"bob"}"#.to_owned()))
+            .unwrap();
+        assert_ne!(event.fields["user"], "anonymous");
+        assert_ne!(event.fields["user"], "bob");
+    }
+}
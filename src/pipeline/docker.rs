@@ -0,0 +1,119 @@
+//! Unwrap the lines of Docker's `json-file` logs, eg.
+//! `{"log":"started\n","stream":"stdout","time":"2021-09-07T03:37:53.250Z"}`
+use crate::pipeline::{Event, Stage};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+pub const STREAM_HEADER: &str = "x-docker-stream";
+pub const CONTAINER_HEADER: &str = "x-container-id";
+
+/// Suffix of the log files, they're named after the ID of their container
+const FILE_SUFFIX: &str = "-json.log";
+
+#[derive(Deserialize)]
+struct Entry {
+    log: String,
+    stream: Option<String>,
+    time: Option<DateTime<Utc>>,
+}
+
+/// The line becomes the `log` field, the stream and the container ID are attached as headers
+#[derive(Default)]
+pub struct DockerStage {
+    /// Docker splits the long lines, their beginning is kept until their last part, by file
+    partial: HashMap<Option<PathBuf>, String>,
+}
+
+impl DockerStage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Stage for DockerStage {
+    fn process(&mut self, mut event: Event) -> Option<Event> {
+        let entry = match serde_json::from_str::<Entry>(&event.line) {
+            Ok(entry) => entry,
+            Err(e) => {
+                debug!("pos <{}>: not a Docker log: {}", event.position, e);
+                return Some(event);
+            }
+        };
+
+        // the last part of a line ends with the line breaker
+        let log = match entry.log.strip_suffix('\n') {
+            Some(log) => log,
+            None => {
+                self.partial
+                    .entry(event.file.clone())
+                    .or_default()
+                    .push_str(&entry.log);
+                return None;
+            }
+        };
+
+        event.line = match self.partial.remove(&event.file) {
+            Some(beginning) => beginning + log,
+            None => log.to_owned(),
+        };
+        event.timestamp = event.timestamp.or(entry.time);
+
+        if let Some(stream) = entry.stream {
+            event.headers.insert(STREAM_HEADER.to_owned(), stream);
+        }
+        if let Some(container) = event
+            .file
+            .as_ref()
+            .and_then(|file| file.file_name()?.to_str()?.strip_suffix(FILE_SUFFIX))
+        {
+            event
+                .headers
+                .insert(CONTAINER_HEADER.to_owned(), container.to_owned());
+        }
+
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(line: &str) -> Event {
+        let mut event = Event::new(0, line.to_owned());
+        event.file = Some(PathBuf::from(
+            "/var/lib/docker/containers/0123abcd/0123abcd-json.log",
+        ));
+        event
+    }
+
+    #[test]
+    fn test_unwrap() {
+        let mut stage = DockerStage::new();
+        let event = stage
+            .process(event(
+                r#"{"log":"started\n","stream":"stderr","time":"2021-09-07T03:37:53.250Z"}"#,
+            ))
+            .unwrap();
+
+        assert_eq!(event.line, "started");
+        assert_eq!(event.headers[STREAM_HEADER], "stderr");
+        assert_eq!(event.headers[CONTAINER_HEADER], "0123abcd");
+        assert!(event.timestamp.is_some());
+    }
+
+    #[test]
+    fn test_split_line() {
+        let mut stage = DockerStage::new();
+        assert!(stage
+            .process(event(r#"{"log":"a long ","stream":"stdout"}"#))
+            .is_none());
+
+        let event = stage
+            .process(event(r#"{"log":"line\n","stream":"stdout"}"#))
+            .unwrap();
+        assert_eq!(event.line, "a long line");
+    }
+}
@@ -0,0 +1,110 @@
+//! Enrich an IP field with its location and ASN from MaxMind databases (GeoIP2/GeoLite2),
+//! so consumers don't each need their own GeoIP database.
+use crate::pipeline::{Event, Result, Stage};
+use maxminddb::{geoip2, Reader};
+use serde_json::{Map, Value};
+use std::net::IpAddr;
+use std::path::Path;
+
+pub struct GeoIpStage {
+    /// Field holding the IP to look up
+    field: String,
+    /// Field the location gets written into
+    target: String,
+    city: Option<Reader<Vec<u8>>>,
+    asn: Option<Reader<Vec<u8>>>,
+}
+
+impl GeoIpStage {
+    /// `city` is a City (or Country) database, `asn` an ASN database, at least one is expected
+    pub fn new(
+        field: String,
+        target: String,
+        city: Option<&Path>,
+        asn: Option<&Path>,
+    ) -> Result<Self> {
+        let city = city.map(Reader::open_readfile).transpose()?;
+        let asn = asn.map(Reader::open_readfile).transpose()?;
+
+        Ok(Self {
+            field,
+            target,
+            city,
+            asn,
+        })
+    }
+
+    fn lookup(&self, ip: IpAddr) -> Map<String, Value> {
+        let mut geoip = Map::new();
+
+        if let Some(city) = self
+            .city
+            .as_ref()
+            .and_then(|reader| reader.lookup::<geoip2::City>(ip).ok())
+        {
+            if let Some(country) = city.country {
+                insert(&mut geoip, "country_code", country.iso_code);
+                insert(&mut geoip, "country_name", english_name(&country.names));
+            }
+
+            if let Some(city) = city.city {
+                insert(&mut geoip, "city_name", english_name(&city.names));
+            }
+
+            if let Some(location) = city.location {
+                if let (Some(lat), Some(lon)) = (location.latitude, location.longitude) {
+                    let mut point = Map::new();
+                    point.insert("lat".to_owned(), lat.into());
+                    point.insert("lon".to_owned(), lon.into());
+                    geoip.insert("location".to_owned(), Value::Object(point));
+                }
+                insert(&mut geoip, "timezone", location.time_zone);
+            }
+        }
+
+        if let Some(asn) = self
+            .asn
+            .as_ref()
+            .and_then(|reader| reader.lookup::<geoip2::Asn>(ip).ok())
+        {
+            if let Some(number) = asn.autonomous_system_number {
+                geoip.insert("asn".to_owned(), number.into());
+            }
+            insert(&mut geoip, "as_org", asn.autonomous_system_organization);
+        }
+
+        geoip
+    }
+}
+
+fn english_name<'a>(
+    names: &Option<std::collections::BTreeMap<&'a str, &'a str>>,
+) -> Option<&'a str> {
+    names.as_ref()?.get("en").copied()
+}
+
+fn insert(map: &mut Map<String, Value>, key: &str, value: Option<&str>) {
+    if let Some(value) = value {
+        map.insert(key.to_owned(), Value::String(value.to_owned()));
+    }
+}
+
+impl Stage for GeoIpStage {
+    fn process(&mut self, mut event: Event) -> Option<Event> {
+        let ip = event
+            .field_str(&self.field)
+            .and_then(|ip| ip.trim().parse::<IpAddr>().ok());
+
+        if let Some(ip) = ip {
+            let geoip = self.lookup(ip);
+
+            if !geoip.is_empty() {
+                event
+                    .fields
+                    .insert(self.target.to_owned(), Value::Object(geoip));
+            }
+        }
+
+        Some(event)
+    }
+}
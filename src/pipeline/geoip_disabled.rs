@@ -0,0 +1,26 @@
+//! Stands in for the GeoIP stage once the `geoip` feature is disabled, the databases can't be
+//! read
+use crate::pipeline::{Error, Event, Result, Stage};
+use std::convert::Infallible;
+use std::path::Path;
+
+pub struct GeoIpStage {
+    never: Infallible,
+}
+
+impl GeoIpStage {
+    pub fn new(
+        _field: String,
+        _target: String,
+        _city: Option<&Path>,
+        _asn: Option<&Path>,
+    ) -> Result<Self> {
+        Err(Error::GeoIpDisabled)
+    }
+}
+
+impl Stage for GeoIpStage {
+    fn process(&mut self, _event: Event) -> Option<Event> {
+        match self.never {}
+    }
+}
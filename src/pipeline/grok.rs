@@ -0,0 +1,351 @@
+//! Grok parser, turns unstructured lines (nginx, syslog, ...) into structured fields
+//!
+//! A grok pattern is a regex where `%{NAME:field}` references a named pattern from the library
+//! and captures it into `field`. A type can be appended to convert the capture, eg.
+//! `%{NUMBER:bytes:int}` (`int` or `float`).
+use crate::pipeline::{split_definition, Error, Event, Result, Stage};
+use regex::{Regex, RegexBuilder};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Patterns can reference other patterns, this prevents cycles to loop forever
+const MAX_DEPTH: usize = 32;
+
+/// The common built-in pattern library, adapted from logstash's `grok-patterns`
+/// (the regex crate doesn't support look-arounds nor atomic groups).
+const BUILTIN_PATTERNS: &[(&str, &str)] = &[
+    ("USERNAME", r"[a-zA-Z0-9._-]+"),
+    ("USER", r"%{USERNAME}"),
+    (
+        "EMAILLOCALPART",
+        r"[a-zA-Z0-9!#$%&'*+/=?^_`{|}~-]+(?:\.[a-zA-Z0-9!#$%&'*+/=?^_`{|}~-]+)*",
+    ),
+    ("EMAILADDRESS", r"%{EMAILLOCALPART}@%{HOSTNAME}"),
+    ("INT", r"[+-]?[0-9]+"),
+    ("BASE10NUM", r"[+-]?(?:[0-9]+(?:\.[0-9]+)?|\.[0-9]+)"),
+    ("NUMBER", r"%{BASE10NUM}"),
+    ("BASE16NUM", r"[+-]?(?:0x)?[0-9A-Fa-f]+"),
+    ("POSINT", r"\b[1-9][0-9]*\b"),
+    ("NONNEGINT", r"\b[0-9]+\b"),
+    ("WORD", r"\b\w+\b"),
+    ("NOTSPACE", r"\S+"),
+    ("SPACE", r"\s*"),
+    ("DATA", r".*?"),
+    ("GREEDYDATA", r".*"),
+    ("QUOTEDSTRING", r#""(?:[^"\\]|\\.)*"|'(?:[^'\\]|\\.)*'"#),
+    ("QS", r"%{QUOTEDSTRING}"),
+    (
+        "UUID",
+        r"[A-Fa-f0-9]{8}-(?:[A-Fa-f0-9]{4}-){3}[A-Fa-f0-9]{12}",
+    ),
+    ("MAC", r"(?:[A-Fa-f0-9]{2}[:-]){5}[A-Fa-f0-9]{2}"),
+    (
+        "IPV4",
+        r"(?:25[0-5]|2[0-4][0-9]|1[0-9]{2}|[1-9]?[0-9])(?:\.(?:25[0-5]|2[0-4][0-9]|1[0-9]{2}|[1-9]?[0-9])){3}",
+    ),
+    (
+        "IPV6",
+        r"(?:[0-9A-Fa-f]{1,4}:){7}[0-9A-Fa-f]{1,4}|(?:[0-9A-Fa-f]{1,4}:){1,7}:|(?:[0-9A-Fa-f]{1,4}:){1,6}(?::[0-9A-Fa-f]{1,4}){1,6}|::(?:[0-9A-Fa-f]{1,4}:){0,6}[0-9A-Fa-f]{0,4}",
+    ),
+    ("IP", r"%{IPV6}|%{IPV4}"),
+    (
+        "HOSTNAME",
+        r"\b[0-9A-Za-z][0-9A-Za-z-]{0,62}(?:\.[0-9A-Za-z][0-9A-Za-z-]{0,62})*\.?\b",
+    ),
+    ("IPORHOST", r"%{IP}|%{HOSTNAME}"),
+    ("HOSTPORT", r"%{IPORHOST}:%{POSINT}"),
+    ("UNIXPATH", r"(?:/[^/\s]*)+"),
+    ("WINPATH", r"(?:[A-Za-z]+:|\\)(?:\\[^\\?*]*)+"),
+    ("PATH", r"%{UNIXPATH}|%{WINPATH}"),
+    ("URIPROTO", r"[A-Za-z][A-Za-z0-9+\-.]*"),
+    ("URIHOST", r"%{IPORHOST}(?::%{POSINT})?"),
+    ("URIPATH", r"(?:/[A-Za-z0-9$.+!*'(){},~:;=@#%&_\-]*)+"),
+    ("URIPARAM", r"\?[A-Za-z0-9$.+!*'|(){},~@#%&/=:;_?\-\[\]<>]*"),
+    ("URIPATHPARAM", r"%{URIPATH}(?:%{URIPARAM})?"),
+    (
+        "URI",
+        r"%{URIPROTO}://(?:%{USER}(?::[^@]*)?@)?(?:%{URIHOST})?(?:%{URIPATHPARAM})?",
+    ),
+    (
+        "MONTH",
+        r"\b(?:[Jj]an(?:uary)?|[Ff]eb(?:ruary)?|[Mm]ar(?:ch)?|[Aa]pr(?:il)?|[Mm]ay|[Jj]un(?:e)?|[Jj]ul(?:y)?|[Aa]ug(?:ust)?|[Ss]ep(?:tember)?|[Oo]ct(?:ober)?|[Nn]ov(?:ember)?|[Dd]ec(?:ember)?)\b",
+    ),
+    ("MONTHNUM", r"0?[1-9]|1[0-2]"),
+    ("MONTHDAY", r"0[1-9]|[12][0-9]|3[01]|[1-9]"),
+    (
+        "DAY",
+        r"Mon(?:day)?|Tue(?:sday)?|Wed(?:nesday)?|Thu(?:rsday)?|Fri(?:day)?|Sat(?:urday)?|Sun(?:day)?",
+    ),
+    ("YEAR", r"(?:\d\d){1,2}"),
+    ("HOUR", r"2[0123]|[01]?[0-9]"),
+    ("MINUTE", r"[0-5][0-9]"),
+    ("SECOND", r"(?:[0-5]?[0-9]|60)(?:[:.,][0-9]+)?"),
+    ("TIME", r"%{HOUR}:%{MINUTE}(?::%{SECOND})?"),
+    ("DATE_US", r"%{MONTHNUM}[/-]%{MONTHDAY}[/-]%{YEAR}"),
+    ("DATE_EU", r"%{MONTHDAY}[./-]%{MONTHNUM}[./-]%{YEAR}"),
+    ("ISO8601_TIMEZONE", r"Z|[+-]%{HOUR}(?::?%{MINUTE})"),
+    (
+        "TIMESTAMP_ISO8601",
+        r"%{YEAR}-%{MONTHNUM}-%{MONTHDAY}[T ]%{HOUR}:?%{MINUTE}(?::?%{SECOND})?%{ISO8601_TIMEZONE}?",
+    ),
+    ("HTTPDATE", r"%{MONTHDAY}/%{MONTH}/%{YEAR}:%{TIME} %{INT}"),
+    ("SYSLOGTIMESTAMP", r"%{MONTH} +%{MONTHDAY} %{TIME}"),
+    ("PROG", r"[\x21-\x5a\x5c\x5e-\x7e]+"),
+    ("SYSLOGPROG", r"%{PROG:program}(?:\[%{POSINT:pid}\])?"),
+    ("SYSLOGHOST", r"%{IPORHOST}"),
+    (
+        "SYSLOGFACILITY",
+        r"<%{NONNEGINT:facility}.%{NONNEGINT:priority}>",
+    ),
+    (
+        "SYSLOGBASE",
+        r"%{SYSLOGTIMESTAMP:timestamp} (?:%{SYSLOGFACILITY} )?%{SYSLOGHOST:logsource} %{SYSLOGPROG}:",
+    ),
+    ("SYSLOGLINE", r"%{SYSLOGBASE} %{GREEDYDATA:message}"),
+    (
+        "LOGLEVEL",
+        r"[Aa]lert|ALERT|[Tt]race|TRACE|[Dd]ebug|DEBUG|[Nn]otice|NOTICE|[Ii]nfo(?:rmation)?|INFO(?:RMATION)?|[Ww]arn(?:ing)?|WARN(?:ING)?|[Ee]rr(?:or)?|ERR(?:OR)?|[Cc]rit(?:ical)?|CRIT(?:ICAL)?|[Ff]atal|FATAL|[Ss]evere|SEVERE|EMERG(?:ENCY)?|[Ee]merg(?:ency)?",
+    ),
+    ("HTTPDUSER", r"%{EMAILADDRESS}|%{USER}"),
+    (
+        "COMMONAPACHELOG",
+        r#"%{IPORHOST:clientip} %{HTTPDUSER:ident} %{HTTPDUSER:auth} \[%{HTTPDATE:timestamp}\] "(?:%{WORD:verb} %{NOTSPACE:request}(?: HTTP/%{NUMBER:httpversion})?|%{DATA:rawrequest})" %{NUMBER:response} (?:%{NUMBER:bytes}|-)"#,
+    ),
+    (
+        "COMBINEDAPACHELOG",
+        r"%{COMMONAPACHELOG} %{QS:referrer} %{QS:agent}",
+    ),
+    ("NGINXACCESS", r"%{COMBINEDAPACHELOG}"),
+];
+
+/// How a capture has to be converted once matched
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FieldType {
+    String,
+    Int,
+    Float,
+}
+
+/// A compiled grok pattern
+#[derive(Debug)]
+pub struct Grok {
+    regex: Regex,
+    /// Regex group name -> (field name, type)
+    fields: Vec<(String, String, FieldType)>,
+}
+
+impl Grok {
+    /// Compile a pattern against the built-in library, extended with `definitions`
+    pub fn compile(pattern: &str, definitions: &HashMap<String, String>) -> Result<Self> {
+        let reference = Regex::new(r"%\{(\w+)(?::([\w@.\[\]-]+))?(?::(int|float))?\}")?;
+        let mut fields = vec![];
+        let expanded = expand(pattern, definitions, &reference, &mut fields, 0)?;
+
+        let regex = RegexBuilder::new(&expanded)
+            .size_limit(50 * 1024 * 1024)
+            .build()?;
+
+        Ok(Self { regex, fields })
+    }
+
+    /// Returns the captured fields, or `None` if the line doesn't match
+    pub fn parse(&self, line: &str) -> Option<Vec<(String, Value)>> {
+        let captures = self.regex.captures(line)?;
+
+        let fields = self
+            .fields
+            .iter()
+            .filter_map(|(group, field, kind)| {
+                let value = captures.name(group)?.as_str();
+                let value = match kind {
+                    FieldType::String => Value::String(value.to_owned()),
+                    FieldType::Int => value.parse::<i64>().ok()?.into(),
+                    FieldType::Float => value.parse::<f64>().ok()?.into(),
+                };
+
+                Some((field.to_owned(), value))
+            })
+            .collect();
+
+        Some(fields)
+    }
+}
+
+/// Replace every `%{NAME:field}` by its definition, recursively
+fn expand(
+    pattern: &str,
+    definitions: &HashMap<String, String>,
+    reference: &Regex,
+    fields: &mut Vec<(String, String, FieldType)>,
+    depth: usize,
+) -> Result<String> {
+    if depth > MAX_DEPTH {
+        return Err(Error::RecursionLimit(pattern.to_owned()));
+    }
+
+    let mut expanded = String::with_capacity(pattern.len());
+    let mut last = 0;
+
+    for captures in reference.captures_iter(pattern) {
+        let whole = captures.get(0).unwrap(); // unwrap() is safe, group 0 always exists
+        let name = &captures[1];
+
+        let definition = definitions
+            .get(name)
+            .map(String::as_str)
+            .or_else(|| lookup_builtin(name))
+            .ok_or_else(|| Error::UnknownPattern(name.to_owned()))?;
+
+        expanded.push_str(&pattern[last..whole.start()]);
+
+        match captures.get(2) {
+            Some(field) => {
+                // field names may contain chars that aren't allowed in a regex group name
+                let group = format!("g{}", fields.len());
+                let kind = match captures.get(3).map(|m| m.as_str()) {
+                    Some("int") => FieldType::Int,
+                    Some("float") => FieldType::Float,
+                    _ => FieldType::String,
+                };
+                fields.push((group.clone(), field.as_str().to_owned(), kind));
+
+                let inner = expand(definition, definitions, reference, fields, depth + 1)?;
+                expanded.push_str(&format!("(?P<{}>{})", group, inner));
+            }
+            None => {
+                let inner = expand(definition, definitions, reference, fields, depth + 1)?;
+                expanded.push_str(&format!("(?:{})", inner));
+            }
+        }
+
+        last = whole.end();
+    }
+
+    expanded.push_str(&pattern[last..]);
+
+    Ok(expanded)
+}
+
+fn lookup_builtin(name: &str) -> Option<&'static str> {
+    BUILTIN_PATTERNS
+        .iter()
+        .find(|(builtin, _)| *builtin == name)
+        .map(|(_, definition)| *definition)
+}
+
+/// Try each pattern in order, the first one that matches populates the event's fields
+pub struct GrokStage {
+    patterns: Vec<Grok>,
+}
+
+impl GrokStage {
+    /// `definitions` are custom patterns formatted as `NAME=REGEX`
+    pub fn new(patterns: &[String], definitions: &[String]) -> Result<Self> {
+        let definitions = definitions
+            .iter()
+            .map(|definition| {
+                split_definition(definition)
+                    .map(|(name, value)| (name.to_owned(), value.to_owned()))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+
+        let patterns = patterns
+            .iter()
+            .map(|pattern| Grok::compile(pattern, &definitions))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { patterns })
+    }
+}
+
+impl Stage for GrokStage {
+    fn process(&mut self, mut event: Event) -> Option<Event> {
+        match self
+            .patterns
+            .iter()
+            .find_map(|grok| grok.parse(&event.line))
+        {
+            Some(fields) => event.fields.extend(fields),
+            None => debug!("pos <{}>: no grok pattern matched", event.position),
+        }
+
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile(pattern: &str) -> Grok {
+        Grok::compile(pattern, &HashMap::new()).unwrap()
+    }
+
+    fn field<'a>(fields: &'a [(String, Value)], name: &str) -> &'a Value {
+        &fields.iter().find(|(field, _)| field == name).unwrap().1
+    }
+
+    #[test]
+    fn test_nginx_combined() {
+        let grok = compile("%{NGINXACCESS}");
+        let line = r#"93.184.216.34 - - [07/Sep/2021:03:37:53 +0000] "GET /index.html?page=2 HTTP/1.1" 200 612 "-" "curl/7.68.0""#;
+        let fields = grok.parse(line).unwrap();
+
+        assert_eq!(field(&fields, "clientip"), "93.184.216.34");
+        assert_eq!(field(&fields, "timestamp"), "07/Sep/2021:03:37:53 +0000");
+        assert_eq!(field(&fields, "verb"), "GET");
+        assert_eq!(field(&fields, "request"), "/index.html?page=2");
+        assert_eq!(field(&fields, "response"), "200");
+        assert_eq!(field(&fields, "agent"), r#""curl/7.68.0""#);
+    }
+
+    #[test]
+    fn test_syslog() {
+        let grok = compile("%{SYSLOGLINE}");
+        let line = "Sep  7 03:37:53 web-01 sshd[4242]: Accepted publickey for dizda";
+        let fields = grok.parse(line).unwrap();
+
+        assert_eq!(field(&fields, "logsource"), "web-01");
+        assert_eq!(field(&fields, "program"), "sshd");
+        assert_eq!(field(&fields, "pid"), "4242");
+        assert_eq!(field(&fields, "message"), "Accepted publickey for dizda");
+    }
+
+    #[test]
+    fn test_types_and_custom_definitions() {
+        let definitions = vec!["DURATION=[0-9]+ms".to_owned()];
+        let mut stage = GrokStage::new(
+            &[
+                "took %{NUMBER:took:float}s status=%{INT:status:int} %{DURATION:duration}"
+                    .to_owned(),
+            ],
+            &definitions,
+        )
+        .unwrap();
+
+        let event = stage
+            .process(Event::new(0, "took 0.25s status=503 12ms".to_owned()))
+            .unwrap();
+
+        assert_eq!(event.fields["took"], 0.25);
+        assert_eq!(event.fields["status"], 503);
+        assert_eq!(event.fields["duration"], "12ms");
+    }
+
+    #[test]
+    fn test_no_match_keeps_the_line() {
+        let mut stage = GrokStage::new(&["^%{IPV4:ip}$".to_owned()], &[]).unwrap();
+        let event = stage
+            .process(Event::new(0, "not an ip".to_owned()))
+            .unwrap();
+
+        assert!(event.fields.is_empty());
+        assert_eq!(event.payload(), "not an ip");
+    }
+
+    #[test]
+    fn test_unknown_pattern() {
+        let grok = Grok::compile("%{NOPE:field}", &HashMap::new());
+        assert!(matches!(grok, Err(Error::UnknownPattern(name)) if name == "NOPE"));
+    }
+}
@@ -0,0 +1,101 @@
+//! IBM QRadar Log Event Extended Format
+//!
+//! - `LEEF:1.0|Vendor|Product|Version|EventID|key=value<tab>key=value`
+//! - `LEEF:2.0|Vendor|Product|Version|EventID|Delimiter|key=value<delimiter>key=value`
+//!
+//! The line can be prefixed by a syslog header, everything before `LEEF:` is ignored.
+use serde_json::{Map, Value};
+
+const HEADERS: [&str; 5] = [
+    "leefVersion",
+    "deviceVendor",
+    "deviceProduct",
+    "deviceVersion",
+    "eventId",
+];
+
+/// Returns `None` if the line isn't LEEF
+pub fn parse(line: &str) -> Option<Map<String, Value>> {
+    let start = line.find("LEEF:")?;
+    let mut rest = &line[start + 5..];
+    let mut fields = Map::new();
+
+    for name in HEADERS {
+        let (value, remaining) = rest.split_once('|')?;
+        fields.insert(name.to_owned(), Value::String(value.to_owned()));
+        rest = remaining;
+    }
+
+    let mut delimiter = '\t';
+
+    // LEEF 2.0 defines its own delimiter
+    if !fields["leefVersion"].as_str()?.starts_with('1') {
+        let (value, remaining) = rest.split_once('|')?;
+        delimiter = parse_delimiter(value)?;
+        rest = remaining;
+    }
+
+    for attribute in rest.split(delimiter) {
+        if let Some((key, value)) = attribute.split_once('=') {
+            fields.insert(key.trim().to_owned(), Value::String(value.to_owned()));
+        }
+    }
+
+    Some(fields)
+}
+
+/// The delimiter is either a single char, or its hex value `0x5E` or `x5E`
+fn parse_delimiter(value: &str) -> Option<char> {
+    let hex = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("x"))
+        .filter(|hex| !hex.is_empty());
+
+    match hex {
+        Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+        None if value.is_empty() => Some('\t'),
+        None => value.chars().next(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_v1() {
+        let line = "Jan 18 11:07:53 host LEEF:1.0|Microsoft|MSExchange|4.0 SP1|15345|src=192.0.2.0\tdst=172.50.123.1\tsev=5\tcat=anomaly\tmsg=there are spaces";
+        let fields = parse(line).unwrap();
+
+        assert_eq!(fields["leefVersion"], "1.0");
+        assert_eq!(fields["deviceVendor"], "Microsoft");
+        assert_eq!(fields["deviceProduct"], "MSExchange");
+        assert_eq!(fields["deviceVersion"], "4.0 SP1");
+        assert_eq!(fields["eventId"], "15345");
+        assert_eq!(fields["src"], "192.0.2.0");
+        assert_eq!(fields["sev"], "5");
+        assert_eq!(fields["msg"], "there are spaces");
+    }
+
+    #[test]
+    fn test_parse_v2_with_hex_delimiter() {
+        let line = "LEEF:2.0|Lancope|StealthWatch|1.0|41|^|src=10.0.1.8^dst=10.0.0.5^sev=5";
+        let fields = parse(line).unwrap();
+
+        assert_eq!(fields["eventId"], "41");
+        assert_eq!(fields["src"], "10.0.1.8");
+        assert_eq!(fields["dst"], "10.0.0.5");
+
+        let line = "LEEF:2.0|Lancope|StealthWatch|1.0|41|0x7C|src=10.0.1.8|dst=10.0.0.5";
+        let fields = parse(line).unwrap();
+
+        assert_eq!(fields["src"], "10.0.1.8");
+        assert_eq!(fields["dst"], "10.0.0.5");
+    }
+
+    #[test]
+    fn test_not_leef() {
+        assert!(parse("a plain line").is_none());
+        assert!(parse("LEEF:1.0|truncated").is_none());
+    }
+}
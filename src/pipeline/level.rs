@@ -0,0 +1,210 @@
+//! Detect the severity of the line, normalize it, and optionally drop the least severe ones
+use crate::metrics;
+use crate::pipeline::{Error, Event, Result, Stage};
+use regex::Regex;
+use serde_json::Value;
+use std::fmt;
+use std::str::FromStr;
+
+/// Header carrying the normalized level
+pub const LEVEL_HEADER: &str = "x-level";
+/// Header carrying the matching syslog severity, from 0 (emergency) to 7 (debug)
+pub const SYSLOG_SEVERITY_HEADER: &str = "x-syslog-severity";
+
+const SUPPRESSED_METRIC: &str = "log_bouncer_suppressed_lines_total";
+
+/// Used when the level is read from the raw line
+const DEFAULT_PATTERN: &str = r"(?i)\b(trace|debug|info|notice|warn(?:ing)?|err(?:or)?|crit(?:ical)?|fatal|alert|emerg(?:ency)?|panic)\b";
+
+/// Normalized levels, from the least to the most severe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Notice,
+    Warn,
+    Error,
+    Critical,
+    Alert,
+    Emergency,
+}
+
+impl FromStr for Level {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let level = match s.trim().to_lowercase().as_str() {
+            "trace" | "trc" | "finest" | "finer" | "7" => Level::Trace,
+            "debug" | "dbg" | "fine" | "verbose" => Level::Debug,
+            "info" | "inf" | "information" | "informational" | "6" => Level::Info,
+            "notice" | "5" => Level::Notice,
+            "warn" | "wrn" | "warning" | "4" => Level::Warn,
+            "error" | "err" | "3" => Level::Error,
+            "critical" | "crit" | "fatal" | "severe" | "2" => Level::Critical,
+            "alert" | "1" => Level::Alert,
+            "emergency" | "emerg" | "panic" | "0" => Level::Emergency,
+            _ => return Err(Error::UnknownLevel(s.to_owned())),
+        };
+
+        Ok(level)
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Level::Trace => "trace",
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Notice => "notice",
+            Level::Warn => "warn",
+            Level::Error => "error",
+            Level::Critical => "critical",
+            Level::Alert => "alert",
+            Level::Emergency => "emergency",
+        })
+    }
+}
+
+impl Level {
+    /// Trace and debug share the same syslog severity
+    pub fn syslog_severity(&self) -> u8 {
+        match self {
+            Level::Trace | Level::Debug => 7,
+            Level::Info => 6,
+            Level::Notice => 5,
+            Level::Warn => 4,
+            Level::Error => 3,
+            Level::Critical => 2,
+            Level::Alert => 1,
+            Level::Emergency => 0,
+        }
+    }
+}
+
+pub struct LevelStage {
+    /// Read the level from this field rather than from the raw line
+    field: Option<String>,
+    /// The first capture group, or the whole match, is the level
+    pattern: Regex,
+    /// Events below this level are dropped, those without a level are kept
+    min_level: Option<Level>,
+}
+
+impl LevelStage {
+    pub fn new(
+        field: Option<String>,
+        pattern: Option<&str>,
+        min_level: Option<Level>,
+    ) -> Result<Self> {
+        if min_level.is_some() {
+            metrics::registry().describe(SUPPRESSED_METRIC, "Lines dropped by the filters", None);
+        }
+
+        Ok(Self {
+            field,
+            pattern: Regex::new(pattern.unwrap_or(DEFAULT_PATTERN))?,
+            min_level,
+        })
+    }
+
+    fn detect(&self, event: &Event) -> Option<Level> {
+        if let Some(field) = &self.field {
+            if let Some(level) = event.field_str(field) {
+                return level.parse().ok();
+            }
+        }
+
+        let captures = self.pattern.captures(&event.line)?;
+        let matched = captures.get(1).or_else(|| captures.get(0))?;
+
+        matched.as_str().parse().ok()
+    }
+}
+
+impl Stage for LevelStage {
+    fn process(&mut self, mut event: Event) -> Option<Event> {
+        let level = match self.detect(&event) {
+            Some(level) => level,
+            None => return Some(event),
+        };
+
+        if self.min_level.is_some_and(|min_level| level < min_level) {
+            trace!("pos <{}>: line suppressed ({})", event.position, level);
+            metrics::registry().increment(SUPPRESSED_METRIC, &[("reason", "level")], 1);
+
+            return None;
+        }
+
+        // the raw value is normalized in structured events
+        if let Some(field) = &self.field {
+            if event.fields.contains_key(field) {
+                event
+                    .fields
+                    .insert(field.to_owned(), Value::String(level.to_string()));
+            }
+        }
+
+        event
+            .headers
+            .insert(LEVEL_HEADER.to_owned(), level.to_string());
+        event.headers.insert(
+            SYSLOG_SEVERITY_HEADER.to_owned(),
+            level.syslog_severity().to_string(),
+        );
+        event.level = Some(level);
+
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!("WARNING".parse::<Level>().unwrap(), Level::Warn);
+        assert_eq!("Err".parse::<Level>().unwrap(), Level::Error);
+        assert_eq!("fatal".parse::<Level>().unwrap(), Level::Critical);
+        assert_eq!("6".parse::<Level>().unwrap(), Level::Info);
+        assert!("verbose-ish".parse::<Level>().is_err());
+        assert!(Level::Debug < Level::Warn);
+    }
+
+    #[test]
+    fn test_from_the_line() {
+        let mut stage = LevelStage::new(None, None, None).unwrap();
+        let event = Event::new(0, "2021-09-07 03:37:53 [WARNING] disk is full".to_owned());
+        let event = stage.process(event).unwrap();
+
+        assert_eq!(event.level, Some(Level::Warn));
+        assert_eq!(event.headers[LEVEL_HEADER], "warn");
+        assert_eq!(event.headers[SYSLOG_SEVERITY_HEADER], "4");
+        assert!(event.fields.is_empty());
+    }
+
+    #[test]
+    fn test_from_a_field() {
+        let mut stage = LevelStage::new(Some("lvl".to_owned()), None, None).unwrap();
+        let mut event = Event::new(0, "{}".to_owned());
+        event.fields.insert("lvl".to_owned(), "ERR".into());
+        let event = stage.process(event).unwrap();
+
+        assert_eq!(event.fields["lvl"], "error");
+        assert_eq!(event.level, Some(Level::Error));
+    }
+
+    #[test]
+    fn test_min_level() {
+        let mut stage = LevelStage::new(None, None, Some(Level::Warn)).unwrap();
+        let mut process = |line: &str| stage.process(Event::new(0, line.to_owned())).is_some();
+
+        assert!(!process("DEBUG starting"));
+        assert!(!process("INFO started"));
+        assert!(process("WARN slow request"));
+        assert!(process("FATAL out of memory"));
+        assert!(process("no level at all"));
+    }
+}
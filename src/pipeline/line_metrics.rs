@@ -0,0 +1,108 @@
+//! Derive metrics from the lines, turning log-bouncer into a lightweight log-to-metrics bridge
+use crate::metrics;
+use crate::pipeline::{split_definition, Event, Result, Stage};
+use regex::Regex;
+use serde_json::Value;
+
+pub struct LineMetricsStage {
+    /// Count the lines matching the regex, (metric, regex)
+    counters: Vec<(String, Regex)>,
+    /// Observe the value of a numeric field, (metric, field)
+    histograms: Vec<(String, String)>,
+}
+
+impl LineMetricsStage {
+    /// `counters` are formatted as `METRIC=REGEX`, `histograms` as `METRIC=FIELD`
+    pub fn new(counters: &[String], histograms: &[String], buckets: &[f64]) -> Result<Self> {
+        let counters = counters
+            .iter()
+            .map(|definition| {
+                let (name, regex) = split_definition(definition)?;
+                Ok((name.to_owned(), Regex::new(regex)?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let histograms = histograms
+            .iter()
+            .map(|definition| {
+                split_definition(definition)
+                    .map(|(name, field)| (name.to_owned(), field.to_owned()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let registry = metrics::registry();
+
+        for (name, regex) in &counters {
+            registry.describe(name, &format!("Lines matching `{}`", regex), None);
+        }
+
+        for (name, field) in &histograms {
+            let buckets = if buckets.is_empty() {
+                None
+            } else {
+                Some(buckets)
+            };
+            registry.describe(name, &format!("Values of the field `{}`", field), buckets);
+        }
+
+        Ok(Self {
+            counters,
+            histograms,
+        })
+    }
+}
+
+impl Stage for LineMetricsStage {
+    fn process(&mut self, event: Event) -> Option<Event> {
+        let registry = metrics::registry();
+
+        for (name, regex) in &self.counters {
+            if regex.is_match(&event.line) {
+                registry.increment(name, &[], 1);
+            }
+        }
+
+        for (name, field) in &self.histograms {
+            let value = match event.fields.get(field) {
+                Some(Value::Number(number)) => number.as_f64(),
+                Some(Value::String(value)) => value.trim().parse::<f64>().ok(),
+                _ => None,
+            };
+
+            if let Some(value) = value {
+                registry.observe(name, &[], value);
+            }
+        }
+
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_metrics() {
+        let mut stage = LineMetricsStage::new(
+            &["test_http_5xx_total=status=5\\d\\d".to_owned()],
+            &["test_duration_ms=duration_ms".to_owned()],
+            &[10.0, 100.0],
+        )
+        .unwrap();
+
+        for (line, duration) in [("status=503", 50), ("status=200", 5), ("status=500", 500)] {
+            let mut event = Event::new(0, line.to_owned());
+            event
+                .fields
+                .insert("duration_ms".to_owned(), duration.into());
+            stage.process(event).unwrap();
+        }
+
+        let rendered = metrics::registry().render();
+        assert!(rendered.contains("test_http_5xx_total 2\n"));
+        assert!(rendered.contains("test_duration_ms_bucket{le=\"10\"} 1\n"));
+        assert!(rendered.contains("test_duration_ms_bucket{le=\"100\"} 2\n"));
+        assert!(rendered.contains("test_duration_ms_count 3\n"));
+    }
+}
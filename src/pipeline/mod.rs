@@ -0,0 +1,208 @@
+pub mod alert;
+pub mod anonymize;
+pub mod cef;
+pub mod checksum;
+pub mod config;
+pub mod docker;
+#[cfg(feature = "geoip")]
+pub mod geoip;
+#[cfg(not(feature = "geoip"))]
+#[path = "geoip_disabled.rs"]
+pub mod geoip;
+pub mod grok;
+pub mod leef;
+pub mod level;
+pub mod line_metrics;
+pub mod noise;
+pub mod parse;
+pub mod remap;
+pub mod route;
+pub mod spike;
+pub mod template;
+pub mod timestamp;
+
+use crate::output::Message;
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("unknown grok pattern `{0}`")]
+    UnknownPattern(String),
+    #[error("grok pattern `{0}` is too deeply nested")]
+    RecursionLimit(String),
+    #[error("invalid definition `{0}`, expected `NAME=VALUE`")]
+    InvalidDefinition(String),
+    #[error("unknown format `{0}`")]
+    UnknownFormat(String),
+    #[error("unknown level `{0}`")]
+    UnknownLevel(String),
+    #[error("invalid template `{0}`")]
+    InvalidTemplate(String),
+    #[error("missing option: {0}")]
+    MissingOption(&'static str),
+    #[cfg(feature = "geoip")]
+    #[error("geoip: {0}")]
+    GeoIp(#[from] maxminddb::MaxMindDBError),
+    #[cfg(not(feature = "geoip"))]
+    #[error("the MaxMind databases can only be read with the `geoip` feature")]
+    GeoIpDisabled,
+    #[error("regex: {0}")]
+    Regex(#[from] regex::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Header carrying the number of the line in the file
+pub const LINE_NUMBER_HEADER: &str = "x-line-number";
+/// Header set when the line has been read without its line breaker, see `--partial-line-timeout-ms`
+pub const PARTIAL_LINE_HEADER: &str = "x-partial-line";
+
+/// A line going through the pipeline, each stage can enrich or drop it
+#[derive(Debug, Clone)]
+pub struct Event {
+    /// Position of the end of the line in the file
+    pub position: u64,
+    /// Number of the line in the file, starting at 1, unknown if 0
+    pub line_number: u64,
+    /// The raw line, as read from the file
+    pub line: String,
+    /// Structured fields extracted by the parsing stages
+    pub fields: Map<String, Value>,
+    /// When the event occurred, if it has been extracted
+    pub timestamp: Option<DateTime<Utc>>,
+    /// Normalized severity, if it has been detected
+    pub level: Option<level::Level>,
+    /// Overrides the default routing key of the output
+    pub routing_key: Option<String>,
+    /// Headers attached to the published message
+    pub headers: BTreeMap<String, String>,
+    /// The file the line has been read from
+    pub file: Option<PathBuf>,
+    /// The line as it's been read when it isn't valid UTF-8, see `--invalid-utf8 bytes`
+    pub raw: Option<Vec<u8>>,
+}
+
+impl Event {
+    pub fn new(position: u64, line: String) -> Self {
+        Self {
+            position,
+            line_number: 0,
+            line,
+            fields: Map::new(),
+            timestamp: None,
+            level: None,
+            routing_key: None,
+            headers: BTreeMap::new(),
+            file: None,
+            raw: None,
+        }
+    }
+
+    /// Get a field as a string, numbers and booleans are formatted
+    pub fn field_str(&self, name: &str) -> Option<String> {
+        match self.fields.get(name)? {
+            Value::String(value) => Some(value.to_owned()),
+            Value::Null | Value::Array(_) | Value::Object(_) => None,
+            value => Some(value.to_string()),
+        }
+    }
+
+    /// Get what the stages found out about the event, but isn't part of its fields, or where it
+    /// comes from: the `filename` of its file and the name of its directory, `dirname`
+    pub fn metadata(&self, name: &str) -> Option<String> {
+        let file = self.file.as_deref();
+        let name_of = |path: &Path| Some(path.file_name()?.to_string_lossy().into_owned());
+
+        match name {
+            "level" => self.level.map(|level| level.to_string()),
+            "filename" => name_of(file?),
+            "dirname" => name_of(file?.parent()?),
+            _ => None,
+        }
+    }
+
+    /// The payload to publish
+    ///
+    /// Lines that haven't been parsed are sent untouched, otherwise the fields are encoded in
+    /// JSON along with the original line in `message` (unless a stage already set it).
+    pub fn payload(&self) -> String {
+        if self.fields.is_empty() {
+            return self.line.clone();
+        }
+
+        let mut fields = self.fields.clone();
+        fields
+            .entry("message")
+            .or_insert_with(|| Value::String(self.line.clone()));
+
+        Value::Object(fields).to_string()
+    }
+
+    /// Turn the event into what the outputs publish
+    pub fn into_message(self) -> Message {
+        let payload = self.payload();
+        let mut headers = self.headers;
+
+        if self.line_number > 0 {
+            headers.insert(LINE_NUMBER_HEADER.to_owned(), self.line_number.to_string());
+        }
+
+        // the raw line is only published if no stage has changed it
+        let raw = self
+            .raw
+            .filter(|raw| String::from_utf8_lossy(raw) == payload);
+
+        Message {
+            position: self.position,
+            payload,
+            raw,
+            routing_key: self.routing_key,
+            headers,
+        }
+    }
+}
+
+/// A processing step applied to every line before it gets published
+pub trait Stage: Send {
+    /// Returns `None` if the event has to be dropped
+    fn process(&mut self, event: Event) -> Option<Event>;
+}
+
+/// Ordered list of stages
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Box<dyn Stage>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push<S: Stage + 'static>(&mut self, stage: S) {
+        self.stages.push(Box::new(stage));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    /// Run the event through every stage, stops as soon as one of them drops it
+    pub fn process(&mut self, event: Event) -> Option<Event> {
+        self.stages
+            .iter_mut()
+            .try_fold(event, |event, stage| stage.process(event))
+    }
+}
+
+/// Split a `NAME=VALUE` definition
+pub(crate) fn split_definition(definition: &str) -> Result<(&str, &str)> {
+    definition
+        .split_once('=')
+        .map(|(name, value)| (name.trim(), value))
+        .filter(|(name, _)| !name.is_empty())
+        .ok_or_else(|| Error::InvalidDefinition(definition.to_owned()))
+}
@@ -0,0 +1,57 @@
+use crate::metrics;
+use crate::pipeline::{Event, Stage};
+
+const SUPPRESSED_METRIC: &str = "log_bouncer_suppressed_lines_total";
+
+/// Drop empty lines, whitespace-only lines, and lines containing a known noise literal
+/// (eg. ELB health checks)
+pub struct NoiseStage {
+    literals: Vec<String>,
+}
+
+impl NoiseStage {
+    pub fn new(literals: Vec<String>) -> Self {
+        metrics::registry().describe(SUPPRESSED_METRIC, "Lines dropped by the filters", None);
+
+        Self { literals }
+    }
+}
+
+impl Stage for NoiseStage {
+    fn process(&mut self, event: Event) -> Option<Event> {
+        let reason = if event.line.trim().is_empty() {
+            "empty"
+        } else if self
+            .literals
+            .iter()
+            .any(|literal| event.line.contains(literal.as_str()))
+        {
+            "noise"
+        } else {
+            return Some(event);
+        };
+
+        trace!("pos <{}>: line suppressed ({})", event.position, reason);
+        metrics::registry().increment(SUPPRESSED_METRIC, &[("reason", reason)], 1);
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_noise() {
+        let mut stage = NoiseStage::new(vec!["ELB-HealthChecker/2.0".to_owned()]);
+        let mut process = |line: &str| stage.process(Event::new(0, line.to_owned())).is_some();
+
+        assert!(!process(""));
+        assert!(!process(" \t "));
+        assert!(!process(
+            r#"10.0.0.1 - - "GET /health HTTP/1.1" 200 2 "-" "ELB-HealthChecker/2.0""#
+        ));
+        assert!(process("GET /index.html"));
+    }
+}
@@ -0,0 +1,54 @@
+use crate::pipeline::{cef, leef, Error, Event, Stage};
+use std::str::FromStr;
+
+/// Built-in line formats
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    Cef,
+    Leef,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(Format::Json),
+            "cef" => Ok(Format::Cef),
+            "leef" => Ok(Format::Leef),
+            _ => Err(Error::UnknownFormat(s.to_owned())),
+        }
+    }
+}
+
+/// Parse the line with a built-in format and populate the event's fields
+pub struct ParseStage {
+    format: Format,
+}
+
+impl ParseStage {
+    pub fn new(format: Format) -> Self {
+        Self { format }
+    }
+}
+
+impl Stage for ParseStage {
+    fn process(&mut self, mut event: Event) -> Option<Event> {
+        let fields = match self.format {
+            Format::Json => serde_json::from_str(&event.line).ok(),
+            Format::Cef => cef::parse(&event.line),
+            Format::Leef => leef::parse(&event.line),
+        };
+
+        match fields {
+            Some(fields) => event.fields.extend(fields),
+            None => debug!(
+                "pos <{}>: line can't be parsed as {:?}",
+                event.position, self.format
+            ),
+        }
+
+        Some(event)
+    }
+}
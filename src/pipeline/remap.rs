@@ -0,0 +1,71 @@
+use crate::pipeline::{split_definition, Event, Result, Stage};
+use serde_json::Value;
+
+/// Rename fields and add static ones, so logs of different apps converge to the same schema
+pub struct RemapStage {
+    /// (from, to)
+    renames: Vec<(String, String)>,
+    /// (field, value)
+    static_fields: Vec<(String, String)>,
+}
+
+impl RemapStage {
+    /// `renames` are formatted as `FROM=TO`, `static_fields` as `FIELD=VALUE`
+    pub fn new(renames: &[String], static_fields: &[String]) -> Result<Self> {
+        Ok(Self {
+            renames: split_all(renames)?,
+            static_fields: split_all(static_fields)?,
+        })
+    }
+}
+
+fn split_all(definitions: &[String]) -> Result<Vec<(String, String)>> {
+    definitions
+        .iter()
+        .map(|definition| {
+            split_definition(definition).map(|(name, value)| (name.to_owned(), value.to_owned()))
+        })
+        .collect()
+}
+
+impl Stage for RemapStage {
+    fn process(&mut self, mut event: Event) -> Option<Event> {
+        for (from, to) in &self.renames {
+            if let Some(value) = event.fields.remove(from) {
+                event.fields.insert(to.to_owned(), value);
+            }
+        }
+
+        for (field, value) in &self.static_fields {
+            event
+                .fields
+                .insert(field.to_owned(), Value::String(value.to_owned()));
+        }
+
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remap() {
+        let mut stage = RemapStage::new(
+            &["msg=message".to_owned(), "lvl=level".to_owned()],
+            &["env=prod".to_owned()],
+        )
+        .unwrap();
+
+        let mut event = Event::new(0, "{}".to_owned());
+        event.fields.insert("msg".to_owned(), "hello".into());
+        event.fields.insert("lvl".to_owned(), "info".into());
+        let event = stage.process(event).unwrap();
+
+        assert!(!event.fields.contains_key("msg"));
+        assert_eq!(event.fields["message"], "hello");
+        assert_eq!(event.fields["level"], "info");
+        assert_eq!(event.fields["env"], "prod");
+    }
+}
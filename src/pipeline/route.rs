@@ -0,0 +1,66 @@
+use crate::pipeline::template::Template;
+use crate::pipeline::{split_definition, Event, Result, Stage};
+
+/// Derive the routing key and the headers of the message from the parsed fields
+pub struct RouteStage {
+    routing_key: Option<Template>,
+    /// (header, field)
+    headers: Vec<(String, String)>,
+}
+
+impl RouteStage {
+    /// `headers` are formatted as `HEADER=FIELD`
+    pub fn new(routing_key: Option<&str>, headers: &[String]) -> Result<Self> {
+        let routing_key = routing_key.map(Template::parse).transpose()?;
+        let headers = headers
+            .iter()
+            .map(|header| {
+                split_definition(header).map(|(name, field)| (name.to_owned(), field.to_owned()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            routing_key,
+            headers,
+        })
+    }
+}
+
+impl Stage for RouteStage {
+    fn process(&mut self, mut event: Event) -> Option<Event> {
+        if let Some(template) = &self.routing_key {
+            // when a field is missing, the output's default routing key is used
+            event.routing_key =
+                template.render(|name| event.field_str(name).or_else(|| event.metadata(name)));
+        }
+
+        for (header, field) in &self.headers {
+            if let Some(value) = event.field_str(field) {
+                event.headers.insert(header.to_owned(), value);
+            }
+        }
+
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_routing_key_by_file() {
+        let mut stage = RouteStage::new(Some("logs.{dirname}.{filename}"), &[]).unwrap();
+
+        let mut event = Event::new(0, "started".to_owned());
+        event.file = Some(PathBuf::from("/var/log/nginx/access.log"));
+        let event = stage.process(event).unwrap();
+        assert_eq!(event.routing_key.as_deref(), Some("logs.nginx.access.log"));
+
+        // the standard input has no directory, the default routing key is used
+        let mut event = Event::new(0, "started".to_owned());
+        event.file = Some(PathBuf::from("-"));
+        assert_eq!(stage.process(event).unwrap().routing_key, None);
+    }
+}
@@ -0,0 +1,149 @@
+//! Detect sudden volume spikes, which usually means an app is in a crash loop
+use crate::alert::{Alert, AlertSender};
+use crate::metrics;
+use crate::pipeline::{Event, Stage};
+use std::time::{Duration, Instant};
+
+const RATE_METRIC: &str = "log_bouncer_lines_per_second";
+const SPIKES_METRIC: &str = "log_bouncer_volume_spikes_total";
+
+/// The baseline is never considered lower than that, so a quiet file doesn't spike on 2 lines
+const MIN_BASELINE: f64 = 1.0;
+
+pub struct SpikeStage {
+    /// A spike is detected when the rate exceeds `multiplier` times the baseline
+    multiplier: f64,
+    /// Smoothing factor of the rolling baseline, derived from its window in seconds
+    alpha: f64,
+    /// Rolling average of lines/sec
+    baseline: Option<f64>,
+    /// Start of the second being counted
+    second: Option<Instant>,
+    count: u64,
+    /// Currently spiking, only the beginning of a spike is reported
+    spiking: bool,
+    alerts: Option<AlertSender>,
+}
+
+impl SpikeStage {
+    pub fn new(multiplier: f64, baseline_window: Duration, alerts: Option<AlertSender>) -> Self {
+        let registry = metrics::registry();
+        registry.describe(RATE_METRIC, "Lines read during the last second", None);
+        registry.describe(SPIKES_METRIC, "Volume spikes detected", None);
+
+        Self {
+            multiplier,
+            alpha: 2.0 / (baseline_window.as_secs().max(1) as f64 + 1.0),
+            baseline: None,
+            second: None,
+            count: 0,
+            spiking: false,
+            alerts,
+        }
+    }
+
+    /// Count a line, returns the rate that triggered a spike, if any
+    fn observe(&mut self, now: Instant) -> Option<f64> {
+        let second = *self.second.get_or_insert(now);
+        let elapsed = now.duration_since(second).as_secs();
+
+        if elapsed == 0 {
+            self.count += 1;
+            return None;
+        }
+
+        // the second is over, and the ones without any line count as 0
+        let rate = self.count as f64;
+        let spike = self.close_second(rate);
+        for _ in 1..elapsed.min(3600) {
+            self.close_second(0.0);
+        }
+
+        self.second = Some(second + Duration::from_secs(elapsed));
+        self.count = 1;
+
+        spike.then_some(rate)
+    }
+
+    /// Returns true if it's the beginning of a spike
+    fn close_second(&mut self, rate: f64) -> bool {
+        metrics::registry().set_gauge(RATE_METRIC, &[], rate);
+
+        let baseline = match self.baseline {
+            Some(baseline) => baseline,
+            None => {
+                // the first second gives the initial baseline
+                self.baseline = Some(rate);
+                return false;
+            }
+        };
+
+        let threshold = baseline.max(MIN_BASELINE) * self.multiplier;
+        let started = rate > threshold && !self.spiking;
+        self.spiking = rate > threshold;
+
+        // a spike shouldn't become the norm too quickly, it's kept out of the baseline
+        if !self.spiking {
+            self.baseline = Some(baseline + self.alpha * (rate - baseline));
+        }
+
+        started
+    }
+}
+
+impl Stage for SpikeStage {
+    fn process(&mut self, event: Event) -> Option<Event> {
+        if let Some(rate) = self.observe(Instant::now()) {
+            let baseline = self.baseline.unwrap_or_default();
+            let message = format!(
+                "volume spike: {} lines/sec while the baseline is {:.1} lines/sec",
+                rate, baseline
+            );
+            warn!("{}", message);
+            metrics::registry().increment(SPIKES_METRIC, &[], 1);
+
+            if let Some(alerts) = &self.alerts {
+                alerts.send(Alert {
+                    name: "volume_spike".to_owned(),
+                    message,
+                    line: None,
+                    position: Some(event.position),
+                });
+            }
+        }
+
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spike() {
+        let mut stage = SpikeStage::new(10.0, Duration::from_secs(60), None);
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+
+        // 5 lines/sec during 10 seconds
+        for second in 0..10 {
+            for _ in 0..5 {
+                assert!(stage.observe(at(second)).is_none());
+            }
+        }
+
+        // then 100 lines in a second
+        for _ in 0..100 {
+            assert!(stage.observe(at(10)).is_none());
+        }
+
+        // the spike is detected once the second is over
+        assert_eq!(stage.observe(at(11)), Some(100.0));
+        // and reported once
+        for _ in 0..100 {
+            stage.observe(at(11));
+        }
+        assert!(stage.observe(at(12)).is_none());
+    }
+}
@@ -0,0 +1,127 @@
+//! Small templating used for routing keys, eg. `siem.{deviceVendor}.{deviceProduct}`
+use crate::pipeline::{Error, Result};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Placeholder(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Self> {
+        let invalid = || Error::InvalidTemplate(template.to_owned());
+        let mut parts = vec![];
+        let mut rest = template;
+
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..].find('}').ok_or_else(invalid)? + start;
+            let name = rest[start + 1..end].trim();
+
+            if name.is_empty() || name.contains('{') {
+                return Err(invalid());
+            }
+
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_owned()));
+            }
+            parts.push(Part::Placeholder(name.to_owned()));
+            rest = &rest[end + 1..];
+        }
+
+        if rest.contains('}') {
+            return Err(invalid());
+        }
+
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_owned()));
+        }
+
+        Ok(Self { parts })
+    }
+
+    /// Render the template, returns `None` if one of the placeholders can't be resolved
+    pub fn render<F>(&self, lookup: F) -> Option<String>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut rendered = String::new();
+
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => rendered.push_str(literal),
+                Part::Placeholder(name) => rendered.push_str(&lookup(name)?),
+            }
+        }
+
+        Some(rendered)
+    }
+
+    pub fn placeholders(&self) -> impl Iterator<Item = &str> {
+        self.parts.iter().filter_map(|part| match part {
+            Part::Placeholder(name) => Some(name.as_str()),
+            Part::Literal(_) => None,
+        })
+    }
+
+    /// Regex matching the rendered template, `placeholder` gives the regex of each placeholder
+    pub fn to_regex<F>(&self, placeholder: F) -> String
+    where
+        F: Fn(&str) -> String,
+    {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(literal) => regex::escape(literal),
+                Part::Placeholder(name) => placeholder(name),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let template = Template::parse("siem.{deviceVendor}.{ severity }").unwrap();
+        let lookup = |name: &str| match name {
+            "deviceVendor" => Some("fortinet".to_owned()),
+            "severity" => Some("5".to_owned()),
+            _ => None,
+        };
+
+        assert_eq!(template.render(lookup).unwrap(), "siem.fortinet.5");
+        assert!(Template::parse("{missing}")
+            .unwrap()
+            .render(lookup)
+            .is_none());
+    }
+
+    #[test]
+    fn test_to_regex() {
+        let template = Template::parse("{name}.{date}.log").unwrap();
+        let regex = template.to_regex(|name| match name {
+            "name" => regex::escape("app.log"),
+            _ => ".+".to_owned(),
+        });
+
+        assert_eq!(regex, r"app\.log\..+\.log");
+        assert_eq!(
+            template.placeholders().collect::<Vec<_>>(),
+            ["name", "date"]
+        );
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(Template::parse("siem.{vendor").is_err());
+        assert!(Template::parse("siem.vendor}").is_err());
+        assert!(Template::parse("siem.{}").is_err());
+    }
+}
@@ -0,0 +1,220 @@
+//! Extract the event's timestamp, either from a parsed field or from the beginning of the line,
+//! and normalize it to RFC3339/UTC in the `@timestamp` field.
+use crate::metrics;
+use crate::pipeline::{Event, Stage};
+use chrono::{DateTime, Datelike, NaiveDateTime, SecondsFormat, TimeZone, Utc};
+use serde_json::Value;
+use std::str::FromStr;
+
+/// Field the normalized timestamp is written into
+pub const TIMESTAMP_FIELD: &str = "@timestamp";
+
+const LAG_METRIC: &str = "log_bouncer_ingestion_lag_seconds";
+
+/// Formats tried when none has been configured
+const DEFAULT_FORMATS: &[&str] = &[
+    "rfc3339",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%d/%b/%Y:%H:%M:%S %z",
+    "%b %e %H:%M:%S",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TimeFormat {
+    Rfc3339,
+    Rfc2822,
+    /// Seconds since epoch, can have decimals
+    Unix,
+    /// Milliseconds since epoch
+    UnixMs,
+    /// strptime-like format, timestamps without offset are considered UTC
+    Strftime(String),
+}
+
+impl FromStr for TimeFormat {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "rfc3339" | "iso8601" => TimeFormat::Rfc3339,
+            "rfc2822" => TimeFormat::Rfc2822,
+            "unix" => TimeFormat::Unix,
+            "unix_ms" => TimeFormat::UnixMs,
+            format => TimeFormat::Strftime(format.to_owned()),
+        })
+    }
+}
+
+impl TimeFormat {
+    /// Parse the whole value
+    pub fn parse(&self, value: &str) -> Option<DateTime<Utc>> {
+        let value = value.trim();
+
+        match self {
+            TimeFormat::Rfc3339 => DateTime::parse_from_rfc3339(value)
+                .ok()
+                .map(|date| date.with_timezone(&Utc)),
+            TimeFormat::Rfc2822 => DateTime::parse_from_rfc2822(value)
+                .ok()
+                .map(|date| date.with_timezone(&Utc)),
+            TimeFormat::Unix => {
+                let secs = value.parse::<f64>().ok()?;
+                Utc.timestamp_millis_opt((secs * 1000.0) as i64).single()
+            }
+            TimeFormat::UnixMs => Utc.timestamp_millis_opt(value.parse().ok()?).single(),
+            TimeFormat::Strftime(format) => parse_strftime(value, format, false),
+        }
+    }
+
+    /// Parse the beginning of a line, the rest of the line is ignored
+    pub fn parse_prefix(&self, line: &str) -> Option<DateTime<Utc>> {
+        match self {
+            TimeFormat::Strftime(format) => parse_strftime(line, format, true),
+            // those formats don't contain any space
+            TimeFormat::Rfc3339 | TimeFormat::Unix | TimeFormat::UnixMs => {
+                self.parse(line.split_whitespace().next()?)
+            }
+            TimeFormat::Rfc2822 => None,
+        }
+    }
+}
+
+/// Parse the configured formats, the default ones if there isn't any
+pub fn formats(formats: &[String]) -> Vec<TimeFormat> {
+    let formats = if formats.is_empty() {
+        DEFAULT_FORMATS.iter().map(|f| f.to_string()).collect()
+    } else {
+        formats.to_vec()
+    };
+
+    // unwrap() is safe, it's infallible
+    formats.iter().map(|f| f.parse().unwrap()).collect()
+}
+
+fn parse_strftime(value: &str, format: &str, prefix: bool) -> Option<DateTime<Utc>> {
+    // syslog-like timestamps don't have a year, we assume the current one
+    let has_year = ["%Y", "%y", "%G", "%s", "%F", "%c", "%+"]
+        .iter()
+        .any(|spec| format.contains(spec));
+    let (value, format) = if has_year {
+        (value.to_owned(), format.to_owned())
+    } else {
+        (
+            format!("{} {}", Utc::now().year(), value),
+            format!("%Y {}", format),
+        )
+    };
+
+    if prefix {
+        DateTime::parse_and_remainder(&value, &format)
+            .map(|(date, _)| date.with_timezone(&Utc))
+            .or_else(|_| {
+                NaiveDateTime::parse_and_remainder(&value, &format).map(|(n, _)| n.and_utc())
+            })
+            .ok()
+    } else {
+        DateTime::parse_from_str(&value, &format)
+            .map(|date| date.with_timezone(&Utc))
+            .or_else(|_| NaiveDateTime::parse_from_str(&value, &format).map(|n| n.and_utc()))
+            .ok()
+    }
+}
+
+pub struct TimestampStage {
+    /// Read the timestamp from this field rather than from the beginning of the line
+    field: Option<String>,
+    formats: Vec<TimeFormat>,
+}
+
+impl TimestampStage {
+    pub fn new(field: Option<String>, formats: &[String]) -> Self {
+        metrics::registry().describe(
+            LAG_METRIC,
+            "Time elapsed between the event's timestamp and its ingestion",
+            None,
+        );
+
+        Self {
+            field,
+            formats: self::formats(formats),
+        }
+    }
+
+    fn extract(&self, event: &Event) -> Option<DateTime<Utc>> {
+        match &self.field {
+            Some(field) => {
+                let value = match event.fields.get(field)? {
+                    Value::String(value) => value.to_owned(),
+                    Value::Number(number) => number.to_string(),
+                    _ => return None,
+                };
+
+                self.formats.iter().find_map(|format| format.parse(&value))
+            }
+            None => self
+                .formats
+                .iter()
+                .find_map(|format| format.parse_prefix(&event.line)),
+        }
+    }
+}
+
+impl Stage for TimestampStage {
+    fn process(&mut self, mut event: Event) -> Option<Event> {
+        match self.extract(&event) {
+            Some(timestamp) => {
+                let lag = (Utc::now() - timestamp).num_milliseconds().max(0) as f64 / 1000.0;
+                metrics::registry().observe(LAG_METRIC, &[], lag);
+
+                let normalized = timestamp.to_rfc3339_opts(SecondsFormat::Millis, true);
+                event
+                    .fields
+                    .insert(TIMESTAMP_FIELD.to_owned(), Value::String(normalized));
+                event.timestamp = Some(timestamp);
+            }
+            None => debug!("pos <{}>: no timestamp found", event.position),
+        }
+
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_formats() {
+        let expected = Utc.with_ymd_and_hms(2021, 9, 7, 3, 37, 53).unwrap();
+
+        let format = TimeFormat::from_str("%d/%b/%Y:%H:%M:%S %z").unwrap();
+        assert_eq!(format.parse("07/Sep/2021:05:37:53 +0200"), Some(expected));
+
+        let format = TimeFormat::from_str("unix").unwrap();
+        assert_eq!(format.parse("1630985873"), Some(expected));
+
+        let format = TimeFormat::from_str("%Y-%m-%d %H:%M:%S").unwrap();
+        assert_eq!(format.parse("2021-09-07 03:37:53"), Some(expected));
+    }
+
+    #[test]
+    fn test_from_the_beginning_of_the_line() {
+        let mut stage = TimestampStage::new(None, &[]);
+        let event = Event::new(0, "2021-09-07T05:37:53.250+02:00 INFO started".to_owned());
+        let event = stage.process(event).unwrap();
+
+        assert_eq!(event.fields[TIMESTAMP_FIELD], "2021-09-07T03:37:53.250Z");
+    }
+
+    #[test]
+    fn test_from_a_field() {
+        let mut stage = TimestampStage::new(Some("ts".to_owned()), &["unix_ms".to_owned()]);
+        let mut event = Event::new(0, "{}".to_owned());
+        event
+            .fields
+            .insert("ts".to_owned(), 1630985873250u64.into());
+        let event = stage.process(event).unwrap();
+
+        assert_eq!(event.fields[TIMESTAMP_FIELD], "2021-09-07T03:37:53.250Z");
+    }
+}
@@ -1,6 +1,25 @@
-use crate::output::OutputAdapter;
-use crate::reader::LineInfo;
+use crate::encoding::Encoding;
+use crate::error::Error;
+use crate::hooks::{self, Hooks};
+use crate::metrics;
+use crate::output::{Message, OutputAdapter, Unavailable};
+use crate::pipeline::{Event, Pipeline, PARTIAL_LINE_HEADER};
+use crate::reader::{Batch, Line};
+use crate::shutdown::Shutdown;
+use crate::state::Cursor;
+use crate::stats;
+use crate::telemetry::{self, TRACEPARENT_HEADER};
+use serde_json::json;
+use std::collections::VecDeque;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use tokio::sync::{mpsc, watch};
+use tracing::Instrument;
 
 // TODO: Or we could use a different (probably safer) way to make the publisher concurrent:
 //         -When we publish, if success, push the line into a buffer, once the buffer reaches a certain
@@ -11,40 +30,631 @@ use tokio::sync::{mpsc, watch};
 //         -Everytime the buffer is being saved, we trim the head of the log of these msg as they
 //         don't need to be there anymore.
 
+const LATENCY_METRIC: &str = "log_bouncer_line_latency_seconds";
+
+/// From a millisecond to a minute, the broker may slow down by orders of magnitude
+const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+#[derive(thiserror::Error, Debug)]
+#[error("unknown policy `{0}`, expected `replace`, `skip` or `bytes`")]
+pub struct UnknownPolicy(String);
+
+/// What happens to the lines that aren't valid UTF-8
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InvalidUtf8 {
+    /// The invalid sequences are replaced by `U+FFFD`
+    Replace,
+    /// The line is dropped with a warning
+    Skip,
+    /// The line goes through the pipeline with its invalid sequences replaced, but the outputs
+    /// able to publish bytes send it untouched
+    Bytes,
+}
+
+impl FromStr for InvalidUtf8 {
+    type Err = UnknownPolicy;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "replace" | "lossy" => Ok(InvalidUtf8::Replace),
+            "skip" => Ok(InvalidUtf8::Skip),
+            "bytes" => Ok(InvalidUtf8::Bytes),
+            _ => Err(UnknownPolicy(s.to_owned())),
+        }
+    }
+}
+
+/// Since when the output has been sending the current message, see [`Publisher::busy_since`]
+pub type BusySince = Arc<Mutex<Option<Instant>>>;
+
+/// A tailed file, the published lines are acknowledged to its state saver
+#[derive(Debug)]
+pub struct Source {
+    pub path: PathBuf,
+    state_tx: watch::Sender<Cursor>,
+    /// Lines read from it since the start, see [`Line::sequence`]
+    read: AtomicU64,
+}
+
+impl Source {
+    pub fn new(path: PathBuf, state_tx: watch::Sender<Cursor>) -> Self {
+        Self {
+            path,
+            state_tx,
+            read: AtomicU64::new(0),
+        }
+    }
+
+    /// Nobody saves its position, eg. the standard input
+    pub fn unsaved(path: PathBuf) -> Self {
+        let (state_tx, _) = watch::channel(Cursor::default());
+        Self::new(path, state_tx)
+    }
+
+    /// Whether its position gets saved, the receiver of an unsaved source is dropped right away
+    fn is_saved(&self) -> bool {
+        !self.state_tx.is_closed()
+    }
+
+    /// The sequence number of the line being read
+    pub fn next_sequence(&self) -> u64 {
+        self.read.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// The line right before the cursor won't be read again, the file may not be tailed anymore
+    fn acknowledge(&self, cursor: Cursor) {
+        stats::stats().acknowledged(&self.path, cursor.position);
+        let _ = self.state_tx.send(cursor);
+    }
+}
+
+/// Publish the lines, then advance the cursor of their file
+///
+/// The delivery is at-least-once: the cursor only moves past a line once the output has
+/// acknowledged it, a crash before the position gets saved publishes the line again.
 pub struct Publisher<Output: OutputAdapter> {
-    rx: mpsc::Receiver<LineInfo>,
+    rx: mpsc::Receiver<Batch>,
+    /// The lines of the batches received, not published yet
+    pending: VecDeque<Line>,
     fnc: Output,
-    state_tx: watch::Sender<u64>,
+    pipeline: Pipeline,
+    /// Receive the new pipeline when the config gets reloaded
+    reload_rx: mpsc::Receiver<Pipeline>,
+    /// Messages that aren't lines of the file, eg. the rotation events
+    events_rx: mpsc::Receiver<Message>,
+    invalid_utf8: InvalidUtf8,
+    /// Transcode the lines to UTF-8, unless they're UTF-8 already
+    encoding: Option<Encoding>,
+    /// Since when the output has been sending the current message, see [`busy_since`]
+    ///
+    /// [`busy_since`]: Self::busy_since
+    busy_since: BusySince,
+    /// Name of the header identifying every line, and the host it's prefixed with
+    correlation: Option<(String, String)>,
+    /// Stop publishing once the current line has been sent, the rest is left to [`drain`]
+    ///
+    /// [`drain`]: Self::drain
+    shutdown: Shutdown,
+    hooks: Hooks,
 }
 
 impl<Output: OutputAdapter> Publisher<Output> {
-    pub fn new(output: Output, rx: mpsc::Receiver<LineInfo>, state_tx: watch::Sender<u64>) -> Self {
+    pub fn new(
+        output: Output,
+        pipeline: Pipeline,
+        rx: mpsc::Receiver<Batch>,
+        reload_rx: mpsc::Receiver<Pipeline>,
+        events_rx: mpsc::Receiver<Message>,
+        invalid_utf8: InvalidUtf8,
+        encoding: Option<Encoding>,
+    ) -> Self {
+        metrics::registry().describe(
+            LATENCY_METRIC,
+            "Time from the line being read until the output confirmed it, by output",
+            Some(LATENCY_BUCKETS),
+        );
+
         Self {
             fnc: output,
+            pipeline,
             rx,
-            state_tx,
+            pending: VecDeque::new(),
+            reload_rx,
+            events_rx,
+            invalid_utf8,
+            encoding,
+            busy_since: Arc::default(),
+            correlation: None,
+            shutdown: Shutdown::new(),
+            hooks: Hooks::default(),
         }
     }
 
-    /// Send lines to the defined output
-    pub async fn publish(&mut self) {
-        // don't decrement the position sent if
-        // amqp returns response at a different order
-        let _last_pos = 0;
+    /// Every line is published with the header `<host>:<file>:<position>`, which stays the same
+    /// when the line gets published again
+    pub fn with_correlation_header(mut self, header: String, host: String) -> Self {
+        self.correlation = Some((header, host));
+        self
+    }
+
+    /// Return from [`publish`] once it's triggered, between two lines
+    ///
+    /// [`publish`]: Self::publish
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Tell whether each line has been published, see [`hooks::Event`]
+    pub fn with_hooks(mut self, hooks: Hooks) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    /// Since when the output has been sending the current message, `None` while it's waiting for
+    /// the next line, eg. to tell whether the output hangs
+    pub fn busy_since(&self) -> BusySince {
+        self.busy_since.clone()
+    }
 
+    /// Send lines to the defined output, until every reader has stopped, the output fails or it's
+    /// shut down
+    pub async fn publish(&mut self) -> Result<(), Error> {
         // The messages are published in a sequential order,
         // we might need to use `last_pos` if we want to send messages to amqp concurrently.
-        while let Some((pos, line)) = self.rx.recv().await {
-            // todo: we could potentially spawn this in a new thread
-            //       to make it concurrent.
-            if let Err(e) = self.fnc.send(pos, line).await {
-                error!("pos <{}>: {}", pos, e);
-                break; // we exit the software
-            } else {
-                // if successfully published, we memorize the last position sent
-                // which will be used to be stored in a file as a saved state in order to recover it
-                self.state_tx.send(pos).unwrap();
+        loop {
+            // it's only removed once it's been published, so it's spooled if the publishing
+            // fails or gets cancelled
+            if let Some(line) = self.pending.front().cloned() {
+                self.publish_line(line).await?;
+                self.pending.pop_front();
+                if self.shutdown.is_triggered() {
+                    return Ok(());
+                }
+                continue;
+            }
+
+            let batch = tokio::select! {
+                batch = self.rx.recv() => match batch {
+                    Some(batch) => batch,
+                    None => return Ok(()),
+                },
+                Some(message) = self.events_rx.recv() => {
+                    // it isn't part of the file, there's no position to save
+                    if let Err(e) = self.fnc.send(message).await {
+                        return Err(Unavailable(format!("can't publish the event: {}", e)).into());
+                    }
+                    continue;
+                }
+                _ = self.shutdown.triggered() => return Ok(()),
+            };
+
+            self.pending.extend(batch);
+        }
+    }
+
+    /// Publish the lines already read, until there isn't any left, eg. once the readers are
+    /// stopped
+    pub async fn drain(&mut self) {
+        loop {
+            // removed once it's been published, like in `publish`
+            let line = match self.pending.front().cloned() {
+                Some(line) => line,
+                None => match self.rx.try_recv() {
+                    Ok(batch) => {
+                        self.pending.extend(batch);
+                        continue;
+                    }
+                    Err(_) => break,
+                },
+            };
+
+            if self.publish_line(line).await.is_err() {
+                break;
+            }
+            self.pending.pop_front();
+        }
+    }
+
+    /// Write the lines left to the spool, their positions aren't committed so only the ones which
+    /// can't be read again are written, eg. the ones of the standard input. Returns how many
+    /// have been written, and how many couldn't be, both are counted as dropped
+    pub fn spool(&mut self, path: Option<&Path>) -> std::io::Result<(usize, usize)> {
+        let mut spool = None;
+        let (mut spooled, mut lost) = (0, 0);
+
+        let pending = std::mem::take(&mut self.pending);
+        let batches = std::iter::from_fn(|| self.rx.try_recv().ok());
+
+        for Line { source, bytes, .. } in pending.into_iter().chain(batches.flatten()) {
+            if source.is_saved() {
+                continue;
+            }
+
+            let path = match path {
+                Some(path) => path,
+                None => {
+                    stats::stats().dropped("lost");
+                    lost += 1;
+                    continue;
+                }
+            };
+            let file = match spool.as_mut() {
+                Some(file) => file,
+                None => spool.insert(OpenOptions::new().create(true).append(true).open(path)?),
+            };
+            file.write_all(&bytes)?;
+            file.write_all(b"\n")?;
+            stats::stats().dropped("spooled");
+            spooled += 1;
+        }
+
+        Ok((spooled, lost))
+    }
+
+    async fn publish_line(
+        &mut self,
+        Line {
+            source,
+            cursor,
+            bytes,
+            partial,
+            sequence,
+            read_at,
+        }: Line,
+    ) -> Result<(), Error> {
+        // swapped between two lines, so a line always goes through a single pipeline
+        while let Ok(pipeline) = self.reload_rx.try_recv() {
+            info!("Pipeline reloaded");
+            self.pipeline = pipeline;
+        }
+
+        let pos = cursor.position;
+        // the logs of the line, down to the ones of the output, carry where it comes from
+        let span = debug_span!(
+            "line",
+            file = %source.path.to_string_lossy(),
+            offset = pos,
+            line = cursor.line,
+            sequence
+        );
+
+        let decoded = match self.encoding {
+            Some(encoding) => Some(encoding.decode(&bytes)),
+            None => std::str::from_utf8(&bytes).ok().map(str::to_owned),
+        };
+        let (line, raw) = match decoded {
+            Some(line) => (line, None),
+            None => {
+                let line = String::from_utf8_lossy(&bytes).into_owned();
+                match self.invalid_utf8 {
+                    InvalidUtf8::Replace => (line, None),
+                    InvalidUtf8::Bytes => (line, Some(bytes.to_vec())),
+                    InvalidUtf8::Skip => {
+                        warn!(
+                            "{} line <{}> isn't valid UTF-8, skipped: {}",
+                            source.path.to_string_lossy(),
+                            cursor.line,
+                            line
+                        );
+                        stats::stats().dropped("invalid_utf8");
+                        source.acknowledge(cursor);
+                        return Ok(());
+                    }
+                }
+            }
+        };
+
+        let mut event = Event::new(pos, line);
+        event.line_number = cursor.line;
+        event.file = Some(source.path.clone());
+        event.raw = raw;
+        if partial {
+            // the rest of the line may follow
+            event
+                .headers
+                .insert(PARTIAL_LINE_HEADER.to_owned(), "true".to_owned());
+        }
+
+        let mut trace = telemetry::tracer().sample();
+        if let Some(trace) = &mut trace {
+            trace.attribute("log.file.path", json!(source.path.to_string_lossy()));
+            trace.attribute("log.line", json!(cursor.line));
+            trace.attribute("log.position", json!(pos));
+        }
+
+        let processed = {
+            let _step = trace.as_ref().map(|trace| trace.step("transform"));
+            span.in_scope(|| self.pipeline.process(event))
+        };
+        let mut event = match processed {
+            Some(event) => event,
+            None => {
+                // the line has been dropped by the pipeline, there's nothing to publish
+                stats::stats().dropped("filtered");
+                source.acknowledge(cursor);
+                if let Some(trace) = trace {
+                    trace.end("dropped");
+                }
+                return Ok(());
             }
+        };
+
+        if let Some(trace) = &trace {
+            event
+                .headers
+                .insert(TRACEPARENT_HEADER.to_owned(), trace.traceparent());
+        }
+        if let Some((header, host)) = &self.correlation {
+            let id = format!("{}:{}:{}", host, source.path.to_string_lossy(), pos);
+            event.headers.insert(header.clone(), id);
+        }
+
+        // todo: we could potentially spawn this in a new thread
+        //       to make it concurrent.
+        let message = event.into_message();
+        let bytes = message.raw.as_ref().map_or(message.payload.len(), Vec::len);
+        let started = Instant::now();
+        *self.busy_since.lock().unwrap() = Some(started);
+        let sent = {
+            let _step = trace.as_ref().map(|trace| trace.step("publish"));
+            self.fnc.send(message).instrument(span.clone()).await
+        };
+        *self.busy_since.lock().unwrap() = None;
+        if let Some(trace) = trace {
+            trace.end(if sent.is_ok() { "published" } else { "failed" });
+        }
+
+        if let Err(e) = sent {
+            stats::stats().error("publish_failed", &e);
+            self.hooks.emit(|| hooks::Event::PublishFailed {
+                path: source.path.clone(),
+                position: pos,
+                error: e.to_string(),
+            });
+            span.in_scope(|| {
+                error!("{} pos <{}>: {}", source.path.to_string_lossy(), pos, e);
+            });
+            return Err(Unavailable(e.to_string()).into()); // we exit the software
+        } else {
+            // if successfully published, we memorize the last position acknowledged
+            // which will be used to be stored in a file as a saved state in order to recover it
+            stats::stats().published(bytes, started.elapsed());
+            let labels = [("output", self.fnc.name())];
+            let latency = read_at.elapsed().as_secs_f64();
+            metrics::registry().observe(LATENCY_METRIC, &labels, latency);
+            source.acknowledge(cursor);
+            self.hooks.emit(|| hooks::Event::Published {
+                path: source.path.clone(),
+                position: pos,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+
+    /// Refuses the lines containing `nack`
+    #[derive(Default)]
+    struct Output {
+        published: Arc<Mutex<Vec<String>>>,
+        raw: Arc<Mutex<Vec<Vec<u8>>>>,
+        headers: Arc<Mutex<Vec<BTreeMap<String, String>>>>,
+    }
+
+    #[async_trait]
+    impl OutputAdapter for Output {
+        async fn send(&self, message: Message) -> Result<(), Error> {
+            if message.payload.contains("nack") {
+                Err("nacked")?;
+            }
+            self.published.lock().unwrap().push(message.payload);
+            self.raw.lock().unwrap().extend(message.raw);
+            self.headers.lock().unwrap().push(message.headers);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_the_cursor_only_moves_once_acknowledged() {
+        let output = Output::default();
+        let published = output.published.clone();
+        let headers = output.headers.clone();
+        let (tx, rx) = mpsc::channel(4);
+        let (state_tx, state_rx) = watch::channel(Cursor::default());
+        let source = Arc::new(Source::new(PathBuf::from("test.log"), state_tx));
+        let (_reload_tx, reload_rx) = mpsc::channel(1);
+        let (_events_tx, events_rx) = mpsc::channel(1);
+
+        for (position, line) in [(6, "first"), (11, "nack"), (17, "third")] {
+            let cursor = Cursor {
+                position,
+                ..Cursor::default()
+            };
+            tx.send(vec![Line::new(&source, cursor, line.as_bytes().to_vec())])
+                .await
+                .unwrap();
+        }
+
+        let mut publisher = Publisher::new(
+            output,
+            Pipeline::new(),
+            rx,
+            reload_rx,
+            events_rx,
+            InvalidUtf8::Replace,
+            None,
+        )
+        .with_correlation_header("x-correlation-id".to_owned(), "web-1".to_owned());
+        assert!(publisher.publish().await.is_err());
+
+        assert_eq!(*published.lock().unwrap(), vec!["first"]);
+        assert_eq!(state_rx.borrow().position, 6);
+        assert_eq!(
+            headers.lock().unwrap()[0]["x-correlation-id"],
+            "web-1:test.log:6"
+        );
+        assert!(metrics::registry()
+            .render()
+            .contains("log_bouncer_line_latency_seconds_count{output=\"output\"}"));
+    }
+
+    #[tokio::test]
+    async fn test_drain_then_spool() {
+        let output = Output::default();
+        let published = output.published.clone();
+        let (tx, rx) = mpsc::channel(4);
+        let (state_tx, state_rx) = watch::channel(Cursor::default());
+        let file = Arc::new(Source::new(PathBuf::from("test.log"), state_tx));
+        let stdin = Arc::new(Source::unsaved(PathBuf::from("-")));
+        let (_reload_tx, reload_rx) = mpsc::channel(1);
+        let (_events_tx, events_rx) = mpsc::channel(1);
+
+        for (source, position, line) in [
+            (&file, 6, "first"),
+            (&file, 11, "nack"),
+            (&stdin, 6, "piped"),
+            (&file, 17, "third"),
+        ] {
+            let cursor = Cursor {
+                position,
+                ..Cursor::default()
+            };
+            let line = line.as_bytes().to_vec();
+            tx.send(vec![Line::new(source, cursor, line)])
+                .await
+                .unwrap();
+        }
+
+        let mut publisher = Publisher::new(
+            output,
+            Pipeline::new(),
+            rx,
+            reload_rx,
+            events_rx,
+            InvalidUtf8::Replace,
+            None,
+        );
+        // the output stops accepting the lines
+        publisher.drain().await;
+        assert_eq!(*published.lock().unwrap(), vec!["first"]);
+
+        let dir = tempfile::tempdir().unwrap();
+        let spool = dir.path().join("spool.log");
+        assert_eq!(publisher.spool(Some(&spool)).unwrap(), (1, 0));
+        assert_eq!(std::fs::read_to_string(&spool).unwrap(), "piped\n");
+        assert_eq!(state_rx.borrow().position, 6);
+    }
+
+    #[tokio::test]
+    async fn test_failed_line_is_spooled() {
+        let (tx, rx) = mpsc::channel(1);
+        let stdin = Arc::new(Source::unsaved(PathBuf::from("-")));
+        let (_reload_tx, reload_rx) = mpsc::channel(1);
+        let (_events_tx, events_rx) = mpsc::channel(1);
+        let lines = [(6, "nacked"), (12, "next")].map(|(position, line)| {
+            let cursor = Cursor {
+                position,
+                ..Cursor::default()
+            };
+            Line::new(&stdin, cursor, line.as_bytes().to_vec())
+        });
+        tx.send(lines.to_vec()).await.unwrap();
+
+        let mut publisher = Publisher::new(
+            Output::default(),
+            Pipeline::new(),
+            rx,
+            reload_rx,
+            events_rx,
+            InvalidUtf8::Replace,
+            None,
+        );
+        publisher.drain().await;
+
+        let dropped = |reason| {
+            let serie = format!("{}{{reason=\"{}\"}} ", stats::DROPPED_METRIC, reason);
+            metrics::registry()
+                .render()
+                .lines()
+                .find_map(|line| line.strip_prefix(&serie)?.parse::<u64>().ok())
+                .unwrap_or(0)
+        };
+        // the counters are shared with the tests running alongside
+        let (spooled, lost) = (dropped("spooled"), dropped("lost"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let spool = dir.path().join("spool.log");
+        assert_eq!(publisher.spool(Some(&spool)).unwrap(), (2, 0));
+        assert_eq!(std::fs::read_to_string(&spool).unwrap(), "nacked\nnext\n");
+        assert!(dropped("spooled") >= spooled + 2);
+
+        // without a spool, they're lost
+        tx.send(lines[..1].to_vec()).await.unwrap();
+        assert_eq!(publisher.spool(None).unwrap(), (0, 1));
+        assert!(dropped("lost") > lost);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_utf8() {
+        let line = b"caf\xe9".to_vec();
+
+        let latin1 = "latin1".parse().ok();
+
+        for (policy, encoding, published, raw) in [
+            (InvalidUtf8::Replace, None, vec!["caf\u{fffd}"], None),
+            (InvalidUtf8::Skip, None, vec![], None),
+            (
+                InvalidUtf8::Bytes,
+                None,
+                vec!["caf\u{fffd}"],
+                Some(line.clone()),
+            ),
+            // it's not UTF-8 in the first place
+            (InvalidUtf8::Skip, latin1, vec!["café"], None),
+        ] {
+            let output = Output::default();
+            let messages = output.published.clone();
+            let raws = output.raw.clone();
+            let (tx, rx) = mpsc::channel(4);
+            let (state_tx, state_rx) = watch::channel(Cursor::default());
+            let source = Arc::new(Source::new(PathBuf::from("test.log"), state_tx));
+            let (_reload_tx, reload_rx) = mpsc::channel(1);
+            let (_events_tx, events_rx) = mpsc::channel(1);
+
+            let cursor = Cursor {
+                position: 5,
+                ..Cursor::default()
+            };
+            tx.send(vec![Line::new(&source, cursor, line.clone())])
+                .await
+                .unwrap();
+            drop(tx);
+
+            let mut publisher = Publisher::new(
+                output,
+                Pipeline::new(),
+                rx,
+                reload_rx,
+                events_rx,
+                policy,
+                encoding,
+            );
+            publisher.publish().await.unwrap();
+
+            assert_eq!(*messages.lock().unwrap(), published);
+            assert_eq!(raws.lock().unwrap().pop(), raw);
+            // the skipped line isn't read again
+            assert_eq!(state_rx.borrow().position, 5);
         }
     }
 }
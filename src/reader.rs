@@ -1,70 +1,713 @@
-use crate::tail;
-use crate::tail::TailedFile;
-use std::error::Error;
+use crate::error::Error;
+use crate::hooks::{self, Hooks};
+use crate::publisher::Source;
+use crate::shutdown::Shutdown;
+use crate::state::{self, Cursor};
+use crate::stats;
+use crate::tail::{Event, LineBreak, TailedFile};
+use bytes::Bytes;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
 use std::thread::sleep;
-use std::time::Duration;
-use tokio::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{Sender, UnboundedSender};
 use tokio::sync::Notify;
 
-const TAIL_WAIT_DURATION: Duration = Duration::from_millis(500);
+/// Check the file anyway, in case a change hasn't been notified, eg. on a network filesystem
+const NOTIFIED_WAIT_DURATION: Duration = Duration::from_secs(5);
 
-pub type LineInfo = (u64, String);
+/// Log the progress of the catch-up that often
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Lines sent at once at most, so the publish queue stays bounded, see [`Batch`]
+const MAX_BATCH_LINES: usize = 512;
+
+/// A line read from an input, on its way to the publisher
+#[derive(Debug, Clone)]
+pub struct Line {
+    /// Where it's been read from, its cursor is acknowledged to it once it's published
+    pub source: Arc<Source>,
+    /// Right after the line: its offset, its number and the identity of the file
+    pub cursor: Cursor,
+    /// As it's been read, without its line breaker, it's decoded by the publisher according to
+    /// `--input-encoding` and `--invalid-utf8`. The lines of a file share the buffer they've
+    /// been read into
+    pub bytes: Bytes,
+    /// It's been read without its line breaker, the rest of it may follow
+    pub partial: bool,
+    /// Lines read from the source since the start, from 1, to tell them apart in the logs
+    pub sequence: u64,
+    /// When it's been read, to measure how long it took to publish it
+    pub read_at: Instant,
+}
+
+/// The lines read at once, sent to the publisher together rather than one by one
+pub type Batch = Vec<Line>;
+
+impl Line {
+    pub fn new(source: &Arc<Source>, cursor: Cursor, bytes: impl Into<Bytes>) -> Self {
+        Self {
+            source: source.clone(),
+            cursor,
+            bytes: bytes.into(),
+            partial: false,
+            sequence: source.next_sequence(),
+            read_at: Instant::now(),
+        }
+    }
+}
 
 /// Read a file, then send every new line to the other thread
 pub struct Reader {
-    /// Path of the file to monitor
-    path: PathBuf,
-    /// The recovered position from the last launch
-    pos: u64,
-    /// Send each line to the publisher
-    tx: Sender<LineInfo>,
+    /// The file to monitor
+    source: Arc<Source>,
+    /// The recovered cursor from the last launch
+    cursor: Cursor,
+    /// Check the file that often when its changes can't be notified
+    poll_interval: Duration,
+    /// Be notified of the changes of the file, rather than polling it
+    watch: bool,
+    /// Read the line being written once it hasn't grown for that long
+    partial_timeout: Option<Duration>,
+    /// Lines per second sent at most when the reader is behind, see [`RateLimiter`], unlimited
+    /// if 0. It can change while reading, eg. when the config gets reloaded
+    catch_up_rate: Arc<AtomicU64>,
+    line_break: LineBreak,
+    /// Stop reading the file, eg. once it's been deleted
+    shutdown: Shutdown,
+    /// Don't read the file while any of them is set, eg. from the admin API
+    paused: Vec<Arc<AtomicBool>>,
+    /// Receives the path once the end of the file has been reached, see `--exit-on-eof`
+    eof_tx: Option<UnboundedSender<PathBuf>>,
+    hooks: Hooks,
 }
 
 impl Reader {
-    pub fn new(path: PathBuf, pos: u64, tx: Sender<LineInfo>) -> Result<Self, Box<dyn Error>> {
-        info!("Recovered the cursor from the position <{}>", pos);
+    pub fn new(
+        source: Arc<Source>,
+        cursor: Cursor,
+        poll_interval: Duration,
+        watch: bool,
+        partial_timeout: Option<Duration>,
+        catch_up_rate: Arc<AtomicU64>,
+    ) -> Result<Self, Error> {
+        info!(
+            "Recovered the cursor from the position <{}>, line <{}>",
+            cursor.position, cursor.line
+        );
+
+        Ok(Self {
+            source,
+            cursor,
+            poll_interval,
+            watch,
+            partial_timeout,
+            catch_up_rate,
+            line_break: LineBreak::Byte,
+            shutdown: Shutdown::new(),
+            paused: vec![],
+            eof_tx: None,
+            hooks: Hooks::default(),
+        })
+    }
+
+    /// The lines end according to the encoding of the file, see [`LineBreak`]
+    pub fn with_line_break(mut self, line_break: LineBreak) -> Self {
+        self.line_break = line_break;
+        self
+    }
+
+    /// The reading is paused while it's set, along with the ones already given, eg. the flag of
+    /// the admin API shared by every source, or the one of the source once it's being drained
+    pub fn with_pause(mut self, paused: Arc<AtomicBool>) -> Self {
+        self.paused.push(paused);
+        self
+    }
+
+    /// Tell once the end of the file has been reached, the reader keeps on tailing it
+    pub fn with_eof(mut self, eof_tx: Option<UnboundedSender<PathBuf>>) -> Self {
+        self.eof_tx = eof_tx;
+        self
+    }
 
-        Ok(Self { path, pos, tx })
+    /// Tell once the file has been rotated, see [`hooks::Event::Rotated`]
+    pub fn with_hooks(mut self, hooks: Hooks) -> Self {
+        self.hooks = hooks;
+        self
     }
 
-    pub fn work(mut self) -> Arc<Notify> {
+    /// Once it's triggered, the file is read one last time, then the reader stops
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Send each line to the publisher, from another thread. Notified once it's stopped
+    pub fn work(self, tx: Sender<Batch>) -> Arc<Notify> {
         let panicked = Arc::new(Notify::new());
         let notifier = panicked.clone();
 
         std::thread::spawn(move || {
-            let tx = self.tx;
+            let source = self.source;
+
+            let waker = if self.watch {
+                Waker::new(&source.path, self.poll_interval)
+            } else {
+                Waker::polling(self.poll_interval)
+            };
+            let (mut tail, created) = match open(&source.path, &waker, &self.shutdown) {
+                Some(opened) => opened,
+                None => {
+                    notifier.notify_one();
+                    return;
+                }
+            };
+            // it's been created meanwhile, it's read from its beginning
+            let cursor = match created {
+                true => Cursor::default(),
+                false => self.cursor,
+            };
+            tail.set_pos(cursor.position); // recover previous position
+            tail.set_line(cursor.line);
+            tail.set_partial_timeout(self.partial_timeout);
+            tail.set_line_break(self.line_break);
+            let mut limiter: Option<RateLimiter> = None;
+            let mut progress_logged = Instant::now();
+            let mut catching_up = false;
+            let mut eof_tx = self.eof_tx;
 
-            let mut tail = TailedFile::new(&self.path).unwrap();
-            tail.set_pos(self.pos); // recover previous position
+            'reading: loop {
+                // the file is still read once it's stopped, so the position is final
+                let paused = self
+                    .paused
+                    .iter()
+                    .any(|paused| paused.load(Ordering::Relaxed));
+                if paused && !self.shutdown.is_triggered() {
+                    std::thread::sleep(self.poll_interval);
+                    continue;
+                }
 
-            loop {
                 match tail.follow() {
-                    Ok(lines) => {
-                        for line in lines {
-                            if let Err(e) = tx.blocking_send((tail.pos(), line)) {
-                                error!("Can't send to mpsc: {}", e); // this is a fatal error
-                                break;
-                            }
+                    Ok(Event::Read(0)) => {
+                        // the end of the file has been reached
+                        if std::mem::take(&mut catching_up) {
+                            info!("Caught up on `{}`", source.path.to_string_lossy());
+                        }
+                        if let Some(eof_tx) = eof_tx.take() {
+                            let _ = eof_tx.send(source.path.clone());
                         }
                     }
-                    Err(err) => match err {
-                        tail::Error::FileRotated | tail::Error::FileTruncated => warn!("{}", err),
-                        _ => {
-                            error!("{}", err); // this may be fatal, too
+                    Ok(Event::Read(count)) => {
+                        let first_line = tail.line() - count as u64;
+                        limiter = match self.catch_up_rate.load(Ordering::Relaxed) {
+                            0 => None,
+                            rate if limiter
+                                .as_ref()
+                                .is_some_and(|limiter| limiter.limits(rate)) =>
+                            {
+                                limiter
+                            }
+                            rate => Some(RateLimiter::new(rate)),
+                        };
+                        let _batch = debug_span!(
+                            "batch",
+                            file = %source.path.to_string_lossy(),
+                            first_line,
+                            lines = count
+                        )
+                        .entered();
+
+                        let (file_id, last_partial) = (tail.file_id(), tail.partial());
+                        let size = tail.size().ok();
+                        let mut batch = Vec::with_capacity(count.min(MAX_BATCH_LINES));
+                        for (i, (line, offset)) in tail.take_lines().enumerate() {
+                            // only the last line can lack its line breaker
+                            let partial = i + 1 == count && last_partial;
+                            let cursor = Cursor {
+                                position: offset,
+                                line: first_line + i as u64 + 1,
+                                file_id,
+                                fingerprint: Some(state::fingerprint(&line)),
+                            };
+
+                            let throttled = limiter.as_mut().is_some_and(RateLimiter::acquire);
+                            catching_up |= throttled;
+                            if throttled && progress_logged.elapsed() >= PROGRESS_INTERVAL {
+                                // the lines left can't be counted without reading them
+                                let behind = size.map_or(0, |size| size.saturating_sub(offset));
+                                info!(
+                                    "Catching up on `{}` at <{}> lines/s: line <{}>, <{}> bytes behind",
+                                    source.path.to_string_lossy(),
+                                    self.catch_up_rate.load(Ordering::Relaxed),
+                                    cursor.line,
+                                    behind
+                                );
+                                progress_logged = Instant::now();
+                            }
+
+                            batch.push(Line {
+                                partial,
+                                ..Line::new(&source, cursor, line)
+                            });
+                            // the lines let through by the limiter aren't held back
+                            // the publisher has stopped, this is a fatal error
+                            if (batch.len() == MAX_BATCH_LINES || throttled)
+                                && !send(&tx, std::mem::take(&mut batch))
+                            {
+                                break 'reading;
+                            }
+                        }
+                        if !send(&tx, batch) {
                             break;
                         }
-                    },
+                    }
+                    Ok(event @ Event::Rotated) => {
+                        // the former file has been read until its end, no need to wait for the
+                        // new one
+                        warn!("{}", event);
+                        self.hooks.emit(|| hooks::Event::Rotated {
+                            path: source.path.clone(),
+                        });
+                        continue;
+                    }
+                    Ok(event) => warn!("{}", event),
+                    Err(err) => {
+                        stats::stats().error("read", &err);
+                        error!("{}", err); // this may be fatal, too
+                        break;
+                    }
                 };
 
-                sleep(TAIL_WAIT_DURATION);
+                if self.shutdown.is_triggered() {
+                    break;
+                }
+
+                waker.wait();
             }
 
-            // will exit the software
+            // will exit the software, unless the reader has been stopped
             notifier.notify_one();
         });
 
         panicked
     }
 }
+
+/// Open the file, it's waited for if it doesn't exist, like once it's been deleted. Tells if it
+/// had to be, `None` if the reader has been stopped meanwhile
+fn open<'a>(
+    path: &'a Path,
+    waker: &Waker,
+    shutdown: &Shutdown,
+) -> Option<(TailedFile<&'a Path>, bool)> {
+    let mut waited = false;
+
+    loop {
+        match TailedFile::new(path) {
+            Ok(tail) => return Some((tail, waited)),
+            Err(e) if !waited => {
+                warn!(
+                    "Can't open `{}`, waiting for it: {}",
+                    path.to_string_lossy(),
+                    e
+                );
+            }
+            Err(_) => {}
+        }
+        waited = true;
+
+        if shutdown.is_triggered() {
+            return None;
+        }
+        waker.wait();
+    }
+}
+
+/// Send the lines to the publisher, `false` once it has stopped
+fn send(tx: &Sender<Batch>, batch: Batch) -> bool {
+    if batch.is_empty() {
+        return true;
+    }
+
+    match tx.blocking_send(batch) {
+        Ok(()) => true,
+        Err(e) => {
+            error!("Can't send to mpsc: {}", e);
+            false
+        }
+    }
+}
+
+/// Read the standard input, then send every line to the other thread, until the input is closed
+///
+/// Nothing is saved, the lines can't be read again anyway.
+pub fn read_stdin(tx: Sender<Batch>, line_break: LineBreak) -> Arc<Notify> {
+    let closed = Arc::new(Notify::new());
+    let notifier = closed.clone();
+    let source = Arc::new(Source::unsaved(PathBuf::from("-")));
+
+    std::thread::spawn(move || {
+        let mut cursor = Cursor::default();
+        let mut stdin = std::io::stdin().lock();
+        if let Err(e) = read_to_end(&mut stdin, &source, &mut cursor, &tx, line_break) {
+            error!("Can't read the standard input: {}", e);
+        }
+
+        // the publisher stops once the remaining lines are published, if it's the only input
+        drop(tx);
+        notifier.notify_one();
+    });
+
+    closed
+}
+
+/// Read a named pipe, then send every line to the other thread
+///
+/// A pipe can neither be rotated nor be read again, it's opened again once its writer closes it.
+pub fn read_fifo(
+    source: Arc<Source>,
+    tx: Sender<Batch>,
+    shutdown: Shutdown,
+    line_break: LineBreak,
+) -> Arc<Notify> {
+    let stopped = Arc::new(Notify::new());
+    let notifier = stopped.clone();
+
+    std::thread::spawn(move || {
+        let mut cursor = Cursor::default();
+
+        while !shutdown.is_triggered() {
+            // blocks until a writer opens the pipe
+            let result = File::open(&source.path).and_then(|fifo| {
+                read_to_end(
+                    &mut BufReader::new(fifo),
+                    &source,
+                    &mut cursor,
+                    &tx,
+                    line_break,
+                )
+            });
+
+            match result {
+                Ok(true) => debug!("`{}` has been closed", source.path.to_string_lossy()),
+                Ok(false) => break,
+                Err(e) => {
+                    error!("Can't read `{}`: {}", source.path.to_string_lossy(), e);
+                    break;
+                }
+            }
+        }
+
+        notifier.notify_one();
+    });
+
+    stopped
+}
+
+/// Whether the file is a named pipe, rather than a regular file
+#[cfg(unix)]
+pub fn is_fifo(path: &Path) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+
+    std::fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_fifo())
+}
+
+#[cfg(not(unix))]
+pub fn is_fifo(_path: &Path) -> bool {
+    false
+}
+
+/// Send every line of the input until its end, `false` if they can't be sent anymore
+///
+/// The last line is sent even without a line breaker, nothing will be appended to it. Each line
+/// is sent as soon as it's read, the next one may not come before long.
+pub fn read_to_end(
+    input: &mut impl BufRead,
+    source: &Arc<Source>,
+    cursor: &mut Cursor,
+    tx: &Sender<Batch>,
+    line_break: LineBreak,
+) -> std::io::Result<bool> {
+    loop {
+        let mut line = vec![];
+        let n = match line_break.read_line(input, &mut line)? {
+            0 => return Ok(true),
+            n => n as u64,
+        };
+
+        cursor.position += n;
+        cursor.line += 1;
+        line.truncate(line_break.strip(&line).len());
+
+        if !send(tx, vec![Line::new(source, *cursor, line)]) {
+            return Ok(false);
+        }
+    }
+}
+
+/// Bounds the rate of the lines sent, so catching up after a downtime saturates neither the
+/// CPU nor the output
+///
+/// It holds a second worth of lines, the lines appended while the reader is up to date aren't
+/// delayed as long as they're written slower than the rate.
+struct RateLimiter {
+    rate: f64,
+    /// Number of lines that can be sent right away
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    fn new(rate: u64) -> Self {
+        let rate = rate.max(1) as f64;
+
+        Self {
+            rate,
+            tokens: rate,
+            refilled: Instant::now(),
+        }
+    }
+
+    fn limits(&self, rate: u64) -> bool {
+        self.rate == rate.max(1) as f64
+    }
+
+    /// Wait until a line can be sent, `true` if it had to wait
+    fn acquire(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.refilled = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return false;
+        }
+
+        let wait = Duration::from_secs_f64((1.0 - self.tokens) / self.rate);
+        sleep(wait);
+        self.tokens = 0.0;
+        self.refilled = now + wait;
+
+        true
+    }
+}
+
+/// Wakes the reader up as soon as the file changes, thanks to inotify or kqueue, or polls it if
+/// the changes can't be notified
+struct Waker {
+    watcher: Option<RecommendedWatcher>,
+    rx: mpsc::Receiver<()>,
+    poll_interval: Duration,
+}
+
+impl Waker {
+    fn polling(poll_interval: Duration) -> Self {
+        Self {
+            watcher: None,
+            rx: mpsc::channel().1,
+            poll_interval,
+        }
+    }
+
+    fn new(path: &Path, poll_interval: Duration) -> Self {
+        if path.is_symlink() {
+            // its target can be anywhere, and can change
+            info!("`{}` is a symlink, it's polled", path.to_string_lossy());
+            return Self::polling(poll_interval);
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let name = path.file_name().map(ToOwned::to_owned);
+
+        // the directory is watched, so the file can be replaced
+        let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            let changed = event.is_ok_and(|event| {
+                event
+                    .paths
+                    .iter()
+                    .any(|path| path.file_name() == name.as_deref())
+            });
+
+            if changed {
+                let _ = tx.send(());
+            }
+        })
+        .and_then(|mut watcher| {
+            // unwrap() is safe, the path has been canonicalized
+            watcher.watch(path.parent().unwrap(), RecursiveMode::NonRecursive)?;
+            Ok(watcher)
+        });
+
+        match watcher {
+            Ok(watcher) => Self {
+                watcher: Some(watcher),
+                rx,
+                poll_interval,
+            },
+            Err(e) => {
+                warn!("The file can't be watched, it's polled: {}", e);
+                Self::polling(poll_interval)
+            }
+        }
+    }
+
+    /// Until the file changes
+    fn wait(&self) {
+        if self.watcher.is_none() {
+            return sleep(self.poll_interval);
+        }
+
+        let _ = self.rx.recv_timeout(NOTIFIED_WAIT_DURATION);
+        // a write can be notified many times
+        while self.rx.try_recv().is_ok() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::time::Instant;
+
+    #[test]
+    fn test_woken_up_by_a_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.log");
+        std::fs::write(&path, "").unwrap();
+
+        let waker = Waker::new(&path, Duration::from_secs(60));
+        assert!(waker.watcher.is_some());
+
+        let writer = std::thread::spawn({
+            let path = path.clone();
+            move || {
+                sleep(Duration::from_millis(100));
+                std::fs::write(path, "first\n").unwrap();
+            }
+        });
+
+        let start = Instant::now();
+        waker.wait();
+        assert!(start.elapsed() < NOTIFIED_WAIT_DURATION);
+        writer.join().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fifo_opened_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.pipe");
+        let status = std::process::Command::new("mkfifo")
+            .arg(&path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        assert!(is_fifo(&path));
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(4);
+        let source = Arc::new(Source::unsaved(path.clone()));
+        read_fifo(source, tx, Shutdown::new(), LineBreak::Byte);
+
+        // two writers, one after the other
+        for line in ["first\n", "second"] {
+            let mut fifo = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+            fifo.write_all(line.as_bytes()).unwrap();
+        }
+
+        let [line] = <[Line; 1]>::try_from(rx.blocking_recv().unwrap()).unwrap();
+        assert_eq!((line.cursor.line, &line.bytes[..]), (1, &b"first"[..]));
+        let [line] = <[Line; 1]>::try_from(rx.blocking_recv().unwrap()).unwrap();
+        assert_eq!(
+            (line.cursor.position, &line.bytes[..]),
+            (12, &b"second"[..])
+        );
+        assert_eq!(line.sequence, 2);
+    }
+
+    #[tokio::test]
+    async fn test_stops_once_the_publisher_has() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.log");
+        std::fs::write(&path, "first\nsecond\n").unwrap();
+
+        let (state_tx, _state_rx) = tokio::sync::watch::channel(Cursor::default());
+        let source = Arc::new(Source::new(path, state_tx));
+        let interval = Duration::from_millis(10);
+        let reader = Reader::new(
+            source,
+            Cursor::default(),
+            interval,
+            false,
+            None,
+            Arc::default(),
+        );
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+        drop(rx);
+
+        let stopped = reader.unwrap().work(tx);
+        tokio::time::timeout(Duration::from_secs(1), stopped.notified())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_waits_for_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.log");
+
+        let (state_tx, _state_rx) = tokio::sync::watch::channel(Cursor::default());
+        let source = Arc::new(Source::new(path.clone(), state_tx));
+        let interval = Duration::from_millis(10);
+        let shutdown = Shutdown::new();
+        let cursor = Cursor {
+            position: 3,
+            ..Cursor::default()
+        };
+        let reader = Reader::new(source, cursor, interval, false, None, Arc::default())
+            .unwrap()
+            .with_shutdown(shutdown.clone());
+        let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+        let stopped = reader.work(tx);
+
+        // it's read from its beginning once it's created
+        tokio::time::sleep(interval * 3).await;
+        std::fs::write(&path, "first\n").unwrap();
+        let batch = rx.recv().await.unwrap();
+        assert_eq!(batch[0].bytes, "first");
+
+        shutdown.trigger();
+        tokio::time::timeout(Duration::from_secs(1), stopped.notified())
+            .await
+            .unwrap();
+
+        // stopped while waiting
+        let (state_tx, _state_rx) = tokio::sync::watch::channel(Cursor::default());
+        let source = Arc::new(Source::new(dir.path().join("missing.log"), state_tx));
+        let shutdown = Shutdown::new();
+        let reader = Reader::new(source, cursor, interval, false, None, Arc::default())
+            .unwrap()
+            .with_shutdown(shutdown.clone());
+        let stopped = reader.work(tokio::sync::mpsc::channel(1).0);
+        shutdown.trigger();
+        tokio::time::timeout(Duration::from_secs(1), stopped.notified())
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut limiter = RateLimiter::new(20);
+        let started = Instant::now();
+
+        // a second worth of lines goes right away
+        assert!((0..20).all(|_| !limiter.acquire()));
+        assert!(limiter.acquire());
+        assert!(limiter.acquire());
+        assert!(started.elapsed() >= Duration::from_millis(100));
+    }
+}
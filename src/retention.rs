@@ -0,0 +1,159 @@
+//! Delete the oldest rotated files, so they don't fill the disk
+use regex::Regex;
+use std::fs::Metadata;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Rotated files are deleted as soon as one of the limits is exceeded
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Retention {
+    /// Number of rotated files to keep
+    pub count: Option<usize>,
+    /// Rotated files older than this are deleted
+    pub max_age: Option<Duration>,
+    /// The oldest rotated files are deleted once they all weigh more than this, in bytes
+    pub total_bytes: Option<u64>,
+}
+
+impl Retention {
+    pub fn is_enabled(&self) -> bool {
+        self.count.is_some() || self.max_age.is_some() || self.total_bytes.is_some()
+    }
+
+    /// Delete the rotated files exceeding the limits, returns the deleted files
+    ///
+    /// The rotated files are the ones of `dir` whose name is matched by `pattern`.
+    pub fn apply(&self, dir: &Path, pattern: &Regex) -> io::Result<Vec<PathBuf>> {
+        if !self.is_enabled() {
+            return Ok(vec![]);
+        }
+
+        let now = SystemTime::now();
+        let mut total_bytes = 0;
+        let mut deleted = vec![];
+
+        for (i, (path, metadata)) in rotated_files(dir, pattern)?.into_iter().enumerate() {
+            total_bytes += metadata.len();
+
+            let age = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| now.duration_since(modified).ok())
+                .unwrap_or_default();
+
+            let expired = self.count.is_some_and(|count| i >= count)
+                || self.max_age.is_some_and(|max_age| age > max_age)
+                || self.total_bytes.is_some_and(|max| total_bytes > max);
+
+            if expired {
+                debug!("Deleting the rotated file `{}`", path.to_string_lossy());
+                std::fs::remove_file(&path)?;
+                deleted.push(path);
+            }
+        }
+
+        Ok(deleted)
+    }
+}
+
+/// The rotated files, the most recent first
+fn rotated_files(dir: &Path, pattern: &Regex) -> io::Result<Vec<(PathBuf, Metadata)>> {
+    let mut files = vec![];
+
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+
+        if metadata.is_file() && pattern.is_match(&entry.file_name().to_string_lossy()) {
+            files.push((entry.path(), metadata));
+        }
+    }
+
+    // the date format is configurable, thus the modification time is more reliable than the name
+    files.sort_by_key(|(_, metadata)| std::cmp::Reverse(metadata.modified().ok()));
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    fn rotated(dir: &Path, name: &str, size: usize, age: u64) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, vec![b'a'; size]).unwrap();
+        let modified = SystemTime::now() - Duration::from_secs(age);
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+
+        path
+    }
+
+    fn pattern() -> Regex {
+        Regex::new(r"^app\.log\..+$").unwrap()
+    }
+
+    fn setup() -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let live = dir.path().join("app.log");
+        std::fs::write(&live, "live").unwrap();
+        std::fs::write(dir.path().join(".app.log.log-bouncer"), "state").unwrap();
+        std::fs::write(dir.path().join("other.log.2021-09-06"), "other").unwrap();
+
+        rotated(dir.path(), "app.log.2021-09-07", 10, 3 * 86400);
+        rotated(dir.path(), "app.log.2021-09-08", 10, 2 * 86400);
+        rotated(dir.path(), "app.log.2021-09-09", 10, 86400);
+
+        (dir, live)
+    }
+
+    fn names(paths: Vec<PathBuf>) -> Vec<String> {
+        paths
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_keep_count() {
+        let (_dir, live) = setup();
+        let retention = Retention {
+            count: Some(1),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            names(retention.apply(live.parent().unwrap(), &pattern()).unwrap()),
+            vec!["app.log.2021-09-08", "app.log.2021-09-07"]
+        );
+        assert!(live.exists());
+    }
+
+    #[test]
+    fn test_keep_days_and_bytes() {
+        let (_dir, live) = setup();
+        let retention = Retention {
+            max_age: Some(Duration::from_secs(2 * 86400 + 60)),
+            ..Default::default()
+        };
+        assert_eq!(
+            names(retention.apply(live.parent().unwrap(), &pattern()).unwrap()),
+            vec!["app.log.2021-09-07"]
+        );
+
+        let retention = Retention {
+            total_bytes: Some(15),
+            ..Default::default()
+        };
+        assert_eq!(
+            names(retention.apply(live.parent().unwrap(), &pattern()).unwrap()),
+            vec!["app.log.2021-09-08"]
+        );
+    }
+}
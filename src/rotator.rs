@@ -1,18 +1,35 @@
-use chrono::Utc;
+use crate::archive::Archiver;
+use crate::compression::Compression;
+use crate::ledger::{self, Ledger};
+use crate::metrics;
+use crate::output::Message;
+use crate::pipeline::template::Template;
+use crate::retention::Retention;
+use crate::schedule::Schedule;
+use crate::shutdown::Shutdown;
+use crate::state::{Cursor, FileId};
+use chrono::{DateTime, SecondsFormat, Utc};
+use regex::Regex;
+use serde_json::json;
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::{Read, Seek, Write};
-use std::path::PathBuf;
-use std::time::{Duration, SystemTime};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::fs;
-use tokio::io::SeekFrom;
-use tokio::sync::watch;
+use tokio::sync::{mpsc, watch, Notify};
 use tokio::task::JoinHandle;
 use tokio::time::MissedTickBehavior;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
-    #[error("corrupted saved state: {0}")]
-    CorruptedSavedState(String),
+    #[error("unknown placeholder `{{{0}}}` in the rotated filename")]
+    UnknownPlaceholder(String),
+    #[error("unknown rotate mode `{0}`, expected `rename` or `copytruncate`")]
+    UnknownRotateMode(String),
     #[error("i/o: {0}")]
     Io(#[from] std::io::Error),
     #[error("SystemTime: {0}")]
@@ -21,9 +38,108 @@ pub enum Error {
 
 type Result<T> = std::result::Result<T, Error>;
 
-/// Rotator has 2 missions
-///   1. Rotate at launch if target file exists
-///   2. Check periodically if file is larger than defined size then rotate
+/// Header telling the published rotation events apart from the lines
+pub const EVENT_HEADER: &str = "x-event";
+
+const ROTATIONS_METRIC: &str = "log_bouncer_rotations_total";
+const ROTATION_FAILURES_METRIC: &str = "log_bouncer_rotation_failures_total";
+const ROTATION_DURATION_METRIC: &str = "log_bouncer_rotation_duration_seconds";
+const ROTATED_BYTES_METRIC: &str = "log_bouncer_rotated_bytes_total";
+const EMERGENCY_ROTATIONS_METRIC: &str = "log_bouncer_emergency_rotations_total";
+
+/// How the live file is turned into the rotated one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RotateMode {
+    /// Rename the file, then create a new one
+    Rename,
+    /// Copy the file, then truncate it in place, for the applications keeping their fd open.
+    /// The lines written in between the copy and the truncation are lost.
+    CopyTruncate,
+}
+
+impl FromStr for RotateMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "rename" => Ok(RotateMode::Rename),
+            "copytruncate" => Ok(RotateMode::CopyTruncate),
+            _ => Err(Error::UnknownRotateMode(s.to_owned())),
+        }
+    }
+}
+
+/// When and how the file gets rotated
+#[derive(Debug, Clone)]
+pub struct RotationPolicy {
+    pub mode: RotateMode,
+    /// Rotate after reaching this file size
+    pub max_size: u64,
+    /// Rotate once this number of lines has been read
+    pub max_lines: Option<u64>,
+    /// Rotate on a schedule too, even if the file hasn't reached `max_size`
+    pub schedule: Option<Schedule>,
+    /// Name of the rotated files, see [`Rotator::rotated_path`]
+    pub filename: Template,
+    /// Date format the logs will contain once rotated
+    pub date_format: String,
+    /// Which rotated files are kept
+    pub retention: Retention,
+    /// Shell command run after each rotation
+    pub post_rotate_hook: Option<String>,
+    /// Move the rotated files into this directory rather than next to the live file
+    pub rotate_dir: Option<PathBuf>,
+    /// Upload the rotated files to an object storage
+    pub archiver: Option<Arc<Archiver>>,
+    /// Defer the rotation until the whole file has been published, or this timeout elapsed
+    pub catch_up_timeout: Option<Duration>,
+    /// Record each rotation in this ledger
+    pub ledger: Option<Ledger>,
+    /// Rotate right away once the filesystem gets fuller than this percentage, the rotated file
+    /// is compressed right away too
+    pub max_disk_usage: Option<f64>,
+    /// Compress the rotated files, in the background
+    pub compression: Option<Compression>,
+}
+
+/// Where one file ends and the next begins, for the downstream systems
+#[derive(Debug, Clone, PartialEq)]
+pub struct RotationEvent {
+    pub old_filename: PathBuf,
+    pub new_filename: PathBuf,
+    /// Size of the rotated file, in bytes
+    pub size: u64,
+    /// Time it took to rotate the file
+    pub duration: Duration,
+}
+
+impl RotationEvent {
+    pub fn to_json(&self) -> String {
+        json!({
+            "event": "rotated",
+            "old_filename": self.old_filename,
+            "new_filename": self.new_filename,
+            "size": self.size,
+            "duration_ms": self.duration.as_millis() as u64,
+            "@timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        })
+        .to_string()
+    }
+
+    /// Turn the event into what the outputs publish, it has no position in the file
+    pub fn into_message(self) -> Message {
+        let mut headers = BTreeMap::new();
+        headers.insert(EVENT_HEADER.to_owned(), "rotated".to_owned());
+
+        Message {
+            payload: self.to_json(),
+            headers,
+            ..Message::default()
+        }
+    }
+}
+
+/// Check periodically if file is larger than defined size, or if it's scheduled, then rotate
 ///
 /// The rotate will rename the file from `input.log` to `input-%Y-%m-%d-%H-%M-%S.log`
 /// eg. `systemd.log.2021-09-07-03-37-53`
@@ -32,120 +148,571 @@ pub struct Rotator {
     filepath: PathBuf,
     /// Rotation checks interval
     rotation_interval: Duration,
-    /// Save state interval
-    save_state_interval: Duration,
-    /// Receive the current offset position on the file
-    state_rx: watch::Receiver<u64>,
-    /// The SavedState will be saved in a file.
-    state: SavedState,
-    /// Date format the logs will contain once rotated
-    date_format: String,
-    /// Rotate after reaching this file size
-    max_size: u64,
-    /// The position that has to be resumed from
-    pos: u64,
+    /// Receive the current cursor on the file
+    state_rx: watch::Receiver<Cursor>,
+    /// Reset the saved state once the file has been rotated
+    state_reset: Arc<Notify>,
+    /// When and how the file gets rotated
+    policy: RotationPolicy,
+    /// Next time the file has to be rotated, if it's scheduled
+    next_scheduled: Option<DateTime<Utc>>,
+    /// Rotate without waiting for the next check
+    rotate_now: Arc<Notify>,
+    /// When the rotation became due, while waiting for the publisher to catch up
+    pending_since: Option<Instant>,
+    /// Line count of the cursor at the last rotation, until the reader switches to the new file
+    line_base: u64,
+    /// Publish an event after each rotation
+    events: Option<mpsc::Sender<Message>>,
+    /// Identity and size of the file at the last check, to notice when another tool rotates it
+    seen: Option<(Option<FileId>, u64)>,
+    /// The filesystem is fuller than `max_disk_usage`
+    disk_full: bool,
+    /// Stop checking the file, a rotation in progress is completed first
+    shutdown: Shutdown,
 }
 
 impl Rotator {
     pub fn new(
         filepath: PathBuf,
         rotation_interval: Duration,
-        save_state_interval: Duration,
-        state_rx: watch::Receiver<u64>,
-        max_size: u64,
-        date_format: String,
+        state_rx: watch::Receiver<Cursor>,
+        state_reset: Arc<Notify>,
+        policy: RotationPolicy,
     ) -> Result<Self> {
         info!("Watching the logfile `{}`...", filepath.to_string_lossy());
 
-        // create if the file hasn't been created
-        let _file = Rotator::touch_file(&filepath)?;
+        if let Some(dir) = &policy.rotate_dir {
+            std::fs::create_dir_all(dir)?;
+        }
 
-        let mut saved_state = SavedState::new(&filepath)?;
+        if let Some(name) = policy
+            .filename
+            .placeholders()
+            .find(|name| !["name", "stem", "ext", "date", "seq"].contains(name))
+        {
+            return Err(Error::UnknownPlaceholder(name.to_owned()));
+        }
 
-        let pos = Self::recover_position(&mut saved_state)?;
+        let registry = metrics::registry();
+        registry.describe(ROTATIONS_METRIC, "Files rotated", None);
+        registry.describe(ROTATION_FAILURES_METRIC, "Rotations that failed", None);
+        registry.describe(
+            ROTATION_DURATION_METRIC,
+            "Time it took to rotate the file",
+            None,
+        );
+        registry.describe(ROTATED_BYTES_METRIC, "Size of the rotated files", None);
+        registry.describe(
+            EMERGENCY_ROTATIONS_METRIC,
+            "Rotations triggered by the disk usage",
+            None,
+        );
 
         Ok(Self {
             filepath: filepath.to_owned(),
-            date_format,
             state_rx,
-            state: saved_state,
-            max_size,
+            state_reset,
+            next_scheduled: policy
+                .schedule
+                .as_ref()
+                .and_then(|schedule| schedule.next_after(Utc::now())),
+            policy,
             rotation_interval,
-            save_state_interval,
-            pos,
+            rotate_now: Arc::new(Notify::new()),
+            pending_since: None,
+            line_base: 0,
+            events: None,
+            seen: None,
+            disk_full: false,
+            shutdown: Shutdown::new(),
         })
     }
 
-    /// Get position we should start to read the file from
-    pub fn get_position(&self) -> u64 {
-        self.pos
+    /// Publish the rotation events on the output, through the publisher
+    pub fn with_events(mut self, events: mpsc::Sender<Message>) -> Self {
+        self.events = Some(events);
+        self
     }
 
-    /// Create or use a file
-    fn touch_file(filename: &PathBuf) -> Result<File> {
-        filename.to_str().expect("Invalid path");
+    /// The task returns once it's triggered, see [`watch`]
+    ///
+    /// [`watch`]: Self::watch
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
 
-        let file = std::fs::OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(false)
-            .open(filename)?;
+    async fn check_file_exists(&self) -> Result<bool> {
+        let metadata = fs::metadata(&self.filepath).await?;
 
-        Ok(file)
+        Ok(metadata.is_file())
     }
 
-    fn recover_position(saved_state: &mut SavedState) -> Result<u64> {
-        match saved_state.read_file() {
-            Ok(pos) => {
-                info!("Saved state exists, we recover it");
-                Ok(pos)
+    async fn can_be_rotated(&mut self) -> Result<bool> {
+        if !self.check_file_exists().await? {
+            return Ok(false);
+        }
+
+        let metadata = fs::metadata(&self.filepath).await?;
+
+        if metadata.len() > self.policy.max_size {
+            return Ok(true);
+        }
+
+        if let Some(max_lines) = self.policy.max_lines {
+            if metadata.len() > 0 && self.lines_since_rotation() >= max_lines {
+                return Ok(true);
             }
-            Err(e) => match e {
-                Error::CorruptedSavedState(_) => {
-                    warn!("Corrupted saved state, we create a new one");
-                    let pos = 0; // starts from scratch
-                    saved_state.save(pos).unwrap();
-                    Ok(pos)
-                }
-                _ => Err(e),
-            },
+        }
+
+        let now = Utc::now();
+        match (&self.policy.schedule, self.next_scheduled) {
+            (Some(schedule), Some(next)) if now >= next => {
+                self.next_scheduled = schedule.next_after(now);
+                debug!(
+                    "Scheduled rotation, the next one is at {:?}",
+                    self.next_scheduled
+                );
+
+                // there's no point in archiving an empty file
+                Ok(metadata.len() > 0)
+            }
+            _ => Ok(false),
         }
     }
 
-    async fn check_file_exists(&self) -> Result<bool> {
+    /// Rotate the file, the post-rotation steps run in the background
+    async fn rotate(&self) -> Result<PathBuf> {
+        let rotated = self.move_aside().await?;
+
+        // the compression, the hook and the upload can take a while, the rotator keeps saving
+        // the state meanwhile
+        tokio::spawn(self.post_rotate(&rotated, self.policy.compression));
+
+        self.apply_retention();
+
+        Ok(rotated)
+    }
+
+    /// Rotate the file, then compress it before anything else, to free some room on the disk.
+    /// The retention applies to the compressed file as well
+    async fn rotate_urgently(&self) -> Result<PathBuf> {
+        let rotated = self.move_aside().await?;
+        let compression = self.policy.compression.unwrap_or_default();
+        let rotated = compress(compression, rotated).await;
+
+        tokio::spawn(self.post_rotate(&rotated, None));
+
+        self.apply_retention();
+
+        Ok(rotated)
+    }
+
+    /// Rotate the file, and wait for the post-rotation steps to be done
+    pub async fn rotate_once(&self) -> Result<PathBuf> {
+        let rotated = self.move_aside().await?;
+        self.post_rotate(&rotated, self.policy.compression).await;
+        self.apply_retention();
+
+        Ok(rotated)
+    }
+
+    /// Move a file then create a new one
+    async fn move_aside(&self) -> Result<PathBuf> {
+        let started = Instant::now();
+        let new_filename = self.rotated_path(Utc::now());
+        debug!("Renaming {:?} to {:?}...", &self.filepath, new_filename);
+
         let metadata = fs::metadata(&self.filepath).await?;
 
-        Ok(metadata.is_file())
+        match self.policy.mode {
+            RotateMode::Rename => {
+                // a power loss mustn't leave us with a rotated file missing its last lines
+                File::open(&self.filepath)?.sync_all()?;
+                move_file(&self.filepath, &new_filename).await?;
+                // then create a new file
+                File::create(&self.filepath)?.sync_all()?;
+
+                // the renaming and the creation are durable once the directories are synced
+                // unwrap() is safe, the path has been canonicalized
+                let live_dir = self.filepath.parent().unwrap();
+                sync_dir(live_dir)?;
+                if self.rotated_dir() != live_dir {
+                    sync_dir(&self.rotated_dir())?;
+                }
+
+                // otherwise the application may not be able to write to it
+                #[cfg(unix)]
+                if let Err(e) = copy_ownership(&self.filepath, &metadata) {
+                    warn!("Can't preserve the ownership of the new file: `{}`", e);
+                }
+            }
+            RotateMode::CopyTruncate => {
+                fs::copy(&self.filepath, &new_filename).await?;
+                // the copy has to be on the disk before the original gets truncated
+                File::open(&new_filename)?.sync_all()?;
+                sync_dir(&self.rotated_dir())?;
+
+                // the writers keep appending to the same file, from the beginning
+                let file = std::fs::OpenOptions::new()
+                    .write(true)
+                    .open(&self.filepath)?;
+                file.set_len(0)?;
+                file.sync_all()?;
+            }
+        }
+
+        info!("File rotated to `{}`", new_filename.to_string_lossy());
+
+        let event = RotationEvent {
+            old_filename: self.filepath.clone(),
+            new_filename: new_filename.clone(),
+            size: metadata.len(),
+            duration: started.elapsed(),
+        };
+        self.record(event);
+
+        Ok(new_filename)
     }
 
-    async fn can_be_rotated(&self) -> Result<bool> {
-        if !self.check_file_exists().await? {
-            return Ok(false);
+    /// Compress the rotated file if asked, run the hook, then upload it
+    fn post_rotate(
+        &self,
+        rotated: &Path,
+        compression: Option<Compression>,
+    ) -> impl Future<Output = ()> + Send + 'static {
+        let hook = self.policy.post_rotate_hook.clone();
+        let archiver = self.policy.archiver.clone();
+        let old_filename = self.filepath.clone();
+        let rotated = rotated.to_path_buf();
+
+        async move {
+            let rotated = match compression {
+                Some(compression) => compress(compression, rotated).await,
+                None => rotated,
+            };
+
+            if let Some(command) = hook {
+                match run_hook(&command, &old_filename, &rotated).await {
+                    Ok(status) if status.success() => debug!("Post-rotate hook succeeded"),
+                    Ok(status) => error!("Post-rotate hook failed: {}", status),
+                    Err(e) => error!("Can't run the post-rotate hook: `{}`", e),
+                }
+            }
+
+            if let Some(archiver) = archiver {
+                if let Err(e) = archiver.archive(&rotated).await {
+                    error!("Can't archive `{}`: {}", rotated.to_string_lossy(), e);
+                }
+            }
         }
+    }
 
-        let metadata = fs::metadata(&self.filepath).await?;
+    /// Update the metrics and the ledger, and publish the event if enabled
+    fn record(&self, event: RotationEvent) {
+        let registry = metrics::registry();
+        let mode = match self.policy.mode {
+            RotateMode::Rename => "rename",
+            RotateMode::CopyTruncate => "copytruncate",
+        };
+        registry.increment(ROTATIONS_METRIC, &[("mode", mode)], 1);
+        registry.increment(ROTATED_BYTES_METRIC, &[], event.size);
+        registry.observe(ROTATION_DURATION_METRIC, &[], event.duration.as_secs_f64());
+
+        if let Some(ledger) = &self.policy.ledger {
+            let published = *self.state_rx.borrow();
+            let entry = ledger::Entry {
+                rotated_at: Utc::now(),
+                old_filename: event.old_filename.clone(),
+                new_filename: event.new_filename.clone(),
+                size: event.size,
+                published_position: published.position,
+                published_line: published.line,
+            };
+
+            if let Err(e) = ledger.record(&entry) {
+                error!("Can't record the rotation in the ledger: `{}`", e);
+            }
+        }
+
+        if let Some(events) = &self.events {
+            // never blocks the rotation, the publisher may be stuck on the output
+            if let Err(e) = events.try_send(event.into_message()) {
+                warn!("Rotation event discarded: {}", e);
+            }
+        }
+    }
 
-        if metadata.len() > self.max_size {
-            Ok(true)
+    /// Whether the filesystem just got fuller than the threshold, then the file is rotated right
+    /// away, once, until the usage goes back under the threshold
+    fn disk_usage_exceeded(&mut self) -> bool {
+        let threshold = match self.policy.max_disk_usage {
+            Some(threshold) => threshold,
+            None => return false,
+        };
+
+        match disk_usage(&self.filepath) {
+            Ok(usage) => self.crossed_disk_usage(usage, threshold),
+            Err(e) => {
+                debug!("Can't get the disk usage: `{}`", e);
+                false
+            }
+        }
+    }
+
+    fn crossed_disk_usage(&mut self, usage: f64, threshold: f64) -> bool {
+        let was_full = std::mem::replace(&mut self.disk_full, usage >= threshold);
+        if !self.disk_full || was_full {
+            return false;
+        }
+
+        warn!(
+            "The filesystem is {:.1}% full, over {}%, rotating the file right away",
+            usage, threshold
+        );
+        metrics::registry().increment(EMERGENCY_ROTATIONS_METRIC, &[], 1);
+
+        if let Some(events) = &self.events {
+            let mut headers = BTreeMap::new();
+            headers.insert(EVENT_HEADER.to_owned(), "disk_usage".to_owned());
+            let payload = json!({
+                "event": "disk_usage",
+                "filename": self.filepath,
+                "usage": usage,
+                "threshold": threshold,
+                "@timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            });
+            let message = Message {
+                payload: payload.to_string(),
+                headers,
+                ..Message::default()
+            };
+
+            if let Err(e) = events.try_send(message) {
+                warn!("Disk usage event discarded: {}", e);
+            }
+        }
+
+        true
+    }
+
+    /// Lines published since the last rotation
+    fn lines_since_rotation(&mut self) -> u64 {
+        let line = self.state_rx.borrow().line;
+
+        // the reader moved to the new file, its count has been reset
+        if line < self.line_base {
+            self.line_base = 0;
+        }
+
+        line - self.line_base
+    }
+
+    /// Whether the publisher has read the whole file, or has been waited for long enough
+    async fn has_caught_up(&mut self) -> bool {
+        let timeout = match self.policy.catch_up_timeout {
+            Some(timeout) => timeout,
+            None => return true,
+        };
+        let pending_since = *self.pending_since.get_or_insert_with(Instant::now);
+
+        let len = match fs::metadata(&self.filepath).await {
+            Ok(metadata) => metadata.len(),
+            Err(_) => return true,
+        };
+        let position = self.state_rx.borrow().position;
+
+        if position >= len {
+            true
+        } else if pending_since.elapsed() >= timeout {
+            warn!(
+                "The publisher hasn't caught up in time, rotating with {} bytes left unread",
+                len - position
+            );
+            true
         } else {
-            Ok(false)
+            debug!(
+                "Rotation deferred, {} bytes left to publish",
+                len - position
+            );
+            false
         }
     }
 
-    /// Move a file then create a new one
-    async fn rotate(&self) -> Result<()> {
-        let now = Utc::now();
-        let timestamp = now.format(&self.date_format).to_string();
-        let new_filename = format!("{}.{}", self.filepath.to_str().unwrap(), timestamp);
-        debug!("Renaming {:?} to `{}`...", &self.filepath, new_filename);
+    async fn rotate_and_reset(&mut self, urgently: bool) {
+        self.pending_since = None;
+        self.seen = None;
+        self.line_base = self.state_rx.borrow().line;
 
-        fs::rename(&self.filepath, &new_filename).await?;
-        // then create a new file
-        File::create(&self.filepath)?;
+        let rotated = match urgently {
+            true => self.rotate_urgently().await,
+            false => self.rotate().await,
+        };
+        if let Err(e) = rotated {
+            error!("Can't rotate the file: `{}`", e);
+            metrics::registry().increment(ROTATION_FAILURES_METRIC, &[], 1);
+            return;
+        }
 
-        info!("File rotated to `{}`", new_filename);
+        // file has been rotated, we reset the last position
+        self.state_reset.notify_one();
+    }
 
-        Ok(())
+    /// Notify it to rotate the file right away, whatever its size
+    pub fn rotate_trigger(&self) -> Arc<Notify> {
+        self.rotate_now.clone()
+    }
+
+    /// Directory the rotated files are moved into
+    fn rotated_dir(&self) -> PathBuf {
+        match &self.policy.rotate_dir {
+            Some(dir) => dir.clone(),
+            // unwrap() is safe, the path has been canonicalized
+            None => self.filepath.parent().unwrap().to_path_buf(),
+        }
+    }
+
+    /// Value of the filename template placeholders, except `{date}` and `{seq}`
+    fn filename_placeholder(&self, name: &str) -> Option<String> {
+        let path = Path::new(self.filepath.file_name()?);
+
+        match name {
+            "name" => Some(path.to_string_lossy().into_owned()),
+            "stem" => Some(path.file_stem()?.to_string_lossy().into_owned()),
+            "ext" => Some(
+                path.extension()
+                    .map(|ext| ext.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Render the filename template: `{name}` of the file, its `{stem}` and `{ext}`, the `{date}`
+    /// and `{seq}`, the smallest counter avoiding a collision.
+    ///
+    /// When the template doesn't contain `{seq}`, it's appended as `.{seq}` on collision.
+    fn rotated_path(&self, now: DateTime<Utc>) -> PathBuf {
+        let dir = self.rotated_dir();
+        let date = now.format(&self.policy.date_format).to_string();
+        let render = |seq: Option<u64>| {
+            let name = self.policy.filename.render(|name| match name {
+                "date" => Some(date.clone()),
+                "seq" => seq.map(|seq| seq.to_string()),
+                name => self.filename_placeholder(name),
+            });
+
+            // unwrap() is safe, the placeholders have been checked when built
+            dir.join(name.unwrap())
+        };
+
+        // nor is a compressed one overwritten
+        let is_free = |path: &PathBuf| {
+            !path.exists()
+                && path != &self.filepath
+                && Compression::ALL
+                    .iter()
+                    .all(|compression| !compression.path(path).exists())
+        };
+
+        // unwrap() are safe, the ranges are infinite
+        if self
+            .policy
+            .filename
+            .placeholders()
+            .any(|name| name == "seq")
+        {
+            (1..).map(|seq| render(Some(seq))).find(is_free).unwrap()
+        } else {
+            let path = render(None);
+            let suffixed = (1..).map(|seq| PathBuf::from(format!("{}.{}", path.display(), seq)));
+
+            std::iter::once(path.clone())
+                .chain(suffixed)
+                .find(is_free)
+                .unwrap()
+        }
+    }
+
+    /// Regex matching the names of the rotated files
+    fn rotated_pattern(&self) -> Regex {
+        self.filename_pattern(".+")
+    }
+
+    /// Regex matching the rotated filenames, with `date` matching the `{date}` placeholder
+    fn filename_pattern(&self, date: &str) -> Regex {
+        let pattern = self.policy.filename.to_regex(|name| match name {
+            "date" => date.to_owned(),
+            "seq" => "[0-9]+".to_owned(),
+            name => regex::escape(&self.filename_placeholder(name).unwrap_or_default()),
+        });
+
+        // unwrap() is safe, every part has been escaped
+        Regex::new(&format!(r"^{}(\.[0-9]+)?(\.(gz|zst))?$", pattern)).unwrap()
+    }
+
+    /// Whether files rotated by another tool lie next to the live file, eg. logrotate's `file.1`,
+    /// `file.2.gz` or `file-20210907.gz`
+    fn has_external_rotated_files(&self) -> bool {
+        let name = regex::escape(&self.filename_placeholder("name").unwrap_or_default());
+        // unwrap() is safe, the name has been escaped
+        let external = Regex::new(&format!(
+            r"^{}(?:(?:\.[0-9]+|-[0-9]{{8,10}})(?:\.(?:gz|bz2|xz|zst))?|\.(?:gz|bz2|xz|zst))$",
+            name
+        ))
+        .unwrap();
+        // ours can look alike, eg. `file.1` with `{name}.{seq}`
+        let ours = self.filename_pattern(&date_shape(&self.policy.date_format));
+
+        // unwrap() is safe, the path has been canonicalized
+        let entries = match std::fs::read_dir(self.filepath.parent().unwrap()) {
+            Ok(entries) => entries,
+            Err(_) => return false,
+        };
+
+        entries.flatten().any(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            external.is_match(&name) && !ours.is_match(&name)
+        })
+    }
+
+    /// Whether the file has been replaced or truncated since the last check, while we didn't
+    /// rotate it
+    async fn rotated_externally(&mut self) -> bool {
+        let (metadata, file_id) = match (
+            fs::metadata(&self.filepath).await,
+            FileId::of_path(&self.filepath),
+        ) {
+            (Ok(metadata), Ok(file_id)) => (metadata, file_id),
+            _ => return false, // it's being replaced
+        };
+        let current = (file_id, metadata.len());
+
+        match self.seen.replace(current) {
+            Some((file_id, len)) => current.0 != file_id || current.1 < len,
+            None => false,
+        }
+    }
+
+    /// Delete the rotated files that don't have to be kept anymore
+    fn apply_retention(&self) {
+        let live = self.filepath.file_name().map(|name| name.to_string_lossy());
+        let pattern = self.rotated_pattern();
+        if live.is_some_and(|live| pattern.is_match(&live)) && self.policy.rotate_dir.is_none() {
+            warn!("The rotated filename template matches the live file, the retention is skipped");
+            return;
+        }
+
+        match self.policy.retention.apply(&self.rotated_dir(), &pattern) {
+            Ok(deleted) => {
+                for path in deleted {
+                    info!("Rotated file `{}` deleted", path.to_string_lossy());
+                }
+            }
+            Err(e) => error!("Can't apply the retention policy: `{}`", e),
+        }
     }
 
     /// Launch the cron job
@@ -160,179 +727,536 @@ impl Rotator {
             self.rotation_interval.as_millis()
         );
         let mut rotate_interval = tokio::time::interval(self.rotation_interval);
-        let mut state_interval = tokio::time::interval(self.save_state_interval);
 
         // don't catch up the missed ticks
         rotate_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
-        state_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
 
         // first tick completes immediately
         rotate_interval.tick().await;
-        state_interval.tick().await;
+
+        if self.has_external_rotated_files() {
+            warn!("The file is rotated by another tool, eg. logrotate, the rotation is disabled");
+            return;
+        }
+
+        // the retention may have changed since the last launch
+        self.apply_retention();
+
+        let rotate_now = self.rotate_now.clone();
+        let shutdown = self.shutdown.clone();
 
         loop {
             tokio::select! {
-                // _ = rotate_interval.tick() => {
-                //     trace!("Tick(rotate): do a job");
-                //     match self.can_be_rotated().await {
-                //         Ok(res) => {
-                //             if res {
-                //                 if let Err(e) = self.rotate().await {
-                //                     error!("Can't rotate the file: `{}`", e);
-                //                 } else {
-                //                     // file has been rotated, we reset the last position
-                //                     if let Err(e) = self.state.reset() {
-                //                         error!("Can't reset the state, after rotating the file: `{}`", e);
-                //                     }
-                //
-                //                     // we discard this value as we just changed the file
-                //                     let _pos = *self.state_rx.borrow_and_update();
-                //                 }
-                //             } else {
-                //                 debug!("File can't be rotated, yet");
-                //             }
-                //         }
-                //         Err(e) => debug!("Can't rotate the file: `{}`", e),
-                //     }
-                // }
-                _ = state_interval.tick() => {
-                    trace!("Tick(state): do a job");
-
-                    // THIS BLOCKS THE THIS ENTIRE LOOP THREAD,
-                    // which is okay as we don't need to check the file every X seconds if nothing
-                    // has been written in it.
-                    self.state_rx.changed().await.expect("State_rx::changed() failed");
-
-                    // get the value
-                    let pos = *self.state_rx.borrow_and_update();
-
-                    if let Err(e) = self.state.save(pos) {
-                        error!("Can't save current state: `{}`", e);
+                _ = rotate_interval.tick() => {
+                    trace!("Tick(rotate): do a job");
+                    if self.rotated_externally().await {
+                        warn!("The file has been rotated by another tool, the rotation is disabled");
+                        break;
+                    }
+
+                    // the publisher isn't waited for, the disk is about to be full
+                    if self.disk_usage_exceeded() {
+                        self.rotate_and_reset(true).await;
+                        continue;
+                    }
+
+                    let due = self.pending_since.is_some() || match self.can_be_rotated().await {
+                        Ok(res) => res,
+                        Err(e) => {
+                            debug!("Can't rotate the file: `{}`", e);
+                            false
+                        }
+                    };
+
+                    if !due {
+                        debug!("File can't be rotated, yet");
+                    } else if self.has_caught_up().await {
+                        self.rotate_and_reset(false).await;
                     }
                 }
+                _ = rotate_now.notified() => {
+                    info!("Rotation requested");
+                    self.rotate_and_reset(false).await;
+                }
+                _ = shutdown.triggered() => break,
             }
         }
     }
 }
 
-use crc::{Algorithm, Crc, CRC_32_ISCSI};
-pub const HASHER: Crc<u32> = Crc::<u32>::new(&CRC_32_ISCSI);
+/// Regex matching the dates formatted with `format`, assuming their width doesn't vary
+fn date_shape(format: &str) -> String {
+    Utc::now()
+        .format(format)
+        .to_string()
+        .chars()
+        .map(|c| match c {
+            '0'..='9' => "[0-9]".to_owned(),
+            c if c.is_alphabetic() => "[[:alpha:]]".to_owned(),
+            c => regex::escape(&c.to_string()),
+        })
+        .collect()
+}
+
+/// Percentage of the filesystem of `path` that is used, as seen by an unprivileged user
+fn disk_usage(path: &Path) -> std::io::Result<f64> {
+    let total = fs2::total_space(path)?;
+    if total == 0 {
+        return Ok(0.0);
+    }
 
-/// The SavedState will be saved in a file.
-pub struct SavedState {
-    /// Filename of the log file in order to get the first line
-    filepath: PathBuf,
-    /// State file
-    state_file: File,
-    /// Last position saved
-    /// To make sure to not trigger writes every time for nothing
-    position: u64,
+    let available = fs2::available_space(path)?;
+    Ok(100.0 - available as f64 * 100.0 / total as f64)
 }
 
-impl SavedState {
-    pub fn new(filepath: &PathBuf) -> Result<Self> {
-        // get the filename of the logfile
-        let file_name = (*filepath)
-            .file_name()
-            .expect("Can't get the filename of the logfile")
-            .to_str()
-            .unwrap();
-        // using the same directory for our saved state
-        let mut state_filepath = filepath
-            .parent()
-            .expect("Can not get parent directory")
-            .to_path_buf();
-        let state_filename = format!(".{}.log-bouncer", file_name);
-
-        // using the same directory but a different filename (prefixed with ".")
-        state_filepath.push(state_filename);
-
-        debug!(
-            "Store the state in the file `{}`",
-            state_filepath.to_string_lossy()
-        );
+/// Flush the entries of the directory, so a renaming or a creation survives a power loss
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> std::io::Result<()> {
+    File::open(path)?.sync_all()
+}
 
-        let state_file = std::fs::OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .truncate(false)
-            .open(&state_filepath)?;
+/// Directories can't be opened on this platform
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
 
-        Ok(Self {
-            filepath: filepath.to_owned(),
-            state_file,
-            position: 0,
-        })
+/// Give the file the mode, owner and group of the rotated one, changing the owner requires to
+/// run as root or with `CAP_CHOWN`
+#[cfg(unix)]
+fn copy_ownership(path: &Path, metadata: &std::fs::Metadata) -> std::io::Result<()> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let current = std::fs::metadata(path)?;
+    if current.uid() != metadata.uid() || current.gid() != metadata.gid() {
+        std::os::unix::fs::chown(path, Some(metadata.uid()), Some(metadata.gid()))?;
     }
 
-    /// Recover the saved state if exists
-    pub fn read_file(&mut self) -> Result<u64> {
-        let mut string = String::new();
-        self.state_file.read_to_string(&mut string)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(metadata.mode()))
+}
 
-        let state = string
-            .split(";")
-            .map(|e| e.parse::<u64>())
-            .filter_map(std::result::Result::ok)
-            .collect::<Vec<u64>>();
+/// Compress the rotated file, it's kept as is if it can't be
+async fn compress(compression: Compression, rotated: PathBuf) -> PathBuf {
+    match compression.compress(&rotated).await {
+        Ok(compressed) => {
+            debug!("Rotated file compressed to {:?}", compressed);
+            compressed
+        }
+        Err(e) => {
+            error!("Can't compress `{}`: {}", rotated.to_string_lossy(), e);
+            rotated
+        }
+    }
+}
 
-        if state.len() != 2 {
-            Err(Error::CorruptedSavedState(
-                "State should contains 2 entries".into(),
-            ))?;
+/// Rename the file, or copy then delete it if the destination is on another filesystem
+async fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    match fs::rename(from, to).await {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            debug!("{:?} is on another filesystem, copying the file", to);
+            fs::copy(from, to).await?;
+            File::open(to)?.sync_all()?;
+            fs::remove_file(from).await
         }
+        result => result,
+    }
+}
 
-        // we recover file's uniq id, which is a u32
-        let uniq_id = *state.get(0).unwrap() as u32; // unwrap() is safe here
-        debug!("Recovered uniq_id of the file `{}`", uniq_id);
+/// Run the command with `sh -c` (`cmd /C` on Windows), the filenames are passed as environment
+/// variables
+async fn run_hook(
+    command: &str,
+    old_filename: &Path,
+    new_filename: &Path,
+) -> std::io::Result<ExitStatus> {
+    #[cfg(not(windows))]
+    let (shell, flag) = ("sh", "-c");
+    #[cfg(windows)]
+    let (shell, flag) = ("cmd", "/C");
 
-        if uniq_id == self.get_uniq_id()? {
-            // same file, we recover the saved position
-            Ok(state.get(1).unwrap().clone()) // unwrap() is safe here too
-        } else {
-            // this is a new file, we start from 0
-            Ok(0)
+    tokio::process::Command::new(shell)
+        .arg(flag)
+        .arg(command)
+        .env("LOG_BOUNCER_OLD_FILENAME", old_filename)
+        .env("LOG_BOUNCER_NEW_FILENAME", new_filename)
+        .status()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> RotationPolicy {
+        RotationPolicy {
+            mode: RotateMode::Rename,
+            max_size: 1,
+            max_lines: None,
+            schedule: None,
+            filename: Template::parse("{name}.{date}").unwrap(),
+            date_format: "%Y".to_owned(),
+            retention: Retention::default(),
+            post_rotate_hook: None,
+            rotate_dir: None,
+            archiver: None,
+            catch_up_timeout: None,
+            ledger: None,
+            max_disk_usage: None,
+            compression: None,
         }
     }
+    #[tokio::test]
+    async fn test_copytruncate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.log");
+        std::fs::write(&path, "first\nsecond\n").unwrap();
 
-    /// Get the `created_at` from the file, converted to a timestamp
-    ///
-    /// Seems to not work on a docker image... because of being built in static?
-    pub fn get_uniq_id(&self) -> Result<u32> {
-        use std::io::{BufRead, BufReader, Cursor};
+        let (_state_tx, state_rx) = watch::channel(Cursor::default());
+        let policy = RotationPolicy {
+            mode: RotateMode::CopyTruncate,
+            ..policy()
+        };
+        let interval = Duration::from_secs(1);
+        let rotator =
+            Rotator::new(path.clone(), interval, state_rx, Arc::default(), policy).unwrap();
+        let file_id = FileId::of_path(&path).unwrap();
+
+        rotator.rotate().await.unwrap();
+
+        let rotated = dir
+            .path()
+            .join(format!("test.log.{}", Utc::now().format("%Y")));
+        assert_eq!(std::fs::read_to_string(rotated).unwrap(), "first\nsecond\n");
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+        assert_eq!(FileId::of_path(&path).unwrap(), file_id);
+    }
+
+    #[tokio::test]
+    async fn test_rotation_event() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.log");
+        std::fs::write(&path, "first\nsecond\n").unwrap();
+
+        let (_state_tx, state_rx) = watch::channel(Cursor::default());
+        let (events_tx, mut events_rx) = mpsc::channel(1);
+        let interval = Duration::from_secs(1);
+        let rotator = Rotator::new(path.clone(), interval, state_rx, Arc::default(), policy())
+            .unwrap()
+            .with_events(events_tx);
+
+        let rotated = rotator.rotate().await.unwrap();
+
+        let message = events_rx.try_recv().unwrap();
+        assert_eq!(message.headers[EVENT_HEADER], "rotated");
+
+        let payload: serde_json::Value = serde_json::from_str(&message.payload).unwrap();
+        assert_eq!(payload["event"], "rotated");
+        assert_eq!(payload["old_filename"], path.to_string_lossy().as_ref());
+        assert_eq!(payload["new_filename"], rotated.to_string_lossy().as_ref());
+        assert_eq!(payload["size"], 13);
+    }
+
+    #[test]
+    fn test_disk_usage() {
+        let (_state_tx, state_rx) = watch::channel(Cursor::default());
+        let (events_tx, mut events_rx) = mpsc::channel(2);
+        let interval = Duration::from_secs(1);
+        let mut rotator = Rotator::new(
+            PathBuf::from("/var/log/test.log"),
+            interval,
+            state_rx,
+            Arc::default(),
+            policy(),
+        )
+        .unwrap()
+        .with_events(events_tx);
+
+        let usage = disk_usage(&std::env::temp_dir()).unwrap();
+        assert!((0.0..=100.0).contains(&usage));
 
-        let file = File::open(&self.filepath)?;
-        let mut reader = BufReader::new(file);
+        // only once, when the threshold is crossed
+        assert!(!rotator.crossed_disk_usage(80.0, 90.0));
+        assert!(rotator.crossed_disk_usage(91.0, 90.0));
+        assert!(!rotator.crossed_disk_usage(95.0, 90.0));
+        assert!(!rotator.crossed_disk_usage(85.0, 90.0));
+        assert!(rotator.crossed_disk_usage(90.0, 90.0));
 
-        let mut first_line = String::new();
-        reader.read_line(&mut first_line)?;
+        let message = events_rx.try_recv().unwrap();
+        assert_eq!(message.headers[EVENT_HEADER], "disk_usage");
+        assert!(events_rx.try_recv().is_ok());
+        assert!(events_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_urgent_rotation_is_compressed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.log");
+        std::fs::write(&path, "first\nsecond\n").unwrap();
+
+        let (_state_tx, state_rx) = watch::channel(Cursor::default());
+        let policy = RotationPolicy {
+            retention: Retention {
+                count: Some(1),
+                ..Retention::default()
+            },
+            ..policy()
+        };
+        let interval = Duration::from_secs(1);
+        let rotator =
+            Rotator::new(path.clone(), interval, state_rx, Arc::default(), policy).unwrap();
+
+        let rotated = rotator.rotate_urgently().await.unwrap();
+        let name = format!("test.log.{}", Utc::now().format("%Y"));
+        assert_eq!(rotated, dir.path().join(format!("{}.gz", name)));
+        assert!(!dir.path().join(&name).exists());
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+
+        // the compressed files count for the retention, and they aren't overwritten
+        std::fs::write(&path, "third\n").unwrap();
+        let rotated = rotator.rotate_urgently().await.unwrap();
+        assert_eq!(rotated, dir.path().join(format!("{}.1.gz", name)));
+        assert!(!dir.path().join(format!("{}.gz", name)).exists());
+    }
+
+    #[tokio::test]
+    async fn test_external_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.log");
+        std::fs::write(&path, "first\n").unwrap();
 
-        let first_line = first_line.trim();
-        debug!("File's first line content is `{}`", &first_line);
+        let (_state_tx, state_rx) = watch::channel(Cursor::default());
+        let interval = Duration::from_secs(1);
+        let mut rotator =
+            Rotator::new(path.clone(), interval, state_rx, Arc::default(), policy()).unwrap();
 
-        let hashed = HASHER.checksum(first_line.as_bytes());
-        debug!("File's first line hash is `{}`", hashed);
+        // ours don't count
+        rotator.rotate().await.unwrap();
+        std::fs::write(dir.path().join("test.log.bak"), "").unwrap();
+        assert!(!rotator.has_external_rotated_files());
 
-        Ok(hashed)
+        std::fs::write(dir.path().join("test.log.1.gz"), "").unwrap();
+        assert!(rotator.has_external_rotated_files());
+
+        // replaced behind our back
+        assert!(!rotator.rotated_externally().await);
+        std::fs::rename(&path, dir.path().join("test.log.2")).unwrap();
+        std::fs::write(&path, "").unwrap();
+        assert!(rotator.rotated_externally().await);
     }
 
-    /// Reset the position to the beginning of the file
-    pub fn reset(&mut self) -> Result<()> {
-        self.save(0)
+    #[tokio::test]
+    async fn test_run_hook() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("hook");
+        let command = format!(
+            "echo \"$LOG_BOUNCER_OLD_FILENAME $LOG_BOUNCER_NEW_FILENAME\" > {}",
+            output.to_string_lossy()
+        );
+
+        let status = run_hook(&command, Path::new("/a.log"), Path::new("/a.log.1"))
+            .await
+            .unwrap();
+        assert!(status.success());
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "/a.log /a.log.1\n"
+        );
+
+        let status = run_hook("exit 3", Path::new("/a.log"), Path::new("/a.log.1"))
+            .await
+            .unwrap();
+        assert_eq!(status.code(), Some(3));
+    }
+
+    #[tokio::test]
+    async fn test_rotate_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.log");
+        std::fs::write(&path, "first\n").unwrap();
+
+        let (_state_tx, state_rx) = watch::channel(Cursor::default());
+        let policy = RotationPolicy {
+            rotate_dir: Some(dir.path().join("archive")),
+            ..policy()
+        };
+        let interval = Duration::from_secs(1);
+        let rotator =
+            Rotator::new(path.clone(), interval, state_rx, Arc::default(), policy).unwrap();
+
+        let rotated = rotator.rotate().await.unwrap();
+
+        assert_eq!(rotated.parent().unwrap(), dir.path().join("archive"));
+        assert_eq!(std::fs::read_to_string(rotated).unwrap(), "first\n");
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_rotated_filename_collisions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.log");
+        let (_state_tx, state_rx) = watch::channel(Cursor::default());
+        let interval = Duration::from_secs(1);
+        let year = Utc::now().format("%Y").to_string();
+        std::fs::write(&path, "").unwrap();
+
+        let mut policy = policy();
+        let rotator = Rotator::new(
+            path.clone(),
+            interval,
+            state_rx.clone(),
+            Arc::default(),
+            policy.clone(),
+        )
+        .unwrap();
+
+        let first = rotator.rotate().await.unwrap();
+        let second = rotator.rotate().await.unwrap();
+        assert_eq!(first, dir.path().join(format!("test.log.{}", year)));
+        assert_eq!(second, dir.path().join(format!("test.log.{}.1", year)));
+
+        policy.filename = Template::parse("{stem}-{date}.{seq}.{ext}").unwrap();
+        let rotator =
+            Rotator::new(path.clone(), interval, state_rx, Arc::default(), policy).unwrap();
+
+        let first = rotator.rotate().await.unwrap();
+        let second = rotator.rotate().await.unwrap();
+        assert_eq!(first, dir.path().join(format!("test-{}.1.log", year)));
+        assert_eq!(second, dir.path().join(format!("test-{}.2.log", year)));
+
+        // the live file doesn't match, the state file neither
+        let pattern = rotator.rotated_pattern();
+        assert!(pattern.is_match(&format!("test-{}.2.log", year)));
+        assert!(!pattern.is_match("test.log"));
+        assert!(!pattern.is_match(".test.log.log-bouncer"));
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_the_publisher_to_catch_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.log");
+        std::fs::write(&path, "first\nsecond\n").unwrap();
+
+        let (state_tx, state_rx) = watch::channel(Cursor::default());
+        let interval = Duration::from_secs(1);
+        let mut policy = RotationPolicy {
+            catch_up_timeout: Some(Duration::from_secs(3600)),
+            ..policy()
+        };
+        let mut rotator = Rotator::new(
+            path.clone(),
+            interval,
+            state_rx.clone(),
+            Arc::default(),
+            policy.clone(),
+        )
+        .unwrap();
+
+        state_tx
+            .send(Cursor {
+                position: 6,
+                line: 1,
+                ..Cursor::default()
+            })
+            .unwrap();
+        assert!(!rotator.has_caught_up().await);
+
+        state_tx
+            .send(Cursor {
+                position: 13,
+                line: 2,
+                ..Cursor::default()
+            })
+            .unwrap();
+        assert!(rotator.has_caught_up().await);
+
+        // gave up on waiting
+        policy.catch_up_timeout = Some(Duration::ZERO);
+        let mut rotator = Rotator::new(path, interval, state_rx, Arc::default(), policy).unwrap();
+        state_tx
+            .send(Cursor {
+                position: 6,
+                line: 1,
+                ..Cursor::default()
+            })
+            .unwrap();
+        assert!(rotator.has_caught_up().await);
+    }
+
+    #[tokio::test]
+    async fn test_max_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.log");
+        std::fs::write(&path, "first\nsecond\n").unwrap();
+
+        let (state_tx, state_rx) = watch::channel(Cursor::default());
+        let interval = Duration::from_secs(1);
+        let policy = RotationPolicy {
+            max_size: 1000,
+            max_lines: Some(2),
+            ..policy()
+        };
+        let mut rotator =
+            Rotator::new(path.clone(), interval, state_rx, Arc::default(), policy).unwrap();
+
+        state_tx
+            .send(Cursor {
+                position: 6,
+                line: 1,
+                ..Cursor::default()
+            })
+            .unwrap();
+        assert!(!rotator.can_be_rotated().await.unwrap());
+
+        state_tx
+            .send(Cursor {
+                position: 13,
+                line: 2,
+                ..Cursor::default()
+            })
+            .unwrap();
+        assert!(rotator.can_be_rotated().await.unwrap());
+
+        // the reader hasn't switched to the new file yet
+        rotator.rotate_and_reset(false).await;
+        std::fs::write(&path, "third\n").unwrap();
+        assert!(!rotator.can_be_rotated().await.unwrap());
+
+        state_tx
+            .send(Cursor {
+                position: 6,
+                line: 1,
+                ..Cursor::default()
+            })
+            .unwrap();
+        assert!(!rotator.can_be_rotated().await.unwrap());
+        state_tx
+            .send(Cursor {
+                position: 12,
+                line: 2,
+                ..Cursor::default()
+            })
+            .unwrap();
+        assert!(rotator.can_be_rotated().await.unwrap());
     }
 
-    /// Save state in a file
-    pub fn save(&mut self, pos: u64) -> Result<()> {
-        debug!("Saving a state at position <{}>", pos);
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_preserve_permissions() {
+        use std::os::unix::fs::PermissionsExt;
 
-        let data = format!("{};{}", self.get_uniq_id()?, pos);
-        self.state_file.set_len(0)?; // truncate the file before writing it
-        self.state_file.seek(SeekFrom::Start(0))?; // reset the cursor position to the beginning
-        self.state_file.write_all(data.as_bytes())?;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.log");
+        std::fs::write(&path, "first\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
 
-        self.position = pos;
+        let (_state_tx, state_rx) = watch::channel(Cursor::default());
+        let interval = Duration::from_secs(1);
+        let policy = policy();
+        let rotator =
+            Rotator::new(path.clone(), interval, state_rx, Arc::default(), policy).unwrap();
+        rotator.rotate().await.unwrap();
 
-        Ok(())
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
     }
 }
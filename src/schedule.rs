@@ -0,0 +1,85 @@
+//! Time-based rotation, in addition to the size
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use std::str::FromStr;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("invalid schedule `{0}`: {1}")]
+    InvalidSchedule(String, cron::error::Error),
+}
+
+/// Times are in UTC
+#[derive(Debug, Clone)]
+pub enum Schedule {
+    /// At the beginning of every hour
+    Hourly,
+    /// Every day at midnight
+    Daily,
+    /// Cron expression, with the seconds: `sec min hour day-of-month month day-of-week [year]`
+    Cron(Box<cron::Schedule>),
+}
+
+impl FromStr for Schedule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "hourly" => Ok(Schedule::Hourly),
+            "daily" => Ok(Schedule::Daily),
+            expression => cron::Schedule::from_str(expression)
+                .map(|schedule| Schedule::Cron(Box::new(schedule)))
+                .map_err(|e| Error::InvalidSchedule(s.to_owned(), e)),
+        }
+    }
+}
+
+impl Schedule {
+    /// The first time the schedule fires strictly after `after`
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let period = match self {
+            Schedule::Hourly => Duration::hours(1),
+            Schedule::Daily => Duration::days(1),
+            Schedule::Cron(schedule) => return schedule.after(&after).next(),
+        };
+
+        after
+            .duration_trunc(period)
+            .ok()
+            .and_then(|start| start.checked_add_signed(period))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_next_after() {
+        let now = Utc.with_ymd_and_hms(2021, 9, 7, 3, 37, 53).unwrap();
+
+        let schedule = Schedule::from_str("hourly").unwrap();
+        assert_eq!(
+            schedule.next_after(now),
+            Some(Utc.with_ymd_and_hms(2021, 9, 7, 4, 0, 0).unwrap())
+        );
+
+        let schedule = Schedule::from_str("daily").unwrap();
+        assert_eq!(
+            schedule.next_after(now),
+            Some(Utc.with_ymd_and_hms(2021, 9, 8, 0, 0, 0).unwrap())
+        );
+
+        // every 15 minutes
+        let schedule = Schedule::from_str("0 */15 * * * *").unwrap();
+        assert_eq!(
+            schedule.next_after(now),
+            Some(Utc.with_ymd_and_hms(2021, 9, 7, 3, 45, 0).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(Schedule::from_str("weekly-ish").is_err());
+    }
+}
@@ -0,0 +1,341 @@
+//! Credentials fetched from a secret store rather than passed on the command line, eg.
+//! `--amqp-uri-secret vault:secret/data/log-bouncer#amqp_uri`. They're fetched again
+//! periodically, the output reconnects once they've rotated.
+//!
+//! - `vault:<path>#<field>`: the field of a HashiCorp Vault secret, KV v1 or v2, read from
+//!   `VAULT_ADDR` with `VAULT_TOKEN`
+//! - `aws-sm:<secret id>[#<field>]`: an AWS Secrets Manager secret, or a field of it when it's
+//!   JSON, with the credentials and the region of `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`,
+//!   `AWS_SESSION_TOKEN` and `AWS_REGION`
+#[cfg(feature = "http")]
+use crate::archive::{hmac, signing_key};
+#[cfg(feature = "http")]
+use chrono::{DateTime, Utc};
+#[cfg(feature = "http")]
+use serde_json::{json, Value};
+#[cfg(feature = "http")]
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::sync::watch;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("invalid secret `{0}`, expected `vault:<path>#<field>` or `aws-sm:<id>[#<field>]`")]
+    InvalidReference(String),
+    #[error("`{0}` isn't set")]
+    MissingEnv(&'static str),
+    #[error("the secret `{0}` has no field `{1}`")]
+    MissingField(String, String),
+    #[cfg(feature = "http")]
+    #[error("can't fetch the secret `{0}`, status {1}: {2}")]
+    Rejected(String, reqwest::StatusCode, String),
+    #[cfg(feature = "http")]
+    #[error("http: {0}")]
+    Http(#[from] reqwest::Error),
+    #[cfg(not(feature = "http"))]
+    #[error("can't fetch the secret `{0}`, the `http` feature isn't enabled")]
+    HttpDisabled(String),
+    #[error("json: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Where a secret is kept
+#[derive(Debug, Clone, PartialEq)]
+pub enum SecretRef {
+    Vault { path: String, field: String },
+    AwsSecretsManager { id: String, field: Option<String> },
+}
+
+impl FromStr for SecretRef {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::InvalidReference(s.to_owned());
+        let (store, reference) = s.split_once(':').ok_or_else(invalid)?;
+        let (name, field) = match reference.split_once('#') {
+            Some((name, field)) => (name, Some(field.to_owned())),
+            None => (reference, None),
+        };
+        if name.is_empty() || field.as_deref() == Some("") {
+            return Err(invalid());
+        }
+
+        match store {
+            "vault" => Ok(Self::Vault {
+                path: name.trim_matches('/').to_owned(),
+                field: field.ok_or_else(invalid)?,
+            }),
+            "aws-sm" => Ok(Self::AwsSecretsManager {
+                id: name.to_owned(),
+                field,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for SecretRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Vault { path, field } => write!(f, "vault:{}#{}", path, field),
+            Self::AwsSecretsManager { id, field: None } => write!(f, "aws-sm:{}", id),
+            Self::AwsSecretsManager {
+                id,
+                field: Some(field),
+            } => write!(f, "aws-sm:{}#{}", id, field),
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+fn env(name: &'static str) -> Result<String> {
+    std::env::var(name).map_err(|_| Error::MissingEnv(name))
+}
+
+/// The current value of the secret
+#[cfg(feature = "http")]
+pub async fn fetch(client: &reqwest::Client, secret: &SecretRef) -> Result<String> {
+    match secret {
+        SecretRef::Vault { path, field } => {
+            let url = format!("{}/v1/{}", env("VAULT_ADDR")?.trim_end_matches('/'), path);
+            let response = client
+                .get(url)
+                .header("X-Vault-Token", env("VAULT_TOKEN")?)
+                .send()
+                .await?;
+            let body = checked(secret, response).await?;
+
+            vault_field(&serde_json::from_str(&body)?, field)
+                .ok_or_else(|| Error::MissingField(secret.to_string(), field.clone()))
+        }
+        SecretRef::AwsSecretsManager { id, field } => {
+            let secret_string = aws_secret_string(client, secret, id).await?;
+            match field {
+                Some(field) => serde_json::from_str::<Value>(&secret_string)?[field]
+                    .as_str()
+                    .map(str::to_owned)
+                    .ok_or_else(|| Error::MissingField(secret.to_string(), field.clone())),
+                None => Ok(secret_string),
+            }
+        }
+    }
+}
+
+/// The body of a successful response
+#[cfg(feature = "http")]
+async fn checked(secret: &SecretRef, response: reqwest::Response) -> Result<String> {
+    let status = response.status();
+    let body = response.text().await?;
+
+    match status.is_success() {
+        true => Ok(body),
+        false => Err(Error::Rejected(secret.to_string(), status, body)),
+    }
+}
+
+/// The field of a KV v2 secret, nested in `data.data`, or of a KV v1 one
+#[cfg(feature = "http")]
+fn vault_field(body: &Value, field: &str) -> Option<String> {
+    let data = &body["data"];
+    data["data"][field]
+        .as_str()
+        .or_else(|| data[field].as_str())
+        .map(str::to_owned)
+}
+
+#[cfg(feature = "http")]
+async fn aws_secret_string(
+    client: &reqwest::Client,
+    secret: &SecretRef,
+    id: &str,
+) -> Result<String> {
+    let region = env("AWS_REGION").or_else(|_| env("AWS_DEFAULT_REGION"))?;
+    let request = AwsRequest {
+        host: format!("secretsmanager.{}.amazonaws.com", region),
+        region,
+        access_key: env("AWS_ACCESS_KEY_ID")?,
+        secret_key: env("AWS_SECRET_ACCESS_KEY")?,
+        session_token: env("AWS_SESSION_TOKEN").ok(),
+        body: json!({ "SecretId": id }).to_string(),
+    };
+    let now = Utc::now();
+
+    let mut builder = client
+        .post(format!("https://{}/", request.host))
+        .header("content-type", AWS_CONTENT_TYPE)
+        .header("x-amz-date", now.format("%Y%m%dT%H%M%SZ").to_string())
+        .header("x-amz-target", AWS_TARGET)
+        .header("authorization", request.authorization(now));
+    if let Some(token) = &request.session_token {
+        builder = builder.header("x-amz-security-token", token);
+    }
+    let body = checked(secret, builder.body(request.body).send().await?).await?;
+
+    serde_json::from_str::<Value>(&body)?["SecretString"]
+        .as_str()
+        .map(str::to_owned)
+        .ok_or_else(|| Error::MissingField(secret.to_string(), "SecretString".to_owned()))
+}
+
+#[cfg(feature = "http")]
+const AWS_CONTENT_TYPE: &str = "application/x-amz-json-1.1";
+#[cfg(feature = "http")]
+const AWS_TARGET: &str = "secretsmanager.GetSecretValue";
+
+/// A `GetSecretValue` request, signed with AWS Signature Version 4
+#[cfg(feature = "http")]
+struct AwsRequest {
+    host: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+    body: String,
+}
+
+#[cfg(feature = "http")]
+impl AwsRequest {
+    /// The `Authorization` header of the request
+    fn authorization(&self, now: DateTime<Utc>) -> String {
+        let date = now.format("%Y%m%d").to_string();
+        let timestamp = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let scope = format!("{}/{}/secretsmanager/aws4_request", date, self.region);
+
+        let mut headers = format!(
+            "content-type:{}\nhost:{}\nx-amz-date:{}\n",
+            AWS_CONTENT_TYPE, self.host, timestamp
+        );
+        let signed_headers = match &self.session_token {
+            Some(token) => {
+                headers.push_str(&format!("x-amz-security-token:{}\n", token));
+                "content-type;host;x-amz-date;x-amz-security-token;x-amz-target"
+            }
+            None => "content-type;host;x-amz-date;x-amz-target",
+        };
+        headers.push_str(&format!("x-amz-target:{}\n", AWS_TARGET));
+
+        let canonical_request = format!(
+            "POST\n/\n\n{}\n{}\n{}",
+            headers,
+            signed_headers,
+            hex::encode(Sha256::digest(self.body.as_bytes()))
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            timestamp,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let key = signing_key(&self.secret_key, &date, &self.region, "secretsmanager");
+        let signature = hex::encode(hmac(&key, string_to_sign.as_bytes()));
+
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key, scope, signed_headers, signature
+        )
+    }
+}
+
+/// Fetch the secret, then again every interval, unless it's 0. The receiver changes once the
+/// secret has rotated, a failed fetch keeps the current value.
+#[cfg(feature = "http")]
+pub async fn watch(secret: SecretRef, interval: Duration) -> Result<watch::Receiver<String>> {
+    let client = reqwest::Client::new();
+    let (tx, rx) = watch::channel(fetch(&client, &secret).await?);
+    info!("The secret `{}` has been fetched", secret);
+
+    if !interval.is_zero() {
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                match fetch(&client, &secret).await {
+                    Ok(value) if *tx.borrow() == value => {}
+                    Ok(value) => {
+                        info!("The secret `{}` has rotated", secret);
+                        if tx.send(value).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => warn!(
+                        "Can't fetch the secret again, keeping the current one: {}",
+                        e
+                    ),
+                }
+            }
+        });
+    }
+
+    Ok(rx)
+}
+
+#[cfg(not(feature = "http"))]
+pub async fn watch(secret: SecretRef, _interval: Duration) -> Result<watch::Receiver<String>> {
+    Err(Error::HttpDisabled(secret.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "http")]
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_secret_ref() {
+        assert_eq!(
+            "vault:/secret/data/log-bouncer#amqp_uri"
+                .parse::<SecretRef>()
+                .unwrap(),
+            SecretRef::Vault {
+                path: "secret/data/log-bouncer".to_owned(),
+                field: "amqp_uri".to_owned()
+            }
+        );
+        let aws = "aws-sm:prod/log-bouncer".parse::<SecretRef>().unwrap();
+        assert_eq!(aws.to_string(), "aws-sm:prod/log-bouncer");
+
+        assert!("vault:secret/log-bouncer".parse::<SecretRef>().is_err());
+        assert!("aws-sm:prod#".parse::<SecretRef>().is_err());
+        assert!("gcp:log-bouncer#uri".parse::<SecretRef>().is_err());
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn test_vault_field() {
+        let v2 = json!({ "data": { "data": { "amqp_uri": "amqp://v2" }, "metadata": {} } });
+        let v1 = json!({ "data": { "amqp_uri": "amqp://v1" } });
+
+        assert_eq!(vault_field(&v2, "amqp_uri").as_deref(), Some("amqp://v2"));
+        assert_eq!(vault_field(&v1, "amqp_uri").as_deref(), Some("amqp://v1"));
+        assert_eq!(vault_field(&v1, "password"), None);
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn test_aws_authorization() {
+        let mut request = AwsRequest {
+            host: "secretsmanager.eu-west-1.amazonaws.com".to_owned(),
+            region: "eu-west-1".to_owned(),
+            access_key: "AKIDEXAMPLE".to_owned(),
+            secret_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_owned(),
+            session_token: None,
+            body: json!({ "SecretId": "prod/log-bouncer" }).to_string(),
+        };
+        let now = Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap();
+
+        let authorization = request.authorization(now);
+        assert!(authorization.starts_with(
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20240102/eu-west-1/secretsmanager/aws4_request, \
+             SignedHeaders=content-type;host;x-amz-date;x-amz-target, Signature="
+        ));
+
+        request.session_token = Some("token".to_owned());
+        let with_token = request.authorization(now);
+        assert!(with_token.contains("x-amz-security-token"));
+        assert_ne!(authorization, with_token);
+    }
+}
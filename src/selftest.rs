@@ -0,0 +1,111 @@
+//! `log-bouncer selftest`: publish test messages to the output and wait for their confirms, so a
+//! deployment can check the credentials and the network path before cutting the traffic over
+use crate::heartbeat;
+use crate::opt::SelftestOpt;
+use crate::output::amqp::AmqpOutput;
+use crate::output::{Message, OutputAdapter};
+use crate::rotator::EVENT_HEADER;
+use chrono::{SecondsFormat, Utc};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+/// Connect, publish, then print the round-trip latency of every message
+pub async fn probe(opts: SelftestOpt) -> Result<(), Box<dyn Error>> {
+    let timeout = Duration::from_secs(opts.timeout);
+    let probing = async {
+        let connecting = Instant::now();
+        let output =
+            AmqpOutput::new(&opts.amqp_uri, &opts.amqp_exchange, &opts.routing_key).await?;
+        println!("connected in {:.1}ms", millis(connecting.elapsed()));
+
+        let latencies = publish(&output, &heartbeat::hostname(), opts.count).await?;
+        println!("{}", summary(&latencies));
+        Ok(())
+    };
+
+    match tokio::time::timeout(timeout, probing).await {
+        Ok(probed) => probed,
+        Err(_) => Err(format!("the output didn't confirm within {}s", timeout.as_secs()).into()),
+    }
+}
+
+/// Publish the messages one after the other, each once the former one has been confirmed
+async fn publish<O: OutputAdapter>(
+    output: &O,
+    host: &str,
+    count: u32,
+) -> Result<Vec<Duration>, Box<dyn Error>> {
+    let mut latencies = vec![];
+
+    for sequence in 1..=count {
+        let sent = Instant::now();
+        output.send(message(host, sequence)).await?;
+        let latency = sent.elapsed();
+
+        println!("message {} confirmed in {:.1}ms", sequence, millis(latency));
+        latencies.push(latency);
+    }
+
+    Ok(latencies)
+}
+
+fn message(host: &str, sequence: u32) -> Message {
+    let payload = json!({
+        "event": "selftest",
+        "host": host,
+        "sequence": sequence,
+        "@timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+    });
+    let mut headers = BTreeMap::new();
+    headers.insert(EVENT_HEADER.to_owned(), "selftest".to_owned());
+
+    Message {
+        payload: payload.to_string(),
+        headers,
+        ..Message::default()
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+fn summary(latencies: &[Duration]) -> String {
+    let (min, max) = match (latencies.iter().min(), latencies.iter().max()) {
+        (Some(min), Some(max)) => (min, max),
+        _ => return "no message published".to_owned(),
+    };
+    let avg = latencies.iter().sum::<Duration>() / latencies.len() as u32;
+
+    format!(
+        "round-trip min/avg/max = {:.1}/{:.1}/{:.1}ms",
+        millis(*min),
+        millis(avg),
+        millis(*max)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::stdout::StdOut;
+
+    #[tokio::test]
+    async fn test_publish() {
+        let latencies = publish(&StdOut {}, "web-1", 2).await.unwrap();
+        assert_eq!(latencies.len(), 2);
+
+        let message = message("web-1", 2);
+        assert!(message.payload.contains(r#""sequence":2"#));
+        assert_eq!(message.headers[EVENT_HEADER], "selftest");
+
+        let latencies = [Duration::from_millis(2), Duration::from_millis(4)];
+        assert_eq!(
+            summary(&latencies),
+            "round-trip min/avg/max = 2.0/3.0/4.0ms"
+        );
+        assert_eq!(summary(&[]), "no message published");
+    }
+}
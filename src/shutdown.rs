@@ -0,0 +1,115 @@
+//! Stop the tasks deterministically: a [`Shutdown`] is triggered once, along with its children,
+//! and every task waiting on it finishes what it's doing, then returns. The process has one,
+//! each source a child of it, and each file of the source a child of the source's.
+use std::sync::{Arc, Mutex, Weak};
+use tokio::sync::watch;
+
+struct Node {
+    tx: watch::Sender<bool>,
+    children: Mutex<Vec<Weak<Node>>>,
+}
+
+impl Node {
+    fn trigger(&self) {
+        self.tx.send_replace(true);
+        // a child created from now on is triggered right away, see `child`
+        let children = std::mem::take(&mut *self.children.lock().unwrap());
+        for child in children.iter().filter_map(Weak::upgrade) {
+            child.trigger();
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Shutdown {
+    node: Arc<Node>,
+    rx: watch::Receiver<bool>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let (tx, rx) = watch::channel(false);
+        let node = Node {
+            tx,
+            children: Mutex::default(),
+        };
+
+        Self {
+            node: Arc::new(node),
+            rx,
+        }
+    }
+
+    /// Triggered along with this one, or on its own without triggering this one
+    pub fn child(&self) -> Self {
+        let child = Self::new();
+        let mut children = self.node.children.lock().unwrap();
+        if self.is_triggered() {
+            child.trigger();
+        } else {
+            children.retain(|child| child.strong_count() > 0);
+            children.push(Arc::downgrade(&child.node));
+        }
+
+        child
+    }
+
+    pub fn trigger(&self) {
+        self.node.trigger();
+    }
+
+    /// Whether it's been triggered, eg. from a reading thread
+    pub fn is_triggered(&self) -> bool {
+        *self.rx.borrow()
+    }
+
+    /// Resolves once it's been triggered
+    pub async fn triggered(&self) {
+        let mut rx = self.rx.clone();
+        while !*rx.borrow_and_update() {
+            // the sender lives as long as `self`
+            if rx.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let process = Shutdown::new();
+        let source = process.child();
+        let file = source.child();
+        let other = source.child();
+
+        file.trigger();
+        assert!(file.is_triggered());
+        assert!(!source.is_triggered() && !other.is_triggered());
+
+        let waiting = tokio::spawn({
+            let other = other.clone();
+            async move { other.triggered().await }
+        });
+        process.trigger();
+        tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(source.is_triggered() && other.is_triggered());
+
+        // it's already triggered
+        assert!(source.child().is_triggered());
+        source.child().triggered().await;
+    }
+}
@@ -0,0 +1,61 @@
+//! Unix signals used by external orchestration
+//!
+//! - `SIGUSR1` rotates the file right away
+//! - `SIGHUP` reloads the config file
+//! - `SIGUSR2` switches to the debug logs, then back to the former ones
+//! - `SIGTERM` and `SIGINT` stop it, once the lines read have been published, for
+//!   `--shutdown-timeout` at most, and the position saved
+use crate::logs::LogFilter;
+use std::sync::Arc;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Notify;
+
+/// Forward the signals to the tasks handling them
+pub fn listen(
+    rotate: Arc<Notify>,
+    reload: Arc<Notify>,
+    log_filter: Arc<LogFilter>,
+) -> std::io::Result<()> {
+    let mut sigusr1 = signal(SignalKind::user_defined1())?;
+    let mut sigusr2 = signal(SignalKind::user_defined2())?;
+    let mut sighup = signal(SignalKind::hangup())?;
+
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                Some(_) = sigusr1.recv() => {
+                    debug!("SIGUSR1 received");
+                    rotate.notify_one();
+                }
+                Some(_) = sighup.recv() => {
+                    debug!("SIGHUP received");
+                    reload.notify_one();
+                }
+                Some(_) = sigusr2.recv() => match log_filter.toggle_debug() {
+                    Ok(true) => info!("SIGUSR2 received, debug logs enabled"),
+                    Ok(false) => info!("SIGUSR2 received, debug logs disabled"),
+                    Err(e) => error!("Can't change the log filter: {}", e),
+                },
+                else => break,
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Wait for `SIGTERM` or `SIGINT`
+pub async fn terminated() {
+    let (mut sigterm, mut sigint) = match (
+        signal(SignalKind::terminate()),
+        signal(SignalKind::interrupt()),
+    ) {
+        (Ok(sigterm), Ok(sigint)) => (sigterm, sigint),
+        _ => return std::future::pending().await,
+    };
+
+    tokio::select! {
+        _ = sigterm.recv() => debug!("SIGTERM received"),
+        _ = sigint.recv() => debug!("SIGINT received"),
+    }
+}
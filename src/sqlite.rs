@@ -0,0 +1,261 @@
+//! SQLite backend of the state store and of the rotation ledger, for the deployments tailing many
+//! files that would rather have a single database than many dot-files
+use crate::ledger;
+use crate::state::{Entry, FileId, Head};
+use rusqlite::{params, Connection, Row};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS positions (
+        dev INTEGER NOT NULL,
+        ino INTEGER NOT NULL,
+        path TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        line INTEGER NOT NULL,
+        fingerprint INTEGER,
+        saved_at TEXT NOT NULL,
+        PRIMARY KEY (dev, ino)
+    );
+    CREATE TABLE IF NOT EXISTS rotations (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        rotated_at TEXT NOT NULL,
+        old_filename TEXT NOT NULL,
+        new_filename TEXT NOT NULL,
+        size INTEGER NOT NULL,
+        published_position INTEGER NOT NULL,
+        published_line INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS cursors (
+        input TEXT PRIMARY KEY,
+        cursor TEXT NOT NULL
+    );
+";
+
+/// Open the database, the tables are created if they don't exist yet
+pub fn open(path: &Path) -> rusqlite::Result<Connection> {
+    let connection = Connection::open(path)?;
+    // the rotator writes the ledger while the state saver writes the positions
+    connection.busy_timeout(Duration::from_secs(5))?;
+    connection.pragma_update(None, "journal_mode", "WAL")?;
+    connection.pragma_update(None, "synchronous", "FULL")?;
+    connection.execute_batch(SCHEMA)?;
+    add_head_columns(&connection)?;
+
+    Ok(connection)
+}
+
+/// The heads of the files have been added to the positions of the former databases
+fn add_head_columns(connection: &Connection) -> rusqlite::Result<()> {
+    let columns = connection
+        .prepare("SELECT name FROM pragma_table_info('positions')")?
+        .query_map([], |row: &Row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    if !columns.iter().any(|column| column == "head_size") {
+        connection.execute_batch(
+            "ALTER TABLE positions ADD COLUMN head_size INTEGER;
+            ALTER TABLE positions ADD COLUMN head_checksum INTEGER;",
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Integers are stored signed, the bits are kept as they are
+fn to_sql(value: u64) -> i64 {
+    value as i64
+}
+
+fn from_sql(value: i64) -> u64 {
+    value as u64
+}
+
+pub fn load_positions(connection: &Connection) -> rusqlite::Result<Vec<Entry>> {
+    let mut statement = connection.prepare(
+        "SELECT dev, ino, path, position, line, fingerprint, saved_at, head_size, head_checksum
+        FROM positions",
+    )?;
+    let entries = statement.query_map([], |row: &Row| {
+        Ok(Entry {
+            file_id: FileId {
+                dev: from_sql(row.get(0)?),
+                ino: from_sql(row.get(1)?),
+            },
+            path: PathBuf::from(row.get::<_, String>(2)?),
+            position: from_sql(row.get(3)?),
+            line: from_sql(row.get(4)?),
+            fingerprint: row.get(5)?,
+            saved_at: row.get(6)?,
+            head: match (row.get::<_, Option<i64>>(7)?, row.get(8)?) {
+                (Some(size), Some(checksum)) => Some(Head {
+                    size: from_sql(size),
+                    checksum,
+                }),
+                _ => None,
+            },
+        })
+    })?;
+
+    entries.collect()
+}
+
+pub fn load_cursors(connection: &Connection) -> rusqlite::Result<BTreeMap<String, String>> {
+    let mut statement = connection.prepare("SELECT input, cursor FROM cursors")?;
+    let cursors = statement.query_map([], |row: &Row| Ok((row.get(0)?, row.get(1)?)))?;
+
+    cursors.collect()
+}
+
+/// Replace every position and cursor in a single transaction
+pub fn save_positions(
+    connection: &mut Connection,
+    entries: &[Entry],
+    cursors: &BTreeMap<String, String>,
+) -> rusqlite::Result<()> {
+    let transaction = connection.transaction()?;
+    transaction.execute("DELETE FROM positions", [])?;
+    transaction.execute("DELETE FROM cursors", [])?;
+
+    {
+        let mut statement = transaction.prepare(
+            "INSERT INTO positions
+            (dev, ino, path, position, line, fingerprint, saved_at, head_size, head_checksum)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        )?;
+        for entry in entries {
+            statement.execute(params![
+                to_sql(entry.file_id.dev),
+                to_sql(entry.file_id.ino),
+                entry.path.to_string_lossy(),
+                to_sql(entry.position),
+                to_sql(entry.line),
+                entry.fingerprint,
+                entry.saved_at,
+                entry.head.map(|head| to_sql(head.size)),
+                entry.head.map(|head| head.checksum),
+            ])?;
+        }
+
+        let mut statement =
+            transaction.prepare("INSERT INTO cursors (input, cursor) VALUES (?1, ?2)")?;
+        for (input, cursor) in cursors {
+            statement.execute(params![input, cursor])?;
+        }
+    }
+
+    transaction.commit()
+}
+
+/// Append a rotation, then drop the oldest ones if there are too many
+pub fn record_rotation(
+    connection: &mut Connection,
+    entry: &ledger::Entry,
+    max_entries: usize,
+) -> rusqlite::Result<()> {
+    let transaction = connection.transaction()?;
+    transaction.execute(
+        "INSERT INTO rotations
+        (rotated_at, old_filename, new_filename, size, published_position, published_line)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            entry.rotated_at,
+            entry.old_filename.to_string_lossy(),
+            entry.new_filename.to_string_lossy(),
+            to_sql(entry.size),
+            to_sql(entry.published_position),
+            to_sql(entry.published_line),
+        ],
+    )?;
+    transaction.execute(
+        "DELETE FROM rotations WHERE id NOT IN (SELECT id FROM rotations ORDER BY id DESC LIMIT ?1)",
+        [max_entries as i64],
+    )?;
+
+    transaction.commit()
+}
+
+/// Every rotation, from the oldest to the latest
+pub fn rotations(connection: &Connection) -> rusqlite::Result<Vec<ledger::Entry>> {
+    let mut statement = connection.prepare(
+        "SELECT rotated_at, old_filename, new_filename, size, published_position, published_line
+        FROM rotations ORDER BY id",
+    )?;
+    let entries = statement.query_map([], |row: &Row| {
+        Ok(ledger::Entry {
+            rotated_at: row.get(0)?,
+            old_filename: PathBuf::from(row.get::<_, String>(1)?),
+            new_filename: PathBuf::from(row.get::<_, String>(2)?),
+            size: from_sql(row.get(3)?),
+            published_position: from_sql(row.get(4)?),
+            published_line: from_sql(row.get(5)?),
+        })
+    })?;
+
+    entries.collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::StateStore;
+    use chrono::Utc;
+
+    #[test]
+    fn test_positions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.db");
+
+        let entry = Entry {
+            file_id: FileId {
+                dev: 1,
+                ino: u64::MAX,
+            },
+            path: dir.path().join("app.log"),
+            position: 42,
+            line: 3,
+            fingerprint: Some(7),
+            head: Some(Head {
+                size: 1024,
+                checksum: 9,
+            }),
+            saved_at: Utc::now(),
+        };
+
+        let mut store = StateStore::open(path.clone()).unwrap();
+        store.set(entry.clone());
+        store.set_cursor("journal", "s=1;i=2".to_owned());
+        store.save().unwrap();
+
+        let mut store = StateStore::open(path).unwrap();
+        store.load().unwrap();
+        assert_eq!(store.get(&entry.file_id), Some(&entry));
+        assert_eq!(store.cursor("journal"), Some("s=1;i=2"));
+    }
+
+    #[test]
+    fn test_rotations() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut connection = open(&dir.path().join("state.db")).unwrap();
+
+        for size in 1..=3 {
+            let entry = ledger::Entry {
+                rotated_at: Utc::now(),
+                old_filename: PathBuf::from("/var/log/app.log"),
+                new_filename: PathBuf::from(format!("/var/log/app.log.{}", size)),
+                size,
+                published_position: size,
+                published_line: 1,
+            };
+            record_rotation(&mut connection, &entry, 2).unwrap();
+        }
+
+        let sizes = rotations(&connection)
+            .unwrap()
+            .iter()
+            .map(|entry| entry.size)
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, vec![2, 3]);
+    }
+}
@@ -0,0 +1,1530 @@
+//! Persist the cursor, so the file is resumed where it was left after a restart
+use crate::hooks::{self, Hooks};
+use crate::stats;
+use crate::tail::LineBreak;
+use chrono::{DateTime, Utc};
+use crc::{Crc, CRC_32_ISCSI};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{watch, Notify};
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("corrupted saved state: {0}")]
+    CorruptedSavedState(String),
+    #[error("the saved state has been written by a newer version (format v{0})")]
+    UnsupportedVersion(u32),
+    #[error("offset <{0}> isn't the beginning of a line")]
+    NotLineBoundary(u64),
+    #[error("offset <{0}> is beyond the end of the file (<{1}> bytes)")]
+    BeyondEnd(u64, u64),
+    #[error("`{0}` is locked, another log-bouncer is already tailing the file")]
+    Locked(String),
+    #[error("unknown start `{0}`, expected `saved`, `beginning` or `end`")]
+    UnknownStart(String),
+    #[error("json: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("i/o: {0}")]
+    Io(#[from] std::io::Error),
+    #[cfg(not(feature = "sqlite"))]
+    #[error("`{0}` is a SQLite database, but the `sqlite` feature isn't enabled")]
+    SqliteDisabled(String),
+    #[cfg(feature = "sqlite")]
+    #[error("sqlite: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+pub const HASHER: Crc<u32> = Crc::<u32>::new(&CRC_32_ISCSI);
+
+/// Version of the format of the state file
+pub const STATE_VERSION: u32 = 3;
+
+/// Extensions of the state files that are SQLite databases
+const DATABASE_EXTENSIONS: &[&str] = &["db", "sqlite", "sqlite3"];
+
+/// Where the files that were already there when log-bouncer started are read from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StartFrom {
+    /// The saved position, the beginning of the files that haven't been read yet
+    Saved,
+    /// The beginning of every file, the saved positions are ignored
+    Beginning,
+    /// The saved position, the end of the files that haven't been read yet, like `tail -f`
+    End,
+}
+
+impl FromStr for StartFrom {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "saved" => Ok(StartFrom::Saved),
+            "beginning" => Ok(StartFrom::Beginning),
+            "end" => Ok(StartFrom::End),
+            _ => Err(Error::UnknownStart(s.to_owned())),
+        }
+    }
+}
+
+/// Where the reading stopped in the file
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Cursor {
+    /// Offset in bytes
+    pub position: u64,
+    /// Number of lines read, reset when the file gets rotated
+    pub line: u64,
+    /// The file the cursor is on, if known
+    ///
+    /// The lines of a file rotated by another tool can still be published while the new file
+    /// already took its place, they mustn't be saved as the position in the new one.
+    pub file_id: Option<FileId>,
+    /// See [`fingerprint`], of the last line read
+    pub fingerprint: Option<u32>,
+}
+
+/// Identity of a file, it stays the same when the file gets renamed, formatted as `dev:ino`
+///
+/// On Windows, these are the volume serial number and the file index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FileId {
+    pub dev: u64,
+    pub ino: u64,
+}
+
+impl FileId {
+    /// `None` if the platform can't tell the files apart
+    #[cfg(unix)]
+    pub fn of(file: &File) -> std::io::Result<Option<Self>> {
+        use std::os::unix::fs::MetadataExt;
+
+        let metadata = file.metadata()?;
+        Ok(Some(Self {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+        }))
+    }
+
+    #[cfg(windows)]
+    pub fn of(file: &File) -> std::io::Result<Option<Self>> {
+        let information = winapi_util::file::information(file)?;
+        Ok(Some(Self {
+            dev: information.volume_serial_number(),
+            ino: information.file_index(),
+        }))
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    pub fn of(_file: &File) -> std::io::Result<Option<Self>> {
+        Ok(None)
+    }
+
+    pub fn of_path(path: &Path) -> std::io::Result<Option<Self>> {
+        Self::of(&File::open(path)?)
+    }
+}
+
+impl fmt::Display for FileId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.dev, self.ino)
+    }
+}
+
+impl Serialize for FileId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for FileId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl FromStr for FileId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::CorruptedSavedState(format!("invalid file id `{}`", s));
+        let (dev, ino) = s.split_once(':').ok_or_else(invalid)?;
+
+        Ok(Self {
+            dev: dev.parse().map_err(|_| invalid())?,
+            ino: ino.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// Checksum of the first bytes of a file, it tells a file apart from a former one that had the
+/// same inode, see `--fingerprint-bytes`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Head {
+    pub size: u64,
+    pub checksum: u32,
+}
+
+/// Position saved for a file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub file_id: FileId,
+    /// Path of the file when it got saved
+    pub path: PathBuf,
+    pub position: u64,
+    pub line: u64,
+    /// Of the last published line, see [`fingerprint`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<u32>,
+    /// Unknown until the file is long enough
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub head: Option<Head>,
+    pub saved_at: DateTime<Utc>,
+}
+
+/// Every position of a store, as printed by `log-bouncer state export`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Export {
+    pub version: u32,
+    pub exported_at: DateTime<Utc>,
+    pub files: Vec<Entry>,
+}
+
+/// What the state file contains, the checksum is the one of the entries
+#[derive(Serialize, Deserialize)]
+struct StoreFile {
+    version: u32,
+    files: Vec<Entry>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    cursors: BTreeMap<String, String>,
+    crc: u32,
+}
+
+/// The state of a single file, as saved by the v1
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Record {
+    version: u32,
+    file_id: FileId,
+    position: u64,
+    line: u64,
+    saved_at: DateTime<Utc>,
+}
+
+/// The record along with its checksum, so a corrupted state is detected
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    #[serde(flatten)]
+    record: Record,
+    crc: u32,
+}
+
+impl Record {
+    fn from_json(json: &str) -> Result<Self> {
+        let envelope = serde_json::from_str::<Envelope>(json)
+            .map_err(|e| Error::CorruptedSavedState(e.to_string()))?;
+
+        if HASHER.checksum(&serde_json::to_vec(&envelope.record)?) != envelope.crc {
+            return Err(Error::CorruptedSavedState("checksum mismatch".into()));
+        }
+
+        Ok(envelope.record)
+    }
+}
+
+/// Positions of many files, keyed by their identity, saved in a single state file
+#[derive(Debug)]
+pub struct StateStore {
+    path: PathBuf,
+    files: BTreeMap<FileId, Entry>,
+    /// Where the inputs that aren't files are, eg. the cursor of the journal, by input
+    cursors: BTreeMap<String, String>,
+    /// State saved in a former format, it can only be matched against the file it was next to
+    legacy: Option<String>,
+    /// The positions are kept in a database rather than in a JSON file
+    #[cfg(feature = "sqlite")]
+    database: Option<rusqlite::Connection>,
+}
+
+impl StateStore {
+    pub fn new(path: PathBuf) -> Self {
+        debug!("Store the state in the file `{}`", path.to_string_lossy());
+
+        Self {
+            path,
+            files: BTreeMap::new(),
+            cursors: BTreeMap::new(),
+            legacy: None,
+            #[cfg(feature = "sqlite")]
+            database: None,
+        }
+    }
+
+    /// Open the store, it's a SQLite database if the path ends with `.db`, `.sqlite` or `.sqlite3`
+    pub fn open(path: PathBuf) -> Result<Self> {
+        if is_database(&path) {
+            Self::open_database(path)
+        } else {
+            Ok(Self::new(path))
+        }
+    }
+
+    #[cfg(feature = "sqlite")]
+    fn open_database(path: PathBuf) -> Result<Self> {
+        let database = crate::sqlite::open(&path)?;
+        debug!(
+            "Store the state in the database `{}`",
+            path.to_string_lossy()
+        );
+
+        Ok(Self {
+            path,
+            files: BTreeMap::new(),
+            cursors: BTreeMap::new(),
+            legacy: None,
+            database: Some(database),
+        })
+    }
+
+    #[cfg(not(feature = "sqlite"))]
+    fn open_database(path: PathBuf) -> Result<Self> {
+        Err(Error::SqliteDisabled(path.to_string_lossy().into_owned()))
+    }
+
+    /// The store of a single file lies next to it, eg. `.app.log.log-bouncer` for `app.log`
+    pub fn default_path(filepath: &Path) -> PathBuf {
+        let name = filepath.file_name().unwrap_or_default().to_string_lossy();
+        filepath.with_file_name(format!(".{}.log-bouncer", name))
+    }
+
+    /// Read the state file, a missing one is an empty store
+    pub fn load(&mut self) -> Result<()> {
+        self.files.clear();
+        self.cursors.clear();
+        self.legacy = None;
+
+        #[cfg(feature = "sqlite")]
+        if let Some(database) = &self.database {
+            self.files = crate::sqlite::load_positions(database)?
+                .into_iter()
+                .map(|entry| (entry.file_id, entry))
+                .collect();
+            self.cursors = crate::sqlite::load_cursors(database)?;
+
+            return Ok(());
+        }
+
+        let string = match std::fs::read_to_string(&self.path) {
+            Ok(string) => string,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => Err(e)?,
+        };
+
+        let value = match serde_json::from_str::<serde_json::Value>(&string) {
+            Ok(value) if value.get("files").is_some() => value,
+            _ => {
+                self.legacy = Some(string);
+                return Ok(());
+            }
+        };
+
+        let version = value["version"].as_u64().unwrap_or_default() as u32;
+        if version > STATE_VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+
+        let file = serde_json::from_value::<StoreFile>(value)
+            .map_err(|e| Error::CorruptedSavedState(e.to_string()))?;
+        if HASHER.checksum(&serde_json::to_vec(&file.files)?) != file.crc {
+            return Err(Error::CorruptedSavedState("checksum mismatch".into()));
+        }
+
+        self.files = file
+            .files
+            .into_iter()
+            .map(|entry| (entry.file_id, entry))
+            .collect();
+        self.cursors = file.cursors;
+
+        Ok(())
+    }
+
+    /// The cursor of an input that isn't a file
+    pub fn cursor(&self, input: &str) -> Option<&str> {
+        self.cursors.get(input).map(String::as_str)
+    }
+
+    pub fn set_cursor(&mut self, input: &str, cursor: String) {
+        self.cursors.insert(input.to_owned(), cursor);
+    }
+
+    pub fn get(&self, file_id: &FileId) -> Option<&Entry> {
+        self.files.get(file_id)
+    }
+
+    /// The entry of the file that was at this path
+    fn by_path(&self, path: &Path) -> Option<&Entry> {
+        self.files.values().find(|entry| entry.path == path)
+    }
+
+    /// Set the position of a file, the one formerly at the same path is forgotten
+    pub fn set(&mut self, entry: Entry) {
+        self.files
+            .retain(|file_id, saved| *file_id == entry.file_id || saved.path != entry.path);
+        self.files.insert(entry.file_id, entry);
+    }
+
+    /// Forget every file
+    pub fn clear(&mut self) {
+        self.files.clear();
+        self.cursors.clear();
+        self.legacy = None;
+    }
+
+    /// Write the state file, the former formats are then replaced
+    pub fn save(&mut self) -> Result<()> {
+        let files = self.files.values().cloned().collect::<Vec<_>>();
+
+        #[cfg(feature = "sqlite")]
+        if let Some(database) = &mut self.database {
+            crate::sqlite::save_positions(database, &files, &self.cursors)?;
+            return Ok(());
+        }
+
+        let file = StoreFile {
+            version: STATE_VERSION,
+            crc: HASHER.checksum(&serde_json::to_vec(&files)?),
+            files,
+            cursors: self.cursors.clone(),
+        };
+
+        write_atomically(&self.path, serde_json::to_string(&file)?.as_bytes())?;
+        self.legacy = None;
+
+        Ok(())
+    }
+}
+
+/// What's known about the saved position of a file, printed by `log-bouncer state show`
+#[derive(Debug, Serialize)]
+pub struct Status {
+    pub file: PathBuf,
+    pub state_file: PathBuf,
+    pub file_id: FileId,
+    /// Whether a position has been saved for this very file, otherwise it's read from the start
+    pub saved: bool,
+    pub position: u64,
+    pub line: u64,
+    /// Unknown for the states saved in a former format
+    pub saved_at: Option<DateTime<Utc>>,
+    pub file_size: u64,
+    /// Bytes left to publish
+    pub lag: u64,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "file:       {}", self.file.to_string_lossy())?;
+        writeln!(f, "state file: {}", self.state_file.to_string_lossy())?;
+        writeln!(f, "file id:    {}", self.file_id)?;
+
+        if !self.saved {
+            return writeln!(
+                f,
+                "no position saved for this file id, it's read from the beginning"
+            );
+        }
+
+        writeln!(f, "position:   {} (line {})", self.position, self.line)?;
+        match self.saved_at {
+            Some(saved_at) => writeln!(f, "saved at:   {}", saved_at.to_rfc3339())?,
+            None => writeln!(f, "saved at:   unknown, former state format")?,
+        }
+        writeln!(f, "file size:  {}", self.file_size)?;
+        writeln!(f, "lag:        {} bytes", self.lag)?;
+
+        if self.position > self.file_size {
+            writeln!(
+                f,
+                "the file is smaller than the position, it's read from the beginning"
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Held as long as the file is tailed, so that another instance can't tail it meanwhile
+#[derive(Debug)]
+pub struct InstanceLock {
+    _file: File,
+}
+
+impl InstanceLock {
+    /// Lock `<state file>.lock`, released when dropped or when the process dies
+    pub fn acquire(state_path: &Path) -> Result<Self> {
+        use fs2::FileExt;
+
+        let mut path = state_path.as_os_str().to_owned();
+        path.push(".lock");
+
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)?;
+        if file.try_lock_exclusive().is_err() {
+            return Err(Error::Locked(path.to_string_lossy().into_owned()));
+        }
+
+        // who holds it, for the operators
+        file.set_len(0)?;
+        writeln!(file, "{}", std::process::id())?;
+
+        Ok(Self { _file: file })
+    }
+}
+
+/// The state of a file, in a store that can be shared with other files
+pub struct SavedState {
+    /// Log file whose position is saved
+    filepath: PathBuf,
+    store: Arc<Mutex<StateStore>>,
+    /// Identify the file by its first bytes as well, see [`Head`]
+    head_size: Option<u64>,
+    /// The head of the file once it's been computed, it doesn't change as lines get appended
+    head: Option<(FileId, Head)>,
+    line_break: LineBreak,
+}
+
+impl SavedState {
+    /// The state is stored next to the file, see [`StateStore::default_path`]
+    pub fn new(filepath: &Path) -> Result<Self> {
+        let store = StateStore::new(StateStore::default_path(filepath));
+
+        Ok(Self::in_store(filepath, Arc::new(Mutex::new(store))))
+    }
+
+    pub fn in_store(filepath: &Path, store: Arc<Mutex<StateStore>>) -> Self {
+        Self {
+            filepath: filepath.to_owned(),
+            store,
+            head_size: None,
+            head: None,
+            line_break: LineBreak::Byte,
+        }
+    }
+
+    /// Identify the file by its first bytes along with its inode, which can be recycled once
+    /// the file is deleted, see [`Head`]
+    pub fn with_head_size(mut self, head_size: Option<u64>) -> Self {
+        self.head_size = head_size.filter(|size| *size > 0);
+        self
+    }
+
+    /// The lines end according to the encoding of the file, see [`LineBreak`]
+    pub fn with_line_break(mut self, line_break: LineBreak) -> Self {
+        self.line_break = line_break;
+        self
+    }
+
+    /// Get the cursor we should start to read the file from, a corrupted state is discarded
+    pub fn recover(&mut self) -> Result<Cursor> {
+        match self.read_file() {
+            Ok(cursor) => {
+                info!("Saved state exists, we recover it");
+                Ok(cursor)
+            }
+            Err(e) => match e {
+                Error::CorruptedSavedState(_) => {
+                    warn!("Corrupted saved state, we create a new one");
+                    self.store.lock().unwrap().clear();
+                    let cursor = Cursor::default(); // starts from scratch
+                    self.save(cursor)?;
+                    Ok(cursor)
+                }
+                _ => Err(e),
+            },
+        }
+    }
+
+    /// Get the cursor we should start to read the file from, see [`StartFrom`]
+    pub fn start(&mut self, start_from: StartFrom) -> Result<Cursor> {
+        match start_from {
+            StartFrom::Saved => self.recover(),
+            StartFrom::Beginning => {
+                info!("Reading the file from its beginning");
+                self.reset()?;
+                Ok(Cursor::default())
+            }
+            StartFrom::End if self.is_known()? => self.recover(),
+            StartFrom::End => {
+                info!("The file hasn't been read yet, reading it from its end");
+                self.seek_end()
+            }
+        }
+    }
+
+    /// Whether a position of the file has been saved, even under another identity or format
+    fn is_known(&self) -> Result<bool> {
+        let mut store = self.store.lock().unwrap();
+        store.load()?;
+
+        Ok(store.get(&self.file_id()?).is_some()
+            || store.legacy.is_some()
+            || store.by_path(&self.filepath).is_some())
+    }
+
+    /// Recover the saved state if exists, see [`SavedState::read_legacy`] for the former formats
+    pub fn read_file(&mut self) -> Result<Cursor> {
+        let file_id = self.file_id()?;
+        let head = self.head(file_id)?;
+        let mut store = self.store.lock().unwrap();
+        store.load()?;
+
+        let recycled = store
+            .get(&file_id)
+            .is_some_and(|entry| is_recycled(entry, head, self.head_size));
+        if recycled {
+            warn!(
+                "`{}` has the inode of a former file, it's read from its beginning",
+                self.filepath.to_string_lossy()
+            );
+            return Ok(Cursor::default());
+        }
+
+        if let Some(entry) = store.get(&file_id) {
+            return Ok(Cursor {
+                position: entry.position,
+                line: entry.line,
+                file_id: Some(file_id),
+                fingerprint: entry.fingerprint,
+            });
+        }
+
+        let cursor = match &store.legacy {
+            Some(legacy) => self.read_legacy(legacy)?,
+            // this is a new file, we start from 0
+            None => Cursor::default(),
+        };
+
+        // the file can have been replaced by a copy, holding the lines already published
+        match store.by_path(&self.filepath) {
+            Some(published) => self.skip_published(cursor, published, file_id),
+            None => Ok(cursor),
+        }
+    }
+
+    /// The head of the file at this identity, `None` if it's disabled or the file is too short
+    fn head(&mut self, file_id: FileId) -> Result<Option<Head>> {
+        let size = match self.head_size {
+            Some(size) => size,
+            None => return Ok(None),
+        };
+        if let Some((id, head)) = self.head {
+            if id == file_id && head.size == size {
+                return Ok(Some(head));
+            }
+        }
+
+        // the path may hold another file by now, eg. once it's been rotated
+        if self.file_id()? != file_id {
+            return Ok(None);
+        }
+        let mut buffer = vec![];
+        File::open(&self.filepath)?
+            .take(size)
+            .read_to_end(&mut buffer)?;
+        if (buffer.len() as u64) < size {
+            return Ok(None);
+        }
+
+        let head = Head {
+            size,
+            checksum: HASHER.checksum(&buffer),
+        };
+        self.head = Some((file_id, head));
+
+        Ok(Some(head))
+    }
+
+    /// Move the cursor after the last published line, if the file still holds this very line
+    /// at the same line number
+    fn skip_published(&self, cursor: Cursor, published: &Entry, file_id: FileId) -> Result<Cursor> {
+        use std::io::BufReader;
+
+        let fingerprint = match published.fingerprint {
+            Some(fingerprint) if published.line > cursor.line => fingerprint,
+            _ => return Ok(cursor),
+        };
+
+        let mut reader = BufReader::new(File::open(&self.filepath)?);
+        reader.seek(SeekFrom::Start(cursor.position))?;
+
+        let (mut position, mut line) = (cursor.position, cursor.line);
+        let mut buffer = vec![];
+
+        while line < published.line {
+            buffer.clear();
+            let n = self.line_break.read_line(&mut reader, &mut buffer)?;
+            if !self.line_break.ends_line(&buffer) {
+                return Ok(cursor); // the file is shorter
+            }
+
+            position += n as u64;
+            line += 1;
+        }
+
+        if HASHER.checksum(self.line_break.strip(&buffer)) != fingerprint {
+            return Ok(cursor);
+        }
+
+        info!(
+            "The lines up to <{}> have already been published, they're skipped",
+            line
+        );
+
+        Ok(Cursor {
+            position,
+            line,
+            file_id: Some(file_id),
+            fingerprint: Some(fingerprint),
+        })
+    }
+
+    /// Every position of the store, to back them up or to move them to another host
+    pub fn export(&self) -> Result<Export> {
+        let mut store = self.store.lock().unwrap();
+        store.load()?;
+
+        Ok(Export {
+            version: STATE_VERSION,
+            exported_at: Utc::now(),
+            files: store.files.values().cloned().collect(),
+        })
+    }
+
+    /// Lock the state, see [`InstanceLock`]
+    pub fn lock(&self) -> Result<InstanceLock> {
+        InstanceLock::acquire(&self.store.lock().unwrap().path)
+    }
+
+    /// The saved position compared to the file, without changing anything
+    pub fn status(&mut self) -> Result<Status> {
+        let cursor = self.read_file()?;
+        let file_id = self.file_id()?;
+        let file_size = std::fs::metadata(&self.filepath)?.len();
+        let store = self.store.lock().unwrap();
+
+        Ok(Status {
+            file: self.filepath.clone(),
+            state_file: store.path.clone(),
+            file_id,
+            saved: cursor.file_id.is_some(),
+            position: cursor.position,
+            line: cursor.line,
+            saved_at: store.get(&file_id).map(|entry| entry.saved_at),
+            file_size,
+            lag: file_size.saturating_sub(cursor.position),
+        })
+    }
+
+    /// The v1 [`Record`], or the state formatted as `dev:ino;position;line`, states saved before
+    /// the line numbers were tracked only contain `uniq_id;position`, the line is then counted
+    /// from the file, and before the inodes were used the `uniq_id` is the checksum of the first
+    /// line.
+    fn read_legacy(&self, string: &str) -> Result<Cursor> {
+        if string.trim_start().starts_with('{') {
+            let record = Record::from_json(string)?;
+            let file_id = self.file_id()?;
+
+            if record.file_id != file_id {
+                return Ok(Cursor::default());
+            }
+
+            return Ok(Cursor {
+                position: record.position,
+                line: record.line,
+                file_id: Some(file_id),
+                fingerprint: None,
+            });
+        }
+
+        let state = string.trim().split(';').collect::<Vec<_>>();
+
+        if state.len() != 2 && state.len() != 3 {
+            Err(Error::CorruptedSavedState(
+                "State should contains 2 or 3 entries".into(),
+            ))?;
+        }
+
+        let numbers = state[1..]
+            .iter()
+            .map(|number| number.parse::<u64>())
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|e| Error::CorruptedSavedState(e.to_string()))?;
+
+        debug!("Recovered uniq_id of the file `{}`", state[0]);
+        let file_id = self.file_id()?;
+        let same_file = match state[0].parse::<FileId>() {
+            Ok(uniq_id) => uniq_id == file_id,
+            Err(e) => match state[0].parse::<u32>() {
+                Ok(checksum) => checksum == self.first_line_checksum()?,
+                Err(_) => return Err(e),
+            },
+        };
+
+        if !same_file {
+            // this is a new file, we start from 0
+            return Ok(Cursor::default());
+        }
+
+        // same file, we recover the saved position
+        let position = numbers[0];
+        let line = match numbers.get(1) {
+            Some(line) => *line,
+            None => self.count_lines(position)?,
+        };
+
+        Ok(Cursor {
+            position,
+            line,
+            file_id: Some(file_id),
+            fingerprint: None,
+        })
+    }
+
+    /// Count the lines before the position
+    fn count_lines(&self, position: u64) -> Result<u64> {
+        use std::io::BufReader;
+
+        let mut reader = BufReader::new(File::open(&self.filepath)?.take(position));
+        let mut lines = 0;
+        let mut line = vec![];
+
+        while self.line_break.read_line(&mut reader, &mut line)? > 0 {
+            line.clear();
+            lines += 1;
+        }
+
+        Ok(lines)
+    }
+
+    /// Identity of the file, see [`FileId`]
+    pub fn file_id(&self) -> Result<FileId> {
+        match FileId::of_path(&self.filepath)? {
+            Some(file_id) => Ok(file_id),
+            // the platform doesn't identify the files, the first line tells them apart
+            None => Ok(FileId {
+                dev: 0,
+                ino: self.first_line_checksum()? as u64,
+            }),
+        }
+    }
+
+    /// Checksum of the first line, which identified the file before the inodes were used
+    fn first_line_checksum(&self) -> Result<u32> {
+        use std::io::{BufRead, BufReader};
+
+        let file = File::open(&self.filepath)?;
+        let mut reader = BufReader::new(file);
+
+        let mut first_line = String::new();
+        reader.read_line(&mut first_line)?;
+
+        let first_line = first_line.trim();
+        debug!("File's first line content is `{}`", &first_line);
+
+        let hashed = HASHER.checksum(first_line.as_bytes());
+        debug!("File's first line hash is `{}`", hashed);
+
+        Ok(hashed)
+    }
+
+    /// Reset the position to the beginning of the file
+    pub fn reset(&mut self) -> Result<()> {
+        self.save(Cursor::default())
+    }
+
+    /// Save the position at this offset, which has to be the beginning of a line
+    pub fn seek(&mut self, offset: u64) -> Result<Cursor> {
+        let mut file = File::open(&self.filepath)?;
+        let size = file.metadata()?.len();
+
+        if offset > size {
+            return Err(Error::BeyondEnd(offset, size));
+        }
+
+        if offset > 0 {
+            // the line breaker takes two bytes in UTF-16
+            let width = self.line_break.width();
+            if !offset.is_multiple_of(width as u64) {
+                return Err(Error::NotLineBoundary(offset));
+            }
+
+            let mut previous = vec![0u8; width];
+            file.seek(SeekFrom::Start(offset - width as u64))?;
+            file.read_exact(&mut previous)?;
+
+            if !self.line_break.ends_line(&previous) {
+                return Err(Error::NotLineBoundary(offset));
+            }
+        }
+
+        let cursor = Cursor {
+            position: offset,
+            line: self.count_lines(offset)?,
+            file_id: Some(self.file_id()?),
+            fingerprint: None,
+        };
+        self.save(cursor)?;
+
+        Ok(cursor)
+    }
+
+    /// Save the position at the end of the last complete line, a line being written is kept
+    pub fn seek_end(&mut self) -> Result<Cursor> {
+        let mut file = File::open(&self.filepath)?;
+        let len = file.metadata()?.len();
+        // an odd byte at the end of UTF-16 is a line being written
+        let mut end = len - len % self.line_break.width() as u64;
+        let mut buffer = [0u8; 4096];
+
+        while end > 0 {
+            let start = end.saturating_sub(buffer.len() as u64);
+            let chunk = &mut buffer[..(end - start) as usize];
+            file.seek(SeekFrom::Start(start))?;
+            file.read_exact(chunk)?;
+
+            if let Some(len) = self.line_break.rfind(chunk) {
+                return self.seek(start + len as u64);
+            }
+
+            end = start;
+        }
+
+        self.seek(0)
+    }
+
+    /// Save state in a file
+    pub fn save(&mut self, cursor: Cursor) -> Result<()> {
+        debug!(
+            "Saving a state at position <{}>, line <{}>",
+            cursor.position, cursor.line
+        );
+
+        let file_id = match cursor.file_id {
+            Some(file_id) => file_id,
+            None => self.file_id()?,
+        };
+        let entry = Entry {
+            file_id,
+            path: self.filepath.clone(),
+            position: cursor.position,
+            line: cursor.line,
+            fingerprint: cursor.fingerprint,
+            // the file may have been deleted meanwhile
+            head: self.head(file_id).unwrap_or(None),
+            saved_at: Utc::now(),
+        };
+
+        let mut store = self.store.lock().unwrap();
+        store.set(entry);
+        store.save()
+    }
+}
+
+/// Save the cursor of the last published line periodically
+pub struct StateSaver {
+    state: SavedState,
+    /// Receive the current cursor on the file
+    state_rx: watch::Receiver<Cursor>,
+    /// Save state interval
+    interval: Duration,
+    /// Save the state even if it hasn't changed, eg. if the state file got deleted meanwhile
+    flush_interval: Duration,
+    /// Reset the state to the beginning of the file, once it has been rotated
+    reset: Arc<Notify>,
+    /// Save the state a last time, then stop
+    stop: Arc<Notify>,
+    hooks: Hooks,
+}
+
+impl StateSaver {
+    pub fn new(
+        state: SavedState,
+        state_rx: watch::Receiver<Cursor>,
+        interval: Duration,
+        flush_interval: Duration,
+    ) -> Self {
+        Self {
+            state,
+            state_rx,
+            interval,
+            flush_interval,
+            reset: Arc::new(Notify::new()),
+            stop: Arc::new(Notify::new()),
+            hooks: Hooks::default(),
+        }
+    }
+
+    /// Tell once the position has been saved, see [`hooks::Event::StateSaved`]
+    pub fn with_hooks(mut self, hooks: Hooks) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    /// Notify it to reset the state
+    pub fn reset_trigger(&self) -> Arc<Notify> {
+        self.reset.clone()
+    }
+
+    /// Notify it to save the last position then stop, on shutdown
+    pub fn stop_trigger(&self) -> Arc<Notify> {
+        self.stop.clone()
+    }
+
+    pub fn watch(mut self) -> JoinHandle<()> {
+        tokio::spawn(async move { self.work().await })
+    }
+
+    async fn work(&mut self) {
+        let mut interval = tokio::time::interval(self.interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        let mut flush_interval = tokio::time::interval(self.flush_interval);
+        flush_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        let reset = self.reset.clone();
+        let stop = self.stop.clone();
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    trace!("Tick(state): do a job");
+
+                    match self.state_rx.has_changed() {
+                        Ok(true) => self.save(),
+                        // nothing has been published since the last save
+                        Ok(false) => {}
+                        // the source is gone, nothing more will be published
+                        Err(_) => {
+                            info!("Saving the last position");
+                            self.save();
+                            break;
+                        }
+                    }
+                }
+                _ = flush_interval.tick() => {
+                    trace!("Tick(state): flush");
+                    self.save();
+                }
+                _ = stop.notified() => {
+                    info!("Saving the last position");
+                    self.save();
+                    break;
+                }
+                _ = reset.notified() => {
+                    if let Err(e) = self.state.reset() {
+                        error!("Can't reset the state, after rotating the file: `{}`", e);
+                    }
+
+                    // we discard this value as the file just changed
+                    let _cursor = *self.state_rx.borrow_and_update();
+                }
+            }
+        }
+    }
+
+    fn save(&mut self) {
+        let cursor = *self.state_rx.borrow_and_update();
+
+        match self.state.save(cursor) {
+            Ok(()) => self.hooks.emit(|| hooks::Event::StateSaved {
+                path: self.state.filepath.clone(),
+                position: cursor.position,
+            }),
+            Err(e) => {
+                stats::stats().error("state", &e);
+                error!("Can't save current state: `{}`", e);
+            }
+        }
+    }
+}
+
+/// Write a temporary file then rename it over the former one, so a crash can't leave the file
+/// half written, it's either the former content or the new one
+fn write_atomically(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+
+    let mut file = File::create(&tmp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path)?;
+
+    // the renaming is durable once the directory is synced
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        File::open(dir)?.sync_all()?;
+    }
+
+    Ok(())
+}
+
+/// Whether the entry has been saved for a deleted file whose inode is now the one of this file,
+/// its head is different or the file is shorter
+fn is_recycled(entry: &Entry, head: Option<Head>, head_size: Option<u64>) -> bool {
+    match (entry.head, head_size) {
+        (Some(saved), Some(size)) if saved.size == size => head != Some(saved),
+        _ => false,
+    }
+}
+
+/// Checksum of a line as it's been read, without its line break, it tells whether a file still
+/// holds a line that has been published
+pub fn fingerprint(line: &[u8]) -> u32 {
+    HASHER.checksum(line)
+}
+
+/// Whether the state file is a SQLite database, according to its extension
+pub fn is_database(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| DATABASE_EXTENSIONS.contains(&extension))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_recover_the_cursor() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.log");
+        std::fs::write(&path, "first\nsecond\nthird\n").unwrap();
+
+        let cursor = Cursor {
+            position: 13,
+            line: 2,
+            file_id: None,
+            fingerprint: None,
+        };
+        let mut state = SavedState::new(&path).unwrap();
+        state.save(cursor).unwrap();
+
+        assert_eq!(
+            SavedState::new(&path).unwrap().read_file().unwrap(),
+            Cursor {
+                file_id: Some(state.file_id().unwrap()),
+                ..cursor
+            }
+        );
+    }
+
+    #[test]
+    fn test_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.log");
+        std::fs::write(&path, "first\nsecond\nthird\n").unwrap();
+
+        let mut state = SavedState::new(&path).unwrap();
+        assert!(!state.status().unwrap().saved);
+
+        state
+            .save(Cursor {
+                position: 13,
+                line: 2,
+                file_id: None,
+                fingerprint: None,
+            })
+            .unwrap();
+
+        let status = state.status().unwrap();
+        assert!(status.saved);
+        assert!(status.saved_at.is_some());
+        assert_eq!(status.state_file, dir.path().join(".test.log.log-bouncer"));
+        assert_eq!((status.position, status.file_size, status.lag), (13, 19, 6));
+        assert!(status.to_string().contains("position:   13 (line 2)"));
+    }
+
+    #[test]
+    fn test_seek() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.log");
+        std::fs::write(&path, "first\nsecond\nthi").unwrap();
+
+        let mut state = SavedState::new(&path).unwrap();
+        assert_eq!(state.seek(6).unwrap().line, 1);
+        assert!(matches!(state.seek(8), Err(Error::NotLineBoundary(8))));
+        assert!(matches!(state.seek(20), Err(Error::BeyondEnd(20, 16))));
+
+        let cursor = state.seek_end().unwrap();
+        assert_eq!((cursor.position, cursor.line), (13, 2));
+        assert_eq!(state.read_file().unwrap(), cursor);
+    }
+
+    #[test]
+    fn test_seek_utf16() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.log");
+        let text = "first\nsecond\nthi".encode_utf16();
+        std::fs::write(&path, text.flat_map(u16::to_le_bytes).collect::<Vec<_>>()).unwrap();
+
+        let mut state = SavedState::new(&path)
+            .unwrap()
+            .with_line_break(LineBreak::Utf16Le);
+        assert_eq!(state.seek(12).unwrap().line, 1);
+        assert!(matches!(state.seek(11), Err(Error::NotLineBoundary(11))));
+
+        let cursor = state.seek_end().unwrap();
+        assert_eq!((cursor.position, cursor.line), (26, 2));
+    }
+
+    #[test]
+    fn test_recycled_inode() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.log");
+        std::fs::write(&path, "first\nsecond\n").unwrap();
+
+        let mut state = SavedState::new(&path).unwrap().with_head_size(Some(4));
+        state.seek(6).unwrap();
+        assert_eq!(state.read_file().unwrap().position, 6);
+
+        // the same inode, holding another file
+        std::fs::write(&path, "other\nlines\n").unwrap();
+        let mut state = SavedState::new(&path).unwrap().with_head_size(Some(4));
+        assert_eq!(state.read_file().unwrap(), Cursor::default());
+
+        // without the head, only the inode is compared
+        let mut state = SavedState::new(&path).unwrap();
+        assert_eq!(state.read_file().unwrap().position, 6);
+    }
+
+    #[test]
+    fn test_start_from() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.log");
+        std::fs::write(&path, "first\nsecond\n").unwrap();
+
+        let mut state = SavedState::new(&path).unwrap();
+        assert_eq!(state.start(StartFrom::End).unwrap().position, 13);
+
+        // the saved position is honored on the next runs
+        std::fs::write(&path, "first\nsecond\nthird\n").unwrap();
+        let mut state = SavedState::new(&path).unwrap();
+        assert_eq!(state.start(StartFrom::End).unwrap().position, 13);
+        assert_eq!(state.start(StartFrom::Saved).unwrap().position, 13);
+
+        assert_eq!(
+            state.start(StartFrom::Beginning).unwrap(),
+            Cursor::default()
+        );
+        assert_eq!(state.read_file().unwrap().position, 0);
+    }
+
+    #[tokio::test]
+    async fn test_saver_stops_once_the_source_is_gone() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.log");
+        std::fs::write(&path, "first\n").unwrap();
+
+        let (state_tx, state_rx) = watch::channel(Cursor::default());
+        let interval = Duration::from_millis(10);
+        let handle = StateSaver::new(
+            SavedState::new(&path).unwrap(),
+            state_rx,
+            interval,
+            interval,
+        )
+        .watch();
+        state_tx.send_replace(Cursor {
+            position: 6,
+            line: 1,
+            file_id: None,
+            fingerprint: None,
+        });
+        drop(state_tx);
+
+        tokio::time::timeout(Duration::from_secs(1), handle)
+            .await
+            .unwrap()
+            .unwrap();
+        let saved = SavedState::new(&path).unwrap().read_file().unwrap();
+        assert_eq!(saved.position, 6);
+    }
+
+    #[tokio::test]
+    async fn test_saver_flushes_and_saves_on_stop() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.log");
+        std::fs::write(&path, "first\nsecond\n").unwrap();
+        let state_path = StateStore::default_path(&path);
+
+        let (state_tx, state_rx) = watch::channel(Cursor::default());
+        let saver = StateSaver::new(
+            SavedState::new(&path).unwrap(),
+            state_rx,
+            Duration::from_secs(3600),
+            Duration::from_millis(50),
+        );
+        let stop = saver.stop_trigger();
+        let handle = saver.watch();
+
+        // saved even though nothing changed
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(state_path.exists());
+
+        state_tx
+            .send(Cursor {
+                position: 13,
+                line: 2,
+                file_id: None,
+                fingerprint: None,
+            })
+            .unwrap();
+        stop.notify_one();
+        handle.await.unwrap();
+
+        assert_eq!(
+            SavedState::new(&path)
+                .unwrap()
+                .read_file()
+                .unwrap()
+                .position,
+            13
+        );
+    }
+
+    #[test]
+    fn test_instance_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.log");
+        std::fs::write(&path, "first\n").unwrap();
+
+        let state = SavedState::new(&path).unwrap();
+        let lock = state.lock().unwrap();
+        assert!(matches!(state.lock(), Err(Error::Locked(_))));
+
+        drop(lock);
+        assert!(state.lock().is_ok());
+    }
+
+    #[test]
+    fn test_skip_the_lines_already_published() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.log");
+        std::fs::write(&path, "first\nsecond\nthird\n").unwrap();
+
+        let mut state = SavedState::new(&path).unwrap();
+        state
+            .save(Cursor {
+                position: 13,
+                line: 2,
+                file_id: None,
+                fingerprint: Some(fingerprint(b"second")),
+            })
+            .unwrap();
+
+        // replaced by a copy, eg. by an editor
+        let copy = dir.path().join("test.log.copy");
+        std::fs::copy(&path, &copy).unwrap();
+        std::fs::rename(&copy, &path).unwrap();
+
+        let cursor = state.read_file().unwrap();
+        assert_eq!((cursor.position, cursor.line), (13, 2));
+        assert_eq!(cursor.file_id, Some(state.file_id().unwrap()));
+        state.save(cursor).unwrap();
+
+        // another file took its place
+        std::fs::write(&copy, "other\nlines\n").unwrap();
+        std::fs::rename(&copy, &path).unwrap();
+        assert_eq!(state.read_file().unwrap(), Cursor::default());
+    }
+
+    #[test]
+    fn test_write_atomically() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".test.log.log-bouncer");
+        std::fs::write(&path, "a longer former state").unwrap();
+
+        write_atomically(&path, b"1;2;3").unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "1;2;3");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_corrupted_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.log");
+        std::fs::write(&path, "first\nsecond\nthird\n").unwrap();
+
+        let cursor = Cursor {
+            position: 13,
+            line: 2,
+            file_id: None,
+            fingerprint: None,
+        };
+        SavedState::new(&path).unwrap().save(cursor).unwrap();
+
+        let state_path = dir.path().join(".test.log.log-bouncer");
+        let json = std::fs::read_to_string(&state_path).unwrap();
+        assert!(json.contains(r#""version":3"#));
+
+        std::fs::write(&state_path, json.replace(":13,", ":6,")).unwrap();
+        assert!(matches!(
+            SavedState::new(&path).unwrap().read_file(),
+            Err(Error::CorruptedSavedState(_))
+        ));
+
+        std::fs::write(
+            &state_path,
+            json.replace(r#""version":3"#, r#""version":4"#),
+        )
+        .unwrap();
+        assert!(matches!(
+            SavedState::new(&path).unwrap().read_file(),
+            Err(Error::UnsupportedVersion(4))
+        ));
+    }
+
+    #[test]
+    fn test_store_of_many_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = StateStore::new(dir.path().join("state.json"));
+        let store = Arc::new(Mutex::new(store));
+
+        let mut states = vec![];
+        for (i, name) in ["a.log", "b.log"].iter().enumerate() {
+            let path = dir.path().join(name);
+            std::fs::write(&path, "first\nsecond\n").unwrap();
+
+            let mut state = SavedState::in_store(&path, store.clone());
+            let cursor = Cursor {
+                position: 6 * (i as u64 + 1),
+                line: i as u64 + 1,
+                file_id: None,
+                fingerprint: None,
+            };
+            state.save(cursor).unwrap();
+            states.push((state, cursor));
+        }
+
+        for (mut state, cursor) in states {
+            let recovered = state.read_file().unwrap();
+            assert_eq!(recovered.position, cursor.position);
+            assert_eq!(recovered.line, cursor.line);
+        }
+
+        // a file replaced at the same path replaces its entry
+        let path = dir.path().join("a.log");
+        std::fs::rename(&path, dir.path().join("a.log.1")).unwrap();
+        std::fs::write(&path, "").unwrap();
+        SavedState::in_store(&path, store.clone())
+            .save(Cursor::default())
+            .unwrap();
+        assert_eq!(store.lock().unwrap().files.len(), 2);
+    }
+
+    #[test]
+    fn test_cursor_of_an_input() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+
+        let mut store = StateStore::new(path.clone());
+        store.set_cursor("journal", "s=1;i=2".to_owned());
+        store.save().unwrap();
+
+        let mut store = StateStore::new(path);
+        store.load().unwrap();
+        assert_eq!(store.cursor("journal"), Some("s=1;i=2"));
+        assert_eq!(store.cursor("other"), None);
+    }
+
+    #[test]
+    fn test_recover_a_v1_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.log");
+        std::fs::write(&path, "first\nsecond\nthird\n").unwrap();
+
+        let mut state = SavedState::new(&path).unwrap();
+        let record = Record {
+            version: 1,
+            file_id: state.file_id().unwrap(),
+            position: 13,
+            line: 2,
+            saved_at: Utc::now(),
+        };
+        let envelope = Envelope {
+            crc: HASHER.checksum(&serde_json::to_vec(&record).unwrap()),
+            record,
+        };
+        std::fs::write(
+            dir.path().join(".test.log.log-bouncer"),
+            serde_json::to_string(&envelope).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(state.read_file().unwrap().position, 13);
+    }
+
+    #[test]
+    fn test_cursor_of_a_renamed_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.log");
+        std::fs::write(&path, "first\nsecond\nthird\n").unwrap();
+
+        let cursor = Cursor {
+            position: 13,
+            line: 2,
+            file_id: None,
+            fingerprint: None,
+        };
+        SavedState::new(&path).unwrap().save(cursor).unwrap();
+
+        // replaced by a file starting with the same line
+        std::fs::rename(&path, dir.path().join("test.log.1")).unwrap();
+        std::fs::write(&path, "first\n").unwrap();
+
+        assert_eq!(
+            SavedState::new(&path).unwrap().read_file().unwrap(),
+            Cursor::default()
+        );
+    }
+
+    #[test]
+    fn test_recover_a_state_without_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.log");
+        std::fs::write(&path, "first\nsecond\nthird\n").unwrap();
+
+        let mut state = SavedState::new(&path).unwrap();
+        // saved before the inodes were used
+        std::fs::write(
+            dir.path().join(".test.log.log-bouncer"),
+            format!("{};13", HASHER.checksum(b"first")),
+        )
+        .unwrap();
+
+        assert_eq!(
+            state.read_file().unwrap(),
+            Cursor {
+                position: 13,
+                line: 2,
+                file_id: Some(state.file_id().unwrap()),
+                fingerprint: None,
+            }
+        );
+    }
+}
@@ -0,0 +1,361 @@
+//! Summary of the activity logged periodically, so the health of the shipper is visible even
+//! without a metrics stack. With `--json`, the figures are fields of the log line.
+//!
+//! The lag of every file, how far the published position is behind its end, and the counts of the
+//! lines dropped and of the errors by category are exposed as metrics as well, to tell the
+//! intentional filtering from an actual loss.
+use crate::metrics;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+const LAG_METRIC: &str = "log_bouncer_lag_bytes";
+const SINCE_PUBLISH_METRIC: &str = "log_bouncer_seconds_since_publish";
+pub(crate) const DROPPED_METRIC: &str = "log_bouncer_dropped_lines_total";
+const ERRORS_METRIC: &str = "log_bouncer_errors_total";
+
+/// The lag metrics are updated that often
+const LAG_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Latencies kept to compute the percentile, the following ones of the interval are ignored
+const MAX_LATENCIES: usize = 100_000;
+
+/// The latest errors kept, eg. for `log-bouncer status`
+const MAX_RECENT_ERRORS: usize = 10;
+
+/// Where the publisher is in a file
+#[derive(Debug, Clone, Copy)]
+struct Progress {
+    position: u64,
+    /// When a line of the file has last been acknowledged, never since the start if `None`
+    at: Option<Instant>,
+}
+
+/// How far the published position is behind the end of the file
+#[derive(Debug, PartialEq)]
+pub struct Lag {
+    pub path: PathBuf,
+    /// Position of the last line published
+    pub position: u64,
+    pub size: u64,
+    /// Since a line has been published, `None` if none has been since the start
+    pub since: Option<Duration>,
+}
+
+impl Lag {
+    pub fn bytes(&self) -> u64 {
+        self.size.saturating_sub(self.position)
+    }
+}
+
+/// What happened since the last summary
+#[derive(Debug, Default)]
+struct Window {
+    lines: u64,
+    bytes: u64,
+    /// Publish latencies, in seconds
+    latencies: Vec<f64>,
+    dropped: BTreeMap<&'static str, u64>,
+    errors: BTreeMap<&'static str, u64>,
+}
+
+/// An error that happened lately
+#[derive(Debug, Clone, Serialize)]
+pub struct RecentError {
+    pub at: DateTime<Utc>,
+    pub kind: &'static str,
+    pub message: String,
+}
+
+#[derive(Default)]
+pub struct Stats {
+    window: Mutex<Window>,
+    /// Position of the last line published, by file
+    positions: Mutex<BTreeMap<PathBuf, Progress>>,
+    recent_errors: Mutex<VecDeque<RecentError>>,
+}
+
+impl Stats {
+    /// A line has been published, along with the time the output took to confirm it
+    pub fn published(&self, bytes: usize, latency: Duration) {
+        let mut window = self.window.lock().unwrap();
+        window.lines += 1;
+        window.bytes += bytes as u64;
+        if window.latencies.len() < MAX_LATENCIES {
+            window.latencies.push(latency.as_secs_f64());
+        }
+    }
+
+    /// The file is read from this position, nothing has been published yet
+    pub fn follow(&self, path: &Path, position: u64) {
+        let progress = Progress { position, at: None };
+        self.positions
+            .lock()
+            .unwrap()
+            .insert(path.to_owned(), progress);
+    }
+
+    /// The file isn't followed anymore, its lag isn't exposed either
+    pub fn forget(&self, path: &Path) {
+        self.positions.lock().unwrap().remove(path);
+
+        let file = path.to_string_lossy();
+        let registry = metrics::registry();
+        registry.remove(LAG_METRIC, &[("file", &file)]);
+        registry.remove(SINCE_PUBLISH_METRIC, &[("file", &file)]);
+    }
+
+    /// The lines of the file won't be read again until this position
+    pub fn acknowledged(&self, path: &Path, position: u64) {
+        let progress = Progress {
+            position,
+            at: Some(Instant::now()),
+        };
+
+        let mut positions = self.positions.lock().unwrap();
+        match positions.get_mut(path) {
+            Some(known) => *known = progress,
+            None => {
+                positions.insert(path.to_owned(), progress);
+            }
+        }
+    }
+
+    /// The lag of every file, the inputs that aren't files are left out, eg. the standard input
+    pub fn lags(&self) -> Vec<Lag> {
+        let positions = self.positions.lock().unwrap().clone();
+
+        positions
+            .into_iter()
+            .filter_map(|(path, progress)| {
+                let size = std::fs::metadata(&path).ok()?.len();
+                Some(Lag {
+                    path,
+                    position: progress.position,
+                    size,
+                    since: progress.at.map(|at| at.elapsed()),
+                })
+            })
+            .collect()
+    }
+
+    /// Expose the lag of every file
+    fn update_lags(&self) {
+        let registry = metrics::registry();
+
+        for lag in self.lags() {
+            let file = lag.path.to_string_lossy();
+            registry.set_gauge(LAG_METRIC, &[("file", &file)], lag.bytes() as f64);
+            if let Some(since) = lag.since {
+                let labels = [("file", file.as_ref())];
+                registry.set_gauge(SINCE_PUBLISH_METRIC, &labels, since.as_secs_f64());
+            }
+        }
+    }
+
+    /// A line won't be published, `reason` tells why: `filtered` by the stages on purpose,
+    /// `invalid_utf8`, `spooled` on shutdown, or `lost` when it can't be spooled
+    pub fn dropped(&self, reason: &'static str) {
+        *self
+            .window
+            .lock()
+            .unwrap()
+            .dropped
+            .entry(reason)
+            .or_default() += 1;
+        metrics::registry().increment(DROPPED_METRIC, &[("reason", reason)], 1);
+    }
+
+    /// `kind` of error: `publish_failed`, `read` or `state`
+    pub fn error(&self, kind: &'static str, message: impl Display) {
+        *self.window.lock().unwrap().errors.entry(kind).or_default() += 1;
+        metrics::registry().increment(ERRORS_METRIC, &[("kind", kind)], 1);
+
+        let mut recent_errors = self.recent_errors.lock().unwrap();
+        if recent_errors.len() == MAX_RECENT_ERRORS {
+            recent_errors.pop_front();
+        }
+        recent_errors.push_back(RecentError {
+            at: Utc::now(),
+            kind,
+            message: message.to_string(),
+        });
+    }
+
+    /// The latest errors, from the oldest
+    pub fn recent_errors(&self) -> Vec<RecentError> {
+        self.recent_errors.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Log what happened since the last summary, then start over
+    fn log(&self, interval: Duration) {
+        let window = std::mem::take(&mut *self.window.lock().unwrap());
+        let detail = |counts: &BTreeMap<&str, u64>| {
+            counts
+                .iter()
+                .map(|(category, count)| format!("{}={}", category, count))
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        let dropped = window.dropped.values().sum::<u64>();
+        let errors = window.errors.values().sum::<u64>();
+        let p99_ms = percentile(window.latencies, 0.99).map_or(0.0, |p99| p99 * 1000.0);
+
+        info!(
+            lines = window.lines,
+            bytes = window.bytes,
+            dropped,
+            drop_reasons = detail(&window.dropped).as_str(),
+            errors,
+            error_kinds = detail(&window.errors).as_str(),
+            p99_ms,
+            "Published <{}> lines (<{}> bytes) in the last {}s, p99 publish latency {:.1}ms, <{}> dropped, <{}> errors",
+            window.lines,
+            window.bytes,
+            interval.as_secs(),
+            p99_ms,
+            dropped,
+            errors
+        );
+
+        for lag in self.lags() {
+            let since = lag.since.map(|since| since.as_secs());
+            info!(
+                file = %lag.path.to_string_lossy(),
+                position = lag.position,
+                size = lag.size,
+                lag = lag.bytes(),
+                since_publish_s = since,
+                "`{}` published up to <{}> of <{}> bytes, <{}> behind",
+                lag.path.to_string_lossy(),
+                lag.position,
+                lag.size,
+                lag.bytes()
+            );
+        }
+    }
+}
+
+/// The value that many of the samples are below, eg. `0.99` for the 99th percentile
+fn percentile(mut samples: Vec<f64>, rank: f64) -> Option<f64> {
+    if samples.is_empty() {
+        return None;
+    }
+
+    samples.sort_by(f64::total_cmp);
+    let i = ((samples.len() as f64 * rank).ceil() as usize).clamp(1, samples.len()) - 1;
+
+    Some(samples[i])
+}
+
+/// The stats of the whole process
+pub fn stats() -> &'static Stats {
+    static STATS: OnceLock<Stats> = OnceLock::new();
+    STATS.get_or_init(|| {
+        let registry = metrics::registry();
+        registry.describe(
+            LAG_METRIC,
+            "Bytes between the published position and the end of the file",
+            None,
+        );
+        registry.describe(
+            SINCE_PUBLISH_METRIC,
+            "Seconds since a line of the file has been published",
+            None,
+        );
+        registry.describe(
+            DROPPED_METRIC,
+            "Lines that won't be published, by reason",
+            None,
+        );
+        registry.describe(ERRORS_METRIC, "Errors, by kind", None);
+
+        Stats::default()
+    })
+}
+
+/// Update the lag metrics periodically
+pub async fn watch_lags() {
+    let mut ticks = tokio::time::interval(LAG_INTERVAL);
+
+    loop {
+        ticks.tick().await;
+        stats().update_lags();
+    }
+}
+
+/// Log the summary periodically
+pub async fn log(interval: Duration) {
+    let mut ticks = tokio::time::interval(interval);
+    ticks.tick().await;
+
+    loop {
+        ticks.tick().await;
+        stats().log(interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        assert_eq!(percentile(vec![], 0.99), None);
+
+        let samples = (1..=200).rev().map(f64::from).collect::<Vec<_>>();
+        assert_eq!(percentile(samples.clone(), 0.99), Some(198.0));
+        assert_eq!(percentile(samples, 0.5), Some(100.0));
+        assert_eq!(percentile(vec![3.0], 0.99), Some(3.0));
+    }
+
+    #[test]
+    fn test_window() {
+        let stats = Stats::default();
+        stats.published(5, Duration::from_millis(2));
+        stats.acknowledged(Path::new("/var/log/app.log"), 6);
+        stats.error("read", "Permission denied");
+        stats.error("read", "Permission denied");
+        stats.dropped("filtered");
+        assert_eq!(stats.window.lock().unwrap().errors["read"], 2);
+        assert_eq!(stats.recent_errors()[1].message, "Permission denied");
+        assert_eq!(stats.window.lock().unwrap().dropped["filtered"], 1);
+        assert!(metrics::registry()
+            .render()
+            .contains("log_bouncer_dropped_lines_total{reason=\"filtered\"}"));
+
+        stats.log(Duration::from_secs(60));
+        let window = stats.window.lock().unwrap();
+        assert_eq!((window.lines, window.errors.len()), (0, 0));
+        assert_eq!(
+            stats.positions.lock().unwrap()[Path::new("/var/log/app.log")].position,
+            6
+        );
+    }
+
+    #[test]
+    fn test_lags() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        std::fs::write(&path, "first\nsecond\n").unwrap();
+
+        let stats = Stats::default();
+        stats.follow(&path, 0);
+        stats.follow(Path::new("-"), 0);
+        let lags = stats.lags();
+        assert_eq!(lags.len(), 1);
+        assert_eq!((lags[0].bytes(), lags[0].since), (13, None));
+
+        stats.acknowledged(&path, 6);
+        let lag = stats.lags().pop().unwrap();
+        assert_eq!(lag.bytes(), 7);
+        assert!(lag.since.is_some());
+
+        stats.forget(&path);
+        assert!(stats.lags().is_empty());
+    }
+}
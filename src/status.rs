@@ -0,0 +1,143 @@
+//! Live view of a running log-bouncer in the terminal, fetched from its admin API: the position,
+//! the lag and the publish rate of every file, and the latest errors
+use crate::opt::StatusOpt;
+use serde_json::Value;
+use std::error::Error;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+/// Clear the terminal, then move the cursor to its top
+const CLEAR: &str = "\x1b[2J\x1b[H";
+
+async fn fetch(client: &reqwest::Client, url: &str) -> Result<Value, Box<dyn Error>> {
+    let body = client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+
+    Ok(serde_json::from_str(&body)?)
+}
+
+/// Refresh the view until interrupted, or print it once with `--once`
+pub async fn watch(opts: StatusOpt) -> Result<(), Box<dyn Error>> {
+    let client = reqwest::Client::new();
+    let url = format!("http://{}/state", opts.admin_addr);
+    let mut interval = tokio::time::interval(Duration::from_secs(opts.refresh.max(1)));
+    let mut previous: Option<(Value, Instant)> = None;
+
+    loop {
+        interval.tick().await;
+        let state = fetch(&client, &url).await?;
+        let now = Instant::now();
+        let previous_state = previous
+            .as_ref()
+            .map(|(state, at)| (state, now.duration_since(*at)));
+
+        if opts.once {
+            print!("{}", render(&state, previous_state));
+            return Ok(());
+        }
+
+        print!("{}{}", CLEAR, render(&state, previous_state));
+        previous = Some((state, now));
+    }
+}
+
+/// The position of a file in the former state, to compute its rate
+fn previous_position(state: &Value, file: &str) -> Option<u64> {
+    state["files"]
+        .as_array()?
+        .iter()
+        .find(|entry| entry["file"] == file)?["position"]
+        .as_u64()
+}
+
+fn render(state: &Value, previous: Option<(&Value, Duration)>) -> String {
+    let mut view = String::new();
+    let paused = if state["paused"] == true {
+        " (paused)"
+    } else {
+        ""
+    };
+    let _ = writeln!(view, "log-bouncer{}\n", paused);
+    let _ = writeln!(
+        view,
+        "{:<40} {:>12} {:>12} {:>12} {:>10} {:>14}",
+        "FILE", "POSITION", "SIZE", "LAG", "RATE", "LAST PUBLISH"
+    );
+
+    for file in state["files"].as_array().into_iter().flatten() {
+        let path = file["file"].as_str().unwrap_or_default();
+        let position = file["position"].as_u64().unwrap_or_default();
+        let rate = previous
+            .and_then(|(previous, elapsed)| {
+                let published = position.checked_sub(previous_position(previous, path)?)?;
+                Some(format!(
+                    "{:.0}B/s",
+                    published as f64 / elapsed.as_secs_f64()
+                ))
+            })
+            .unwrap_or_else(|| "-".to_owned());
+        let since = match file["since_publish_s"].as_u64() {
+            Some(since) => format!("{}s ago", since),
+            None => "never".to_owned(),
+        };
+
+        let _ = writeln!(
+            view,
+            "{:<40} {:>12} {:>12} {:>12} {:>10} {:>14}",
+            path, position, file["size"], file["lag"], rate, since
+        );
+    }
+
+    let errors = state["errors"].as_array().cloned().unwrap_or_default();
+    if !errors.is_empty() {
+        let _ = writeln!(view, "\nRecent errors:");
+    }
+    for error in errors.iter().rev() {
+        let _ = writeln!(
+            view,
+            "  {} [{}] {}",
+            error["at"].as_str().unwrap_or_default(),
+            error["kind"].as_str().unwrap_or_default(),
+            error["message"].as_str().unwrap_or_default()
+        );
+    }
+
+    view
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_render() {
+        let previous = json!({
+            "paused": false,
+            "files": [{ "file": "/var/log/app.log", "position": 100 }],
+            "errors": [],
+        });
+        let state = json!({
+            "paused": true,
+            "files": [
+                { "file": "/var/log/app.log", "position": 300, "size": 500, "lag": 200, "since_publish_s": 1 },
+                { "file": "/var/log/new.log", "position": 0, "size": 10, "lag": 10, "since_publish_s": null },
+            ],
+            "errors": [{ "at": "2024-01-01T00:00:00Z", "kind": "read", "message": "Permission denied" }],
+        });
+
+        let view = render(&state, Some((&previous, Duration::from_secs(2))));
+        let lines = view.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "log-bouncer (paused)");
+        assert!(lines[3].contains("100B/s") && lines[3].ends_with("1s ago"));
+        assert!(lines[4].contains(" - ") && lines[4].ends_with("never"));
+        assert_eq!(lines[7], "  2024-01-01T00:00:00Z [read] Permission denied");
+
+        assert!(render(&state, None).lines().nth(3).unwrap().contains(" - "));
+    }
+}
@@ -0,0 +1,194 @@
+//! Follow a file from another program, as a [`Stream`] of its lines:
+//!
+//! ```no_run
+//! use futures_util::StreamExt;
+//!
+//! # async fn follow() -> Result<(), log_bouncer::stream::Error> {
+//! let mut lines = log_bouncer::stream::LineStream::new("/var/log/app.log")?;
+//! while let Some(line) = lines.next().await {
+//!     println!("{}", String::from_utf8_lossy(&line?.bytes));
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The file is followed through its rotations and truncations, it's read by the task polling
+//! the stream, which must run on a Tokio runtime.
+pub use crate::tail::{Error, LineBreak};
+use crate::tail::{Event, TailedFile};
+use futures_core::Stream;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use tokio::time::{Instant, Sleep};
+
+/// A line of the file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    /// Without its line breaker, they may not be valid UTF-8
+    pub bytes: Vec<u8>,
+    /// Where the next line starts in the file, to resume from, see [`LineStream::with_position`]
+    pub offset: u64,
+    /// Its number in the file, from 1
+    pub number: u64,
+    /// It's been read without its line breaker, see [`LineStream::with_partial_timeout`]
+    pub partial: bool,
+}
+
+/// The lines appended to the file, from its end unless a position is set
+pub struct LineStream {
+    tail: TailedFile<PathBuf>,
+    /// The lines of the last read, not consumed yet
+    lines: VecDeque<Line>,
+    poll_interval: Duration,
+    /// Until the file is read again, once its end has been reached
+    sleep: Pin<Box<Sleep>>,
+    waiting: bool,
+}
+
+impl LineStream {
+    pub fn new(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Ok(Self {
+            tail: TailedFile::new(path.as_ref().to_path_buf())?,
+            lines: VecDeque::new(),
+            poll_interval: Duration::from_millis(500),
+            sleep: Box::pin(tokio::time::sleep(Duration::ZERO)),
+            waiting: false,
+        })
+    }
+
+    /// Resume after a line, eg. `(0, 0)` to read the file from its beginning
+    pub fn with_position(mut self, offset: u64, number: u64) -> Self {
+        self.tail.set_pos(offset);
+        self.tail.set_line(number);
+        self
+    }
+
+    /// Check the file that often once its end has been reached
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Read the line being written once it hasn't grown for that long
+    pub fn with_partial_timeout(mut self, timeout: Duration) -> Self {
+        self.tail.set_partial_timeout(Some(timeout));
+        self
+    }
+
+    /// The lines end according to the encoding of the file, see [`LineBreak`]
+    pub fn with_line_break(mut self, line_break: LineBreak) -> Self {
+        self.tail.set_line_break(line_break);
+        self
+    }
+
+    fn wait(&mut self) {
+        let deadline = Instant::now() + self.poll_interval;
+        self.sleep.as_mut().reset(deadline);
+        self.waiting = true;
+    }
+
+    /// Keep the lines of the last read until they're consumed
+    fn buffer(&mut self, count: usize) {
+        let first_line = self.tail.line() - count as u64;
+        let partial = self.tail.partial();
+
+        for (i, (line, offset)) in self.tail.lines().enumerate() {
+            self.lines.push_back(Line {
+                bytes: line.to_vec(),
+                offset,
+                number: first_line + i as u64 + 1,
+                // only the last line can lack its line breaker
+                partial: i + 1 == count && partial,
+            });
+        }
+    }
+}
+
+impl Stream for LineStream {
+    type Item = Result<Line, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        loop {
+            if let Some(line) = this.lines.pop_front() {
+                return Poll::Ready(Some(Ok(line)));
+            }
+            if this.waiting {
+                ready!(this.sleep.as_mut().poll(cx));
+                this.waiting = false;
+            }
+
+            match this.tail.follow() {
+                Ok(Event::Read(0)) => this.wait(),
+                Ok(Event::Read(count)) => this.buffer(count),
+                // the former file has been read until its end, the new one is read right away
+                Ok(event @ (Event::Rotated | Event::Truncated)) => debug!("{}", event),
+                Ok(event @ Event::Deleted) => {
+                    debug!("{}", event);
+                    this.wait();
+                }
+                Err(e) => {
+                    this.wait();
+                    return Poll::Ready(Some(Err(e)));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+    use std::io::Write;
+
+    async fn next(lines: &mut LineStream) -> Line {
+        tokio::time::timeout(Duration::from_secs(1), lines.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_line_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        std::fs::write(&path, "first\nsecond\n").unwrap();
+
+        let mut lines = LineStream::new(&path)
+            .unwrap()
+            .with_position(6, 1)
+            .with_poll_interval(Duration::from_millis(10));
+        let second = next(&mut lines).await;
+        assert_eq!(
+            second,
+            Line {
+                bytes: b"second".to_vec(),
+                offset: 13,
+                number: 2,
+                partial: false
+            }
+        );
+
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(b"third\n").unwrap();
+        assert_eq!(next(&mut lines).await.bytes, b"third");
+
+        // it's read again from its beginning
+        std::fs::write(&path, "truncated\n").unwrap();
+        let truncated = next(&mut lines).await;
+        assert_eq!(
+            (truncated.bytes.as_slice(), truncated.number),
+            (&b"truncated"[..], 1)
+        );
+    }
+}
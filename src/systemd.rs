@@ -0,0 +1,93 @@
+//! Tell systemd that log-bouncer is ready, then keep petting its watchdog, see `sd_notify(3)`,
+//! for the units with `Type=notify` and `WatchdogSec=`
+use std::ffi::OsStr;
+use std::time::Duration;
+
+/// Send a state to systemd, eg. `READY=1`, nothing happens unless it's started by systemd
+pub fn notify(state: &str) {
+    let socket = match std::env::var_os("NOTIFY_SOCKET") {
+        Some(socket) => socket,
+        None => return,
+    };
+
+    if let Err(e) = send(&socket, state) {
+        warn!("Can't notify systemd `{}`: {}", state, e);
+    }
+}
+
+#[cfg(unix)]
+fn send(socket: &OsStr, state: &str) -> std::io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::UnixDatagram;
+
+    let datagram = UnixDatagram::unbound()?;
+
+    // a socket of the abstract namespace
+    #[cfg(target_os = "linux")]
+    if let Some(name) = socket.as_bytes().strip_prefix(b"@") {
+        use std::os::linux::net::SocketAddrExt;
+
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+        datagram.send_to_addr(state.as_bytes(), &addr)?;
+        return Ok(());
+    }
+
+    datagram.send_to(state.as_bytes(), socket)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn send(_socket: &OsStr, _state: &str) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// systemd restarts the process once it hasn't been notified for that long, `WATCHDOG_USEC`
+pub fn watchdog_timeout() -> Option<Duration> {
+    let pid = std::env::var("WATCHDOG_PID").ok();
+    let usec = std::env::var("WATCHDOG_USEC").ok();
+
+    parse_watchdog(pid.as_deref(), usec.as_deref(), std::process::id())
+}
+
+/// The watchdog may be meant for another process, eg. the parent of log-bouncer
+fn parse_watchdog(pid: Option<&str>, usec: Option<&str>, process: u32) -> Option<Duration> {
+    if pid.is_some_and(|pid| pid.parse() != Ok(process)) {
+        return None;
+    }
+
+    let usec = usec?.parse::<u64>().ok().filter(|usec| *usec > 0)?;
+    Some(Duration::from_micros(usec))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_watchdog() {
+        assert_eq!(
+            parse_watchdog(None, Some("30000000"), 42),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            parse_watchdog(Some("42"), Some("500"), 42),
+            Some(Duration::from_micros(500))
+        );
+        assert_eq!(parse_watchdog(Some("7"), Some("500"), 42), None);
+        assert_eq!(parse_watchdog(None, Some("0"), 42), None);
+        assert_eq!(parse_watchdog(None, None, 42), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_send() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify.sock");
+        let listener = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+
+        send(path.as_os_str(), "READY=1").unwrap();
+        let mut buffer = [0u8; 16];
+        let n = listener.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..n], b"READY=1");
+    }
+}
@@ -1,170 +1,506 @@
-//! Credits to https://git.staart.one/ajmartinez/staart/src/branch/main/src/lib.rs
+//! Follow a file like `tail -F`, through its rotations and truncations, as a small library
 //!
-//! Modified version of `staart` is a Rust implementation of a tail-like program
-//!
-//! The library exposes public methods to allow other programs to follow a file
-//! internally. These methods are exposed on a struct [`TailedFile`].
-//!
-//! # Example
+//! Credits to [staart](https://git.staart.one/ajmartinez/staart/src/branch/main/src/lib.rs), which
+//! [`TailedFile`] started as a modified version of.
 //!
 //! ```no_run
-//! use std::thread::sleep;
+//! use log_bouncer::tail::{Event, TailedFile};
 //! use std::time::Duration;
-//! use staart::{StaartError, TailedFile};
 //!
-//! fn main() -> Result<(), StaartError> {
-//!     let delay = Duration::from_millis(100);
-//!     let args: Vec<String> = std::env::args().collect();
-//!     let path = &args[1].as_str();
-//!     let mut f = TailedFile::new(path)?;
-//!     loop {
-//!        f.follow()?;
-//!        sleep(delay);
+//! # fn follow() -> Result<(), log_bouncer::tail::Error> {
+//! let mut file = TailedFile::new("/var/log/app.log")?;
+//! loop {
+//!     match file.follow()? {
+//!         Event::Read(0) => std::thread::sleep(Duration::from_millis(100)),
+//!         Event::Read(_) => {
+//!             for (line, _offset) in file.lines() {
+//!                 println!("{}", String::from_utf8_lossy(line));
+//!             }
+//!         }
+//!         event => eprintln!("{}", event),
 //!     }
 //! }
+//! # }
 //! ```
-use std::fs::{File, Metadata};
-use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+//!
+//! The API is synchronous, the thread blocks while the file is read: from a Tokio task, run it
+//! with [`tokio::task::spawn_blocking`], or see [`crate::stream::LineStream`] for a
+//! [`Stream`](futures_core::Stream) of the lines. The files are told apart by their inode on
+//! Unix, by their file index on Windows.
+pub use crate::state::FileId;
+use bytes::{Bytes, BytesMut};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, Read, Seek, SeekFrom};
 use std::path::Path;
+use std::time::{Duration, Instant};
 
-#[cfg(target_os = "linux")]
-use std::os::linux::fs::MetadataExt;
+type Result<T> = std::result::Result<T, Error>;
 
-#[cfg(target_os = "macos")]
-use std::os::macos::fs::MetadataExt;
+/// Size of each read
+const CHUNK_SIZE: usize = 64 * 1024;
 
-type Result<T> = std::result::Result<T, Error>;
+/// The lines are returned once that much has been read, the rest is read by the next call
+const MAX_BATCH: usize = 4 * 1024 * 1024;
+
+/// Where a line is in the buffer, without its line breaker
+#[derive(Debug, Clone, Copy)]
+struct Span {
+    start: usize,
+    end: usize,
+    /// Offset of the end of the line in the file, after its line breaker
+    offset: u64,
+}
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
-    #[error("the file has been rotated, file's position has been reset to 0")]
-    FileRotated,
-    #[error("the file has been truncated, file's position has been reset to 0")]
-    FileTruncated,
     #[error("i/o: {0}")]
     IO(#[from] std::io::Error),
-    #[error("str-utf8: {0}")]
-    Utf8(#[from] std::str::Utf8Error),
-    #[error("from-utf8: {0}")]
-    FromUtf8(#[from] std::string::FromUtf8Error),
-    #[error("int-error: {0}")]
-    IntError(#[from] std::num::TryFromIntError),
 }
 
-/// [`TailedFile`] tracks the state of a file being followed. It offers
-/// methods for updating this state, and printing data to `stdout`.
+/// What [`TailedFile::follow`] found out about the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// That many lines have been read, see [`TailedFile::lines`], none once its end is reached
+    Read(usize),
+    /// Another file took its place, it's read from its beginning by the next call
+    Rotated,
+    /// It got shorter than the position, it's read again from its beginning by the next call
+    Truncated,
+    /// The path doesn't exist anymore, reported once, the file is read from its beginning once
+    /// it's created again
+    Deleted,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Read(count) => write!(f, "{} lines read", count),
+            Event::Rotated => write!(
+                f,
+                "the file has been rotated, its position has been reset to 0"
+            ),
+            Event::Truncated => {
+                write!(
+                    f,
+                    "the file has been truncated, its position has been reset to 0"
+                )
+            }
+            Event::Deleted => {
+                write!(
+                    f,
+                    "the file has been deleted, it'll be read from 0 once it's created again"
+                )
+            }
+        }
+    }
+}
+
+/// How the lines end according to the encoding of the file, `\n` or `\r\n`, a file can mix both
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LineBreak {
+    /// UTF-8 and the encodings compatible with ASCII
+    #[default]
+    Byte,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl LineBreak {
+    /// `\n` and `\r` once encoded, the lines are made of units of that length
+    fn units(self) -> (&'static [u8], &'static [u8]) {
+        match self {
+            LineBreak::Byte => (b"\n", b"\r"),
+            LineBreak::Utf16Le => (b"\n\0", b"\r\0"),
+            LineBreak::Utf16Be => (b"\0\n", b"\0\r"),
+        }
+    }
+
+    /// Length of a unit, the lines of UTF-16 have an even length
+    pub fn width(self) -> usize {
+        self.units().0.len()
+    }
+
+    /// Length of the first line of the bytes along with its line breaker, if it's complete
+    pub fn find(self, bytes: &[u8]) -> Option<usize> {
+        let (lf, _) = self.units();
+        match self {
+            LineBreak::Byte => bytes.iter().position(|byte| *byte == b'\n'),
+            // the line breaker can't straddle two characters
+            _ => bytes.chunks_exact(lf.len()).position(|unit| unit == lf),
+        }
+        .map(|i| (i + 1) * lf.len())
+    }
+
+    /// Length of the bytes until the end of their last complete line
+    pub fn rfind(self, bytes: &[u8]) -> Option<usize> {
+        let (lf, _) = self.units();
+        bytes
+            .chunks_exact(lf.len())
+            .rposition(|unit| unit == lf)
+            .map(|i| (i + 1) * lf.len())
+    }
+
+    /// Whether the bytes end with a line breaker
+    pub fn ends_line(self, bytes: &[u8]) -> bool {
+        let (lf, _) = self.units();
+        bytes.len().is_multiple_of(lf.len()) && bytes.ends_with(lf)
+    }
+
+    /// The line without its line breaker
+    pub fn strip(self, line: &[u8]) -> &[u8] {
+        let (lf, cr) = self.units();
+        match line.strip_suffix(lf) {
+            Some(line) => line.strip_suffix(cr).unwrap_or(line),
+            None => line,
+        }
+    }
+
+    /// Append the next line along with its line breaker, like [`BufRead::read_until`]
+    pub fn read_line(
+        self,
+        reader: &mut impl BufRead,
+        line: &mut Vec<u8>,
+    ) -> std::io::Result<usize> {
+        let start = line.len();
+
+        while reader.read_until(b'\n', line)? > 0 {
+            if self == LineBreak::Utf16Le && line.ends_with(b"\n") {
+                reader.by_ref().take(1).read_to_end(line)?;
+            }
+            if self.ends_line(&line[start..]) {
+                break;
+            }
+        }
+
+        Ok(line.len() - start)
+    }
+}
+
+/// Whether the file has been deleted, rather than renamed
+#[cfg(unix)]
+fn is_unlinked(file: &File) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    file.metadata().is_ok_and(|metadata| metadata.nlink() == 0)
+}
+
+/// A file deleted while it's open stays pending until it's closed
+#[cfg(windows)]
+fn is_unlinked(file: &File) -> bool {
+    winapi_util::file::information(file).is_ok_and(|information| information.number_of_links() == 0)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn is_unlinked(_file: &File) -> bool {
+    false
+}
+
+/// A file being followed, along with the position and the number of the last line read
 pub struct TailedFile<T> {
     path: T,
     pos: u64,
-    meta: Metadata,
+    /// Number of lines read so far
+    line: u64,
+    /// Identity of the file being read, see [`FileId`]
+    id: Option<FileId>,
+    /// Kept open so the lines appended after a rotation can still be read
+    file: File,
+    /// Read a line without its line breaker once it hasn't grown for that long
+    partial_timeout: Option<Duration>,
+    /// Length of the line being written, and since when it hasn't grown
+    pending: Option<(u64, Instant)>,
+    /// The last line has been read without its line breaker
+    partial: bool,
+    /// The path doesn't exist anymore, the file is still read until it's created again
+    deleted: bool,
+    /// Holds what has been read by the last call to [`read`](Self::read)
+    buffer: BytesMut,
+    lines: Vec<Span>,
+    max_batch: usize,
+    line_break: LineBreak,
 }
 
 impl<T> TailedFile<T>
 where
-    T: AsRef<Path> + Copy,
+    T: AsRef<Path>,
 {
-    /// Creates an instance of `std::io::Result<staart::TailedFile>`
+    /// Follows the file from its end, see [`set_pos`](Self::set_pos) to start elsewhere
     ///
-    /// # Example
-    /// ```no_run
-    /// let mut f = staart::TailedFile::new("/var/log/syslog");
-    /// ```
-    ///
-    /// # Propagates Errors
-    /// - If the path provided does not exist, or is not readable by the current user
-    /// - If file metadata can not be read
+    /// Fails if the file doesn't exist or can't be read.
     pub fn new(path: T) -> Result<TailedFile<T>> {
-        let f = File::open(path)?;
-        let meta = f.metadata()?;
-        let pos = meta.len();
-
-        Ok(TailedFile { path, pos, meta })
+        let file = File::open(&path)?;
+        let pos = file.metadata()?.len();
+
+        Ok(TailedFile {
+            path,
+            pos,
+            line: 0,
+            id: FileId::of(&file)?,
+            file,
+            partial_timeout: None,
+            pending: None,
+            partial: false,
+            deleted: false,
+            buffer: BytesMut::with_capacity(CHUNK_SIZE),
+            lines: vec![],
+            max_batch: MAX_BATCH,
+            line_break: LineBreak::Byte,
+        })
     }
 
-    /// Reads new lines and return the ones that finishes with line breaker "\n"
-    pub fn read(&mut self, file: &File) -> Result<Vec<String>> {
-        let mut reader = BufReader::new(file);
-        let mut lines = vec![];
-        reader.seek(SeekFrom::Start(self.pos))?;
+    /// Reads new lines, the ones that finishes with line breaker "\n", see [`lines`](Self::lines)
+    ///
+    /// The file is read by chunks into a buffer reused by the next reads, unless its lines have
+    /// been taken, the lines are sliced out of it. Returns the number of lines read.
+    pub fn read(&mut self) -> Result<usize> {
+        self.buffer.clear();
+        self.lines.clear();
+        self.file.seek(SeekFrom::Start(self.pos))?;
+        // beginning of the line being split
+        let mut start = 0;
 
         loop {
-            let mut line = String::new();
-            let n: u64 = reader.read_line(&mut line)? as u64;
+            let len = self.buffer.len();
+            self.buffer.resize(len + CHUNK_SIZE, 0);
+            let n = self.file.read(&mut self.buffer[len..])?;
+            self.buffer.truncate(len + n);
+
+            while let Some(len) = self.line_break.find(&self.buffer[start..]) {
+                let end = start + len;
+                self.push_line(start, end);
+                start = end;
+            }
 
-            if n == 0 || !line.ends_with('\n') {
-                // EOF or the line doesn't contain a line breaker, therefore shouldn't be added
+            if n == 0 {
                 break;
             }
+            if start > 0 && self.buffer.len() >= self.max_batch {
+                // the rest is read by the next call
+                return Ok(self.lines.len());
+            }
+        }
 
-            lines.push(line.replace('\n', "")); // line breakers should be removed
-            self.pos += n;
+        // the line doesn't contain a line breaker, therefore shouldn't be added, unless its
+        // writer has stalled
+        let rest = (self.buffer.len() - start) as u64;
+        if rest == 0 {
+            self.pending = None;
+        } else if self.has_stalled(rest) {
+            self.pos += rest;
+            self.line += 1;
+            self.partial = true;
+            self.lines.push(Span {
+                start,
+                end: self.buffer.len(),
+                offset: self.pos,
+            });
         }
 
-        Ok(lines)
+        Ok(self.lines.len())
     }
 
-    /// Prints new data read on an instance of `staart::TailedFile` to `stdout`
-    pub fn follow(&mut self) -> Result<Vec<String>> {
-        let fd = File::open(self.path)?;
-        self.has_been_rotated(&fd)?;
-        self.has_been_truncated(&fd)?;
-        let data = self.read(&fd)?;
+    /// The buffer holds a line from `start` until its line breaker, at `end`
+    fn push_line(&mut self, start: usize, end: usize) {
+        let line = self.line_break.strip(&self.buffer[start..end]);
+        self.pos += (end - start) as u64;
+        self.pending = None;
 
-        Ok(data)
+        if std::mem::take(&mut self.partial) && line.is_empty() {
+            // the line breaker of the partial line, written along with the next line
+            return;
+        }
+
+        // line breakers should be removed
+        self.line += 1;
+        self.lines.push(Span {
+            start,
+            end: start + line.len(),
+            offset: self.pos,
+        });
     }
 
-    /// Checks for file rotation by inode comparison in Linux-like systems
-    fn has_been_rotated(&mut self, fd: &File) -> Result<()> {
-        let meta = fd.metadata()?;
-        let inode = meta.st_ino();
-        if inode != self.meta.st_ino() {
-            self.pos = 0;
-            self.meta = meta;
+    /// The lines of the last read, along with the offset right after each of them
+    ///
+    /// The lines are raw bytes, they may not be valid UTF-8.
+    pub fn lines(&self) -> impl Iterator<Item = (&[u8], u64)> + '_ {
+        self.lines
+            .iter()
+            .map(|span| (&self.buffer[span.start..span.end], span.offset))
+    }
 
-            Err(Error::FileRotated)?; // trigger an error
+    /// The lines of the last read, sharing the buffer rather than copying each of them, the next
+    /// read gets a buffer of its own
+    pub fn take_lines(&mut self) -> impl Iterator<Item = (Bytes, u64)> + '_ {
+        let buffer = self.buffer.split().freeze();
+        self.lines
+            .drain(..)
+            .map(move |span| (buffer.slice(span.start..span.end), span.offset))
+    }
+
+    /// Whether the line being written hasn't grown during the timeout
+    fn has_stalled(&mut self, len: u64) -> bool {
+        let timeout = match self.partial_timeout {
+            Some(timeout) => timeout,
+            None => return false,
+        };
+
+        match self.pending {
+            Some((pending, since)) if pending == len => {
+                let stalled = since.elapsed() >= timeout;
+                if stalled {
+                    self.pending = None;
+                }
+                stalled
+            }
+            _ => {
+                self.pending = Some((len, Instant::now()));
+                false
+            }
+        }
+    }
+
+    /// Reads the new lines of the file, unless something else happened to it
+    ///
+    /// Once the file has been rotated, the lines appended to the former file are read until its
+    /// end before switching to the new one, so none of them is lost.
+    pub fn follow(&mut self) -> Result<Event> {
+        if self.has_been_truncated()? {
+            return Ok(Event::Truncated);
         }
+        let count = self.read()?;
 
-        Ok(())
+        if count == 0 {
+            if let Some(event) = self.has_been_rotated()? {
+                return Ok(event);
+            }
+        }
+
+        Ok(Event::Read(count))
     }
 
-    /// Checks for file truncation by length comparison to the previous read position
-    fn has_been_truncated(&mut self, fd: &File) -> Result<()> {
-        let meta = fd.metadata()?;
-        let inode = meta.st_ino();
-        let len = meta.len();
-        if inode == self.meta.st_ino() && len < self.pos {
+    /// Like [`follow`](Self::follow), checking the file that often until there's something to
+    /// report
+    ///
+    /// The thread sleeps in between, from a Tokio task it has to be called with
+    /// [`tokio::task::spawn_blocking`].
+    pub fn next_event(&mut self, interval: Duration) -> Result<Event> {
+        loop {
+            match self.follow()? {
+                Event::Read(0) => std::thread::sleep(interval),
+                event => return Ok(event),
+            }
+        }
+    }
+
+    /// Checks for file rotation by comparing the identity of the files, see [`FileId`]
+    fn has_been_rotated(&mut self) -> Result<Option<Event>> {
+        let fd = match File::open(&self.path) {
+            Ok(fd) => fd,
+            // in the middle of the rotation, the new file hasn't been created yet, or the file
+            // has been deleted, which is only reported once
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                if !is_unlinked(&self.file) || std::mem::replace(&mut self.deleted, true) {
+                    return Ok(None);
+                }
+                return Ok(Some(Event::Deleted));
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        self.deleted = false;
+        let id = FileId::of(&fd)?;
+        if id != self.id {
             self.pos = 0;
+            self.line = 0;
+            self.id = id;
+            self.file = fd;
+            self.pending = None;
+            self.partial = false;
 
-            Err(Error::FileTruncated)?; // trigger an error
+            return Ok(Some(Event::Rotated));
         }
 
-        Ok(())
+        Ok(None)
     }
 
-    pub fn pos(&self) -> u64 {
-        self.pos
+    /// Checks for file truncation by length comparison to the previous read position
+    fn has_been_truncated(&mut self) -> Result<bool> {
+        let len = self.file.metadata()?.len();
+        if len < self.pos {
+            self.pos = 0;
+            self.line = 0;
+            self.pending = None;
+            self.partial = false;
+
+            return Ok(true);
+        }
+
+        Ok(false)
     }
 
+    /// Resume right after a line, eg. from the offset of [`lines`](Self::lines)
     pub fn set_pos(&mut self, pos: u64) {
         self.pos = pos
     }
+
+    /// Size of the file being read, what's left to read lies between the position and it
+    pub fn size(&self) -> Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    /// Identity of the file being read, `None` if the platform can't tell
+    pub fn file_id(&self) -> Option<FileId> {
+        self.id
+    }
+
+    /// Number of the last line read, from 1
+    pub fn line(&self) -> u64 {
+        self.line
+    }
+
+    pub fn set_line(&mut self, line: u64) {
+        self.line = line
+    }
+
+    /// The line being written is read anyway once it hasn't grown for that long, eg. when the
+    /// appender only writes the line breaker along with the next line
+    pub fn set_partial_timeout(&mut self, timeout: Option<Duration>) {
+        self.partial_timeout = timeout
+    }
+
+    /// The line breakers of the encoding of the file, eg. UTF-16
+    pub fn set_line_break(&mut self, line_break: LineBreak) {
+        self.line_break = line_break
+    }
+
+    /// Whether the last line read has been read without its line breaker, see
+    /// [`set_partial_timeout`](Self::set_partial_timeout)
+    pub fn partial(&self) -> bool {
+        self.partial
+    }
 }
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::io::Write;
 
-    #[cfg(target_os = "linux")]
-    use std::os::linux::fs::MetadataExt;
+    /// The lines of the next read
+    fn read<T: AsRef<Path>>(tailed_file: &mut TailedFile<T>) -> Vec<Vec<u8>> {
+        tailed_file.read().unwrap();
+        tailed_file.lines().map(|(line, _)| line.to_vec()).collect()
+    }
 
-    #[cfg(target_os = "macos")]
-    use std::os::macos::fs::MetadataExt;
+    fn follow<T: AsRef<Path>>(tailed_file: &mut TailedFile<T>) -> Vec<Vec<u8>> {
+        tailed_file.follow().unwrap();
+        tailed_file.lines().map(|(line, _)| line.to_vec()).collect()
+    }
 
     #[test]
     fn tailed_file() {
         let dir = tempfile::tempdir().unwrap();
         let path = &dir.path().join("test.file");
-        let _f = File::create(&path).unwrap();
+        let _f = File::create(path).unwrap();
         let tailed_file = TailedFile::new(&path);
         assert!(tailed_file.is_ok())
     }
@@ -180,18 +516,18 @@ mod tests {
 {\"data\":\"coucou3\"}
 ";
 
-        let mut f = File::create(&path).unwrap();
+        let mut f = File::create(path).unwrap();
         let mut tailed_file = TailedFile::new(&path).unwrap();
         f.write_all(test_data).unwrap();
-        let f = File::open(&path).unwrap();
-        let read_data = tailed_file.read(&f).unwrap();
+        let read_data = read(&mut tailed_file);
 
         assert_eq!(read_data.len(), 3);
         assert_eq!(tailed_file.pos, test_data.len() as u64);
+        assert_eq!(tailed_file.line, 3);
 
         for line in read_data {
             // making sure line breakers have been removed
-            assert!(!line.contains('\n'));
+            assert!(!line.contains(&b'\n'));
         }
     }
 
@@ -205,15 +541,118 @@ mod tests {
 {\"data\":\"coucou2\"}
 {\"data\":\"coucou3\"}";
 
-        let mut f = File::create(&path).unwrap();
+        let mut f = File::create(path).unwrap();
         let mut tailed_file = TailedFile::new(&path).unwrap();
         f.write_all(test_data).unwrap();
-        let f = File::open(&path).unwrap();
-        let read_data = tailed_file.read(&f).unwrap();
+        let read_data = read(&mut tailed_file);
         assert_eq!(read_data.len(), 2); // only 2 here
         assert_eq!(tailed_file.pos, 38); // and the position should be before the third line
     }
 
+    #[test]
+    fn test_offsets_by_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = &dir.path().join("test.file");
+        let mut f = File::create(path).unwrap();
+        let mut tailed_file = TailedFile::new(&path).unwrap();
+        tailed_file.max_batch = 1;
+        f.write_all(b"first\nsecond\nthird\n").unwrap();
+
+        // a chunk holds every line, the batch is over after it
+        assert_eq!(tailed_file.read().unwrap(), 3);
+        let offsets = tailed_file
+            .lines()
+            .map(|(_, offset)| offset)
+            .collect::<Vec<_>>();
+        assert_eq!(offsets, vec![6, 13, 19]);
+        let taken = tailed_file.take_lines().collect::<Vec<_>>();
+        assert_eq!(taken[1], (Bytes::from_static(b"second"), 13));
+        assert_eq!(tailed_file.lines().count(), 0);
+
+        let long = vec![b'a'; CHUNK_SIZE * 2];
+        f.write_all(&long).unwrap();
+        f.write_all(b"\nlast\n").unwrap();
+        // the line spans many chunks
+        assert_eq!(read(&mut tailed_file), vec![long, b"last".to_vec()]);
+        assert_eq!(tailed_file.pos, 19 + CHUNK_SIZE as u64 * 2 + 6);
+        assert!(read(&mut tailed_file).is_empty());
+    }
+
+    #[test]
+    fn test_line_breaks() {
+        for (data, lines) in [
+            (&b"unix\n"[..], vec![&b"unix"[..]]),
+            (b"windows\r\n", vec![b"windows"]),
+            (
+                b"unix\nwindows\r\nunix\n",
+                vec![b"unix", b"windows", b"unix"],
+            ),
+            (b"\r\n\n", vec![b"", b""]),
+            // only the last carriage return is part of the line breaker
+            (b"in\rside\r\r\n", vec![b"in\rside\r"]),
+            // the line breaker hasn't been fully written yet
+            (b"windows\r", vec![]),
+        ] {
+            let dir = tempfile::tempdir().unwrap();
+            let path = &dir.path().join("test.file");
+            let mut f = File::create(path).unwrap();
+            let mut tailed_file = TailedFile::new(&path).unwrap();
+            f.write_all(data).unwrap();
+
+            assert_eq!(read(&mut tailed_file), lines);
+        }
+    }
+
+    #[test]
+    fn test_utf16_line_breaks() {
+        // `\u{a41}\u{4100}` is `41 0a 00 41`, which holds a line breaker across two characters
+        let text = "\u{a41}\u{4100}\r\nnext\n";
+        let encode = |line: &str| line.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let data: Vec<u8> = encode(text);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = &dir.path().join("test.file");
+        let mut f = File::create(path).unwrap();
+        let mut tailed_file = TailedFile::new(&path).unwrap();
+        tailed_file.set_line_break(LineBreak::Utf16Le);
+        f.write_all(&data).unwrap();
+        assert_eq!(
+            read(&mut tailed_file),
+            vec![encode("\u{a41}\u{4100}"), encode("next")]
+        );
+
+        let mut reader = std::io::Cursor::new(&data);
+        let mut line = vec![];
+        LineBreak::Utf16Le
+            .read_line(&mut reader, &mut line)
+            .unwrap();
+        assert_eq!(line, encode("\u{a41}\u{4100}\r\n"));
+        assert_eq!(LineBreak::Utf16Le.rfind(&data), Some(data.len()));
+    }
+
+    #[test]
+    fn test_partial_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = &dir.path().join("test.file");
+        let mut f = File::create(path).unwrap();
+        let mut tailed_file = TailedFile::new(&path).unwrap();
+        tailed_file.set_partial_timeout(Some(Duration::ZERO));
+
+        f.write_all(b"first\nsecond").unwrap();
+        assert_eq!(read(&mut tailed_file), vec![b"first".to_vec()]);
+        assert!(!tailed_file.partial());
+        // it hasn't grown since the last read
+        assert_eq!(read(&mut tailed_file), vec![b"second".to_vec()]);
+        assert!(tailed_file.partial());
+
+        // its line breaker comes with the next line
+        f.write_all(b"\nthird\n").unwrap();
+        assert_eq!(read(&mut tailed_file), vec![b"third".to_vec()]);
+        assert!(!tailed_file.partial());
+        assert_eq!(tailed_file.pos, 19);
+        assert_eq!(tailed_file.line, 3);
+    }
+
     #[test]
     fn test_check_rotate() {
         let dir = tempfile::tempdir().unwrap();
@@ -221,19 +660,20 @@ mod tests {
         let path2 = &dir.path().join("test2.file");
         let test_data = b"Some data";
         let more_test_data = b"fun";
-        let mut f = File::create(&path).unwrap();
+        let mut f = File::create(path).unwrap();
         f.write_all(test_data).unwrap();
         let mut tailed_file = TailedFile::new(&path).unwrap();
-        std::fs::rename(&path, &path2).unwrap();
-        let mut f = File::create(&path).unwrap();
+        std::fs::rename(path, path2).unwrap();
+        let mut f = File::create(path).unwrap();
         f.write_all(more_test_data).unwrap();
 
         assert_eq!(
-            "Err(FileRotated)",
-            format!("{:?}", tailed_file.has_been_rotated(&f))
+            tailed_file.has_been_rotated().unwrap(),
+            Some(Event::Rotated)
         );
-        assert_eq!(tailed_file.meta.st_ino(), f.metadata().unwrap().st_ino());
-        assert_eq!(tailed_file.pos, 0)
+        assert_eq!(tailed_file.id, FileId::of(&f).unwrap());
+        assert_eq!(tailed_file.pos, 0);
+        assert_eq!(tailed_file.line, 0)
     }
 
     #[test]
@@ -242,15 +682,100 @@ mod tests {
         let path = &dir.path().join("test.file");
         let test_data = b"Some data";
         let more_test_data = b"fun";
-        let mut f = File::create(&path).unwrap();
+        let mut f = File::create(path).unwrap();
         f.write_all(test_data).unwrap();
         let mut tailed_file = TailedFile::new(&path).unwrap();
-        let mut f = File::create(&path).unwrap();
+        let mut f = File::create(path).unwrap();
         f.write_all(more_test_data).unwrap();
+        assert!(tailed_file.has_been_truncated().unwrap());
+        assert_eq!(tailed_file.pos, 0)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_deleted_then_created_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = &dir.path().join("test.file");
+        let mut f = File::create(path).unwrap();
+        f.write_all(b"first\n").unwrap();
+        let mut tailed_file = TailedFile::new(&path).unwrap();
+
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(tailed_file.follow().unwrap(), Event::Deleted);
+        // reported once, then waiting for the file
+        assert!(follow(&mut tailed_file).is_empty());
+
+        std::fs::write(path, "second\n").unwrap();
+        assert_eq!(tailed_file.follow().unwrap(), Event::Rotated);
+        assert_eq!(follow(&mut tailed_file), vec![b"second".to_vec()]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_retargeted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = &dir.path().join("current.log");
+        std::fs::write(dir.path().join("app-1.log"), "").unwrap();
+        std::os::unix::fs::symlink(dir.path().join("app-1.log"), path).unwrap();
+        let mut tailed_file = TailedFile::new(&path).unwrap();
+
+        std::fs::write(dir.path().join("app-2.log"), "first\n").unwrap();
+        std::fs::remove_file(path).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("app-2.log"), path).unwrap();
+
+        assert_eq!(tailed_file.follow().unwrap(), Event::Rotated);
+        assert_eq!(follow(&mut tailed_file), vec![b"first".to_vec()]);
+    }
+
+    /// The lines written to the former file once it has been renamed are read before switching
+    #[test]
+    fn test_drain_after_rotate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = &dir.path().join("test.file");
+        let path2 = &dir.path().join("test.file.1");
+        let mut old = File::create(path).unwrap();
+        let mut tailed_file = TailedFile::new(&path).unwrap();
+        old.write_all(b"first\n").unwrap();
+
+        std::fs::rename(path, path2).unwrap();
+        old.write_all(b"second\n").unwrap();
+        let mut new = File::create(path).unwrap();
+        new.write_all(b"third\n").unwrap();
+
         assert_eq!(
-            "Err(FileTruncated)",
-            format!("{:?}", tailed_file.has_been_truncated(&f))
+            follow(&mut tailed_file),
+            vec![b"first".to_vec(), b"second".to_vec()]
         );
-        assert_eq!(tailed_file.pos, 0)
+        assert_eq!(tailed_file.follow().unwrap(), Event::Rotated);
+        assert_eq!(follow(&mut tailed_file), vec![b"third".to_vec()]);
+        assert_eq!(tailed_file.line, 1);
+    }
+
+    #[tokio::test]
+    async fn test_next_event() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.file");
+        std::fs::write(&path, "first\n").unwrap();
+        let mut tailed_file = TailedFile::new(path.clone()).unwrap();
+        let interval = Duration::from_millis(10);
+
+        // the task keeps running while the file is waited for on a blocking thread
+        let waiting = tokio::task::spawn_blocking(move || {
+            let event = tailed_file.next_event(interval);
+            (tailed_file, event)
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let mut f = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        f.write_all(b"second\n").unwrap();
+
+        let (mut tailed_file, event) = waiting.await.unwrap();
+        assert_eq!(event.unwrap(), Event::Read(1));
+        assert_eq!(tailed_file.line(), 1);
+
+        std::fs::write(&path, "").unwrap();
+        assert_eq!(tailed_file.next_event(interval).unwrap(), Event::Truncated);
     }
 }
@@ -0,0 +1,329 @@
+//! Export the metrics and the traces of the lines to an OpenTelemetry collector, over OTLP/HTTP
+//! in JSON, eg. `--otlp-endpoint http://collector:4318`
+//!
+//! A sampled line is traced from its arrival at the publisher until the output confirms it, with
+//! the `transform` and `publish` steps as child spans. Its `traceparent` header is published
+//! along with it, so the broker-side traces can be correlated.
+#[cfg(feature = "http")]
+use crate::metrics;
+use serde_json::{json, Value};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+#[cfg(feature = "http")]
+use std::time::Duration;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// W3C Trace Context header, see https://www.w3.org/TR/trace-context/
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// The spans are dropped rather than kept once that many are waiting for the export
+const MAX_PENDING_SPANS: usize = 10_000;
+
+/// OTLP's span kinds
+const KIND_INTERNAL: u8 = 1;
+const KIND_PRODUCER: u8 = 4;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos() as u64)
+}
+
+/// Only read by the export, which needs the `http` feature
+#[cfg_attr(not(feature = "http"), allow(dead_code))]
+#[derive(Debug, Clone)]
+struct Span {
+    trace_id: u128,
+    span_id: u64,
+    parent_id: Option<u64>,
+    name: &'static str,
+    kind: u8,
+    start: u64,
+    end: u64,
+    attributes: Vec<(&'static str, Value)>,
+}
+
+#[cfg(feature = "http")]
+impl Span {
+    fn to_otlp(&self) -> Value {
+        let mut span = json!({
+            "traceId": format!("{:032x}", self.trace_id),
+            "spanId": format!("{:016x}", self.span_id),
+            "name": self.name,
+            "kind": self.kind,
+            "startTimeUnixNano": self.start.to_string(),
+            "endTimeUnixNano": self.end.to_string(),
+            "attributes": self.attributes.iter().map(|(key, value)| attribute(key, value.clone())).collect::<Vec<_>>(),
+        });
+        if let Some(parent_id) = self.parent_id {
+            span["parentSpanId"] = json!(format!("{:016x}", parent_id));
+        }
+
+        span
+    }
+}
+
+/// OTLP's `KeyValue`, the value is typed according to its JSON type
+pub fn attribute(key: &str, value: Value) -> Value {
+    let value = match value {
+        Value::Number(number) if number.is_u64() || number.is_i64() => {
+            json!({ "intValue": number.to_string() })
+        }
+        Value::Number(number) => json!({ "doubleValue": number }),
+        Value::Bool(value) => json!({ "boolValue": value }),
+        Value::String(value) => json!({ "stringValue": value }),
+        value => json!({ "stringValue": value.to_string() }),
+    };
+
+    json!({ "key": key, "value": value })
+}
+
+/// Samples the lines to trace, then keeps their spans until they're exported
+#[derive(Default)]
+pub struct Tracer {
+    /// One line out of that many is traced, none if 0
+    every: AtomicU64,
+    lines: AtomicU64,
+    ids: AtomicU64,
+    random: RandomState,
+    spans: Mutex<Vec<Span>>,
+}
+
+impl Tracer {
+    #[cfg(feature = "http")]
+    pub fn enable(&self, every: u64) {
+        self.every.store(every, Ordering::Relaxed);
+    }
+
+    /// Start the trace of the next line, if it's sampled
+    pub fn sample(&'static self) -> Option<LineTrace> {
+        let every = self.every.load(Ordering::Relaxed);
+        if every == 0
+            || !self
+                .lines
+                .fetch_add(1, Ordering::Relaxed)
+                .is_multiple_of(every)
+        {
+            return None;
+        }
+
+        Some(LineTrace {
+            tracer: self,
+            trace_id: (self.id() as u128) << 64 | self.id() as u128,
+            span_id: self.id(),
+            start: now(),
+            attributes: vec![],
+        })
+    }
+
+    /// Random and unique, never 0 which is an invalid ID
+    fn id(&self) -> u64 {
+        let mut hasher = self.random.build_hasher();
+        hasher.write_u64(self.ids.fetch_add(1, Ordering::Relaxed));
+        hasher.write_u64(now());
+        hasher.finish().max(1)
+    }
+
+    fn record(&self, span: Span) {
+        let mut spans = self.spans.lock().unwrap();
+        if spans.len() < MAX_PENDING_SPANS {
+            spans.push(span);
+        }
+    }
+
+    /// Take the finished spans, rendered as OTLP's `ResourceSpans`
+    #[cfg(feature = "http")]
+    fn drain(&self, service: &str) -> Option<Value> {
+        let spans = std::mem::take(&mut *self.spans.lock().unwrap());
+        if spans.is_empty() {
+            return None;
+        }
+
+        Some(json!({
+            "resourceSpans": [{
+                "resource": resource(service),
+                "scopeSpans": [{
+                    "scope": { "name": env!("CARGO_PKG_NAME") },
+                    "spans": spans.iter().map(Span::to_otlp).collect::<Vec<_>>(),
+                }],
+            }],
+        }))
+    }
+}
+
+/// The tracer shared by the whole process
+pub fn tracer() -> &'static Tracer {
+    static TRACER: OnceLock<Tracer> = OnceLock::new();
+    TRACER.get_or_init(Tracer::default)
+}
+
+/// The trace of a line, its root span ends once the line has been published
+pub struct LineTrace {
+    tracer: &'static Tracer,
+    trace_id: u128,
+    span_id: u64,
+    start: u64,
+    attributes: Vec<(&'static str, Value)>,
+}
+
+impl LineTrace {
+    pub fn attribute(&mut self, key: &'static str, value: Value) {
+        self.attributes.push((key, value));
+    }
+
+    /// Start a step of the line, it ends along with the returned value
+    pub fn step(&self, name: &'static str) -> Step<'_> {
+        Step {
+            trace: self,
+            name,
+            start: now(),
+        }
+    }
+
+    /// Value of the `traceparent` header, the line is the parent of the broker-side spans
+    pub fn traceparent(&self) -> String {
+        format!("00-{:032x}-{:016x}-01", self.trace_id, self.span_id)
+    }
+
+    /// The line has been published, or dropped, `outcome` tells which
+    pub fn end(mut self, outcome: &'static str) {
+        self.attribute("outcome", json!(outcome));
+        self.tracer.record(Span {
+            trace_id: self.trace_id,
+            span_id: self.span_id,
+            parent_id: None,
+            name: "line",
+            kind: KIND_PRODUCER,
+            start: self.start,
+            end: now(),
+            attributes: self.attributes,
+        });
+    }
+}
+
+/// A child span of the line, recorded once dropped
+pub struct Step<'a> {
+    trace: &'a LineTrace,
+    name: &'static str,
+    start: u64,
+}
+
+impl Drop for Step<'_> {
+    fn drop(&mut self) {
+        self.trace.tracer.record(Span {
+            trace_id: self.trace.trace_id,
+            span_id: self.trace.tracer.id(),
+            parent_id: Some(self.trace.span_id),
+            name: self.name,
+            kind: KIND_INTERNAL,
+            start: self.start,
+            end: now(),
+            attributes: vec![],
+        });
+    }
+}
+
+#[cfg(feature = "http")]
+fn resource(service: &str) -> Value {
+    json!({ "attributes": [attribute("service.name", json!(service))] })
+}
+
+/// Send the spans and the metrics to the collector periodically, a failed export is dropped
+#[cfg(feature = "http")]
+pub async fn export(endpoint: String, interval: Duration, service: String) {
+    let client = reqwest::Client::new();
+    let endpoint = endpoint.trim_end_matches('/');
+    let started = now();
+    let mut interval = tokio::time::interval(interval);
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+
+        let metrics = json!({
+            "resourceMetrics": [{
+                "resource": resource(&service),
+                "scopeMetrics": [{
+                    "scope": { "name": env!("CARGO_PKG_NAME") },
+                    "metrics": metrics::registry().to_otlp(started, now()),
+                }],
+            }],
+        });
+        let exports = [
+            ("metrics", Some(metrics)),
+            ("traces", tracer().drain(&service)),
+        ];
+
+        for (signal, body) in exports {
+            let body = match body {
+                Some(body) => body,
+                None => continue,
+            };
+
+            let res = client
+                .post(format!("{}/v1/{}", endpoint, signal))
+                .header("Content-Type", "application/json")
+                .body(body.to_string())
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+
+            if let Err(e) = res {
+                warn!("Can't export the {} to `{}`: {}", signal, endpoint, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn test_line_trace() {
+        let tracer: &'static Tracer = Box::leak(Box::default());
+        assert!(tracer.sample().is_none());
+
+        tracer.enable(2);
+        let mut trace = tracer.sample().unwrap();
+        trace.attribute("log.line", json!(1));
+        assert!(tracer.sample().is_none());
+
+        let traceparent = trace.traceparent();
+        assert_eq!(traceparent.len(), 55);
+        drop(trace.step("publish"));
+        trace.end("published");
+
+        let exported = tracer.drain("log-bouncer").unwrap();
+        let spans = &exported["resourceSpans"][0]["scopeSpans"][0]["spans"];
+        assert_eq!(spans[0]["name"], "publish");
+        assert_eq!(spans[0]["parentSpanId"], spans[1]["spanId"]);
+        assert_eq!(spans[1]["attributes"][0]["value"]["intValue"], "1");
+        assert!(traceparent.contains(spans[1]["traceId"].as_str().unwrap()));
+        assert!(tracer.drain("log-bouncer").is_none());
+    }
+
+    #[test]
+    fn test_metrics() {
+        let registry = crate::metrics::Registry::default();
+        registry.describe("latency", "Publish latency", Some(&[0.1, 1.0]));
+        registry.observe("latency", &[("file", "app.log")], 0.05);
+        registry.observe("latency", &[("file", "app.log")], 0.5);
+        registry.observe("latency", &[("file", "app.log")], 5.0);
+        registry.increment("lines", &[], 3);
+
+        let metrics = registry.to_otlp(1, 2);
+        let histogram = &metrics[0]["histogram"]["dataPoints"][0];
+        assert_eq!(metrics[0]["description"], "Publish latency");
+        assert_eq!(histogram["bucketCounts"], json!(["1", "1", "1"]));
+        assert_eq!(histogram["explicitBounds"], json!([0.1, 1.0]));
+        assert_eq!(
+            histogram["attributes"][0]["value"]["stringValue"],
+            "app.log"
+        );
+        assert_eq!(metrics[1]["sum"]["dataPoints"][0]["asInt"], "3");
+    }
+}